@@ -1,5 +1,10 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
 fn main() {
     platform();
+    version();
 }
 
 #[cfg(windows)]
@@ -9,3 +14,46 @@ fn platform() {
 
 #[cfg(not(windows))]
 fn platform() {}
+
+/// Collect build information used in the output of `--version`.
+fn version() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // NB: rebuild when the checked out commit changes.
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    let sha = output("git", &["rev-parse", "--short", "HEAD"]);
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let rustc = output(&rustc, &["--version"]);
+
+    println!(
+        "cargo:rustc-env=QUICKCFG_GIT_SHA={}",
+        sha.as_deref().unwrap_or("unknown")
+    );
+    println!(
+        "cargo:rustc-env=QUICKCFG_RUSTC_VERSION={}",
+        rustc.as_deref().unwrap_or("unknown rustc")
+    );
+}
+
+/// Run the given command and return its trimmed output, if it succeeded.
+fn output(command: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(command).args(args).output().ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let output = String::from_utf8(output.stdout).ok()?;
+    let output = output.trim();
+
+    if output.is_empty() {
+        return None;
+    }
+
+    Some(output.to_string())
+}
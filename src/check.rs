@@ -0,0 +1,136 @@
+//! Validate a configuration without applying it.
+
+use crate::{
+    environment as e,
+    facts::Facts,
+    hierarchy,
+    system::{self, System},
+    Config,
+};
+use anyhow::{anyhow, bail, Context as _, Error};
+use std::path::Path;
+
+/// Check that the given configuration is valid.
+///
+/// Templates and conditions are parsed when the configuration is loaded, so this validates that
+/// every system only requires declared systems, loads the hierarchy and expands all systems,
+/// including the ones loaded from it. Missing hierarchy files are only warned about, since they
+/// might be intentionally missing, like secrets.
+pub fn check<E>(config: &Config, root: &Path, facts: &Facts, environment: E) -> Result<(), Error>
+where
+    E: Copy + e::Environment,
+{
+    let declared = system::declared_ids(&config.systems);
+    let mut queue = config.systems.iter().collect::<Vec<_>>();
+
+    while let Some(system) = queue.pop() {
+        for id in system.requires() {
+            if !declared.contains(id.as_str()) {
+                let name = system
+                    .id()
+                    .map(String::from)
+                    .unwrap_or_else(|| system.to_string());
+                bail!("system `{}` requires unknown system `{}`", name, id);
+            }
+        }
+
+        if let System::OnlyFor(only_for) = system {
+            queue.extend(&only_for.systems);
+        }
+    }
+
+    for template in &config.hierarchy {
+        let path = match template.as_relative_path(facts, environment)? {
+            Some(path) => path.to_path(root),
+            None => continue,
+        };
+
+        if !path.is_file() {
+            log::warn!("missing hierarchy file: {}", path.display());
+        }
+    }
+
+    let data = hierarchy::load(&config.hierarchy, root, facts, environment)
+        .with_context(|| "Failed to load hierarchy")?;
+
+    for system in system::expand(&config.systems, facts) {
+        if let System::FromDb(from_db) = system {
+            from_db
+                .systems(&data, facts, environment)
+                .with_context(|| anyhow!("Failed to load systems: {}", system))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check;
+    use crate::{environment as e, facts::Facts, Config};
+
+    fn config(config: &str) -> Result<Config, serde_yaml::Error> {
+        serde_yaml::from_str(config)
+    }
+
+    #[test]
+    fn test_valid() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("common.yml"), "foo: bar\n").unwrap();
+
+        let config = config(
+            "hierarchy: [common.yml, missing.yml]\nsystems:\n  - type: copy-dir\n    from: a\n    to: home://{foo}\n",
+        )
+        .unwrap();
+
+        check(&config, root.path(), &Facts::new(Vec::new()), e::Real).unwrap();
+    }
+
+    #[test]
+    fn test_unbalanced_template() {
+        let e = config(
+            "hierarchy: []\nsystems:\n  - type: copy-dir\n    from: a\n    to: home://{foo\n",
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("missing closing"), "{}", e);
+    }
+
+    #[test]
+    fn test_unknown_requires() {
+        let root = tempfile::tempdir().unwrap();
+
+        let config = config(
+            "hierarchy: []\nsystems:\n  - type: only-for\n    os: none\n    systems:\n      - type: link-dir\n        from: a\n        to: home://b\n        requires: [nope]\n",
+        )
+        .unwrap();
+
+        let e = check(&config, root.path(), &Facts::new(Vec::new()), e::Real).unwrap_err();
+        assert!(e.to_string().contains("unknown system `nope`"), "{}", e);
+    }
+
+    #[test]
+    fn test_bad_hierarchy() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("common.yml"), "- not a mapping\n").unwrap();
+
+        let config = config("hierarchy: [common.yml]\nsystems: []\n").unwrap();
+        assert!(check(&config, root.path(), &Facts::new(Vec::new()), e::Real).is_err());
+    }
+
+    #[test]
+    fn test_bad_database_system() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root.path().join("common.yml"),
+            "link:\n  - unknown: field\n",
+        )
+        .unwrap();
+
+        let config = config(
+            "hierarchy: [common.yml]\nsystems:\n  - type: from-db\n    system: link\n    key: link\n",
+        )
+        .unwrap();
+
+        assert!(check(&config, root.path(), &Facts::new(Vec::new()), e::Real).is_err());
+    }
+}
@@ -6,8 +6,13 @@ use std::fmt;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+/// How frequently to poll a child process which has a timeout.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 /// The decoded output after running a command.
 pub struct Output {
     pub status: process::ExitStatus,
@@ -51,12 +56,25 @@ impl fmt::Display for OutputError {
     }
 }
 
+/// Error raised when a command didn't complete within its timeout.
+///
+/// Wrapped in an [io::Error] with the kind [io::ErrorKind::TimedOut].
+#[derive(Debug, Error)]
+#[error("command `{name}` timed out after {timeout:?}")]
+pub struct TimeoutError {
+    pub name: String,
+    pub timeout: Duration,
+}
+
 /// A command wrapper that simplifies interaction with external commands.
 #[derive(Debug, Clone)]
 pub struct Command {
     pub(crate) name: PathBuf,
     pub(crate) working_directory: Option<PathBuf>,
     pub(crate) args: Vec<OsString>,
+    pub(crate) envs: Vec<(OsString, OsString)>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) null_stdin: bool,
 }
 
 impl Command {
@@ -66,6 +84,26 @@ impl Command {
             name: name.into(),
             working_directory: None,
             args: Vec::new(),
+            envs: Vec::new(),
+            timeout: None,
+            null_stdin: false,
+        }
+    }
+
+    /// Construct a command which runs `script` through the shell of the current platform.
+    pub fn shell(script: &str) -> Command {
+        #[cfg(windows)]
+        {
+            let mut command = Command::new("cmd");
+            command.args(&["/C", script]);
+            command
+        }
+
+        #[cfg(not(windows))]
+        {
+            let mut command = Command::new("/bin/sh");
+            command.args(&["-c", script]);
+            command
         }
     }
 
@@ -87,14 +125,36 @@ impl Command {
             .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
     }
 
+    /// Set an environment variable for the command.
+    pub fn env<K, V>(&mut self, key: K, value: V)
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+    }
+
     fn command(&self) -> process::Command {
         let mut cmd = process::Command::new(self.name.as_os_str());
         cmd.args(&self.args);
+        cmd.envs(self.envs.iter().map(|(k, v)| (k, v)));
 
         if let Some(working_directory) = self.working_directory.as_ref() {
             cmd.current_dir(working_directory);
         }
 
+        if self.null_stdin {
+            cmd.stdin(process::Stdio::null());
+        }
+
+        // NB: run in a separate process group so that the whole group can be killed on timeout.
+        #[cfg(unix)]
+        if self.timeout.is_some() {
+            use std::os::unix::process::CommandExt as _;
+            cmd.process_group(0);
+        }
+
         cmd
     }
 
@@ -103,6 +163,71 @@ impl Command {
         self.working_directory = Some(path.as_ref().to_owned());
     }
 
+    /// Prevent the command from reading from stdin, so that it can't wait for input.
+    ///
+    /// Output is still inherited when the command is run with [Command::status].
+    pub fn null_stdin(&mut self) {
+        self.null_stdin = true;
+    }
+
+    /// Configure a timeout for this command, after which it is killed.
+    ///
+    /// On unix, this runs the command in its own process group, so that any processes it spawns
+    /// are killed as well.
+    pub fn timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Wait for the given child, killing it if it doesn't complete before the timeout.
+    fn wait(&self, child: &mut process::Child) -> io::Result<process::ExitStatus> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return child.wait(),
+        };
+
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+
+            if Instant::now() >= deadline {
+                break;
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        kill(child)?;
+        child.wait()?;
+
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            TimeoutError {
+                name: self.name.display().to_string(),
+                timeout,
+            },
+        ));
+
+        #[cfg(unix)]
+        fn kill(child: &mut process::Child) -> io::Result<()> {
+            let pid = child.id() as libc::pid_t;
+
+            // NB: negative pid signals the whole process group.
+            if unsafe { libc::kill(-pid, libc::SIGKILL) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        }
+
+        #[cfg(not(unix))]
+        fn kill(child: &mut process::Child) -> io::Result<()> {
+            child.kill()
+        }
+    }
+
     /// Run the given command, return all lines printed to stdout on success.
     pub fn run_lines(self) -> Result<Vec<String>, Error> {
         let lines = self
@@ -142,7 +267,7 @@ impl Command {
     /// thread.
     pub fn run_inherited(&self) -> Result<(), Error> {
         let mut cmd = self.command();
-        let status = cmd.status()?;
+        let status = self.wait(&mut cmd.spawn()?)?;
 
         if !status.success() {
             bail!(
@@ -157,24 +282,86 @@ impl Command {
 
     /// Run the given command, return a string of all output.
     pub fn run(self) -> io::Result<Output> {
-        let output = self.command().output()?;
+        let output = if self.timeout.is_some() {
+            self.output_with_timeout()?
+        } else {
+            self.command().output()?
+        };
 
         let output = Output {
             status: output.status,
-            stdout: String::from_utf8(output.stdout).map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, "Cannot decode stdout as utf-8")
-            })?,
-            stderr: String::from_utf8(output.stderr).map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, "Cannot decode stderr as utf-8")
-            })?,
+            stdout: self.decode("stdout", output.stdout),
+            stderr: self.decode("stderr", output.stderr),
         };
 
         Ok(output)
     }
 
+    /// Decode output from the command.
+    ///
+    /// NB: output which isn't valid UTF-8, like from tools using a non-UTF-8 locale, is decoded
+    /// lossily.
+    fn decode(&self, stream: &str, bytes: Vec<u8>) -> String {
+        match String::from_utf8(bytes) {
+            Ok(string) => string,
+            Err(e) => {
+                log::warn!(
+                    "`{}`: {} is not valid utf-8, invalid characters have been replaced",
+                    self.name.display(),
+                    stream
+                );
+
+                String::from_utf8_lossy(e.as_bytes()).into_owned()
+            }
+        }
+    }
+
+    /// Collect the output of the command, while respecting its timeout.
+    fn output_with_timeout(&self) -> io::Result<process::Output> {
+        use std::io::Read as _;
+
+        let mut child = self
+            .command()
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()?;
+
+        // NB: read output in the background, so that the child doesn't block on full pipes.
+        let read = |pipe: Option<Box<dyn io::Read + Send>>| {
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+
+                if let Some(mut pipe) = pipe {
+                    pipe.read_to_end(&mut buf)?;
+                }
+
+                Ok::<_, io::Error>(buf)
+            })
+        };
+
+        let stdout = read(child.stdout.take().map(|p| Box::new(p) as Box<_>));
+        let stderr = read(child.stderr.take().map(|p| Box::new(p) as Box<_>));
+
+        let status = self.wait(&mut child)?;
+
+        let join = |handle: thread::JoinHandle<io::Result<Vec<u8>>>| {
+            handle
+                .join()
+                .map_err(|_| io::Error::other("output reader panicked"))?
+        };
+
+        Ok(process::Output {
+            status,
+            stdout: join(stdout)?,
+            stderr: join(stderr)?,
+        })
+    }
+
     /// Run the command and wait for exit status.
     pub fn status(self) -> io::Result<process::ExitStatus> {
-        self.command().status()
+        let mut cmd = self.command();
+        self.wait(&mut cmd.spawn()?)
     }
 
     /// Run as administrator.
@@ -183,3 +370,76 @@ impl Command {
         crate::ffi::win::shellapi::runas(self)
     }
 }
+
+/// Construct a command which prints `stderr` and exits with a non-zero status, ignoring any
+/// arguments added to it.
+#[cfg(all(test, unix))]
+pub(crate) fn failing(stderr: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c");
+    command.arg(format!("echo '{}' >&2; exit 1", stderr));
+    command.arg("sh");
+    command
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{Command, TimeoutError};
+    use std::io;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_timeout() {
+        let mut command = Command::new("sleep");
+        command.arg("10");
+        command.timeout(Duration::from_secs(1));
+
+        let start = Instant::now();
+        let e = command.clone().run().err().expect("command to time out");
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+        let e = e
+            .into_inner()
+            .expect("inner error")
+            .downcast::<TimeoutError>()
+            .expect("timeout error");
+
+        assert_eq!(e.timeout, Duration::from_secs(1));
+
+        let start = Instant::now();
+        let e = command.status().expect_err("command to time out");
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_timeout_output() {
+        let mut command = Command::new("echo");
+        command.arg("hello");
+        command.timeout(Duration::from_secs(10));
+
+        assert_eq!(command.run_stdout().unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn test_env() {
+        let mut command = Command::new("sh");
+        command.args(&["-c", "echo $QUICKCFG_TEST"]);
+        command.env("QUICKCFG_TEST", "hello");
+
+        assert_eq!(command.run_stdout().unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let mut command = Command::new("sh");
+        command.args(&["-c", "printf 'caf\\351\\n'; printf 'bad \\377' >&2"]);
+
+        let output = command.clone().run().unwrap();
+        assert_eq!(output.stdout, "caf\u{fffd}\n");
+        assert_eq!(output.stderr, "bad \u{fffd}");
+
+        assert_eq!(command.run_lines().unwrap(), vec!["caf\u{fffd}", ""]);
+    }
+}
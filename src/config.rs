@@ -1,25 +1,33 @@
 //! Model for configuration file.
-use crate::{system::System, template::Template};
+use crate::{environment as e, facts::Facts, system::System, template::Template, Load as _};
+use anyhow::{anyhow, bail, Context as _, Error};
+use directories::BaseDirs;
 use serde::{Deserialize, Deserializer};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// Default git refresh in seconds.
 const DEFAULT_GIT_REFRESH_SECONDS: u64 = 3600 * 24 * 3;
 /// Refresh package state every hour, unless changed.
 const DEFAULT_PACKAGE_REFRESH_SECONDS: u64 = 3600;
+/// Refresh intervals shorter than this are probably a mistake.
+const MIN_REFRESH_SECONDS: u64 = 60;
 
 /// Configuration model.
 #[derive(Deserialize, Default, Debug, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     /// The interval at which we check for git refresh.
-    #[serde(default = "default_git_refresh", deserialize_with = "human_duration")]
+    #[serde(
+        default = "default_git_refresh",
+        deserialize_with = "git_refresh_duration"
+    )]
     pub git_refresh: Duration,
 
     /// The interval at which we check for packages.
     #[serde(
         default = "default_package_refresh",
-        deserialize_with = "human_duration"
+        deserialize_with = "package_refresh_duration"
     )]
     pub package_refresh: Duration,
 
@@ -27,6 +35,128 @@ pub struct Config {
     pub hierarchy: Vec<Template>,
     /// The systems to apply.
     pub systems: Vec<System>,
+
+    /// Other configuration files whose hierarchy and systems are appended to this one.
+    #[serde(default)]
+    pub include: Vec<Template>,
+
+    /// Shell commands to run after all units have been applied successfully.
+    #[serde(default)]
+    pub post_hooks: Vec<String>,
+    /// Shell commands to run after all units have been applied successfully, but only if any of
+    /// them changed something.
+    #[serde(default)]
+    pub on_change: Vec<String>,
+}
+
+/// A configuration file which is included by another.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Include {
+    #[serde(default)]
+    hierarchy: Vec<Template>,
+    #[serde(default)]
+    systems: Vec<System>,
+    #[serde(default)]
+    include: Vec<Template>,
+}
+
+impl Config {
+    /// Load the configuration from the given path, together with all the files it includes.
+    ///
+    /// Includes are resolved relative to `root`, and their hierarchy and systems are appended in
+    /// the order they are included.
+    pub fn load_with_includes<E>(
+        path: &Path,
+        root: &Path,
+        base_dirs: Option<&BaseDirs>,
+        environment: E,
+    ) -> Result<Option<Config>, Error>
+    where
+        E: Copy + e::Environment,
+    {
+        let mut config = match Config::load(path)? {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+
+        let mut stack = vec![path.canonicalize()?];
+        let include = std::mem::take(&mut config.include);
+        config.include_all(&include, root, base_dirs, environment, &mut stack)?;
+        config.include = include;
+        Ok(Some(config))
+    }
+
+    /// Recursively include the given files, where `stack` is the chain of files currently being
+    /// included which is used to detect cycles.
+    fn include_all<E>(
+        &mut self,
+        include: &[Template],
+        root: &Path,
+        base_dirs: Option<&BaseDirs>,
+        environment: E,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<(), Error>
+    where
+        E: Copy + e::Environment,
+    {
+        // NB: facts aren't loaded yet, so only environment variables can be used in includes.
+        let facts = Facts::new(Vec::new());
+
+        for template in include {
+            let path = template
+                .as_path(root, base_dirs, &facts, environment)?
+                .ok_or_else(|| anyhow!("Cannot render include `{}`", template))?;
+
+            let canonical = path
+                .canonicalize()
+                .with_context(|| anyhow!("Missing include: {}", path.display()))?;
+
+            if stack.contains(&canonical) {
+                let chain = stack
+                    .iter()
+                    .chain(std::iter::once(&canonical))
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>();
+
+                bail!("Cyclic include: {}", chain.join(" -> "));
+            }
+
+            let included = Include::load(&path)
+                .with_context(|| anyhow!("Failed to load include: {}", path.display()))?
+                .ok_or_else(|| anyhow!("Missing include: {}", path.display()))?;
+
+            self.hierarchy.extend(included.hierarchy);
+            self.systems.extend(included.systems);
+
+            stack.push(canonical);
+            self.include_all(&included.include, root, base_dirs, environment, stack)?;
+            stack.pop();
+        }
+
+        Ok(())
+    }
+
+    /// Validate the configuration, returning warnings for settings which are probably a mistake.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let min = Duration::from_secs(MIN_REFRESH_SECONDS);
+
+        for (field, value) in [
+            ("git_refresh", self.git_refresh),
+            ("package_refresh", self.package_refresh),
+        ] {
+            if value < min {
+                warnings.push(format!(
+                    "`{}` is set to `{}`, which is less than a minute",
+                    field,
+                    humantime::format_duration(value)
+                ));
+            }
+        }
+
+        warnings
+    }
 }
 
 /// Return default git refresh in seconds.
@@ -45,5 +175,140 @@ where
     D: Deserializer<'de>,
 {
     let string = String::deserialize(deserializer)?;
-    humantime::parse_duration(&string).map_err(serde::de::Error::custom)
+    humantime::parse_duration(&string)
+        .map_err(|e| serde::de::Error::custom(format!("invalid duration `{}`: {}", string, e)))
+}
+
+/// Parse a human duration, naming the field it's for in errors.
+fn field_duration<'de, D>(field: &str, deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    human_duration(deserializer)
+        .map_err(|e| serde::de::Error::custom(format!("`{}`: {}", field, e)))
+}
+
+/// Parse the `git_refresh` duration.
+fn git_refresh_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    field_duration("git_refresh", deserializer)
+}
+
+/// Parse the `package_refresh` duration.
+fn package_refresh_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    field_duration("package_refresh", deserializer)
+}
+
+/// Parse an optional human duration.
+pub fn human_duration_option<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    human_duration(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use crate::environment as e;
+    use std::time::Duration;
+
+    #[test]
+    fn test_include() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("quickcfg.yml");
+
+        std::fs::write(
+            &path,
+            "hierarchy: [common.yml]\nsystems:\n  - type: mkdir\n    path: a\ninclude: [more.yml]\n",
+        )
+        .unwrap();
+        std::fs::create_dir(root.path().join("nested")).unwrap();
+        std::fs::write(
+            root.path().join("more.yml"),
+            "hierarchy: [more.yml]\nsystems:\n  - type: mkdir\n    path: b\ninclude: [nested/last.yml]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.path().join("nested").join("last.yml"),
+            "systems:\n  - type: mkdir\n    path: c\n",
+        )
+        .unwrap();
+
+        let config = Config::load_with_includes(&path, root.path(), None, e::Real)
+            .unwrap()
+            .expect("configuration");
+
+        let hierarchy = config
+            .hierarchy
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(hierarchy, vec!["common.yml", "more.yml"]);
+
+        let systems = config
+            .systems
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            systems,
+            vec![
+                "create directory `a`",
+                "create directory `b`",
+                "create directory `c`"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("quickcfg.yml");
+
+        std::fs::write(&path, "hierarchy: []\nsystems: []\ninclude: [a.yml]\n").unwrap();
+        std::fs::write(root.path().join("a.yml"), "include: [b.yml]\n").unwrap();
+        std::fs::write(root.path().join("b.yml"), "include: [quickcfg.yml]\n").unwrap();
+
+        let e = Config::load_with_includes(&path, root.path(), None, e::Real).unwrap_err();
+        assert!(e.to_string().contains("Cyclic include"), "{}", e);
+
+        std::fs::write(root.path().join("b.yml"), "include: [missing.yml]\n").unwrap();
+        let e = Config::load_with_includes(&path, root.path(), None, e::Real).unwrap_err();
+        assert!(format!("{:#}", e).contains("Missing include"), "{:#}", e);
+    }
+
+    #[test]
+    fn test_invalid_duration() {
+        let e = serde_yaml::from_str::<Config>("git_refresh: soon\nhierarchy: []\nsystems: []\n")
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("`git_refresh`"), "{}", e);
+        assert!(e.contains("invalid duration `soon`"), "{}", e);
+
+        let e = serde_yaml::from_str::<Config>("package_refresh: 1x\nhierarchy: []\nsystems: []\n")
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("`package_refresh`"), "{}", e);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let config = serde_yaml::from_str::<Config>("hierarchy: []\nsystems: []\n").unwrap();
+        assert!(config.warnings().is_empty());
+
+        let config =
+            serde_yaml::from_str::<Config>("git_refresh: 10s\nhierarchy: []\nsystems: []\n")
+                .unwrap();
+        assert_eq!(config.git_refresh, Duration::from_secs(10));
+
+        let warnings = config.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("`git_refresh`"), "{:?}", warnings);
+    }
 }
@@ -0,0 +1,173 @@
+//! Detection of dependency cycles between units.
+//!
+//! Units which are part of a cycle would never be scheduled, so the units are checked for cycles
+//! before any of them are run, and the run fails with the units involved instead.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Error};
+
+use crate::unit::{Dependency, SystemUnit, UnitId};
+
+/// Find a cycle among the dependencies of the given units, if there is one.
+///
+/// Returns the ids of the units involved, where each unit depends on the one following it and the
+/// last unit depends on the first.
+pub fn find_cycle(units: &[SystemUnit]) -> Option<Vec<UnitId>> {
+    let mut providers = HashMap::<Dependency, Vec<usize>>::new();
+
+    for (i, unit) in units.iter().enumerate() {
+        providers
+            .entry(Dependency::Unit(unit.id))
+            .or_default()
+            .push(i);
+
+        for provided in &unit.provides {
+            providers.entry(*provided).or_default().push(i);
+        }
+    }
+
+    let edges = units
+        .iter()
+        .map(|unit| {
+            unit.dependencies
+                .iter()
+                .flat_map(|d| providers.get(d).into_iter().flatten().copied())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // Nodes which have been completely visited, and nodes which are on the current path.
+    let mut visited = vec![false; units.len()];
+    let mut on_path = vec![false; units.len()];
+
+    for start in 0..units.len() {
+        if visited[start] {
+            continue;
+        }
+
+        // Path being visited, with the index of the next edge to follow for each node.
+        let mut path = vec![(start, 0)];
+        on_path[start] = true;
+
+        while let Some((node, edge)) = path.last_mut() {
+            let node = *node;
+
+            let next = match edges[node].get(*edge) {
+                Some(next) => *next,
+                None => {
+                    path.pop();
+                    on_path[node] = false;
+                    visited[node] = true;
+                    continue;
+                }
+            };
+
+            *edge += 1;
+
+            if on_path[next] {
+                let from = path.iter().position(|(n, _)| *n == next)?;
+                return Some(path[from..].iter().map(|(n, _)| units[*n].id).collect());
+            }
+
+            if !visited[next] {
+                on_path[next] = true;
+                path.push((next, 0));
+            }
+        }
+    }
+
+    None
+}
+
+/// Check that there are no dependency cycles among the given units, since those units would never
+/// be scheduled.
+///
+/// `system` looks up the id or description of the system that a unit belongs to, which is
+/// used to describe the units in the error.
+pub fn check_cycles<'a, S>(units: &[SystemUnit], system: S) -> Result<(), Error>
+where
+    S: Fn(UnitId) -> Option<&'a str>,
+{
+    let cycle = match find_cycle(units) {
+        Some(cycle) => cycle,
+        None => return Ok(()),
+    };
+
+    log::error!("Dependency cycle between the following units:");
+
+    for (i, id) in cycle.iter().enumerate() {
+        if let Some(unit) = units.iter().find(|u| u.id == *id) {
+            log::error!("{:2}: {}", i, unit);
+        }
+    }
+
+    let labels = cycle
+        .iter()
+        .chain(cycle.first())
+        .map(|id| match system(*id) {
+            Some(system) => format!("unit({:03}) of `{}`", id, system),
+            None => match units.iter().find(|u| u.id == *id) {
+                Some(unit) => format!("unit({:03}) `{}`", id, unit.unit()),
+                None => format!("unit({:03})", id),
+            },
+        })
+        .collect::<Vec<_>>();
+
+    bail!("Dependency cycle between units: {}", labels.join(" -> "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_cycles, find_cycle};
+    use crate::unit::{test_unit as unit, Dependency};
+
+    #[test]
+    fn test_find_cycle() {
+        let units = vec![
+            unit(0, false, vec![]),
+            unit(1, false, vec![Dependency::Unit(0), Dependency::Unit(2)]),
+            unit(2, false, vec![Dependency::Unit(1)]),
+        ];
+
+        assert_eq!(find_cycle(&units), Some(vec![1, 2]));
+
+        let system = |id| match id {
+            1 => Some("a"),
+            _ => None,
+        };
+
+        let e = check_cycles(&units, system).unwrap_err().to_string();
+        assert!(
+            e.contains("unit(001) of `a` -> unit(002) `create directory dir2` -> unit(001) of `a`"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_find_cycle_through_provides() {
+        let mut provider = unit(1, false, vec![Dependency::Unit(2)]);
+        provider.provides.push(Dependency::File(1));
+
+        let units = vec![
+            unit(0, false, vec![]),
+            provider,
+            unit(2, false, vec![Dependency::File(1)]),
+        ];
+
+        assert_eq!(find_cycle(&units), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_no_cycle() {
+        let units = vec![
+            unit(0, false, vec![]),
+            unit(1, false, vec![Dependency::Unit(0)]),
+            unit(2, true, vec![Dependency::Unit(0), Dependency::Unit(1)]),
+        ];
+
+        assert_eq!(find_cycle(&units), None);
+        assert!(check_cycles(&units, |_| None).is_ok());
+    }
+}
@@ -1,7 +1,7 @@
 //! Loading facts about the system that we are currently running on.
 
-use crate::template::Vars;
-use anyhow::{bail, Error};
+use crate::{template::Vars, Load};
+use anyhow::{anyhow, bail, Context as _, Error};
 use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::fs;
@@ -15,6 +15,15 @@ pub const DISTRO: &str = "distro";
 /// The `os` fact key.
 pub const OS: &str = "os";
 
+/// The `arch` fact key.
+pub const ARCH: &str = "arch";
+
+/// The `hostname` fact key.
+pub const HOSTNAME: &str = "hostname";
+
+/// The `user` fact key.
+pub const USER: &str = "user";
+
 /// The holder of all the facts detected in the system.
 pub struct Facts(HashMap<String, String>);
 
@@ -24,6 +33,16 @@ impl Facts {
         Facts(facts.into_iter().collect())
     }
 
+    /// Load custom facts from a YAML file containing a flat mapping.
+    ///
+    /// A missing file results in an empty set of facts.
+    pub fn load_from(path: &Path) -> Result<Facts, Error> {
+        let facts = HashMap::<String, String>::load(path)
+            .with_context(|| anyhow!("Failed to load facts: {}", path.display()))?;
+
+        Ok(Facts(facts.unwrap_or_default()))
+    }
+
     /// Load facts about the system.
     pub fn load() -> Result<Facts, Error> {
         let mut facts = HashMap::new();
@@ -33,8 +52,57 @@ impl Facts {
         }
 
         facts.insert(OS.to_string(), std::env::consts::OS.to_string());
+        facts.insert(ARCH.to_string(), std::env::consts::ARCH.to_string());
+
+        if let Some(hostname) = detect_hostname() {
+            facts.insert(HOSTNAME.to_string(), hostname);
+        }
+
+        if let Some(user) = detect_user() {
+            facts.insert(USER.to_string(), user);
+        }
+
         return Ok(Facts(facts));
 
+        /// Detect the hostname of the system.
+        #[cfg(unix)]
+        fn detect_hostname() -> Option<String> {
+            use crate::command::Command;
+
+            let hostname = match fs::read_to_string("/proc/sys/kernel/hostname") {
+                Ok(hostname) => hostname,
+                // NB: not all unix systems have procfs, like macos.
+                Err(_) => Command::new("hostname").run_stdout().ok()?,
+            };
+
+            non_empty(hostname)
+        }
+
+        /// Detect the hostname of the system.
+        #[cfg(windows)]
+        fn detect_hostname() -> Option<String> {
+            non_empty(std::env::var("COMPUTERNAME").ok()?)
+        }
+
+        /// Detect the current user.
+        fn detect_user() -> Option<String> {
+            let user = std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .ok()?;
+
+            non_empty(user)
+        }
+
+        fn non_empty(value: String) -> Option<String> {
+            let value = value.trim();
+
+            if value.is_empty() {
+                return None;
+            }
+
+            Some(value.to_string())
+        }
+
         /// Detect which distro we appear to be running.
         #[allow(unreachable_code)]
         fn detect_distro() -> Result<Option<String>, Error> {
@@ -52,6 +120,13 @@ impl Facts {
                 return Ok(Some("gentoo".to_string()));
             }
 
+            if metadata("/etc/alpine-release")?
+                .map(|m| m.is_file())
+                .unwrap_or(false)
+            {
+                return Ok(Some("alpine".to_string()));
+            }
+
             if metadata("/etc/debian_version")?
                 .map(|m| m.is_file())
                 .unwrap_or(false)
@@ -77,14 +152,67 @@ impl Facts {
         }
     }
 
+    /// Extend the current set of facts, overriding any existing ones.
+    pub fn extend(&mut self, facts: impl IntoIterator<Item = (String, String)>) {
+        self.0.extend(facts);
+    }
+
     /// Get the specified fact, if present.
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<&str>
+    pub fn get<Q>(&self, k: &Q) -> Option<&str>
     where
         String: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         self.0.get(k).map(|s| s.as_str())
     }
+
+    /// Facts as environment variables, like `QUICKCFG_DISTRO`, sorted by name.
+    pub fn env_vars(&self) -> Vec<(String, String)> {
+        let mut vars = self
+            .0
+            .iter()
+            .map(|(key, value)| (env_var_name(key), value.to_string()))
+            .collect::<Vec<_>>();
+
+        vars.sort();
+        vars
+    }
+}
+
+/// Convert the name of a fact into the name of an environment variable.
+fn env_var_name(key: &str) -> String {
+    let key = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect::<String>();
+
+    format!("QUICKCFG_{}", key)
+}
+
+/// Parse a custom fact from a `key=value` string.
+pub fn parse_fact(fact: &str) -> Result<(String, String), Error> {
+    let (key, value) = match fact.split_once('=') {
+        Some((key, value)) => (key.trim(), value.trim()),
+        None => bail!("bad fact `{}`: expected `<key>=<value>`", fact),
+    };
+
+    if key.is_empty() {
+        bail!("bad fact `{}`: missing key", fact);
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+impl IntoIterator for Facts {
+    type Item = (String, String);
+    type IntoIter = std::collections::hash_map::IntoIter<String, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
 }
 
 impl Vars for &Facts {
@@ -92,3 +220,89 @@ impl Vars for &Facts {
         Facts::get(self, k)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_fact, Facts, ARCH, DISTRO, OS};
+
+    #[test]
+    fn test_parse_fact() {
+        assert_eq!(
+            parse_fact("role=desktop").unwrap(),
+            (String::from("role"), String::from("desktop"))
+        );
+        assert_eq!(
+            parse_fact("url=a=b").unwrap(),
+            (String::from("url"), String::from("a=b"))
+        );
+        assert_eq!(
+            parse_fact("empty=").unwrap(),
+            (String::from("empty"), String::new())
+        );
+        assert!(parse_fact("role").is_err());
+        assert!(parse_fact("=desktop").is_err());
+    }
+
+    #[test]
+    fn test_env_vars() {
+        let facts = Facts::new(vec![
+            (String::from(DISTRO), String::from("debian")),
+            (String::from("my-role"), String::from("desktop")),
+        ]);
+
+        assert_eq!(
+            facts.env_vars(),
+            vec![
+                (String::from("QUICKCFG_DISTRO"), String::from("debian")),
+                (String::from("QUICKCFG_MY_ROLE"), String::from("desktop")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arch() {
+        let facts = Facts::load().unwrap();
+        let arch = facts.get(ARCH).expect("arch fact");
+        assert!(!arch.is_empty());
+        assert_eq!(arch, std::env::consts::ARCH);
+    }
+
+    #[test]
+    fn test_load_from() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("facts.yml");
+
+        // missing file is not an error.
+        assert_eq!(Facts::load_from(&path).unwrap().get("role"), None);
+
+        std::fs::write(&path, "role: laptop\ndistro: custom\n").unwrap();
+
+        let mut facts = Facts::new(vec![
+            (String::from(DISTRO), String::from("debian")),
+            (String::from(OS), String::from("linux")),
+        ]);
+
+        facts.extend(Facts::load_from(&path).unwrap());
+
+        assert_eq!(facts.get("role"), Some("laptop"));
+        assert_eq!(facts.get(DISTRO), Some("custom"));
+        assert_eq!(facts.get(OS), Some("linux"));
+
+        std::fs::write(&path, "- not a mapping\n").unwrap();
+        assert!(Facts::load_from(&path).is_err());
+    }
+
+    #[test]
+    fn test_override_detected() {
+        let mut facts = Facts::load().unwrap();
+        assert_eq!(facts.get(OS), Some(std::env::consts::OS));
+
+        facts.extend(vec![
+            (String::from(OS), String::from("custom")),
+            (String::from("role"), String::from("desktop")),
+        ]);
+
+        assert_eq!(facts.get(OS), Some("custom"));
+        assert_eq!(facts.get("role"), Some("desktop"));
+    }
+}
@@ -20,6 +20,7 @@ pub fn runas(command: crate::Command) -> io::Result<i32> {
 
     let file = wide_string(&command.name);
     let params = wide_string(encode_params(&command.args)?);
+    let directory = command.working_directory.as_ref().map(wide_string);
 
     let mut exit_code = 0;
 
@@ -31,7 +32,7 @@ pub fn runas(command: crate::Command) -> io::Result<i32> {
             lpVerb: operation.as_ptr(),
             lpFile: file.as_ptr(),
             lpParameters: params.as_ptr(),
-            lpDirectory: ptr::null(),
+            lpDirectory: directory.as_ref().map_or(ptr::null(), |d| d.as_ptr()),
             nShow: winuser::SW_SHOW,
             hInstApp: ptr::null_mut(),
             ..shellapi::SHELLEXECUTEINFOW::default()
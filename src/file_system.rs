@@ -4,22 +4,112 @@
 use crate::{
     hierarchy::Data,
     opts::Opts,
-    unit::{CopyFile, CopyTemplate, CreateDir, Dependency, Symlink, SystemUnit, UnitAllocator},
+    state::State,
+    unit::{
+        CopyFile, CopyTemplate, CreateDir, DeleteOrphans, Dependency, Hardlink, Symlink,
+        SystemUnit, UnitAllocator, UnitId,
+    },
 };
 use anyhow::{anyhow, bail, Context as _, Error};
 use fxhash::FxHashMap;
+use std::collections::BTreeSet;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use std::time::SystemTime;
 
+/// Options for files which are rendered as templates when copied.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TemplateOptions {
+    /// Fail to render templates which refer to variables that are not defined.
+    pub strict: bool,
+}
+
+/// A path which is being modified.
+struct PathEntry {
+    /// The dependency associated with the path.
+    dependency: Dependency,
+    /// The system which first referenced the path.
+    system: String,
+    /// The system which provides the file at the path.
+    provider: Option<String>,
+}
+
+/// A path which is provided by multiple systems.
+struct Conflict {
+    path: PathBuf,
+    first: String,
+    second: String,
+}
+
+/// A path which doesn't exist, but has to be provided by some system.
+struct Required {
+    path: PathBuf,
+    system: String,
+    /// What the path is, like `link target`.
+    what: &'static str,
+}
+
 /// Synchronized bits of FileSystem.
 #[derive(Default)]
 pub struct FileSystemInner {
-    // TODO: include the system that modified the paths for better diagnostics.
-    paths: FxHashMap<PathBuf, Dependency>,
-    invalid: bool,
+    paths: FxHashMap<PathBuf, PathEntry>,
+    conflicts: Vec<Conflict>,
+    required: Vec<Required>,
+}
+
+impl FileSystemInner {
+    /// Register that the directory at `path` is created by `system` through the unit `id`,
+    /// returning the dependencies which the unit provides.
+    ///
+    /// A path which is required but not provided by anyone is taken over, even if it was first
+    /// referenced as a file. Returns `None` if another system already creates the directory.
+    fn create_dir(
+        &mut self,
+        system: &str,
+        path: &Path,
+        id: UnitId,
+    ) -> Result<Option<Vec<Dependency>>, Error> {
+        let entry = match self.paths.get_mut(path) {
+            Some(entry) => entry,
+            None => {
+                let dependency = Dependency::Dir(id);
+
+                self.paths.insert(
+                    path.to_owned(),
+                    PathEntry {
+                        dependency,
+                        system: system.to_owned(),
+                        provider: Some(system.to_owned()),
+                    },
+                );
+
+                return Ok(Some(vec![dependency]));
+            }
+        };
+
+        let provides = match (entry.dependency, entry.provider.is_some()) {
+            (Dependency::Dir(_), true) => return Ok(None),
+            (Dependency::Dir(_), false) => vec![entry.dependency],
+            // NB: whoever depends on the path as a file is satisfied once the directory exists.
+            (Dependency::File(_), false) => {
+                let provides = vec![Dependency::Dir(id), entry.dependency];
+                entry.dependency = Dependency::Dir(id);
+                provides
+            }
+            _ => {
+                bail!(
+                    "Other system `{}` is modifying path, but not as a directory: {}",
+                    entry.system,
+                    path.display()
+                );
+            }
+        };
+
+        entry.provider = Some(system.to_owned());
+        Ok(Some(provides))
+    }
 }
 
 /// Helper and tracker of any filesystem modifications.
@@ -32,22 +122,34 @@ pub struct FileSystem<'a> {
 }
 
 macro_rules! dependency {
-    ($name:ident, $slf:ident, $path:ident) => {{
+    ($name:ident, $slf:ident, $system:ident, $path:ident) => {{
         let mut inner = $slf.inner.lock().map_err(|_| anyhow!("Lock poisoned"))?;
 
-        if let Some(dependency) = inner.paths.get_mut($path) {
-            if let Dependency::$name(_) = *dependency {
-                return Ok(*dependency);
+        if let Some(entry) = inner.paths.get($path) {
+            if let Dependency::$name(_) = entry.dependency {
+                return Ok(entry.dependency);
             }
 
             bail!(
-                "Multiple systems modifying path `{}` in different ways",
-                $path.display()
+                "Multiple systems modifying path `{}` in different ways: `{}` and `{}`",
+                $path.display(),
+                entry.system,
+                $system,
             );
         }
 
         let dependency = Dependency::$name($slf.allocator.allocate());
-        Ok(*inner.paths.entry($path.to_owned()).or_insert(dependency))
+
+        inner.paths.insert(
+            $path.to_owned(),
+            PathEntry {
+                dependency,
+                system: $system.to_owned(),
+                provider: None,
+            },
+        );
+
+        Ok(dependency)
     }};
 }
 
@@ -68,54 +170,311 @@ impl<'a> FileSystem<'a> {
         }
     }
 
-    /// Validate that we haven't created any conflicting files.
+    /// Validate that we haven't created any conflicting files, and that all required paths are
+    /// provided.
     /// Logs details and errors in case duplicates are registered.
     pub fn validate(self) -> Result<(), Error> {
-        let inner = self.inner.lock().map_err(|_| anyhow!("Lock poisoned"))?;
+        let mut inner = self.inner.lock().map_err(|_| anyhow!("Lock poisoned"))?;
+
+        if inner.conflicts.is_empty() {
+            return Self::validate_required(&inner);
+        }
+
+        inner.conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+
+        for conflict in &inner.conflicts {
+            log::error!(
+                "Path `{}` is modified by both `{}` and `{}`",
+                conflict.path.display(),
+                conflict.first,
+                conflict.second
+            );
+        }
+
+        let conflicts = inner
+            .conflicts
+            .iter()
+            .map(|c| format!("`{}` ({} and {})", c.path.display(), c.first, c.second))
+            .collect::<Vec<_>>();
+
+        bail!(
+            "Multiple systems with conflicting path modifications: {}",
+            conflicts.join(", ")
+        );
+    }
+
+    /// Validate that every required path is provided by some system.
+    fn validate_required(inner: &FileSystemInner) -> Result<(), Error> {
+        let missing = inner
+            .required
+            .iter()
+            .filter(|r| {
+                inner
+                    .paths
+                    .get(&r.path)
+                    .map_or(true, |e| e.provider.is_none())
+            })
+            .collect::<Vec<_>>();
 
-        if !inner.invalid {
+        if missing.is_empty() {
             return Ok(());
         }
 
-        bail!("Multiple systems with conflicting path modifications");
+        for r in &missing {
+            log::error!(
+                "The {} `{}` of `{}` does not exist, and is not provided by any system",
+                r.what,
+                r.path.display(),
+                r.system
+            );
+        }
+
+        let missing = missing
+            .iter()
+            .map(|r| format!("{} `{}` ({})", r.what, r.path.display(), r.system))
+            .collect::<Vec<_>>();
+
+        bail!(
+            "Paths do not exist, and are not provided by any system: {}",
+            missing.join(", ")
+        );
+    }
+
+    /// Access or allocate a file dependency of the given path, on behalf of the given system.
+    pub fn file_dependency(&self, system: &str, path: &Path) -> Result<Dependency, Error> {
+        dependency!(File, self, system, path)
+    }
+
+    /// Access or allocate a directory dependency of the given path, on behalf of the given
+    /// system.
+    pub fn dir_dependency(&self, system: &str, path: &Path) -> Result<Dependency, Error> {
+        dependency!(Dir, self, system, path)
+    }
+
+    /// Access or allocate a dependency for a path which doesn't exist, but is needed by the given
+    /// system. Like the target of a link, which is described by `what`.
+    ///
+    /// The dependency is of whatever kind the system providing the path uses. Unless another
+    /// system provides the path, this is reported as an error by [FileSystem::validate], since
+    /// the dependency would never be satisfied.
+    pub fn require(
+        &self,
+        system: &str,
+        path: &Path,
+        what: &'static str,
+    ) -> Result<Dependency, Error> {
+        let mut inner = self.inner.lock().map_err(|_| anyhow!("Lock poisoned"))?;
+
+        let dependency = match inner.paths.get(path) {
+            Some(entry) => entry.dependency,
+            None => {
+                let dependency = Dependency::File(self.allocator.allocate());
+
+                inner.paths.insert(
+                    path.to_owned(),
+                    PathEntry {
+                        dependency,
+                        system: system.to_owned(),
+                        provider: None,
+                    },
+                );
+
+                dependency
+            }
+        };
+
+        inner.required.push(Required {
+            path: path.to_owned(),
+            system: system.to_owned(),
+            what,
+        });
+
+        Ok(dependency)
     }
 
-    /// Access or allocate a file dependency of the given path.
-    pub fn file_dependency(&self, path: &Path) -> Result<Dependency, Error> {
-        dependency!(File, self, path)
+    /// Access or allocate the dependencies for a directory which is created by the given system,
+    /// without creating any units for it.
+    ///
+    /// Returns no dependencies if another system already creates the directory.
+    pub fn provide_dir(&self, system: &str, path: &Path) -> Result<Vec<Dependency>, Error> {
+        let mut inner = self.inner.lock().map_err(|_| anyhow!("Lock poisoned"))?;
+        let id = self.allocator.allocate();
+        Ok(inner.create_dir(system, path, id)?.unwrap_or_default())
     }
 
-    /// Access or allocate a directory dependency of the given path.
-    pub fn dir_dependency(&self, path: &Path) -> Result<Dependency, Error> {
-        dependency!(Dir, self, path)
+    /// Access or allocate a file dependency for a file which is provided by the given system.
+    ///
+    /// If another system already provides the same file, this is recorded as a conflict which is
+    /// reported by [FileSystem::validate].
+    pub fn provide_file(&self, system: &str, path: &Path) -> Result<Dependency, Error> {
+        let dependency = self.file_dependency(system, path)?;
+
+        let mut inner = self.inner.lock().map_err(|_| anyhow!("Lock poisoned"))?;
+        let inner = &mut *inner;
+
+        if let Some(entry) = inner.paths.get_mut(path) {
+            match entry.provider.as_deref() {
+                Some(provider) if provider != system => {
+                    inner.conflicts.push(Conflict {
+                        path: path.to_owned(),
+                        first: provider.to_owned(),
+                        second: system.to_owned(),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    entry.provider = Some(system.to_owned());
+                }
+            }
+        }
+
+        Ok(dependency)
     }
 
     /// Try to create a symlink.
+    ///
+    /// With `fallback_copy`, the file being linked to is copied if we are not privileged to create
+    /// symlinks. An existing copy is kept as long as it has the same content as the link target.
     pub fn symlink(
         &self,
+        system: &str,
         path: &Path,
         link: PathBuf,
         meta: Option<&fs::Metadata>,
+        fallback_copy: bool,
     ) -> Result<Option<SystemUnit>, Error> {
         let remove = match meta {
             Some(meta) => {
                 let ty = meta.file_type();
 
-                if !ty.is_symlink() {
-                    bail!("File exists but is not a symlink: {}", path.display());
+                // NB: copies are only made in place of symlinks on Windows.
+                if fallback_copy && cfg!(windows) && ty.is_file() {
+                    let target = match path.parent() {
+                        Some(parent) => parent.join(&link),
+                        None => link.clone(),
+                    };
+
+                    if target.is_file() && fs::read(path)? == fs::read(&target)? {
+                        return Ok(None);
+                    }
+
+                    // NB: a stale copy made in place of the symlink is replaced.
+                    true
+                } else {
+                    if !ty.is_symlink() {
+                        bail!("File exists but is not a symlink: {}", path.display());
+                    }
+
+                    let actual_link = fs::read_link(path)?;
+
+                    if actual_link == link {
+                        // NB: `exists` follows the symlink, so this tests if the target exists.
+                        if path.exists() {
+                            return Ok(None);
+                        }
+
+                        log::warn!(
+                            "Symlink `{}` is broken, target `{}` does not exist",
+                            path.display(),
+                            link.display()
+                        );
+                    }
+
+                    if actual_link != link && !self.opts.force {
+                        bail!(
+                            "Symlink exists `{}`, but contains the wrong link `{}`, expected: {} (use `--force` to override)",
+                            path.display(),
+                            actual_link.display(),
+                            link.display(),
+                        );
+                    }
+
+                    true
                 }
+            }
+            None => false,
+        };
+
+        let mut unit = self.allocator.unit(Symlink {
+            remove,
+            path: path.to_owned(),
+            link,
+            fallback_copy,
+        });
+
+        if let Some(parent) = path.parent() {
+            if !parent.is_dir() {
+                unit.dependencies.push(self.dir_dependency(system, parent)?);
+            }
+        }
+
+        unit.provides.push(self.provide_file(system, path)?);
+        Ok(Some(unit))
+    }
 
-                let actual_link = fs::read_link(path)?;
+    /// Set up a unit which deletes files previously created by a system that it no longer creates,
+    /// and stores the files it currently creates in the `manifest` with the given `id`.
+    ///
+    /// Only files previously recorded in the manifest are deleted, which avoids touching files
+    /// which weren't created by quickcfg.
+    pub fn delete_orphans(
+        &self,
+        system: &str,
+        state: &State,
+        id: &str,
+        manifest: BTreeSet<PathBuf>,
+    ) -> Result<Option<SystemUnit>, Error> {
+        let previous = state.manifest(id);
 
-                if actual_link == link {
+        if previous == Some(&manifest) {
+            return Ok(None);
+        }
+
+        let orphans = previous
+            .into_iter()
+            .flatten()
+            .filter(|path| !manifest.contains(*path) && path.symlink_metadata().is_ok())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut provides = Vec::new();
+
+        // NB: conflicts with any other system trying to create the orphaned file.
+        for orphan in &orphans {
+            provides.push(self.provide_file(system, orphan)?);
+        }
+
+        let mut unit = self.allocator.unit(DeleteOrphans {
+            id: id.to_string(),
+            manifest,
+            orphans,
+        });
+
+        unit.provides.extend(provides);
+        Ok(Some(unit))
+    }
+
+    /// Try to create a hardlink at `path` which refers to the existing file `link`.
+    pub fn hardlink(
+        &self,
+        system: &str,
+        path: &Path,
+        link: PathBuf,
+    ) -> Result<Option<SystemUnit>, Error> {
+        let remove = match Self::try_open_meta(path)? {
+            Some(meta) => {
+                if !meta.is_file() {
+                    bail!("File exists but is not a regular file: {}", path.display());
+                }
+
+                if link.is_file() && same_file::is_same_file(path, &link)? {
                     return Ok(None);
                 }
 
                 if !self.opts.force {
                     bail!(
-                        "Symlink exists `{}`, but contains the wrong link `{}`, expected: {} (use `--force` to override)",
+                        "File exists `{}`, but is not a hardlink to `{}` (use `--force` to override)",
                         path.display(),
-                        actual_link.display(),
                         link.display(),
                     );
                 }
@@ -125,73 +484,95 @@ impl<'a> FileSystem<'a> {
             None => false,
         };
 
-        let mut unit = self.allocator.unit(Symlink {
-            remove,
-            path: path.to_owned(),
-            link,
-        });
+        let mut dependencies = Vec::new();
 
         if let Some(parent) = path.parent() {
             if !parent.is_dir() {
-                unit.dependencies.push(self.dir_dependency(parent)?);
+                dependencies.push(self.dir_dependency(system, parent)?);
             }
         }
 
-        unit.provides.push(self.file_dependency(path)?);
+        // NB: the file being linked to might be provided by another system.
+        if !link.is_file() {
+            dependencies.push(self.require(system, &link, "link target")?);
+        }
+
+        let mut unit = self.allocator.unit(Hardlink {
+            remove,
+            path: path.to_owned(),
+            link,
+        });
+
+        unit.dependencies.extend(dependencies);
+        unit.provides.push(self.provide_file(system, path)?);
         Ok(Some(unit))
     }
 
-    /// Optionally set up if we should copy a file.
+    /// Optionally set up if we should copy a file, which is rendered as a template if `template`
+    /// is specified.
     ///
     /// This is true if:
     ///
     /// * The destination file does not exist.
     /// * The destination file has a modified timestamp less than the source file.
+    #[allow(clippy::too_many_arguments)]
     pub fn copy_file(
         &self,
+        system: &str,
         from: &Path,
         from_meta: fs::Metadata,
         to: &Path,
         to_meta: Option<&fs::Metadata>,
-        template: bool,
+        template: Option<TemplateOptions>,
+        backup: bool,
     ) -> Result<Option<SystemUnit>, Error> {
-        let from_modified = match self.should_copy_file(&from_meta, to, to_meta, template)? {
-            Some(modified) => modified,
-            None => return Ok(None),
-        };
+        let from_modified =
+            match self.should_copy_file(&from_meta, to, to_meta, template.is_some())? {
+                Some(modified) => modified,
+                None => return Ok(None),
+            };
 
-        let mut unit = if template {
+        let mut unit = if let Some(template) = template {
             self.allocator.unit(CopyTemplate {
                 from: from.to_owned(),
                 from_modified,
                 to: to.to_owned(),
                 to_exists: to_meta.is_some(),
+                backup,
+                strict: template.strict,
             })
         } else {
             self.allocator.unit(CopyFile {
                 from: from.to_owned(),
                 from_modified,
+                permissions: from_meta.permissions(),
                 to: to.to_owned(),
+                backup,
             })
         };
 
         if let Some(parent) = to.parent() {
             if !parent.is_dir() {
-                unit.dependencies.push(self.dir_dependency(parent)?);
+                unit.dependencies.push(self.dir_dependency(system, parent)?);
             }
         }
 
-        unit.provides.push(self.file_dependency(to)?);
+        unit.provides.push(self.provide_file(system, to)?);
         Ok(Some(unit))
     }
 
     /// Recursively set up units with dependencies to create the given directories.
-    pub fn create_dir_all(&self, dir: &Path) -> Result<Vec<SystemUnit>, Error> {
+    pub fn create_dir_all(&self, system: &str, dir: &Path) -> Result<Vec<SystemUnit>, Error> {
         let mut inner = self.inner.lock().map_err(|_| anyhow!("Lock poisoned"))?;
 
         let dirs = {
             // Directory is already being created.
-            if inner.paths.contains_key(dir) {
+            if inner
+                .paths
+                .get(dir)
+                .and_then(|e| e.provider.as_ref())
+                .is_some()
+            {
                 return Ok(vec![]);
             }
 
@@ -210,7 +591,12 @@ impl<'a> FileSystem<'a> {
                     break;
                 }
 
-                if inner.paths.contains_key(parent) {
+                if inner
+                    .paths
+                    .get(parent)
+                    .and_then(|e| e.provider.as_ref())
+                    .is_some()
+                {
                     break;
                 }
 
@@ -225,29 +611,18 @@ impl<'a> FileSystem<'a> {
 
         for dir in dirs.into_iter().rev() {
             let mut unit = self.allocator.unit(CreateDir(dir.to_owned()));
-            let current_dependency = Dependency::Dir(unit.id);
-            let dependency = *inner
-                .paths
-                .entry(dir.to_owned())
-                .or_insert(current_dependency);
-
-            // Someone else is creating this dependency.
-            if dependency != current_dependency {
-                // Other system is creating the directory, do nothing!
-                if let Dependency::Dir(_) = dependency {
-                    continue;
-                }
 
-                bail!(
-                    "Other system is modifying path, but not as a directory: {}",
-                    dir.display()
-                );
-            }
+            // Other system is creating the directory, do nothing!
+            let provides = match inner.create_dir(system, dir, unit.id)? {
+                Some(provides) => provides,
+                None => continue,
+            };
 
-            unit.provides.push(dependency);
+            unit.provides.extend(provides);
 
             if let Some(parent) = dir.parent() {
-                unit.dependencies.extend(inner.paths.get(parent).cloned());
+                unit.dependencies
+                    .extend(inner.paths.get(parent).map(|e| e.dependency));
             }
 
             out.push(unit);
@@ -405,3 +780,68 @@ impl<'a> FileSystem<'a> {
         Ok(None)
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::FileSystem;
+    use crate::{hierarchy::Data, opts::Opts, unit::UnitAllocator};
+    use clap::Parser as _;
+
+    #[test]
+    fn test_broken_symlink() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("target");
+        let path = root.path().join("link");
+
+        let opts = Opts::parse_from(["qc"]);
+        let allocator = UnitAllocator::default();
+        let data = Data::new(None, Vec::new());
+        let file_system = FileSystem::new(&opts, root.path(), &allocator, &data);
+
+        std::os::unix::fs::symlink(&target, &path).unwrap();
+
+        let meta = FileSystem::try_open_meta(&path).unwrap();
+        let unit = file_system
+            .symlink("test", &path, target.clone(), meta.as_ref(), false)
+            .unwrap();
+        assert!(unit.is_some(), "expected broken symlink to be repaired");
+
+        std::fs::write(&target, "content").unwrap();
+
+        let meta = FileSystem::try_open_meta(&path).unwrap();
+        let unit = file_system
+            .symlink("test", &path, target, meta.as_ref(), false)
+            .unwrap();
+        assert!(unit.is_none(), "expected working symlink to be left alone");
+    }
+
+    #[test]
+    fn test_hardlink_missing_target() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("target");
+
+        let opts = Opts::parse_from(["qc"]);
+        let allocator = UnitAllocator::default();
+        let data = Data::new(None, Vec::new());
+
+        let hardlink = |file_system: &FileSystem| {
+            file_system
+                .hardlink("link", &root.path().join("link"), target.clone())
+                .unwrap()
+                .unwrap()
+        };
+
+        let file_system = FileSystem::new(&opts, root.path(), &allocator, &data);
+        hardlink(&file_system);
+        let e = file_system.validate().unwrap_err().to_string();
+        assert!(e.contains("link target"), "{}", e);
+        assert!(e.contains("(link)"), "{}", e);
+
+        // NB: the target is provided by another system.
+        let file_system = FileSystem::new(&opts, root.path(), &allocator, &data);
+        let unit = hardlink(&file_system);
+        let provided = file_system.provide_file("target", &target).unwrap();
+        assert!(unit.dependencies.contains(&provided));
+        file_system.validate().unwrap();
+    }
+}
@@ -2,14 +2,33 @@
 
 use anyhow::Error;
 use std::fmt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[cfg(not(feature = "git2"))]
-#[path = "git/external.rs"]
-mod system;
+#[cfg_attr(feature = "git2", allow(dead_code))]
+mod external;
 #[cfg(feature = "git2")]
 #[path = "git/git2.rs"]
-mod system;
+mod libgit2;
+
+#[cfg(not(feature = "git2"))]
+use self::external as system;
+#[cfg(feature = "git2")]
+use self::libgit2 as system;
+
+/// Options used when cloning or opening a repository.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// The remote branch to track, instead of the default branch of the remote.
+    pub branch: Option<String>,
+    /// Create a shallow clone with a history truncated to the given number of commits.
+    pub depth: Option<u32>,
+    /// The SSH private key to authenticate against the remote with.
+    pub identity: Option<PathBuf>,
+    /// Initialize and update submodules recursively after cloning or updating.
+    pub submodules: bool,
+    /// Remotes to fetch updates from, tried in order. If empty, `origin` is used.
+    pub remotes: Vec<String>,
+}
 
 pub trait Git: Send + fmt::Debug {
     /// The path this git instance is associated with.
@@ -18,6 +37,11 @@ pub trait Git: Send + fmt::Debug {
     /// Check if repo needs to be updated.
     fn needs_update(&self) -> Result<bool, Error>;
 
+    /// Check if an update is available, without fetching anything from the remote.
+    ///
+    /// Unlike [Git::needs_update], this doesn't modify the repository.
+    fn check_update_available(&self) -> Result<bool, Error>;
+
     /// Check if the local repository has not been modified without comitting.
     fn is_fresh(&self) -> Result<bool, Error>;
 
@@ -26,6 +50,11 @@ pub trait Git: Send + fmt::Debug {
 
     /// Update repo.
     fn update(&self) -> Result<(), Error>;
+
+    /// Initialize and update all submodules recursively.
+    fn update_submodules(&self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub trait GitSystem: Send + Sync {
@@ -34,10 +63,81 @@ pub trait GitSystem: Send + Sync {
     }
 
     /// Clone the given path.
-    fn clone(&self, url: &str, path: &Path) -> Result<Box<dyn Git>, Error>;
+    fn clone(&self, url: &str, path: &Path, options: &Options) -> Result<Box<dyn Git>, Error>;
 
     /// Open the given repository.
-    fn open(&self, path: &Path) -> Result<Box<dyn Git>, Error>;
+    fn open(&self, path: &Path, options: &Options) -> Result<Box<dyn Git>, Error>;
+}
+
+/// Try the given operation against each remote in order, until one succeeds.
+///
+/// If no remotes are specified, the operation is performed against `origin`. Failures are logged
+/// as long as there are more remotes to try, otherwise the last error is returned.
+pub fn with_remotes<T, F>(remotes: &[String], mut f: F) -> Result<T, Error>
+where
+    F: FnMut(&str) -> Result<T, Error>,
+{
+    let (last, rest) = match remotes.split_last() {
+        Some(split) => split,
+        None => return f("origin"),
+    };
+
+    for remote in rest {
+        match f(remote) {
+            Ok(value) => return Ok(value),
+            Err(e) => log::warn!("Remote `{}` failed, trying the next one: {}", remote, e),
+        }
+    }
+
+    f(last)
+}
+
+/// The remote reference to track for the given options, if a branch is configured.
+fn branch_ref(options: &Options) -> Option<String> {
+    let branch = options.branch.as_deref()?;
+
+    if branch.starts_with("refs/") {
+        return Some(branch.to_owned());
+    }
+
+    Some(format!("refs/heads/{}", branch))
+}
+
+/// Update the given repository, which is assumed to need an update.
+///
+/// Forced updates discard local modifications, so they are skipped with a warning if the
+/// repository has any, unless `force_dirty` is set. Returns `false` if the update was skipped.
+pub fn update(git: &dyn Git, force: bool, force_dirty: bool) -> Result<bool, Error> {
+    let path = git.path().display();
+
+    if force {
+        if !force_dirty && !git.is_fresh()? {
+            log::warn!(
+                "Not updating `{}` since it has local modifications (use `--force-dirty` to discard them)",
+                path
+            );
+            return Ok(false);
+        }
+
+        log::info!("Force updating `{}`", path);
+        git.force_update()?;
+        return Ok(true);
+    }
+
+    log::info!("Updating `{}`", path);
+
+    if let Err(e) = git.update() {
+        if !git.is_fresh()? {
+            return Err(e.context(format!(
+                "`{}` has local modifications, commit or stash them, or use `--force-dirty` to discard them",
+                path
+            )));
+        }
+
+        return Err(e);
+    }
+
+    Ok(true)
 }
 
 /// Open the given path.
@@ -1,5 +1,5 @@
-use crate::{command, os};
-use anyhow::Error;
+use crate::{command, git::Options, os};
+use anyhow::{anyhow, Error};
 use std::io;
 use std::path::{Path, PathBuf};
 
@@ -13,6 +13,24 @@ impl GitSystem {
             command: command::Command::new(os::command("git")),
         }
     }
+
+    /// Construct the base command to use for a repository with the given options.
+    fn command(&self, options: &Options) -> command::Command {
+        let mut command = self.command.clone();
+
+        if let Some(identity) = options.identity.as_deref() {
+            command.env("GIT_SSH_COMMAND", ssh_command(identity));
+        }
+
+        command
+    }
+}
+
+/// Construct an ssh command which authenticates with the given identity.
+fn ssh_command(identity: &Path) -> String {
+    // NB: the command is interpreted by a shell, so quote the path.
+    let identity = identity.display().to_string().replace('\'', "'\\''");
+    format!("ssh -i '{}' -o IdentitiesOnly=yes", identity)
 }
 
 impl super::GitSystem for GitSystem {
@@ -29,23 +47,39 @@ impl super::GitSystem for GitSystem {
         }
     }
 
-    fn clone(&self, url: &str, path: &Path) -> Result<Box<dyn super::Git>, Error> {
-        let mut command = self.command.clone();
+    fn clone(
+        &self,
+        url: &str,
+        path: &Path,
+        options: &Options,
+    ) -> Result<Box<dyn super::Git>, Error> {
+        let mut command = self.command(options);
         command.arg("clone");
+
+        if let Some(branch) = options.branch.as_deref() {
+            command.args(&["-b", branch]);
+        }
+
+        if let Some(depth) = options.depth {
+            command.args(&["--depth", &depth.to_string()]);
+        }
+
         command.arg(url);
         command.arg(path);
         command.run_checked()?;
 
         Ok(Box::new(External {
             path: path.to_owned(),
-            command: self.command.clone(),
+            command: self.command(options),
+            options: options.clone(),
         }))
     }
 
-    fn open(&self, path: &Path) -> Result<Box<dyn super::Git>, Error> {
+    fn open(&self, path: &Path, options: &Options) -> Result<Box<dyn super::Git>, Error> {
         Ok(Box::new(External {
             path: path.to_owned(),
-            command: self.command.clone(),
+            command: self.command(options),
+            options: options.clone(),
         }))
     }
 }
@@ -55,6 +89,7 @@ impl super::GitSystem for GitSystem {
 struct External {
     pub path: PathBuf,
     command: command::Command,
+    options: Options,
 }
 
 impl External {
@@ -65,6 +100,14 @@ impl External {
         Ok(command.run_stdout()?.trim().to_string())
     }
 
+    /// Get the current head branch.
+    fn head_branch(&self) -> Result<String, Error> {
+        let mut command = self.command.clone();
+        command.working_directory(&self.path);
+        command.args(&["symbolic-ref", "HEAD"]);
+        Ok(command.run_stdout()?.trim().to_string())
+    }
+
     /// Find the merge base between two commits.
     fn merge_base(&self, a: &str, b: &str) -> Result<String, Error> {
         let mut command = self.command.clone();
@@ -82,21 +125,72 @@ impl super::Git for External {
     fn needs_update(&self) -> Result<bool, Error> {
         let head = self.rev_parse("HEAD")?;
 
-        let mut command = self.command.clone();
-        command.working_directory(self.path());
-        command.args(&["fetch", "origin", head.as_str()]);
-        command.run_checked()?;
+        // NB: fetch the configured branch if present, otherwise the branch HEAD refers to.
+        let fetch_ref = match self.options.branch.as_deref() {
+            Some(branch) => branch.to_owned(),
+            None => self.head_branch()?,
+        };
+
+        super::with_remotes(&self.options.remotes, |remote| {
+            let mut command = self.command.clone();
+            command.working_directory(self.path());
+            command.args(&["fetch", remote, fetch_ref.as_str()]);
+            command.run_checked()
+        })?;
 
         let remote_head = self.rev_parse("FETCH_HEAD")?;
 
         if remote_head != head {
-            // check if remote is a base
-            return Ok(self.merge_base(&remote_head, &head)? != remote_head);
+            // check if remote is a base.
+            //
+            // NB: in a shallow clone the history required to find a merge base might not be
+            // present, in which case we treat any difference as an update.
+            return match self.merge_base(&remote_head, &head) {
+                Ok(base) => Ok(base != remote_head),
+                Err(e) if self.options.depth.is_some() => {
+                    log::trace!("merge base failed in shallow clone: {}", e);
+                    Ok(true)
+                }
+                Err(e) => Err(e),
+            };
         }
 
         Ok(false)
     }
 
+    fn check_update_available(&self) -> Result<bool, Error> {
+        let head = self.rev_parse("HEAD")?;
+
+        let remote_ref = match super::branch_ref(&self.options) {
+            Some(remote_ref) => remote_ref,
+            None => self.head_branch()?,
+        };
+
+        let output = super::with_remotes(&self.options.remotes, |remote| {
+            let mut command = self.command.clone();
+            command.working_directory(&self.path);
+            command.args(&["ls-remote", remote, remote_ref.as_str()]);
+            command.run_stdout()
+        })?;
+
+        let remote_head = output
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .find(|(_, name)| *name == remote_ref)
+            .map(|(id, _)| id.to_owned())
+            .ok_or_else(|| anyhow!("remote reference `{}` not found", remote_ref))?;
+
+        if remote_head == head {
+            return Ok(false);
+        }
+
+        // NB: if the remote commit isn't available locally, it's definitely an update.
+        match self.merge_base(&remote_head, &head) {
+            Ok(base) => Ok(base != remote_head),
+            Err(_) => Ok(true),
+        }
+    }
+
     fn is_fresh(&self) -> Result<bool, Error> {
         let mut command = self.command.clone();
         command.working_directory(&self.path);
@@ -117,4 +211,233 @@ impl super::Git for External {
         command.args(&["merge", "--ff-only", "FETCH_HEAD"]);
         command.run_checked()
     }
+
+    fn update_submodules(&self) -> Result<(), Error> {
+        let mut command = self.command.clone();
+        command.working_directory(&self.path);
+        command.args(&["submodule", "update", "--init", "--recursive"]);
+        command.run_checked()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GitSystem;
+    use crate::command::Command;
+    use crate::git::{self, GitSystem as _, Options};
+    use std::ffi::OsString;
+    use std::path::{Path, PathBuf};
+
+    /// Run git in the given directory.
+    fn run_git(dir: &Path, args: &[&str]) {
+        let mut command = Command::new("git");
+        command.working_directory(dir);
+        command.args(&["-c", "user.name=test", "-c", "user.email=test@example.com"]);
+        command.args(args);
+        command.run_checked().expect("git command to succeed");
+    }
+
+    /// Commit a single file to the given work tree and push it to the given branch.
+    fn commit(work: &Path, branch: &str, name: &str) {
+        std::fs::write(work.join(name), name).unwrap();
+        run_git(work, &["add", name]);
+        run_git(work, &["commit", "-m", name]);
+        run_git(work, &["push", "origin", &format!("HEAD:{}", branch)]);
+    }
+
+    #[test]
+    fn test_branch() {
+        let root = tempfile::tempdir().unwrap();
+        let remote = root.path().join("remote.git");
+        let work = root.path().join("work");
+        let checkout = root.path().join("checkout");
+
+        run_git(root.path(), &["init", "--bare", "-b", "main", "remote.git"]);
+        run_git(root.path(), &["clone", remote.to_str().unwrap(), "work"]);
+        commit(&work, "main", "main.txt");
+        run_git(&work, &["checkout", "-b", "dev"]);
+        commit(&work, "dev", "dev.txt");
+
+        let options = Options {
+            branch: Some(String::from("dev")),
+            ..Options::default()
+        };
+
+        let system = GitSystem::new();
+        let repo = system
+            .clone(remote.to_str().unwrap(), &checkout, &options)
+            .unwrap();
+        assert!(checkout.join("dev.txt").is_file());
+        assert!(!repo.needs_update().unwrap());
+
+        // A change on another branch is not an update.
+        run_git(&work, &["checkout", "main"]);
+        commit(&work, "main", "other.txt");
+        assert!(!repo.needs_update().unwrap());
+
+        run_git(&work, &["checkout", "dev"]);
+        commit(&work, "dev", "update.txt");
+
+        let repo = system.open(&checkout, &options).unwrap();
+        assert!(repo.needs_update().unwrap());
+        repo.update().unwrap();
+        assert!(checkout.join("update.txt").is_file());
+        assert!(!checkout.join("other.txt").exists());
+    }
+
+    #[test]
+    fn test_depth() {
+        let root = tempfile::tempdir().unwrap();
+        let remote = root.path().join("remote.git");
+        let work = root.path().join("work");
+        let checkout = root.path().join("checkout");
+
+        run_git(root.path(), &["init", "--bare", "-b", "main", "remote.git"]);
+        run_git(root.path(), &["clone", remote.to_str().unwrap(), "work"]);
+        commit(&work, "main", "first.txt");
+        commit(&work, "main", "second.txt");
+
+        let options = Options {
+            depth: Some(1),
+            ..Options::default()
+        };
+
+        // NB: depth is ignored for plain local paths, so clone through `file://`.
+        let url = format!("file://{}", remote.display());
+
+        let repo = GitSystem::new().clone(&url, &checkout, &options).unwrap();
+
+        assert!(checkout.join(".git").join("shallow").is_file());
+        assert!(!repo.needs_update().unwrap());
+
+        commit(&work, "main", "third.txt");
+        assert!(repo.needs_update().unwrap());
+        repo.update().unwrap();
+        assert!(checkout.join("third.txt").is_file());
+    }
+
+    #[test]
+    fn test_submodules() {
+        let root = tempfile::tempdir().unwrap();
+        let sub = root.path().join("sub.git");
+        let remote = root.path().join("remote.git");
+        let work = root.path().join("work");
+        let checkout = root.path().join("checkout");
+
+        run_git(root.path(), &["init", "--bare", "-b", "main", "sub.git"]);
+        run_git(root.path(), &["clone", sub.to_str().unwrap(), "sub-work"]);
+        commit(&root.path().join("sub-work"), "main", "sub.txt");
+
+        run_git(root.path(), &["init", "--bare", "-b", "main", "remote.git"]);
+        run_git(root.path(), &["clone", remote.to_str().unwrap(), "work"]);
+
+        // NB: newer versions of git refuse to clone submodules from local paths by default.
+        let allow_file = ["-c", "protocol.file.allow=always"];
+
+        let mut args = allow_file.to_vec();
+        args.extend(["submodule", "add", sub.to_str().unwrap(), "sub"]);
+        run_git(&work, &args);
+        commit(&work, "main", "main.txt");
+
+        let mut system = GitSystem::new();
+        system.command.args(allow_file);
+
+        let options = Options {
+            submodules: true,
+            ..Options::default()
+        };
+
+        let repo = system
+            .clone(remote.to_str().unwrap(), &checkout, &options)
+            .unwrap();
+        assert!(!checkout.join("sub").join("sub.txt").exists());
+
+        repo.update_submodules().unwrap();
+        assert!(checkout.join("sub").join("sub.txt").is_file());
+    }
+
+    #[test]
+    fn test_dirty() {
+        let root = tempfile::tempdir().unwrap();
+        let remote = root.path().join("remote.git");
+        let work = root.path().join("work");
+        let checkout = root.path().join("checkout");
+
+        run_git(root.path(), &["init", "--bare", "-b", "main", "remote.git"]);
+        run_git(root.path(), &["clone", remote.to_str().unwrap(), "work"]);
+        commit(&work, "main", "file.txt");
+
+        let system = GitSystem::new();
+        let repo = system
+            .clone(remote.to_str().unwrap(), &checkout, &Options::default())
+            .unwrap();
+
+        std::fs::write(work.join("file.txt"), "remote").unwrap();
+        run_git(&work, &["commit", "-am", "remote"]);
+        run_git(&work, &["push", "origin", "HEAD:main"]);
+
+        std::fs::write(checkout.join("file.txt"), "local").unwrap();
+        assert!(!repo.is_fresh().unwrap());
+        assert!(repo.needs_update().unwrap());
+
+        let e = git::update(&*repo, false, false).unwrap_err();
+        assert!(e.to_string().contains("has local modifications"), "{}", e);
+
+        assert!(!git::update(&*repo, true, false).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(checkout.join("file.txt")).unwrap(),
+            "local"
+        );
+
+        assert!(git::update(&*repo, true, true).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(checkout.join("file.txt")).unwrap(),
+            "remote"
+        );
+        assert!(repo.is_fresh().unwrap());
+    }
+
+    #[test]
+    fn test_check_update_available() {
+        let root = tempfile::tempdir().unwrap();
+        let remote = root.path().join("remote.git");
+        let work = root.path().join("work");
+        let checkout = root.path().join("checkout");
+        let fetch_head = checkout.join(".git").join("FETCH_HEAD");
+
+        run_git(root.path(), &["init", "--bare", "-b", "main", "remote.git"]);
+        run_git(root.path(), &["clone", remote.to_str().unwrap(), "work"]);
+        commit(&work, "main", "first.txt");
+
+        let repo = GitSystem::new()
+            .clone(remote.to_str().unwrap(), &checkout, &Options::default())
+            .unwrap();
+
+        assert!(!repo.check_update_available().unwrap());
+
+        commit(&work, "main", "second.txt");
+        assert!(repo.check_update_available().unwrap());
+        assert!(!fetch_head.exists());
+    }
+
+    #[test]
+    fn test_identity() {
+        let options = Options {
+            identity: Some(PathBuf::from("/home/user/.ssh/it's_key")),
+            ..Options::default()
+        };
+
+        let command = GitSystem::new().command(&options);
+
+        assert_eq!(
+            command.envs,
+            vec![(
+                OsString::from("GIT_SSH_COMMAND"),
+                OsString::from("ssh -i '/home/user/.ssh/it'\\''s_key' -o IdentitiesOnly=yes")
+            )]
+        );
+
+        let command = GitSystem::new().command(&Options::default());
+        assert!(command.envs.is_empty());
+    }
 }
@@ -1,7 +1,9 @@
 //! Git integration using libgit2
 
+use crate::git::Options;
 use anyhow::{anyhow, bail, Result};
 use git2::{ObjectType, Oid, Repository, ResetType};
+use std::convert::TryFrom;
 use std::fmt;
 use std::path::{Path, PathBuf};
 
@@ -14,25 +16,56 @@ impl GitSystem {
 }
 
 impl super::GitSystem for GitSystem {
-    fn clone(&self, url: &str, path: &Path) -> Result<Box<dyn super::Git>> {
+    fn clone(&self, url: &str, path: &Path, options: &Options) -> Result<Box<dyn super::Git>> {
+        let mut builder = git2::build::RepoBuilder::new();
+
+        if let Some(branch) = options.branch.as_deref() {
+            builder.branch(branch);
+        }
+
+        let mut fetch_options = git2::FetchOptions::new();
+        fetch_options.remote_callbacks(remote_callbacks(options));
+
+        if let Some(depth) = options.depth {
+            fetch_options.depth(i32::try_from(depth)?);
+        }
+
+        builder.fetch_options(fetch_options);
+
         Ok(Box::new(Git2 {
             path: path.to_owned(),
-            repo: Repository::clone(url, path)?,
+            repo: builder.clone(url, path)?,
+            options: options.clone(),
         }))
     }
 
-    fn open(&self, path: &Path) -> Result<Box<dyn super::Git>> {
+    fn open(&self, path: &Path, options: &Options) -> Result<Box<dyn super::Git>> {
         Ok(Box::new(Git2 {
             path: path.to_owned(),
             repo: Repository::open(path)?,
+            options: options.clone(),
         }))
     }
 }
 
+/// Construct callbacks used to authenticate against remotes.
+fn remote_callbacks(options: &Options) -> git2::RemoteCallbacks<'_> {
+    let mut callbacks = git2::RemoteCallbacks::new();
+
+    if let Some(identity) = options.identity.as_deref() {
+        callbacks.credentials(move |_, username, _| {
+            git2::Cred::ssh_key(username.unwrap_or("git"), None, identity, None)
+        });
+    }
+
+    callbacks
+}
+
 /// Helper to interact with a git repository.
 pub struct Git2 {
     pub path: PathBuf,
     pub repo: Repository,
+    options: Options,
 }
 
 impl fmt::Debug for Git2 {
@@ -58,6 +91,15 @@ impl Git2 {
         Ok(self.repo.merge_base(a, b)?)
     }
 
+    /// Find the remote with the given name, or construct an anonymous remote for a url.
+    fn remote(&self, remote: &str) -> Result<git2::Remote<'_>> {
+        if git2::Remote::is_valid_name(remote) {
+            return Ok(self.repo.find_remote(remote)?);
+        }
+
+        Ok(self.repo.remote_anonymous(remote)?)
+    }
+
     /// Get the current head branch.
     fn head_branch(&self) -> Result<String> {
         Ok(self
@@ -75,22 +117,78 @@ impl super::Git for Git2 {
     }
 
     fn needs_update(&self) -> Result<bool> {
-        let head_branch = self.head_branch()?;
+        let fetch_ref = match self.options.branch.as_deref() {
+            Some(branch) => branch.to_owned(),
+            None => self.head_branch()?,
+        };
 
-        let mut remote = self.repo.find_remote("origin")?;
-        remote.fetch(&[head_branch.as_str()], None, None)?;
+        super::with_remotes(&self.options.remotes, |remote| {
+            let mut fetch_options = git2::FetchOptions::new();
+            fetch_options.remote_callbacks(remote_callbacks(&self.options));
+
+            let mut remote = self.remote(remote)?;
+            remote.fetch(&[fetch_ref.as_str()], Some(&mut fetch_options), None)?;
+            Ok(())
+        })?;
 
         let head = self.rev_parse("HEAD")?;
         let fetch_head = self.rev_parse("FETCH_HEAD")?;
 
         if fetch_head != head {
-            // check if remote is a base
-            return Ok(self.merge_base(fetch_head, head)? != fetch_head);
+            // check if remote is a base.
+            //
+            // NB: in a shallow clone the history required to find a merge base might not be
+            // present, in which case we treat any difference as an update.
+            return match self.merge_base(fetch_head, head) {
+                Ok(base) => Ok(base != fetch_head),
+                Err(e) if self.repo.is_shallow() => {
+                    log::trace!("merge base failed in shallow clone: {}", e);
+                    Ok(true)
+                }
+                Err(e) => Err(e),
+            };
         }
 
         Ok(false)
     }
 
+    fn check_update_available(&self) -> Result<bool> {
+        let remote_ref = match super::branch_ref(&self.options) {
+            Some(remote_ref) => remote_ref,
+            None => self.head_branch()?,
+        };
+
+        let remote_head = super::with_remotes(&self.options.remotes, |remote| {
+            let mut remote = self.remote(remote)?;
+            let connection = remote.connect_auth(
+                git2::Direction::Fetch,
+                Some(remote_callbacks(&self.options)),
+                None,
+            )?;
+
+            let remote_head = connection
+                .list()?
+                .iter()
+                .find(|head| head.name() == remote_ref)
+                .map(|head| head.oid())
+                .ok_or_else(|| anyhow!("remote reference `{}` not found", remote_ref))?;
+
+            Ok(remote_head)
+        })?;
+
+        let head = self.rev_parse("HEAD")?;
+
+        if remote_head == head {
+            return Ok(false);
+        }
+
+        // NB: if the remote commit isn't available locally, it's definitely an update.
+        match self.merge_base(remote_head, head) {
+            Ok(base) => Ok(base != remote_head),
+            Err(_) => Ok(true),
+        }
+    }
+
     fn is_fresh(&self) -> Result<bool> {
         let diff = self.repo.diff_index_to_workdir(None, None)?;
         Ok(diff.deltas().next().is_none())
@@ -113,4 +211,23 @@ impl super::Git for Git2 {
         self.repo.merge(&[&fetch_head], None, None)?;
         Ok(())
     }
+
+    fn update_submodules(&self) -> Result<()> {
+        return update_submodules(&self.repo, &self.options);
+
+        fn update_submodules(repo: &Repository, options: &Options) -> Result<()> {
+            for mut submodule in repo.submodules()? {
+                let mut fetch_options = git2::FetchOptions::new();
+                fetch_options.remote_callbacks(remote_callbacks(options));
+
+                let mut update_options = git2::SubmoduleUpdateOptions::new();
+                update_options.fetch(fetch_options);
+
+                submodule.update(true, Some(&mut update_options))?;
+                update_submodules(&submodule.open()?, options)?;
+            }
+
+            Ok(())
+        }
+    }
 }
@@ -3,10 +3,12 @@
 use anyhow::{anyhow, bail, Result};
 use serde::Deserialize;
 use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeSet, HashMap};
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard};
 use std::time::SystemTime;
 
 use crate::{environment as e, facts::Facts, Template};
@@ -17,28 +19,96 @@ const HEADER: &str = "quickcfg:";
 pub struct Data {
     /// The last modification timestamp for a file in the hierarchy.
     pub last_modified: Option<SystemTime>,
+    /// Files which were loaded into the hierarchy, in order of precedence.
+    pub loaded: Vec<PathBuf>,
+    /// Entries in the hierarchy which were skipped, since their file doesn't exist or their
+    /// template couldn't be rendered with the known facts.
+    pub skipped: Vec<String>,
     /// The hierarchy with data.
     hierarchy: Vec<Mapping>,
+    /// Values resolved for file specs, keyed by `key` and `kind`.
+    ///
+    /// NB: templates commonly use the same keys, so they are only resolved once.
+    cache: RwLock<Cache>,
 }
 
+/// Cache of resolved values.
+type Cache = HashMap<(String, Option<String>), Value>;
+
 impl Data {
     /// Construct a new set of hierarchical data.
     pub fn new(last_modified: Option<SystemTime>, data: impl IntoIterator<Item = Mapping>) -> Self {
         Data {
             last_modified,
+            loaded: Vec::new(),
+            skipped: Vec::new(),
             hierarchy: data.into_iter().collect(),
+            cache: RwLock::new(HashMap::new()),
         }
     }
 
+    /// Load hierarchical data from the given files, in order of precedence.
+    ///
+    /// Files which don't exist are skipped. The format of each file is determined by its
+    /// extension, one of `yml`, `yaml`, `toml`, or `json`.
+    pub fn from_files(paths: &[PathBuf]) -> Result<Self> {
+        let mut stages = Vec::new();
+        let mut last_modified = None;
+        let mut loaded = Vec::new();
+        let mut skipped = Vec::new();
+
+        for path in paths {
+            let m = match path.metadata() {
+                Ok(m) => m,
+                Err(e) => match e.kind() {
+                    io::ErrorKind::NotFound => {
+                        log::debug!("hierarchy: skipping missing file: {}", path.display());
+                        skipped.push(path.display().to_string());
+                        continue;
+                    }
+                    _ => return Err(anyhow::Error::from(e)),
+                },
+            };
+
+            let modified = m.modified()?;
+
+            last_modified = Some(match last_modified {
+                Some(previous) if previous > modified => previous,
+                _ => modified,
+            });
+
+            let map = load_mapping(path)
+                .map_err(|e| anyhow!("failed to load: {}: {}", path.display(), e))?;
+
+            log::debug!("hierarchy: loaded {}", path.display());
+            stages.push(map);
+            loaded.push(path.clone());
+        }
+
+        let mut data = Data::new(last_modified, stages);
+        data.loaded = loaded;
+        data.skipped = skipped;
+        Ok(data)
+    }
+
+    /// List all top-level keys across all layers in the hierarchy.
+    ///
+    /// Keys which aren't strings are ignored.
+    pub fn keys(&self) -> BTreeSet<&str> {
+        self.hierarchy
+            .iter()
+            .flat_map(|m| m.keys())
+            .filter_map(|key| key.as_str())
+            .collect()
+    }
+
     /// Load the given key.
     pub fn load<'de, T>(&self, key: &str) -> Result<Option<T>>
     where
         T: Deserialize<'de>,
     {
-        let key = serde_yaml::Value::String(key.to_string());
-
         for m in &self.hierarchy {
-            if let Some(value) = m.get(&key) {
+            if let Some(value) = lookup(m, key) {
                 return Ok(Some(T::deserialize(value.clone())?));
             }
         }
@@ -59,12 +129,10 @@ impl Data {
     where
         T: Deserialize<'de>,
     {
-        let key = serde_yaml::Value::String(key.to_string());
-
         let mut out = Vec::new();
 
         for m in &self.hierarchy {
-            if let Some(value) = m.get(&key) {
+            if let Some(value) = lookup(m, key) {
                 out.extend(<Vec<T> as Deserialize>::deserialize(value.clone())?);
             }
         }
@@ -72,6 +140,89 @@ impl Data {
         Ok(out)
     }
 
+    /// Load the given key, recursively merging mappings from all layers in the hierarchy.
+    ///
+    /// Keys are unioned across layers. When layers conflict on a value which isn't a mapping, the
+    /// layer that comes first in the hierarchy takes precedence, just like with [`Data::load`].
+    pub fn load_merged<'de, T>(&self, key: &str) -> Result<Option<T>>
+    where
+        T: Deserialize<'de>,
+    {
+        let mut merged = None;
+
+        // NB: walk from the lowest priority layer, so that higher priority layers override it.
+        for m in self.hierarchy.iter().rev() {
+            if let Some(value) = lookup(m, key) {
+                match merged.as_mut() {
+                    Some(merged) => merge(merged, value.clone()),
+                    None => merged = Some(value.clone()),
+                }
+            }
+        }
+
+        match merged {
+            Some(value) => Ok(Some(T::deserialize(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Load the given key as a mapping, merging the mappings found in all layers.
+    ///
+    /// Unlike [`Data::load_merged`], only the top-level keys of the mappings are merged. When
+    /// layers define the same key, the layer that comes first in the hierarchy takes precedence.
+    pub fn load_map<'de, V>(&self, key: &str) -> Result<HashMap<String, V>>
+    where
+        V: Deserialize<'de>,
+    {
+        let mut merged = HashMap::new();
+
+        // NB: walk from the lowest priority layer, so that higher priority layers override it.
+        for m in self.hierarchy.iter().rev() {
+            let value = match lookup(m, key) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let mapping = match value {
+                Value::Mapping(mapping) => mapping,
+                _ => bail!("expected a mapping in `{}`", key),
+            };
+
+            for (k, v) in mapping {
+                let k = match k {
+                    Value::String(k) => k.clone(),
+                    _ => bail!("expected only string keys in `{}`", key),
+                };
+
+                merged.insert(k, v.clone());
+            }
+        }
+
+        let mut out = HashMap::with_capacity(merged.len());
+
+        for (k, v) in merged {
+            out.insert(k, V::deserialize(v)?);
+        }
+
+        Ok(out)
+    }
+
+    /// Load the given key as an array from all layers, and join its values with `separator`.
+    fn load_joined(&self, key: &str, separator: &str) -> Result<String> {
+        let mut out = Vec::new();
+
+        for value in self.load_array::<Value>(key)? {
+            out.push(match value {
+                Value::String(s) => s,
+                Value::Number(n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                _ => bail!("expected only strings, numbers, or booleans in `{}`", key),
+            });
+        }
+
+        Ok(out.join(separator))
+    }
+
     /// Load data based on a file spec.
     /// This is typically in the first couple of lines in a file.
     pub fn load_from_spec(&self, content: &str) -> Result<Mapping> {
@@ -101,7 +252,8 @@ impl Data {
                 };
 
                 let value = match it.next() {
-                    Some("array") => Value::Sequence(self.load_array::<Value>(key)?),
+                    // NB: environment variables are not part of the hierarchy, so they're not
+                    // cached.
                     Some("env") => {
                         let value = match env::var(key) {
                             Ok(value) => value,
@@ -110,15 +262,10 @@ impl Data {
 
                         Value::String(value)
                     }
-                    None => self
-                        .load::<Value>(key)?
-                        .ok_or_else(|| anyhow!("missing key `{}` in hierarchy", key))?,
-                    Some(other) => {
-                        bail!("bad part in specification `{}`: bad type `{}`", part, other);
-                    }
+                    kind => self.cached(key, kind, part)?,
                 };
 
-                m.insert(Value::String(key.to_string()), value);
+                insert(&mut m, key, value)?;
             }
 
             break;
@@ -126,6 +273,118 @@ impl Data {
 
         Ok(m)
     }
+
+    /// Resolve the value of a part in a file spec, using the cache if it has been resolved before.
+    fn cached(&self, key: &str, kind: Option<&str>, part: &str) -> Result<Value> {
+        let cache_key = (key.to_string(), kind.map(String::from));
+
+        if let Some(value) = self.read_cache().get(&cache_key) {
+            return Ok(value.clone());
+        }
+
+        let value = self.resolve(key, kind, part)?;
+
+        self.cache
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(cache_key, value.clone());
+
+        Ok(value)
+    }
+
+    /// Access the cache for reading.
+    fn read_cache(&self) -> RwLockReadGuard<'_, Cache> {
+        self.cache.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Resolve the value of a part in a file spec from the hierarchy.
+    fn resolve(&self, key: &str, kind: Option<&str>, part: &str) -> Result<Value> {
+        Ok(match kind {
+            Some("array") => Value::Sequence(self.load_array::<Value>(key)?),
+            Some("lines") => Value::String(self.load_joined(key, "\n")?),
+            Some("csv") => Value::String(self.load_joined(key, ",")?),
+            Some("merge") => self
+                .load_merged::<Value>(key)?
+                .ok_or_else(|| anyhow!("missing key `{}` in hierarchy", key))?,
+            None => self
+                .load::<Value>(key)?
+                .ok_or_else(|| anyhow!("missing key `{}` in hierarchy", key))?,
+            Some(other) => {
+                bail!("bad part in specification `{}`: bad type `{}`", part, other);
+            }
+        })
+    }
+}
+
+/// Look up the given key in a layer of the hierarchy.
+///
+/// If there is no key with the exact name, a dotted key like `a.b` is looked up as `b` in the
+/// mapping under `a`.
+fn lookup<'a>(m: &'a Mapping, key: &str) -> Option<&'a Value> {
+    if let Some(value) = m.get(key) {
+        return Some(value);
+    }
+
+    if !key.contains('.') {
+        return None;
+    }
+
+    let mut parts = key.split('.');
+    let mut current = m.get(parts.next()?)?;
+
+    for part in parts {
+        current = current.as_mapping()?.get(part)?;
+    }
+
+    Some(current)
+}
+
+/// Insert a value loaded from a file spec, where a dotted key like `a.b` is inserted as `b` in
+/// the mapping under `a`.
+///
+/// Keys sharing the same parent, like `a.b` and `a.c`, are inserted into the same mapping.
+fn insert(m: &mut Mapping, key: &str, value: Value) -> Result<()> {
+    let mut parts = key.split('.').collect::<Vec<_>>();
+
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => bail!("bad key `{}`", key),
+    };
+
+    let mut current = m;
+
+    for part in parts {
+        let entry = current
+            .entry(Value::String(part.to_string()))
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+        current = match entry {
+            Value::Mapping(mapping) => mapping,
+            _ => bail!("bad key `{}`: `{}` is not a mapping", key, part),
+        };
+    }
+
+    current.insert(Value::String(last.to_string()), value);
+    Ok(())
+}
+
+/// Deeply merge `value` into `target`, where values in `value` take precedence.
+fn merge(target: &mut Value, value: Value) {
+    match (target, value) {
+        (Value::Mapping(target), Value::Mapping(value)) => {
+            for (key, value) in value {
+                match target.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, value) => {
+            *target = value;
+        }
+    }
 }
 
 /// Load a hierarchy.
@@ -133,63 +392,96 @@ pub fn load<'a>(
     it: impl IntoIterator<Item = &'a Template>,
     root: &Path,
     facts: &Facts,
-    environment: impl Copy + e::Environment,
+    environment: impl e::Environment,
 ) -> Result<Data> {
-    let mut stages = Vec::new();
-    let mut last_modified = None;
+    let mut paths = Vec::new();
+    let mut unresolved = Vec::new();
 
     for h in it {
         let path = match h.as_relative_path(facts, environment)? {
-            None => continue,
+            None => {
+                log::debug!("hierarchy: skipping `{}`, since it can't be rendered", h);
+                unresolved.push(h.to_string());
+                continue;
+            }
             Some(path) => path,
         };
 
-        let path = path.to_path(root);
+        paths.push(path.to_path(root));
+    }
 
-        let m = match path.metadata() {
-            Ok(m) => m,
-            Err(e) => match e.kind() {
-                io::ErrorKind::NotFound => {
-                    log::trace!("skipping missing file: {}", path.display());
-                    continue;
-                }
-                _ => return Err(anyhow::Error::from(e)),
-            },
-        };
+    let mut data = Data::from_files(&paths)?;
+    data.skipped.extend(unresolved);
+    Ok(data)
+}
 
-        let modified = m.modified()?;
+/// Load a mapping from the given file.
+///
+/// The format of the file is determined by its extension.
+fn load_mapping(path: &Path) -> Result<serde_yaml::Mapping> {
+    let value = match path.extension().and_then(|e| e.to_str()) {
+        Some("yml") | Some("yaml") => {
+            let file = match File::open(path) {
+                Ok(file) => file,
+                Err(e) => bail!("failed to open file: {e}"),
+            };
 
-        last_modified = Some(match last_modified {
-            Some(previous) if previous > modified => previous,
-            _ => modified,
-        });
+            serde_yaml::from_reader(file)?
+        }
+        Some("toml") => {
+            let content = match fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(e) => bail!("failed to open file: {e}"),
+            };
 
-        let map = load_mapping(&path)
-            .map_err(|e| anyhow!("failed to load: {}: {}", path.display(), e))?;
+            from_toml(toml::from_str(&content)?)
+        }
+        Some("json") => {
+            let file = match File::open(path) {
+                Ok(file) => file,
+                Err(e) => bail!("failed to open file: {e}"),
+            };
 
-        stages.push(map);
+            serde_json::from_reader(io::BufReader::new(file))?
+        }
+        Some(other) => bail!(
+            "unsupported file extension `{}`, expected one of: yml, yaml, toml, json",
+            other
+        ),
+        None => bail!("missing file extension, expected one of: yml, yaml, toml, json"),
+    };
+
+    match value {
+        Value::Mapping(m) => Ok(m),
+        _ => bail!("exists, but is not a mapping"),
     }
+}
 
-    return Ok(Data::new(last_modified, stages));
-
-    /// Extend the existing mapping from the given hierarchy.
-    fn load_mapping(path: &Path) -> Result<serde_yaml::Mapping> {
-        let file = match File::open(path) {
-            Ok(file) => file,
-            Err(e) => bail!("failed to open file: {e}"),
-        };
-
-        match serde_yaml::from_reader(file)? {
-            Value::Mapping(m) => Ok(m),
-            _ => bail!("exists, but is not a mapping"),
-        }
+/// Convert a TOML value into the YAML value used by the hierarchy.
+fn from_toml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(n) => Value::from(n),
+        toml::Value::Float(n) => Value::from(n),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(array) => Value::Sequence(array.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => Value::Mapping(
+            table
+                .into_iter()
+                .map(|(key, value)| (Value::String(key), from_toml(value)))
+                .collect(),
+        ),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Data;
+    use crate::{facts::Facts, Template};
     use serde_yaml::{Mapping, Value};
+    use std::collections::HashMap;
+    use std::fs;
 
     #[test]
     fn test_hiera_lookup() {
@@ -225,4 +517,297 @@ mod tests {
             vec![String::from("item1"), String::from("item2")],
         );
     }
+
+    #[test]
+    fn test_load_merged() {
+        let layer1: Mapping =
+            serde_yaml::from_str("git: {user: {name: high}, aliases: [st]}\nonly1: a").unwrap();
+        let layer2: Mapping = serde_yaml::from_str(
+            "git: {user: {name: low, email: low@example.com}, aliases: [co, br], editor: vim}",
+        )
+        .unwrap();
+
+        let data = Data::new(None, vec![layer1, layer2]);
+
+        let expected: Value = serde_yaml::from_str(
+            "{user: {name: high, email: low@example.com}, aliases: [st], editor: vim}",
+        )
+        .unwrap();
+
+        assert_eq!(data.load_merged::<Value>("git").unwrap(), Some(expected));
+        assert_eq!(
+            data.load_merged::<String>("only1").unwrap(),
+            Some("a".into())
+        );
+        assert_eq!(data.load_merged::<Value>("missing").unwrap(), None);
+
+        let spec = data.load_from_spec("# quickcfg: git:merge").unwrap();
+        let git = spec.get("git").expect("merged git key");
+        assert_eq!(git["user"]["email"], Value::from("low@example.com"));
+        assert_eq!(git["user"]["name"], Value::from("high"));
+    }
+
+    #[test]
+    fn test_load_map() {
+        let layer1: Mapping = serde_yaml::from_str("env: {EDITOR: nvim, PAGER: less}").unwrap();
+        let layer2: Mapping =
+            serde_yaml::from_str("env: {EDITOR: vim, LANG: en_US.UTF-8}\nbad: [a]").unwrap();
+        let data = Data::new(None, vec![layer1, layer2]);
+
+        let env = data.load_map::<String>("env").unwrap();
+
+        let mut expected = HashMap::new();
+        expected.insert(String::from("EDITOR"), String::from("nvim"));
+        expected.insert(String::from("PAGER"), String::from("less"));
+        expected.insert(String::from("LANG"), String::from("en_US.UTF-8"));
+        assert_eq!(env, expected);
+
+        assert!(data.load_map::<String>("missing").unwrap().is_empty());
+
+        let e = data.load_map::<String>("bad").unwrap_err();
+        assert_eq!(e.to_string(), "expected a mapping in `bad`");
+    }
+
+    #[test]
+    fn test_load_from_spec_dotted() {
+        let layer1: Mapping =
+            serde_yaml::from_str("a: {b: high}\nlist: {items: [x]}\nflat.key: flat").unwrap();
+        let layer2: Mapping =
+            serde_yaml::from_str("a: {b: low, c: other}\nlist: {items: [y]}").unwrap();
+        let data = Data::new(None, vec![layer1, layer2]);
+
+        let spec = data
+            .load_from_spec("# quickcfg: a.b, a.c, list.items:array, flat.key")
+            .unwrap();
+
+        let expected: Mapping = serde_yaml::from_str(
+            "{a: {b: high, c: other}, list: {items: [x, y]}, flat: {key: flat}}",
+        )
+        .unwrap();
+        assert_eq!(spec, expected);
+
+        assert!(data.load_from_spec("# quickcfg: a.b, a.b.c").is_err());
+    }
+
+    #[test]
+    fn test_load_joined() {
+        let layer1: Mapping = serde_yaml::from_str("hosts: [a, b]\nports: [80]").unwrap();
+        let layer2: Mapping = serde_yaml::from_str("hosts: [c]\nports: [443]").unwrap();
+        let data = Data::new(None, vec![layer1, layer2]);
+
+        let spec = data
+            .load_from_spec("# quickcfg: hosts:lines, ports:csv")
+            .unwrap();
+        assert_eq!(spec.get("hosts"), Some(&Value::from("a\nb\nc")));
+        assert_eq!(spec.get("ports"), Some(&Value::from("80,443")));
+
+        let nested: Mapping = serde_yaml::from_str("nested: [[a]]").unwrap();
+        let data = Data::new(None, vec![nested]);
+        assert!(data.load_from_spec("# quickcfg: nested:csv").is_err());
+    }
+
+    #[test]
+    fn test_load_from_spec_cached() {
+        let layer1: Mapping =
+            serde_yaml::from_str("name: high\nhosts: [a]\ngit: {user: high}").unwrap();
+        let layer2: Mapping =
+            serde_yaml::from_str("name: low\nhosts: [b]\ngit: {email: low}").unwrap();
+        let data = Data::new(None, vec![layer1, layer2]);
+
+        let spec = "# quickcfg: name, hosts:array, hosts:csv, git:merge";
+
+        let expected: Mapping =
+            serde_yaml::from_str("{name: high, hosts: 'a,b', git: {user: high, email: low}}")
+                .unwrap();
+
+        let first = data.load_from_spec(spec).unwrap();
+        assert_eq!(first, expected);
+        assert_eq!(data.read_cache().len(), 4);
+
+        // NB: the second lookup is served from the cache, and must be identical.
+        let second = data.load_from_spec(spec).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(data.read_cache().len(), 4);
+
+        assert_eq!(
+            data.load_from_spec("# quickcfg: hosts:array").unwrap()["hosts"],
+            Value::Sequence(data.load_array::<Value>("hosts").unwrap())
+        );
+
+        // errors are not cached.
+        assert!(data.load_from_spec("# quickcfg: missing").is_err());
+        assert!(data.load_from_spec("# quickcfg: missing").is_err());
+        assert!(data.load_from_spec("# quickcfg: name:bad").is_err());
+        assert_eq!(data.read_cache().len(), 4);
+    }
+
+    #[test]
+    fn test_load_yaml_and_toml() {
+        let root = tempfile::tempdir().unwrap();
+
+        fs::write(
+            root.path().join("common.yml"),
+            "shell: zsh\npackages: [git]\n",
+        )
+        .unwrap();
+
+        fs::write(
+            root.path().join("extra.toml"),
+            "shell = \"bash\"\neditor = \"vim\"\npackages = [\"curl\"]\n\n[nested]\nvalue = 42\n",
+        )
+        .unwrap();
+
+        let hierarchy = vec![
+            Template::parse("common.yml").unwrap(),
+            Template::parse("extra.toml").unwrap(),
+        ];
+
+        let facts = Facts::new(Vec::new());
+        let environment = HashMap::new();
+        let data = super::load(&hierarchy, root.path(), &facts, &environment).unwrap();
+
+        assert_eq!(data.load::<String>("shell").unwrap(), Some("zsh".into()));
+        assert_eq!(data.load::<String>("editor").unwrap(), Some("vim".into()));
+        assert_eq!(
+            data.load_array::<String>("packages").unwrap(),
+            vec![String::from("git"), String::from("curl")],
+        );
+
+        let nested = data.load::<HashMap<String, u32>>("nested").unwrap();
+        assert_eq!(nested.and_then(|n| n.get("value").copied()), Some(42));
+    }
+
+    #[test]
+    fn test_from_files() {
+        let root = tempfile::tempdir().unwrap();
+        let first = root.path().join("first.yml");
+        let second = root.path().join("second.yml");
+
+        fs::write(&first, "shell: zsh\npackages: [git]\n").unwrap();
+        fs::write(&second, "shell: bash\neditor: vim\n1: number\n").unwrap();
+
+        let paths = vec![first, root.path().join("missing.yml"), second];
+        let data = Data::from_files(&paths).unwrap();
+
+        assert!(data.last_modified.is_some());
+        assert_eq!(data.load::<String>("shell").unwrap(), Some("zsh".into()));
+        assert_eq!(
+            data.keys().into_iter().collect::<Vec<_>>(),
+            vec!["editor", "packages", "shell"]
+        );
+
+        let data = Data::from_files(&[]).unwrap();
+        assert!(data.last_modified.is_none());
+        assert!(data.keys().is_empty());
+    }
+
+    #[test]
+    fn test_load_skipped() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("host")).unwrap();
+        fs::write(root.path().join("common.yml"), "shell: zsh\n").unwrap();
+
+        let templates = ["host/{hostname}.yml", "common.yml", "{missing}.yml"]
+            .iter()
+            .map(|t| Template::parse(t).unwrap())
+            .collect::<Vec<_>>();
+
+        let facts = Facts::new(vec![("hostname".to_string(), "myhost".to_string())]);
+        let data = super::load(&templates, root.path(), &facts, crate::environment::Real).unwrap();
+
+        assert_eq!(data.loaded, vec![root.path().join("common.yml")]);
+        assert_eq!(
+            data.skipped,
+            vec![
+                root.path()
+                    .join("host")
+                    .join("myhost.yml")
+                    .display()
+                    .to_string(),
+                String::from("{missing}.yml"),
+            ]
+        );
+
+        // Once the per-host file exists, it's loaded with higher precedence.
+        fs::write(root.path().join("host/myhost.yml"), "shell: fish\n").unwrap();
+        let data = super::load(&templates, root.path(), &facts, crate::environment::Real).unwrap();
+        assert_eq!(data.loaded.len(), 2);
+        assert_eq!(data.load::<String>("shell").unwrap(), Some("fish".into()));
+    }
+
+    #[test]
+    fn test_load_json_over_yaml() {
+        let root = tempfile::tempdir().unwrap();
+
+        fs::write(
+            root.path().join("local.json"),
+            r#"{"packages": ["curl"], "nested": {"list": [1, 2]}}"#,
+        )
+        .unwrap();
+
+        fs::write(
+            root.path().join("common.yml"),
+            "packages: [git, vim]\nshell: zsh\n",
+        )
+        .unwrap();
+
+        let hierarchy = vec![
+            Template::parse("local.json").unwrap(),
+            Template::parse("common.yml").unwrap(),
+        ];
+
+        let facts = Facts::new(Vec::new());
+        let environment = HashMap::new();
+        let data = super::load(&hierarchy, root.path(), &facts, &environment).unwrap();
+
+        assert_eq!(
+            data.load_array::<String>("packages").unwrap(),
+            vec![
+                String::from("curl"),
+                String::from("git"),
+                String::from("vim")
+            ],
+        );
+
+        assert_eq!(data.load::<String>("shell").unwrap(), Some("zsh".into()));
+
+        let nested = data.load::<HashMap<String, Vec<u32>>>("nested").unwrap();
+        assert_eq!(
+            nested.and_then(|n| n.get("list").cloned()),
+            Some(vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn test_load_bad_json() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("bad.json");
+        fs::write(&path, "{ not json").unwrap();
+
+        let hierarchy = vec![Template::parse("bad.json").unwrap()];
+        let facts = Facts::new(Vec::new());
+        let environment = HashMap::new();
+
+        let e = super::load(&hierarchy, root.path(), &facts, &environment)
+            .err()
+            .expect("bad json to fail");
+
+        assert!(e.to_string().contains(&path.display().to_string()));
+    }
+
+    #[test]
+    fn test_load_unsupported_extension() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("data.ini"), "").unwrap();
+
+        let hierarchy = vec![Template::parse("data.ini").unwrap()];
+        let facts = Facts::new(Vec::new());
+        let environment = HashMap::new();
+
+        let e = super::load(&hierarchy, root.path(), &facts, &environment)
+            .err()
+            .expect("unsupported extension to fail");
+
+        assert!(e.to_string().contains("unsupported file extension"));
+    }
 }
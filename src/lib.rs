@@ -37,6 +37,30 @@
 //! qc --paths
 //! ```
 //!
+//! To see which systems quickcfg would apply, after `only-for` and `from-db` systems have been
+//! expanded, without changing anything, use:
+//!
+//! ```bash
+//! qc --list-systems
+//! ```
+//!
+//! To see which units a single system would create, together with what they depend on and provide,
+//! use `--explain` with the `id` of the system:
+//!
+//! ```bash
+//! qc --explain <id>
+//! ```
+//!
+//! To see what quickcfg would do without changing anything, use `--dry-run`. Git repositories are
+//! only checked against their remote, without fetching anything.
+//!
+//! To see how the content of copied files and templates would change, use `--diff`. This can be
+//! combined with `--dry-run` to only show the changes without applying them.
+//!
+//! To validate the configuration without touching the system, use `--check`. This parses the
+//! configuration, loads the hierarchy and expands all systems, exiting with a non-zero status if
+//! anything is wrong.
+//!
 //! <br>
 //!
 //! ## Features
@@ -74,6 +98,21 @@
 //! git_refresh: 3d
 //! ```
 //!
+//! To check for updates sooner for a single run, pass `--refresh` with a shorter duration, like
+//! `--refresh 10m`. Unlike `--force`, this doesn't discard anything. Pass `--no-git` to apply the
+//! configuration as it is checked out, without checking for updates at all.
+//!
+//! At the end of each run quickcfg prints a summary of what it did. Pass `--quiet` to only log
+//! warnings and errors, while still printing the summary.
+//!
+//! To monitor runs, pass `--report <path>` to write the outcome of every system and unit as JSON.
+//! The report is written even if the run fails.
+//! Pass `--slow-threshold <duration>`, like `--slow-threshold 30s`, to warn about units which take
+//! longer than that to apply.
+//!
+//! Pass `--progress` to show a progress bar of the units being applied instead of logging each of them.
+//! The progress bar is only shown when running interactively in a terminal.
+//!
 //! <br>
 //!
 //! ## Configuration
@@ -93,10 +132,48 @@
 //!   - type: install
 //! ```
 //!
+//! To load an alternate configuration file, while still using the same root for the state and the
+//! hierarchy, use `--config <file>`.
+//!
+//! The state can similarly be stored elsewhere, like on a tmpfs, using `--state-file <file>` and
+//! `--state-dir <dir>`.
+//!
+//! The state file keeps entries for systems which have since been removed from the configuration. To
+//! clean these up, run with `--prune-state`, which removes every entry that wasn't used during a
+//! successful run. It can't be combined with `--tag`, since systems which weren't selected
+//! aren't used.
+//!
+//! To apply a system again even though the state says it's up-to-date, like re-running a
+//! `download-and-run` command or re-installing packages, pass `--force-system <id>` with the id of
+//! the system. It can be repeated.
+//!
+//! Commands which should run after everything has been applied, like reloading a window manager,
+//! can be added to `post_hooks`. Commands in `on_change` only run if something was changed. Hooks
+//! run through the shell in the configuration directory, and are skipped if any unit failed:
+//!
+//! ```no_test
+//! post_hooks:
+//!   - notify-send "quickcfg done"
+//! on_change:
+//!   - i3-msg reload
+//! ```
+//!
+//! Large configurations can be split into several files with `include`. The `hierarchy` and
+//! `systems` of every included file are appended to the configuration in the order they are
+//! included. Included files can include other files, and are resolved relative to the configuration
+//! directory:
+//!
+//! ```no_test
+//! include:
+//!   - systems/packages.yml
+//!   - systems/editors.yml
+//! ```
+//!
 //! You also want to add a `.gitignore` file that looks like this:
 //!
 //! ```gitignore
 //! /secrets.yml
+//! /facts.yml
 //! /.state.yml
 //! /.state
 //! ```
@@ -107,7 +184,10 @@
 //!
 //! The [`hierarchy`] specifies a set of files that should be looked for.
 //! These can use variables like `{distro}`, which will be expanded based on the facts known of the
-//! system you are running on.
+//! system you are running on. The detected facts are `os`, `arch`, `distro`, `hostname`, and `user`,
+//! where the last three are only present if they could be detected.
+//! Custom facts can be added, or detected ones overridden, in a `facts.yml` mapping in the
+//! configuration directory or with `--fact <key>=<value>`, which takes precedence over both.
 //!
 //! You can use my [dotfiles](https://github.com/udoprog/dotfiles) repository as inspiration.
 //!
@@ -121,6 +201,12 @@
 //!
 //! The hierarchy is a collection of files which contain data.
 //!
+//! Files are parsed based on their extension, either as YAML (`.yml` or `.yaml`), TOML
+//! (`.toml`) or JSON (`.json`).
+//!
+//! Files which don't exist are skipped, which is useful for per-host overrides like
+//! `host/{hostname}.yml`. Run with `--debug` to see which files were loaded and which were skipped.
+//!
 //! Some systems query the hierarchy for information, like the `key` setting in [`install`].
 //! This then determines which packages should be installed.
 //!
@@ -134,6 +220,43 @@
 //!
 //! ## Systems
 //!
+//! Any system can be conditionally applied with `when`, which compares a fact with a value using
+//! either `==` or `!=`. If the condition doesn't hold, the system is skipped.
+//!
+//! ```yaml
+//! type: install
+//! provider: pip3
+//! when: distro == debian
+//! ```
+//!
+//! Any system can also wait for paths which are provided outside of quickcfg with `wait_for`. If a
+//! path doesn't exist when the system is applied, it fails. Pass `--wait <duration>` to wait up to
+//! that long for the paths to appear instead.
+//!
+//! ```yaml
+//! type: copy-dir
+//! from: ./plugins
+//! to: home://.foo/plugins
+//! wait_for: [/opt/foo]
+//! ```
+//!
+//! To skip a system entirely without removing it, set `enabled: false`.
+//!
+//! ```yaml
+//! type: install
+//! provider: pip3
+//! enabled: false
+//! ```
+//!
+//! Systems can be grouped with `tags`. Pass `--tag <name>` to only apply the systems with that tag,
+//! together with the systems they require. It can be repeated to select several tags.
+//!
+//! ```yaml
+//! type: install
+//! provider: pip3
+//! tags: [python]
+//! ```
+//!
 //! <br>
 //!
 //! #### `copy-dir`
@@ -145,10 +268,29 @@
 //! from: ./some/dir
 //! to: home://some/dir
 //! templates: false
+//! # Fail templates which refer to variables that are not defined, instead of rendering them as
+//! # empty. (default: false)
+//! strict: false
+//! # Only copy files matching any of these globs. (default: all files)
+//! include: ["*.conf"]
+//! # Skip files and directories matching any of these globs. Takes precedence over `include`.
+//! exclude: ["*.bak"]
+//! # Move existing files with different content to `<name>.bak` (or `<name>.bak.<n>`) before
+//! # overwriting them. (default: false)
+//! backup: false
+//! # Follow symlinks in the source, copying the files and directories they point to instead of
+//! # recreating them as links. (default: false)
+//! follow_symlinks: false
+//! # Delete files previously copied by this system which no longer exist in the source.
+//! # (default: false)
+//! delete_orphans: false
 //! ```
 //!
 //! Will copy a directory recursively.
 //!
+//! With `delete_orphans`, the files copied are recorded in the state file. Only files recorded this
+//! way are deleted, so files you have added to the destination yourself are left alone.
+//!
 //! <br>
 //!
 //! #### `link-dir`
@@ -161,6 +303,12 @@
 //! from: ./some/dir
 //! # Directory to link towards.
 //! to: home://some/dir
+//! # Delete links previously created by this system which no longer exist in the source.
+//! # (default: false)
+//! delete_orphans: false
+//! # Copy files instead of linking them if we are not privileged to create symlinks on Windows.
+//! # (default: false)
+//! symlink_fallback_copy: false
 //! ```
 //!
 //! Will create the corresponding directory structure, but all files will be symbolic links.
@@ -179,6 +327,27 @@
 //! remote: https://github.com/robbyrussell/oh-my-zsh.git
 //! # Refresh once per day.
 //! refresh: 1d
+//! # Branch to track, instead of the default branch of the remote. (optional)
+//! branch: main
+//! # Only clone the given number of commits of history. (optional)
+//! depth: 1
+//! # SSH private key to authenticate against the remote with. (optional)
+//! identity: home://.ssh/id_ed25519
+//! # Initialize and update submodules recursively after cloning or updating. (default: false)
+//! submodules: true
+//! ```
+//!
+//! Repositories are updated with a fast-forward merge. With `--force` they are instead reset to the
+//! state of the remote, unless they have local modifications, in which case the update is skipped
+//! with a warning. Use `--force-dirty` to discard local modifications as well.
+//!
+//! The `remote` can also be a list of remotes, which are tried in order until one of them succeeds.
+//! This applies both when cloning and when fetching updates, so a mirror can be used as a fallback:
+//!
+//! ```yaml
+//! remote:
+//!   - https://github.com/robbyrussell/oh-my-zsh.git
+//!   - https://git.example.com/mirrors/oh-my-zsh.git
 //! ```
 //!
 //! <br>
@@ -196,6 +365,8 @@
 //! provider: pip3
 //! # Hierarchy key to lookup for packages to install.
 //! key: pip3::packages
+//! # Hierarchy key to lookup for packages to remove, if they are installed.
+//! remove_key: pip3::packages_remove
 //! ```
 //!
 //! The simplest example of this system is the one that uses the primary provider:
@@ -208,13 +379,46 @@
 //! This will look up packages under the `packages` key and install it using the primary provider for
 //! the system that you are currently running.
 //!
+//! Any installed packages listed under the `packages_remove` key are removed. Removing packages is
+//! supported by the `debian`, `fedora`, `alpine`, `npm`, and `flatpak` providers.
+//!
+//! Packages can be pinned to a version using either `name=version`, or a `name` and `version` pair.
+//! The version may contain `*` wildcards. A pinned package is installed if it's missing, or if the
+//! provider reports an installed version which doesn't match.
+//! The `winget` provider only supports exact versions, and the `rust components` and
+//! `rust toolchains` providers don't support pinned versions.
+//!
+//! ```yaml
+//! packages:
+//!   - git
+//!   - nodejs=18.*
+//!   - name: ripgrep
+//!     version: 13.0.0
+//! ```
+//!
+//! The `cargo` provider also supports `locked: true` to install using the lockfile of the package,
+//! and `git` to install it from a git repository. Other providers ignore these options.
+//!
+//! ```yaml
+//! cargo::packages:
+//!   - name: cargo-edit
+//!     locked: true
+//!   - name: tool
+//!     git: https://github.com/example/tool
+//! ```
+//!
 //! These are the supported providers:
 //!
 //!  * `debian`: For Debian-based systems. This is a _primary_ provider.
+//!  * `alpine`: For Alpine Linux, using `apk`. This is a _primary_ provider.
 //!  * `pip`: The Python 2 package manager.
 //!  * `pip3`: The Python 3 package manager.
 //!  * `gem`: The Ruby package manager.
 //!  * `cargo`: Install packages using `cargo`.
+//!  * `npm`: Globally installed node packages using `npm`.
+//!  * `flatpak`: Applications from `flathub` using `flatpak`.
+//!  * `winget`: For Windows, using `winget`. This is a _primary_ provider.
+//!    * Options: `scope` and `source`.
 //!  * `rust components`: Rust components using `rustup`.
 //!    * Key: `rust::components`
 //!  * `rust toolchains`: Rust toolchains using `rustup`.
@@ -226,6 +430,17 @@
 //! Explicitly configured providers look up packages based on the hierarchy key `<provider>::packages`.
 //! Default providers use the key `packages`.
 //!
+//! Options which are specific to a provider are specified using `provider_options`, and using an
+//! option which isn't supported by the provider is an error.
+//!
+//! ```yaml
+//! type: install
+//! provider: winget
+//! provider_options:
+//!   scope: user
+//!   source: winget
+//! ```
+//!
 //! <br>
 //!
 //! #### `download`
@@ -239,8 +454,33 @@
 //! url: https://raw.githubusercontent.com/junegunn/vim-plug/master/plug.vim
 //! # Path to download to.
 //! path: home://.config/nvim/autoload/plug.vim
+//! # Headers to add to the request, which are templates. (default: none)
+//! # headers:
+//! #   Authorization: "Bearer $ARTIFACT_TOKEN"
+//! # Basic authentication to use for the request, where `user` and `password` are templates.
+//! # (default: none)
+//! # auth:
+//! #   user: deploy
+//! #   password: $ARTIFACT_PASSWORD
 //! ```
 //!
+//! If `path` is an existing directory or ends with a `/`, the file is downloaded into it using the
+//! file name from the url.
+//!
+//! The `url` is a template, so facts and environment variables can be used in it, like
+//! `https://example.com/releases/tool-{arch}-{os}`. The same is true for `download-and-run`, which
+//! also supports `headers` and `auth`.
+//!
+//! Downloads fail if they take longer than 30 seconds to complete. Pass `--download-timeout <duration>`,
+//! like `--download-timeout 5m`, to change this.
+//!
+//! Files are downloaded to `<name>.part` and only moved into place once complete. If a download
+//! fails partway, it's resumed from where it left off the next time, as long as the server supports
+//! range requests.
+//!
+//! Downloads go through the proxy configured with the `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY`
+//! environment variables. Pass `--proxy <url>` to use a different proxy for all downloads.
+//!
 //! The `id` is to uniquely identify that this system has only been run once.
 //!
 //! <br>
@@ -254,10 +494,28 @@
 //! id: install-oh-my-zsh
 //! # Url to download the command from.
 //! url: https://raw.githubusercontent.com/robbyrussell/oh-my-zsh/master/tools/install.sh
-//! # Set to `true` if the downloaded command requires interaction. (default: false)
+//! # Set to `true` if the downloaded command requires interaction. Output is always shown as the
+//! # command runs, but only interactive commands can read input. (default: false)
 //! interactive: true
 //! # Set to `true` if the command must be run through a shell (`/bin/sh`). (default: false).
 //! shell: true
+//! # Kill the command if it hasn't completed within the given duration. Cannot be used together
+//! # with `interactive` or `root`. (default: no timeout)
+//! # timeout: 10m
+//! # Environment variables to set when running the command, which are templates. All facts are
+//! # also available as `QUICKCFG_<FACT>`, like `QUICKCFG_DISTRO`. (default: none)
+//! # env:
+//! #   ZSH: "$HOME/.oh-my-zsh"
+//! # Run the command again whenever the downloaded file changes, instead of only once. The file is
+//! # downloaded again on every run to check for changes.
+//! # (default: false)
+//! # rerun_on_change: true
+//! # The working directory to run the command in. (default: the current directory)
+//! # cwd: home://src
+//! # Only run the command if this shell command succeeds, or unless it succeeds. These are checked
+//! # every time, and a command which is skipped isn't marked as run. (default: none)
+//! # only_if: test -d "$HOME/.config"
+//! # unless: command -v zsh
 //! ```
 //!
 //! The `id` is to uniquely identify that this system has only been run once.
@@ -276,6 +534,98 @@
 //!
 //! This creates a symbolic link at `path` which contains whatever is specified in `link`.
 //!
+//! Creating symlinks on Windows requires Developer Mode or administrator privileges. Setting
+//! `symlink_fallback_copy: true` on `link` or `link-dir` copies the file instead, with a warning, when
+//! we are not privileged to create the symlink. A copy is replaced if the file it was copied from
+//! changes.
+//!
+//! <br>
+//!
+//! #### `hardlink`
+//!
+//! Creates a hardlink.
+//!
+//! ```yaml
+//! type: hardlink
+//! path: home://.vimrc
+//! link: .vim/vimrc
+//! ```
+//!
+//! This creates a hardlink at `path` which refers to the existing file at `link`, relative to the
+//! configuration directory. If `path` already exists and is not the same file as `link`, quickcfg
+//! refuses to replace it unless `--force` is specified.
+//!
+//! <br>
+//!
+//! #### `template-string`
+//!
+//! Renders a template string into a file.
+//!
+//! ```yaml
+//! type: template-string
+//! to: home://.config/app/name
+//! content: |
+//!   # quickcfg: name
+//!   {{name}}
+//! ```
+//!
+//! The `content` is rendered as a [`handlebars`] template, just like a template file, and written
+//! to `to`. The file is only written again if the rendered content changes.
+//!
+//! <br>
+//!
+//! #### `archive`
+//!
+//! Extracts a `.tar.gz`, `.tgz`, or `.zip` archive into a directory.
+//!
+//! ```yaml
+//! type: archive
+//! # Url to download the archive from.
+//! url: https://github.com/BurntSushi/ripgrep/releases/download/14.1.0/ripgrep-14.1.0-x86_64-unknown-linux-musl.tar.gz
+//! # Or, a local archive to extract.
+//! # from: ./archives/tool.tar.gz
+//! # Directory to extract into.
+//! to: home://opt/ripgrep
+//! # Number of leading path components to strip from every entry. (default: 0)
+//! strip_components: 1
+//! ```
+//!
+//! Downloaded archives are extracted once. Local archives are extracted again if their contents
+//! change.
+//!
+//! <br>
+//!
+//! #### `mode`
+//!
+//! Sets the permissions of a path.
+//!
+//! ```yaml
+//! type: mode
+//! path: home://.ssh
+//! # The permissions to set, in octal.
+//! mode: "0700"
+//! # Also set the permissions of everything inside of the directory. (default: false)
+//! recursive: false
+//! ```
+//!
+//! The permissions are set exactly, so any bits which are not in `mode` are removed. On Windows
+//! this only checks that executable files have the `.exe` extension.
+//!
+//! <br>
+//!
+//! #### `mkdir`
+//!
+//! Creates a directory, and all of its parents.
+//!
+//! ```yaml
+//! type: mkdir
+//! path: home://.local/share/nvim/undo
+//! # The permissions to set on the directory, in octal. (default: not set)
+//! mode: "0700"
+//! ```
+//!
+//! The `mode` is only set on `path`, and not on any of the parents which are created.
+//!
 //! <br>
 //!
 //! #### `only-for`
@@ -294,12 +644,60 @@
 //!     args: ["-y"]
 //! ```
 //!
+//! Systems can also be limited using `conditions` on facts. By default `all` conditions must hold,
+//! use `match: any` if it's enough that any of them do. If `os` is specified as well, it must
+//! also match.
+//!
+//! ```yaml
+//! type: only-for
+//! match: all
+//! conditions:
+//!   - os == linux
+//!   - distro == debian
+//! systems:
+//!   - type: install
+//! ```
+//!
+//! The common case of limiting systems to an OS or a distro can also be written with the `os`,
+//! `not_os`, `distro`, and `not_distro` shorthands. All shorthands which are specified must hold,
+//! in addition to any `conditions`. Like conditions, they are checked against the `os`
+//! and `distro` facts, so they follow any facts which are overridden.
+//!
+//! ```yaml
+//! type: only-for
+//! not_os: windows
+//! distro: debian
+//! systems:
+//!   - type: install
+//! ```
+//!
+//! <br>
+//!
+//! #### `from-db`
+//!
+//! Instantiates one system for every entry in the hierarchy under the key named after the system.
+//!
+//! ```yaml
+//! type: from-db
+//! system: link
+//! key: link
+//! # Only instantiate entries where all of the given fields match. The values are templates, which
+//! # can make use of facts. (optional)
+//! filter:
+//!   host: "{hostname}"
+//! ```
+//!
+//! With the above, an entry like `{host: laptop, path: home://.vimrc, link: vimrc}` under the `link`
+//! key is only instantiated on the host `laptop`. The fields being filtered on are removed before the
+//! system is instantiated.
+//!
 //! <br>
 //!
 //! ## Templating
 //!
 //! Some systems treats files as templates, like [`copy-dir`] when the `templating` option is enabled.
 //! Any file being copied is then treated as a [`handlebars`] template.
+//! Short templates can also be written directly in the configuration with [`template-string`].
 //!
 //! Any template file can make use of hierarchy data, by specifying their dependencies using
 //! a `quickcfg:` tag at the top of the file, like this:
@@ -319,11 +717,32 @@
 //! `hobbies` will be loaded as an array, causing all values in the hierarchy for that value to be
 //! loaded.
 //!
+//! A variable can also be loaded with `:merge`, like `git:merge`. This recursively merges mappings
+//! from all files in the hierarchy. If files conflict on a value, the file that comes first in the
+//! hierarchy takes precedence.
+//!
+//! For simple cases, `:lines` and `:csv` load a variable as an array like `:array` does, but join
+//! all of its values into a single string separated by newlines or commas respectively.
+//!
+//! Dotted variables, like `git.user`, are looked up in nested mappings in the hierarchy and are
+//! available under the same path in the template, like `{{git.user}}`. Variables which share a parent,
+//! like `git.user` and `git.email`, end up in the same mapping.
+//!
+//! Besides the built-in helpers of [`handlebars`], like `eq` and `if`, templates can use:
+//!
+//!  * `upper` and `lower` to change the case of a string, like `{{upper name}}`.
+//!  * `default` to use a fallback if a variable is missing or empty, like `{{default editor "vim"}}`.
+//!  * `env` to read an environment variable, like `{{env "USER"}}`. Changing the variable doesn't
+//!    cause a template file to be rendered again, for that load it with `:env` in the `quickcfg:` tag.
+//!
 //! [`copy-dir`]: #copy-dir
+//! [`template-string`]: #template-string
 //! [`handlebars`]: https://handlebarsjs.com/
 
+mod check;
 mod command;
 mod config;
+pub mod cycles;
 pub mod environment;
 pub mod facts;
 pub mod ffi;
@@ -334,19 +753,25 @@ pub mod hierarchy;
 pub mod opts;
 mod os;
 pub mod packages;
+pub mod results;
+pub mod runner;
 mod rustup;
-pub mod stage;
+pub mod scheduler;
 mod state;
+mod summary;
 pub mod system;
 mod template;
 mod timestamp;
 pub mod unit;
 
+pub use self::check::check;
 pub use self::command::Command;
 pub use self::config::Config;
 pub use self::file_operations::{Load, Save};
 pub use self::file_system::FileSystem;
+pub use self::runner::{run, RunReport};
 pub use self::state::{DiskState, State};
+pub use self::summary::Summary;
 pub use self::template::Template;
 pub use self::timestamp::Timestamp;
 
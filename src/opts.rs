@@ -1,19 +1,43 @@
 //! Set up options.
 
-use anyhow::{bail, Result};
+use crate::facts;
+use anyhow::{anyhow, bail, Context as _, Result};
 use clap::Parser;
 use directories::BaseDirs;
-use std::path::PathBuf;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The version, including the git commit and the compiler that quickcfg was built with.
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("QUICKCFG_GIT_SHA"),
+    ", ",
+    env!("QUICKCFG_RUSTC_VERSION"),
+    ")"
+);
+
 /// Configure your system, quickly!
 #[derive(Parser)]
 #[command(author = "John-John Tedro <udoprog@tedro.se>")]
+#[command(version = VERSION, long_version = LONG_VERSION)]
 pub struct Opts {
     /// Run using the given path as a configuration root.
     #[arg(long, name = "dir")]
     pub root: Option<PathBuf>,
+    /// Load the configuration from the given file, instead of `quickcfg.yml` in the root.
+    #[arg(long, name = "file")]
+    pub config: Option<PathBuf>,
+    /// Store the state file at the given path, instead of `.state.yml` in the root.
+    #[arg(long, name = "state-file")]
+    pub state_file: Option<PathBuf>,
+    /// Store downloaded files and other state in the given directory, instead of `.state` in the
+    /// root.
+    #[arg(long, name = "state-dir")]
+    pub state_dir: Option<PathBuf>,
     /// Initialize against the given repository.
     #[arg(long, name = "url")]
     pub init: Option<String>,
@@ -23,24 +47,106 @@ pub struct Opts {
     /// When updating configuration, force the update.
     #[arg(long)]
     pub force: bool,
+    /// Like `--force`, but also discard local modifications in git repositories when updating.
+    #[arg(long)]
+    pub force_dirty: bool,
+    /// Check the configuration repository for updates if it was last checked longer ago than
+    /// this, like `10m`, instead of using `git_refresh` from the configuration.
+    #[arg(long, name = "duration", value_parser = humantime::parse_duration)]
+    pub refresh: Option<Duration>,
+    /// Don't check the configuration repository for updates, and apply it as it is checked out.
+    /// Systems like `git-sync` still run.
+    #[arg(long, conflicts_with = "updates_only")]
+    pub no_git: bool,
     /// Enable debug logging.
     #[arg(long)]
     pub debug: bool,
+    /// Only log warnings and errors, but still print a summary at the end of the run.
+    #[arg(long)]
+    pub quiet: bool,
+    /// Show a progress bar instead of logging every unit. Disabled when stdout is not a terminal.
+    #[arg(long)]
+    pub progress: bool,
     /// Force to run in non-interactive mode.
     #[arg(long)]
     pub non_interactive: bool,
     /// Only run if there are updates.
     #[arg(long)]
     pub updates_only: bool,
+    /// Don't change anything, only report what would be done.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Show a diff of the content of files before they are copied or rendered.
+    #[arg(long)]
+    pub diff: bool,
+    /// Write machine-readable results of the run as JSON to the given path, even if the run fails.
+    #[arg(long, name = "path")]
+    pub report: Option<PathBuf>,
+    /// After a successful run, remove state entries for systems which no longer exist.
+    ///
+    /// Can't be combined with `--tag`, since the state of systems which weren't selected would be
+    /// removed.
+    #[arg(long, conflicts_with = "tag")]
+    pub prune_state: bool,
+    /// Check that the configuration is valid, without applying it, and exit.
+    #[arg(long)]
+    pub check: bool,
+    /// Print the systems that would be applied, after they have been expanded, and exit.
+    #[arg(long)]
+    pub list_systems: bool,
+    /// Print the units that the system with the given id would create, and exit.
+    #[arg(long, name = "id")]
+    pub explain: Option<String>,
+    /// Wait up to this long for paths in `wait_for` to exist, like `1m`, instead of failing right
+    /// away.
+    #[arg(long, name = "timeout", value_parser = humantime::parse_duration)]
+    pub wait: Option<Duration>,
+    /// Fail downloads which take longer than this to complete, like `5m` (default: 30s).
+    #[arg(long, name = "download-timeout", value_parser = humantime::parse_duration)]
+    pub download_timeout: Option<Duration>,
+    /// Send all downloads through the given proxy, instead of the one configured through the
+    /// `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY` environment variables.
+    #[arg(long, name = "proxy-url")]
+    pub proxy: Option<String>,
+    /// Warn about units which take longer than this to apply, like `30s`.
+    #[arg(long, name = "threshold", value_parser = humantime::parse_duration)]
+    pub slow_threshold: Option<Duration>,
+    /// Apply the system with the given id again, even if its state says that it's up-to-date.
+    /// Can be repeated.
+    #[arg(long = "force-system", name = "system-id")]
+    pub force_system: Vec<String>,
+    /// Only apply systems with the given tag, and the systems they require. Can be repeated.
+    #[arg(long = "tag", name = "tag")]
+    pub tags: Vec<String>,
+    /// The number of threads to use when applying the configuration (default: number of CPUs).
+    #[arg(long, short = 'j', name = "n")]
+    pub jobs: Option<usize>,
+    /// Add a custom fact, overriding any detected fact with the same key. Can be repeated.
+    #[arg(long = "fact", name = "key=value", value_parser = facts::parse_fact)]
+    pub facts: Vec<(String, String)>,
 }
 
 /// Parse command-line options.
 pub fn opts() -> Result<Opts> {
-    let opts = Opts::try_parse()?;
-    Ok(opts)
+    match Opts::try_parse() {
+        Ok(opts) => Ok(opts),
+        // NB: `--help` and `--version` are printed to stdout by clap, which then exits.
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => Err(e.into()),
+    }
 }
 
 impl Opts {
+    /// Test if the options only inspect the configuration, without applying anything.
+    pub fn inspect_only(&self) -> bool {
+        self.check || self.list_systems || self.explain.is_some()
+    }
+
+    /// Test if the system with the given id is forced to run again.
+    pub fn is_forced(&self, id: &str) -> bool {
+        self.force_system.iter().any(|forced| forced == id)
+    }
+
     /// Find root directory based on options.
     pub fn root(&self, base_dirs: Option<&BaseDirs>) -> Result<PathBuf> {
         match self.root.as_ref() {
@@ -52,6 +158,98 @@ impl Opts {
         }
     }
 
+    /// Find the configuration file based on options.
+    pub fn config_path(&self, root: &Path) -> Result<PathBuf> {
+        match self.config.as_ref() {
+            Some(config) => {
+                if !config.is_file() {
+                    bail!("Configuration file does not exist: {}", config.display());
+                }
+
+                Ok(config.to_owned())
+            }
+            None => Ok(root.join("quickcfg.yml")),
+        }
+    }
+
+    /// Find the state file based on options.
+    pub fn state_path(&self, root: &Path) -> PathBuf {
+        match self.state_file.as_ref() {
+            Some(state_file) => state_file.to_owned(),
+            None => root.join(".state.yml"),
+        }
+    }
+
+    /// Find the state directory based on options.
+    pub fn state_dir(&self, root: &Path) -> PathBuf {
+        match self.state_dir.as_ref() {
+            Some(state_dir) => state_dir.to_owned(),
+            None => root.join(".state"),
+        }
+    }
+
+    /// Test if the progress bar should be shown.
+    ///
+    /// It's only shown when requested, interactive, and stdout is a terminal.
+    pub fn progress_enabled(&self) -> bool {
+        use std::io::IsTerminal as _;
+        self.progress && !self.non_interactive && std::io::stdout().is_terminal()
+    }
+
+    /// Construct a progress bar for applying the given number of units.
+    ///
+    /// The progress bar is hidden unless it's enabled.
+    pub fn progress_bar(&self, total: u64) -> ProgressBar {
+        if !self.progress_enabled() {
+            return ProgressBar::hidden();
+        }
+
+        let bar = ProgressBar::new(total);
+
+        if let Ok(style) = ProgressStyle::with_template("{bar:40} {pos}/{len} units ({elapsed})") {
+            bar.set_style(style);
+        }
+
+        bar
+    }
+
+    /// Construct the thread pool used to apply the configuration.
+    pub fn thread_pool(&self) -> Result<rayon::ThreadPool> {
+        let mut builder = rayon::ThreadPoolBuilder::new();
+
+        if let Some(jobs) = self.jobs {
+            if jobs < 1 {
+                bail!("Number of jobs must be at least 1, but got {}", jobs);
+            }
+
+            builder = builder.num_threads(jobs);
+        }
+
+        builder
+            .build()
+            .with_context(|| anyhow!("Failed to construct thread pool"))
+    }
+
+    /// Construct the HTTP client which is shared by all downloads.
+    pub fn http_client(&self) -> Result<reqwest::blocking::Client> {
+        let mut builder = reqwest::blocking::Client::builder();
+
+        if let Some(timeout) = self.download_timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        // NB: an explicit proxy disables the proxies configured through environment variables.
+        if let Some(proxy) = self.proxy.as_deref() {
+            let proxy = reqwest::Proxy::all(proxy)
+                .with_context(|| anyhow!("Illegal proxy url: {}", proxy))?;
+            builder = builder.proxy(proxy);
+        }
+
+        builder
+            .build()
+            .with_context(|| anyhow!("Failed to construct HTTP client"))
+    }
+
     /// Prompt for yes/no.
     pub fn prompt(&self, question: &str, default: bool) -> Result<bool> {
         use std::io::{self, Write};
@@ -105,3 +303,303 @@ impl Opts {
         Ok(Some(input.trim().to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Opts;
+    use crate::{config::Config, DiskState, Load as _, Save as _, Timestamp};
+    use clap::Parser as _;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_version() {
+        let e = match Opts::try_parse_from(["qc", "--version"]) {
+            Ok(_) => panic!("expected version to be displayed"),
+            Err(e) => e,
+        };
+
+        assert_eq!(e.kind(), clap::error::ErrorKind::DisplayVersion);
+
+        let version = e.to_string();
+        assert!(version.contains(super::VERSION), "{}", version);
+        assert!(version.contains(env!("QUICKCFG_GIT_SHA")), "{}", version);
+        assert!(version.contains("rustc"), "{}", version);
+    }
+
+    #[test]
+    fn test_no_git() {
+        assert!(Opts::parse_from(["qc", "--no-git"]).no_git);
+        assert!(Opts::try_parse_from(["qc", "--no-git", "--updates-only"]).is_err());
+    }
+
+    #[test]
+    fn test_prune_state() {
+        assert!(Opts::parse_from(["qc", "--prune-state"]).prune_state);
+        assert!(Opts::try_parse_from(["qc", "--prune-state", "--tag", "foo"]).is_err());
+    }
+
+    #[test]
+    fn test_jobs() {
+        let opts = Opts::parse_from(["qc", "--jobs", "1"]);
+        let pool = opts.thread_pool().unwrap();
+        assert_eq!(pool.current_num_threads(), 1);
+
+        let sum = pool.install(|| (0..100u32).into_par_iter().sum::<u32>());
+        assert_eq!(sum, 4950);
+
+        let opts = Opts::parse_from(["qc", "-j", "0"]);
+        assert!(opts.thread_pool().is_err());
+    }
+
+    #[test]
+    fn test_facts() {
+        let opts = Opts::parse_from(["qc", "--fact", "role=desktop", "--fact", "os=custom"]);
+
+        assert_eq!(
+            opts.facts,
+            vec![
+                (String::from("role"), String::from("desktop")),
+                (String::from("os"), String::from("custom")),
+            ]
+        );
+
+        assert!(Opts::try_parse_from(["qc", "--fact", "role"]).is_err());
+    }
+
+    #[test]
+    fn test_config_path() {
+        let root = tempfile::tempdir().unwrap();
+        let other = root.path().join("other.yml");
+
+        let opts = Opts::parse_from(["qc"]);
+        assert_eq!(
+            opts.config_path(root.path()).unwrap(),
+            root.path().join("quickcfg.yml")
+        );
+
+        let opts = Opts::parse_from(["qc", "--config", other.to_str().unwrap()]);
+        let e = opts.config_path(root.path()).unwrap_err();
+        assert!(e.to_string().contains("does not exist"), "{}", e);
+
+        std::fs::write(&other, "git_refresh: 2d\nhierarchy: []\nsystems: []\n").unwrap();
+
+        let path = opts.config_path(root.path()).unwrap();
+        let config = Config::load(&path).unwrap().expect("configuration");
+        assert_eq!(
+            config.git_refresh,
+            std::time::Duration::from_secs(2 * 24 * 3600)
+        );
+    }
+
+    #[test]
+    fn test_state_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+
+        let opts = Opts::parse_from(["qc"]);
+        assert_eq!(opts.state_path(root.path()), root.path().join(".state.yml"));
+        assert_eq!(opts.state_dir(root.path()), root.path().join(".state"));
+
+        let state_file = other.path().join("state.yml");
+        let state_dir = other.path().join("state");
+
+        let opts = Opts::parse_from([
+            "qc",
+            "--state-file",
+            state_file.to_str().unwrap(),
+            "--state-dir",
+            state_dir.to_str().unwrap(),
+        ]);
+
+        assert_eq!(opts.state_path(root.path()), state_file);
+        assert_eq!(opts.state_dir(root.path()), state_dir);
+
+        let config = Config::default();
+        let now = Timestamp::now();
+
+        // First run, which records that something has run.
+        let mut state = DiskState::default().into_state(&config, now);
+        state.touch_once("test");
+        let serialized = state.serialize().expect("dirty state");
+        serialized.save(&opts.state_path(root.path())).unwrap();
+
+        // Second run, which reads it back from the custom location.
+        let state = DiskState::load(&opts.state_path(root.path()))
+            .unwrap()
+            .expect("saved state");
+        let state = state.into_state(&config, now);
+        assert!(state.has_run_once("test"));
+        assert!(!root.path().join(".state.yml").exists());
+    }
+
+    #[test]
+    fn test_progress() {
+        let opts = Opts::parse_from(["qc", "--progress", "--non-interactive"]);
+        assert!(!opts.progress_enabled());
+        assert!(opts.progress_bar(10).is_hidden());
+
+        let opts = Opts::parse_from(["qc"]);
+        assert!(!opts.progress_enabled());
+        assert!(opts.progress_bar(10).is_hidden());
+    }
+
+    /// Spawn a proxy which answers a single request, and returns the request line it received, or
+    /// `None` if no request was received in time.
+    fn spawn_proxy() -> (String, std::thread::JoinHandle<Option<String>>) {
+        use std::io::{BufRead as _, BufReader, ErrorKind, Write as _};
+        use std::net::TcpListener;
+        use std::time::{Duration, Instant};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let start = Instant::now();
+
+            let stream = loop {
+                match listener.accept() {
+                    Ok((stream, _)) => break stream,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        if start.elapsed() > Duration::from_secs(5) {
+                            return None;
+                        }
+
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(e) => panic!("failed to accept: {}", e),
+                }
+            };
+
+            stream.set_nonblocking(false).unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                if line.trim_end().is_empty() {
+                    break;
+                }
+            }
+
+            let response = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            Some(request.trim_end().to_string())
+        });
+
+        (url, handle)
+    }
+
+    /// Send a request to `url` from a separate process with the given arguments and proxy
+    /// environment variables, since the environment is shared by all tests in this process.
+    fn request_with_env(url: &str, args: &[&str], env: &[(&str, &str)]) {
+        let mut command = std::process::Command::new(std::env::current_exe().unwrap());
+        command.args(["--exact", "opts::tests::proxy_request", "--ignored"]);
+
+        for key in [
+            "HTTP_PROXY",
+            "http_proxy",
+            "HTTPS_PROXY",
+            "https_proxy",
+            "ALL_PROXY",
+            "all_proxy",
+            "NO_PROXY",
+            "no_proxy",
+        ] {
+            command.env_remove(key);
+        }
+
+        command.envs(env.iter().copied());
+        command.env("QUICKCFG_TEST_URL", url);
+        command.env("QUICKCFG_TEST_ARGS", args.join(" "));
+
+        let output = command.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+
+    /// Send a request in the process spawned by `request_with_env`.
+    #[test]
+    #[ignore = "only run by the proxy tests"]
+    fn proxy_request() {
+        let url = std::env::var("QUICKCFG_TEST_URL").unwrap();
+        let args = std::env::var("QUICKCFG_TEST_ARGS").unwrap();
+
+        let mut all = vec!["qc", "--download-timeout", "5s"];
+        all.extend(args.split_whitespace());
+
+        let http = Opts::parse_from(all).http_client().unwrap();
+        // NB: the proxies only answer the request line, so tunneled requests fail.
+        let _ = http.get(url).send();
+    }
+
+    #[test]
+    fn test_proxy() {
+        let (proxy, handle) = spawn_proxy();
+
+        let opts = Opts::parse_from(["qc", "--proxy", &proxy]);
+        let http = opts.http_client().unwrap();
+        let response = http.get("http://example.invalid/file").send().unwrap();
+        assert!(response.status().is_success());
+
+        // NB: requests through a proxy use the absolute url as the target.
+        assert_eq!(
+            handle.join().unwrap().as_deref(),
+            Some("GET http://example.invalid/file HTTP/1.1")
+        );
+
+        let opts = Opts::parse_from(["qc", "--proxy", "not a url"]);
+        assert!(opts.http_client().is_err());
+    }
+
+    #[test]
+    fn test_proxy_env() {
+        let (proxy, handle) = spawn_proxy();
+        request_with_env(
+            "http://example.invalid/file",
+            &[],
+            &[("HTTP_PROXY", &proxy)],
+        );
+        assert_eq!(
+            handle.join().unwrap().as_deref(),
+            Some("GET http://example.invalid/file HTTP/1.1")
+        );
+
+        let (proxy, handle) = spawn_proxy();
+        request_with_env(
+            "https://example.invalid/file",
+            &[],
+            &[("HTTPS_PROXY", &proxy)],
+        );
+        assert_eq!(
+            handle.join().unwrap().as_deref(),
+            Some("CONNECT example.invalid:443 HTTP/1.1")
+        );
+    }
+
+    #[test]
+    fn test_proxy_precedence() {
+        // NB: nothing listens on the proxy in the environment, so requests sent through it fail.
+        let unused = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+
+        let (proxy, handle) = spawn_proxy();
+        request_with_env(
+            "http://example.invalid/file",
+            &["--proxy", &proxy],
+            &[("HTTP_PROXY", &unused), ("HTTPS_PROXY", &unused)],
+        );
+        assert_eq!(
+            handle.join().unwrap().as_deref(),
+            Some("GET http://example.invalid/file HTTP/1.1")
+        );
+    }
+}
@@ -3,6 +3,7 @@
 use crate::unit::{AddMode, Symlink};
 use anyhow::{anyhow, Context, Result};
 use std::borrow::Cow;
+use std::fs;
 use std::path::{Path, PathBuf};
 
 /// Convert into an executable path.
@@ -17,19 +18,30 @@ pub fn command(base: &str) -> Cow<'_, Path> {
     Cow::from(Path::new(base))
 }
 
+/// Test if the current process is running as root.
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
 /// Detect git command.
 #[allow(unused)]
 pub fn detect_git() -> Result<PathBuf> {
     Ok(PathBuf::from("git"))
 }
 
-/// Add the given modes (on top of the existing ones).
+/// Add the given modes (on top of the existing ones), or replace the existing permission bits if
+/// the mode is replacing.
 pub fn add_mode(add_mode: &AddMode) -> Result<()> {
-    use std::fs;
     use std::os::unix::fs::PermissionsExt;
 
     let mut perm = add_mode.path.metadata()?.permissions();
-    let mode = perm.mode() | add_mode.unix_mode();
+
+    let mode = if add_mode.is_replace() {
+        (perm.mode() & !0o777) | add_mode.unix_mode()
+    } else {
+        perm.mode() | add_mode.unix_mode()
+    };
+
     perm.set_mode(mode);
 
     fs::set_permissions(&add_mode.path, perm)
@@ -38,14 +50,47 @@ pub fn add_mode(add_mode: &AddMode) -> Result<()> {
     Ok(())
 }
 
+/// Test if the path already has the given mode.
+pub fn has_mode(add_mode: &AddMode) -> Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = add_mode.path.metadata()?.permissions().mode();
+
+    if add_mode.is_replace() {
+        return Ok(mode & 0o777 == add_mode.unix_mode());
+    }
+
+    Ok(mode & add_mode.unix_mode() == add_mode.unix_mode())
+}
+
+/// Test if the given path can be executed, which is the case if any of its execute bits are set.
+pub fn is_executable(path: &Path) -> Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = path
+        .metadata()
+        .with_context(|| anyhow!("failed to read metadata: {}", path.display()))?;
+
+    Ok(meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Apply the given permissions to a file.
+pub fn copy_permissions(path: &Path, permissions: &fs::Permissions) -> Result<()> {
+    fs::set_permissions(path, permissions.clone())
+        .with_context(|| anyhow!("failed to set permissions: {}", path.display()))?;
+
+    Ok(())
+}
+
 /// Create a symlink.
 pub fn create_symlink(symlink: &Symlink) -> Result<()> {
-    use std::{fs, os::unix};
+    use std::os::unix;
 
     let Symlink {
         remove,
         ref path,
         ref link,
+        ..
     } = *symlink;
 
     if remove {
@@ -58,3 +103,61 @@ pub fn create_symlink(symlink: &Symlink) -> Result<()> {
     unix::fs::symlink(link, path)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{add_mode, copy_permissions, has_mode, is_executable};
+    use crate::unit::AddMode;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_copy_executable() {
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("script.sh");
+        let to = root.path().join("copy.sh");
+
+        fs::write(&from, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(&to, "#!/bin/sh\n").unwrap();
+
+        let permissions = from.metadata().unwrap().permissions();
+        copy_permissions(&to, &permissions).unwrap();
+
+        assert_eq!(to.metadata().unwrap().permissions().mode() & 0o777, 0o755);
+    }
+
+    #[test]
+    fn test_add_mode() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("file");
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+
+        let mode = AddMode::from_unix_mode(&path, 0o600);
+        assert!(has_mode(&mode).unwrap());
+        add_mode(&mode).unwrap();
+        assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o666);
+
+        let mode = mode.replace();
+        assert!(!has_mode(&mode).unwrap());
+        add_mode(&mode).unwrap();
+        assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(has_mode(&mode).unwrap());
+    }
+
+    #[test]
+    fn test_is_executable() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("file");
+        fs::write(&path, "").unwrap();
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(!is_executable(&path).unwrap());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o744)).unwrap();
+        assert!(is_executable(&path).unwrap());
+
+        assert!(!is_executable(root.path()).unwrap());
+    }
+}
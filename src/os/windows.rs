@@ -1,11 +1,16 @@
 //! Windows-specific implementations.
 
 use crate::unit::{AddMode, Symlink};
-use anyhow::{bail, Error};
+use anyhow::{anyhow, bail, Context as _, Error};
 use std::borrow::Cow;
 use std::env::consts;
+use std::fs;
+use std::io;
 use std::path::Path;
 
+/// The error raised when we are not privileged to create symlinks.
+const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
 /// Convert into an executable path.
 pub fn exe_path<P>(path: &P) -> Cow<'_, Path>
 where
@@ -32,6 +37,11 @@ where
     exe_path(base)
 }
 
+/// Test if the current process is running as root, which is never the case on Windows.
+pub fn is_root() -> bool {
+    false
+}
+
 /// Add the given modes (on top of the existing ones).
 pub fn add_mode(mode: &AddMode) -> Result<(), Error> {
     if mode.is_executable() {
@@ -44,36 +54,221 @@ pub fn add_mode(mode: &AddMode) -> Result<(), Error> {
     Ok(())
 }
 
+/// Test if the path already has the given mode.
+///
+/// NB: always false on Windows, so that the executable check in [add_mode] is performed.
+pub fn has_mode(_: &AddMode) -> Result<bool, Error> {
+    Ok(false)
+}
+
+/// Test if the given path can be executed.
+///
+/// NB: windows files are executable if they have the .exe extension.
+pub fn is_executable(path: &Path) -> Result<bool, Error> {
+    Ok(path.is_file() && path.extension() == Some(consts::EXE_EXTENSION.as_ref()))
+}
+
+/// Apply the given permissions to a file.
+///
+/// NB: this is a no-op on Windows, since the only permission which is tracked is the read-only
+/// flag.
+pub fn copy_permissions(_: &Path, _: &fs::Permissions) -> Result<(), Error> {
+    Ok(())
+}
+
 /// Create a symlink.
 pub fn create_symlink(symlink: &Symlink) -> Result<(), Error> {
-    use std::fs;
     use std::os::windows::fs::{symlink_dir, symlink_file};
 
     let Symlink {
         remove,
         ref path,
         ref link,
+        ..
     } = *symlink;
 
     if remove {
         log::info!("re-linking {} to {}", path.display(), link.display());
-        fs::remove_file(path)?;
+
+        // NB: directory symlinks are removed like directories on Windows.
+        if path.is_dir() {
+            fs::remove_dir(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
     } else {
         log::info!("linking {} to {}", path.display(), link.display());
     }
 
-    if path.is_file() {
-        symlink_file(path, path.join(link))?;
-        return Ok(());
+    // NB: the kind of symlink to create on Windows depends on what it points to, and relative
+    // links are resolved relative to the directory containing the symlink.
+    let target = match path.parent() {
+        Some(parent) if link.is_relative() => Cow::Owned(parent.join(link)),
+        _ => Cow::Borrowed(link.as_path()),
+    };
+
+    let meta = fs::metadata(&target).with_context(|| {
+        anyhow!(
+            "cannot symlink `{}` to `{}`: target does not exist",
+            path.display(),
+            target.display()
+        )
+    })?;
+
+    let result = if meta.is_dir() {
+        symlink_dir(link, path)
+    } else {
+        symlink_file(link, path)
+    };
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) => fallback_copy(symlink, &target, &meta, e),
+    }
+}
+
+/// Copy the target of a symlink into place if we failed to create the symlink because we are not
+/// privileged to do so, and the symlink permits it.
+fn fallback_copy(
+    symlink: &Symlink,
+    target: &Path,
+    meta: &fs::Metadata,
+    error: io::Error,
+) -> Result<(), Error> {
+    if !symlink.fallback_copy || error.raw_os_error() != Some(ERROR_PRIVILEGE_NOT_HELD) {
+        return Err(error.into());
     }
 
-    if path.is_dir() {
-        symlink_dir(path, path.join(link))?;
-        return Ok(());
+    if !meta.is_file() {
+        bail!(
+            "cannot copy `{}` in place of symlink: not a file",
+            target.display()
+        );
     }
 
-    bail!(
-        "cannot symlink `{}`: not a file or directory",
-        path.display()
+    log::warn!(
+        "not privileged to create symlinks, copying {} to {} instead",
+        target.display(),
+        symlink.path.display()
     );
+
+    fs::copy(target, &symlink.path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_symlink, fallback_copy, ERROR_PRIVILEGE_NOT_HELD};
+    use crate::unit::Symlink;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    #[test]
+    fn test_create_symlink() {
+        let root = tempfile::tempdir().unwrap();
+        let link = root.path().join("target.txt");
+        let path = root.path().join("link.txt");
+        fs::write(&link, "hello").unwrap();
+
+        create_symlink(&Symlink {
+            remove: false,
+            path: path.clone(),
+            link: link.clone(),
+            fallback_copy: false,
+        })
+        .unwrap();
+
+        assert!(path.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_link(&path).unwrap(), link);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_create_relative_symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        let links = root.path().join("links");
+        fs::create_dir_all(root.path().join("dir")).unwrap();
+        fs::create_dir_all(&links).unwrap();
+        fs::write(root.path().join("dir").join("a.txt"), "a").unwrap();
+        fs::write(root.path().join("file.txt"), "file").unwrap();
+
+        create_symlink(&Symlink {
+            remove: false,
+            path: links.join("file.txt"),
+            link: Path::new("..").join("file.txt"),
+            fallback_copy: false,
+        })
+        .unwrap();
+
+        create_symlink(&Symlink {
+            remove: false,
+            path: links.join("dir"),
+            link: Path::new("..").join("dir"),
+            fallback_copy: false,
+        })
+        .unwrap();
+
+        assert!(links
+            .join("file.txt")
+            .symlink_metadata()
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(links.join("file.txt")).unwrap(), "file");
+        assert!(links.join("dir").is_dir());
+        assert_eq!(
+            fs::read_to_string(links.join("dir").join("a.txt")).unwrap(),
+            "a"
+        );
+    }
+
+    #[test]
+    fn test_create_symlink_missing_target() {
+        let root = tempfile::tempdir().unwrap();
+
+        let result = create_symlink(&Symlink {
+            remove: false,
+            path: root.path().join("link"),
+            link: Path::new("missing").to_owned(),
+            fallback_copy: false,
+        });
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_fallback_copy() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("target.txt");
+        fs::write(&target, "hello").unwrap();
+        let meta = fs::metadata(&target).unwrap();
+
+        let mut symlink = Symlink {
+            remove: false,
+            path: root.path().join("link.txt"),
+            link: target.clone(),
+            fallback_copy: false,
+        };
+
+        let privilege = || io::Error::from_raw_os_error(ERROR_PRIVILEGE_NOT_HELD);
+
+        assert!(fallback_copy(&symlink, &target, &meta, privilege()).is_err());
+        assert!(!symlink.path.exists());
+
+        symlink.fallback_copy = true;
+
+        let other = io::Error::new(io::ErrorKind::Other, "other");
+        assert!(fallback_copy(&symlink, &target, &meta, other).is_err());
+        assert!(!symlink.path.exists());
+
+        fallback_copy(&symlink, &target, &meta, privilege()).unwrap();
+        assert!(!symlink
+            .path
+            .symlink_metadata()
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&symlink.path).unwrap(), "hello");
+    }
 }
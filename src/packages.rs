@@ -2,30 +2,182 @@
 //!
 //! Can check which packages are installed.
 
+mod apk;
 mod cargo;
 mod debian;
 mod fedora;
+mod flatpak;
+mod npm;
 mod python;
 mod ruby;
 mod rustup_components;
 mod rustup_toolchains;
 mod winget;
 
+use crate::command;
 use crate::facts::{self, Facts};
-use anyhow::{bail, Error};
+use crate::unit::{Dependency, SystemUnit, UnitId};
+use anyhow::{anyhow, bail, Error};
 use log::warn;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 use std::fmt;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 /// Information about an installed package.
 #[derive(Debug)]
 pub struct Package {
     pub name: String,
+    /// The installed version, if reported by the package manager.
+    pub version: Option<String>,
 }
 
+/// A package that should be installed, optionally pinned to a version.
+///
+/// Deserialized either from a `name` or `name=version` string, or from a struct with `name` and
+/// optional `version`, `locked`, and `git` fields.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "RawPackageSpec")]
+pub struct PackageSpec {
+    pub name: String,
+    /// The version to install, which might contain `*` wildcards, like `18.*`.
+    pub version: Option<String>,
+    /// Install using the lockfile of the package, if supported by the package manager.
+    pub locked: bool,
+    /// Install from the given git repository, if supported by the package manager.
+    pub git: Option<String>,
+}
+
+impl PackageSpec {
+    /// Parse a package specification from a `name` or `name=version` string.
+    pub fn parse(spec: &str) -> Result<PackageSpec, Error> {
+        let (name, version) = match spec.split_once('=') {
+            Some((name, version)) => (name.trim(), Some(version.trim())),
+            None => (spec.trim(), None),
+        };
+
+        PackageSpec::new(spec, name, version)
+    }
+
+    /// Construct a new specification, where `spec` is what is used in diagnostics.
+    fn new(spec: &str, name: &str, version: Option<&str>) -> Result<PackageSpec, Error> {
+        if name.is_empty() {
+            bail!("bad package `{}`: missing name", spec);
+        }
+
+        if version == Some("") {
+            bail!("bad package `{}`: missing version", spec);
+        }
+
+        Ok(PackageSpec {
+            name: name.to_string(),
+            version: version.map(String::from),
+            locked: false,
+            git: None,
+        })
+    }
+
+    /// Test if the specification has any options beyond a name and a version.
+    pub fn has_options(&self) -> bool {
+        self.locked || self.git.is_some()
+    }
+
+    /// Test if the given installed package satisfies this specification.
+    ///
+    /// If the package manager doesn't report versions, any installed package with a matching name
+    /// satisfies it.
+    pub fn is_satisfied_by(&self, package: &Package) -> Result<bool, Error> {
+        if self.name != package.name {
+            return Ok(false);
+        }
+
+        let (version, installed) = match (self.version.as_deref(), package.version.as_deref()) {
+            (Some(version), Some(installed)) => (version, installed),
+            _ => return Ok(true),
+        };
+
+        let glob = globset::Glob::new(version)
+            .map_err(|e| anyhow!("bad version `{}` for `{}`: {}", version, self.name, e))?;
+
+        Ok(glob.compile_matcher().is_match(installed))
+    }
+}
+
+impl fmt::Display for PackageSpec {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.version.as_deref() {
+            Some(version) => write!(fmt, "{}={}", self.name, version)?,
+            None => self.name.fmt(fmt)?,
+        }
+
+        if let Some(git) = self.git.as_deref() {
+            write!(fmt, " (git: {})", git)?;
+        }
+
+        if self.locked {
+            write!(fmt, " (locked)")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPackageSpec {
+    String(String),
+    Struct {
+        name: String,
+        #[serde(default)]
+        version: Option<String>,
+        #[serde(default)]
+        locked: bool,
+        #[serde(default)]
+        git: Option<String>,
+    },
+}
+
+impl TryFrom<RawPackageSpec> for PackageSpec {
+    type Error = Error;
+
+    fn try_from(raw: RawPackageSpec) -> Result<Self, Self::Error> {
+        match raw {
+            RawPackageSpec::String(spec) => PackageSpec::parse(&spec),
+            RawPackageSpec::Struct {
+                name,
+                version,
+                locked,
+                git,
+            } => {
+                let mut spec =
+                    PackageSpec::new(&name, name.trim(), version.as_deref().map(str::trim))?;
+
+                if let Some(git) = git.as_deref().map(str::trim) {
+                    if git.is_empty() {
+                        bail!("bad package `{}`: empty `git`", name);
+                    }
+
+                    spec.git = Some(git.to_string());
+                }
+
+                spec.locked = locked;
+                Ok(spec)
+            }
+        }
+    }
+}
+
+/// A memoized list of packages.
+type Cached = Arc<Mutex<Option<Arc<Vec<Package>>>>>;
+
 /// A provider of package managers.
 pub struct Provider {
     default: Option<Arc<dyn PackageManager>>,
+    /// Packages listed by each package manager, keyed by name.
+    cache: Mutex<HashMap<String, Cached>>,
+    /// The last install unit for each exclusive package manager, keyed by name.
+    exclusive: Mutex<HashMap<String, UnitId>>,
 }
 
 impl Provider {
@@ -45,16 +197,75 @@ impl Provider {
         match name {
             "debian" => test(debian::PackageManager::new()),
             "fedora" => test(fedora::PackageManager::new()),
+            "alpine" => test(apk::PackageManager::new()),
             "pip" => test(python::PackageManager::new("pip")),
             "pip3" => test(python::PackageManager::new("pip3")),
             "gem" => test(ruby::PackageManager::new()),
             "cargo" => test(cargo::PackageManager::new()),
+            "npm" => test(npm::PackageManager::new()),
+            "flatpak" => test(flatpak::PackageManager::new()),
             "winget" => test(winget::PackageManager::new()),
             "rust toolchains" => test(rustup_toolchains::PackageManager::new()),
             "rust components" => test(rustup_components::PackageManager::new()),
             _ => bail!("No package manager provider for `{}`", name),
         }
     }
+
+    /// List all packages installed by the given package manager.
+    ///
+    /// The result is cached by the name of the package manager, so that it is only listed once
+    /// even if it is used by multiple systems.
+    pub fn list_packages(
+        &self,
+        package_manager: &dyn PackageManager,
+    ) -> Result<Arc<Vec<Package>>, Error> {
+        let cached = {
+            let mut cache = self.cache.lock().map_err(|_| anyhow!("Lock poisoned"))?;
+
+            cache
+                .entry(package_manager.name().to_string())
+                .or_default()
+                .clone()
+        };
+
+        // NB: only the slot for this package manager is locked while listing, so that different
+        // package managers can be listed in parallel.
+        let mut cached = cached.lock().map_err(|_| anyhow!("Lock poisoned"))?;
+
+        if let Some(packages) = cached.as_ref() {
+            return Ok(Arc::clone(packages));
+        }
+
+        let packages = Arc::new(package_manager.list_packages()?);
+        *cached = Some(Arc::clone(&packages));
+        Ok(packages)
+    }
+
+    /// Make the given install unit depend on the previous install unit for the same package
+    /// manager, if the package manager is exclusive.
+    ///
+    /// This chains all install units for an exclusive package manager so that they run one at a
+    /// time.
+    pub fn serialize(
+        &self,
+        package_manager: &dyn PackageManager,
+        unit: &mut SystemUnit,
+    ) -> Result<(), Error> {
+        if !package_manager.exclusive() {
+            return Ok(());
+        }
+
+        let mut exclusive = self
+            .exclusive
+            .lock()
+            .map_err(|_| anyhow!("Lock poisoned"))?;
+
+        if let Some(previous) = exclusive.insert(package_manager.name().to_string(), unit.id) {
+            unit.dependencies.push(Dependency::Unit(previous));
+        }
+
+        Ok(())
+    }
 }
 
 /// Detect which package provider to use.
@@ -65,7 +276,11 @@ pub fn detect(facts: &Facts) -> Result<Provider, Error> {
         by_os(facts)?
     };
 
-    Ok(Provider { default })
+    Ok(Provider {
+        default,
+        cache: Mutex::new(HashMap::new()),
+        exclusive: Mutex::new(HashMap::new()),
+    })
 }
 
 /// Detect package manager by distro.
@@ -79,6 +294,7 @@ fn by_distro(facts: &Facts) -> Result<Option<Arc<dyn PackageManager>>, Error> {
     match distro {
         "debian" => test(debian::PackageManager::new()),
         "fedora" => test(fedora::PackageManager::new()),
+        "alpine" => test(apk::PackageManager::new()),
         distro => {
             warn!("no package integration for distro: {}", distro);
             Ok(None)
@@ -103,6 +319,54 @@ fn by_os(facts: &Facts) -> Result<Option<Arc<dyn PackageManager>>, Error> {
     }
 }
 
+/// Construct a command which runs `command` as root.
+///
+/// Unless we are already `root`, it's wrapped in `sudo` which asks for a password using the given
+/// `prompt`.
+fn as_root(
+    sudo: &command::Command,
+    command: &command::Command,
+    root: bool,
+    prompt: &str,
+) -> command::Command {
+    if root {
+        return command.clone();
+    }
+
+    let mut sudo = sudo.clone();
+    sudo.args(&["-p", prompt, "--"]);
+    sudo.arg(&command.name);
+    sudo.args(&command.args);
+    sudo
+}
+
+/// Format the given specifications into packages to install.
+///
+/// Options that aren't supported by the package manager are ignored with a warning.
+pub(crate) fn format_specs<M>(manager: &M, specs: &[PackageSpec]) -> Vec<String>
+where
+    M: ?Sized + PackageManager,
+{
+    let mut packages = Vec::new();
+
+    for spec in specs {
+        if spec.has_options() {
+            warn!(
+                "package manager `{}` ignores the options of package `{}`",
+                manager.name(),
+                spec
+            );
+        }
+
+        packages.push(match spec.version.as_deref() {
+            Some(version) => manager.install_spec(&spec.name, version),
+            None => spec.name.clone(),
+        });
+    }
+
+    packages
+}
+
 /// Try to detect existing python package managers.
 fn test(manager: impl PackageManager + 'static) -> Result<Option<Arc<dyn PackageManager>>, Error> {
     if manager.test()? {
@@ -124,6 +388,14 @@ pub trait PackageManager: fmt::Debug + Sync + Send {
         false
     }
 
+    /// Must installs using this package manager run one at a time?
+    ///
+    /// Install units for an exclusive package manager depend on each other, so they are not run
+    /// in parallel even if they are not thread-local.
+    fn exclusive(&self) -> bool {
+        false
+    }
+
     /// Hierarchy key to use for package manager.
     fn key(&self) -> Option<&str> {
         None
@@ -138,6 +410,298 @@ pub trait PackageManager: fmt::Debug + Sync + Send {
     /// List all packages on this system.
     fn list_packages(&self) -> Result<Vec<Package>, Error>;
 
+    /// Format the argument used to install a specific version of a package.
+    fn install_spec(&self, name: &str, version: &str) -> String {
+        format!("{}={}", name, version)
+    }
+
+    /// Test that the given version of a package can be installed.
+    ///
+    /// This is checked before anything is installed, so that a pinned version which can't be
+    /// honored is reported as an error instead of being passed on to the underlying tool.
+    fn check_version(&self, name: &str, version: &str) -> Result<(), Error> {
+        let _ = (name, version);
+        Ok(())
+    }
+
     /// Install the given packages.
+    ///
+    /// Where the underlying tool supports it, all packages should be installed with a single
+    /// invocation.
     fn install_packages(&self, packages: &[String]) -> Result<(), Error>;
+
+    /// Options which can be passed to this package manager through `provider_options`.
+    fn options(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Install the given package specifications.
+    ///
+    /// By default, every specification is formatted using [PackageManager::install_spec] and
+    /// installed with [PackageManager::install_packages]. Package managers which support
+    /// additional options, like `locked` or `git`, or any [PackageManager::options] should
+    /// override this.
+    fn install_specs(
+        &self,
+        specs: &[PackageSpec],
+        options: &BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        let _ = options;
+        self.install_packages(&format_specs(self, specs))
+    }
+
+    /// Remove the given packages.
+    fn remove_packages(&self, packages: &[String]) -> Result<(), Error> {
+        let _ = packages;
+        bail!(
+            "package manager `{}` does not support removing packages",
+            self.name()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{as_root, Package, PackageManager, PackageSpec, Provider};
+    use crate::command::Command;
+    use anyhow::Error;
+    use rayon::prelude::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// A fake package manager which counts how many times packages have been listed.
+    #[derive(Debug, Default)]
+    struct Counting {
+        listed: AtomicUsize,
+    }
+
+    impl PackageManager for Counting {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn test(&self) -> Result<bool, Error> {
+            Ok(true)
+        }
+
+        fn list_packages(&self) -> Result<Vec<Package>, Error> {
+            self.listed.fetch_add(1, Ordering::SeqCst);
+
+            Ok(vec![Package {
+                name: String::from("package"),
+                version: None,
+            }])
+        }
+
+        fn install_packages(&self, _: &[String]) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = PackageSpec::parse("git").unwrap();
+        assert_eq!(spec.name, "git");
+        assert_eq!(spec.version, None);
+
+        let spec = PackageSpec::parse("nodejs=18.*").unwrap();
+        assert_eq!(spec.name, "nodejs");
+        assert_eq!(spec.version.as_deref(), Some("18.*"));
+
+        let spec: PackageSpec = serde_yaml::from_str("{name: nodejs, version: '18.*'}").unwrap();
+        assert_eq!(spec.name, "nodejs");
+        assert_eq!(spec.version.as_deref(), Some("18.*"));
+
+        let spec: PackageSpec = serde_yaml::from_str("nodejs=18.*").unwrap();
+        assert_eq!(spec.to_string(), "nodejs=18.*");
+        assert!(!spec.has_options());
+
+        let spec: PackageSpec =
+            serde_yaml::from_str("{name: tool, locked: true, git: 'https://example.com/tool'}")
+                .unwrap();
+        assert!(spec.locked);
+        assert_eq!(spec.git.as_deref(), Some("https://example.com/tool"));
+        assert_eq!(
+            spec.to_string(),
+            "tool (git: https://example.com/tool) (locked)"
+        );
+
+        assert!(serde_yaml::from_str::<PackageSpec>("{name: tool, git: ''}").is_err());
+
+        assert!(PackageSpec::parse("=18").is_err());
+        assert!(PackageSpec::parse("nodejs=").is_err());
+    }
+
+    #[test]
+    fn test_is_satisfied_by() {
+        let package = |name: &str, version: Option<&str>| Package {
+            name: name.to_string(),
+            version: version.map(String::from),
+        };
+
+        let spec = PackageSpec::parse("nodejs=18.*").unwrap();
+        assert!(spec
+            .is_satisfied_by(&package("nodejs", Some("18.2.0")))
+            .unwrap());
+        assert!(!spec
+            .is_satisfied_by(&package("nodejs", Some("16.1.0")))
+            .unwrap());
+        // version not reported by package manager.
+        assert!(spec.is_satisfied_by(&package("nodejs", None)).unwrap());
+        assert!(!spec
+            .is_satisfied_by(&package("npm", Some("18.2.0")))
+            .unwrap());
+
+        let spec = PackageSpec::parse("git").unwrap();
+        assert!(spec.is_satisfied_by(&package("git", Some("2.40"))).unwrap());
+    }
+
+    #[test]
+    fn test_list_packages_cached() {
+        let manager = Arc::new(Counting::default());
+
+        let provider = Provider {
+            default: Some(manager.clone()),
+            cache: Mutex::new(HashMap::new()),
+            exclusive: Mutex::new(HashMap::new()),
+        };
+
+        let results = (0..16)
+            .into_par_iter()
+            .map(|_| provider.list_packages(&*manager))
+            .collect::<Result<Vec<_>, Error>>()
+            .unwrap();
+
+        assert_eq!(manager.listed.load(Ordering::SeqCst), 1);
+
+        for packages in results {
+            assert_eq!(packages.len(), 1);
+            assert_eq!(packages[0].name, "package");
+        }
+    }
+
+    /// A fake package manager which must be used exclusively.
+    #[derive(Debug)]
+    struct Exclusive;
+
+    impl PackageManager for Exclusive {
+        fn exclusive(&self) -> bool {
+            true
+        }
+
+        fn name(&self) -> &str {
+            "exclusive"
+        }
+
+        fn test(&self) -> Result<bool, Error> {
+            Ok(true)
+        }
+
+        fn list_packages(&self) -> Result<Vec<Package>, Error> {
+            Ok(Vec::new())
+        }
+
+        fn install_packages(&self, _: &[String]) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_serialize_exclusive() {
+        use crate::scheduler::Scheduler;
+        use crate::unit::{Dependency, SystemUnit, Unit};
+
+        let provider = Provider {
+            default: None,
+            cache: Mutex::new(HashMap::new()),
+            exclusive: Mutex::new(HashMap::new()),
+        };
+
+        let mut units = Vec::new();
+
+        for id in 0..3 {
+            let mut unit = SystemUnit::new(id, Unit::System);
+            provider.serialize(&Exclusive, &mut unit).unwrap();
+            units.push(unit);
+        }
+
+        for id in 3..5 {
+            let mut unit = SystemUnit::new(id, Unit::System);
+            provider.serialize(&Counting::default(), &mut unit).unwrap();
+            units.push(unit);
+        }
+
+        assert!(units[0].dependencies.is_empty());
+        assert_eq!(units[1].dependencies, vec![Dependency::Unit(0)]);
+        assert_eq!(units[2].dependencies, vec![Dependency::Unit(1)]);
+        assert!(units[3].dependencies.is_empty());
+        assert!(units[4].dependencies.is_empty());
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+
+        let mut scheduler = Scheduler::new(units);
+        let mut completed = Vec::new();
+
+        scheduler.run(
+            &pool,
+            |_| (),
+            |unit, ()| {
+                completed.push(unit.id);
+                true
+            },
+        );
+
+        let position = |id| completed.iter().position(|c| *c == id).unwrap();
+        assert_eq!(completed.len(), 5);
+        assert!(position(0) < position(1));
+        assert!(position(1) < position(2));
+        assert!(scheduler.into_unscheduled().is_empty());
+    }
+
+    #[test]
+    fn test_as_root() {
+        let sudo = Command::new("sudo");
+        let mut apt = Command::new("apt");
+        apt.args(&["install", "-y"]);
+
+        let args = |command: &Command| {
+            command
+                .args
+                .iter()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let command = as_root(&sudo, &apt, true, "prompt: ");
+        assert_eq!(command.name, apt.name);
+        assert_eq!(args(&command), vec!["install", "-y"]);
+
+        let command = as_root(&sudo, &apt, false, "prompt: ");
+        assert_eq!(command.name, sudo.name);
+        assert_eq!(
+            args(&command),
+            vec!["-p", "prompt: ", "--", "apt", "install", "-y"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_as_root_detected() {
+        let root = unsafe { libc::geteuid() == 0 };
+        assert_eq!(crate::os::is_root(), root);
+
+        let sudo = Command::new("sudo");
+        let apt = Command::new("apt");
+        let command = as_root(&sudo, &apt, crate::os::is_root(), "prompt: ");
+
+        if root {
+            assert_eq!(command.name, apt.name);
+        } else {
+            assert_eq!(command.name, sudo.name);
+        }
+    }
 }
@@ -0,0 +1,173 @@
+//! Packages abstraction for Alpine.
+
+use crate::{command, os, packages::Package};
+use anyhow::Error;
+use std::ffi::OsStr;
+use std::io;
+
+#[derive(Debug)]
+pub struct Apk {
+    sudo: command::Command,
+    apk: command::Command,
+}
+
+impl Apk {
+    /// Create a new apk command wrapper.
+    pub fn new() -> Self {
+        Apk {
+            sudo: command::Command::new(os::command("sudo")),
+            apk: command::Command::new(os::command("apk")),
+        }
+    }
+
+    /// Test that the command is available.
+    pub fn test(&self) -> Result<bool, Error> {
+        let mut apk = self.apk.clone();
+        apk.arg("--version");
+
+        match apk.run() {
+            Ok(output) => Ok(output.status.success()),
+            Err(e) => match e.kind() {
+                // no such command.
+                io::ErrorKind::NotFound => Ok(false),
+                _ => Err(Error::from(e)),
+            },
+        }
+    }
+
+    /// Install the given packages.
+    pub fn install_packages<I>(&self, packages: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        let mut apk = super::as_root(
+            &self.sudo,
+            &self.apk,
+            os::is_root(),
+            "[sudo] password for %u to install packages: ",
+        );
+        apk.arg("add");
+        apk.args(packages);
+        apk.run_inherited()?;
+        Ok(())
+    }
+
+    /// Remove the given packages.
+    pub fn remove_packages<I>(&self, packages: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        let mut apk = super::as_root(
+            &self.sudo,
+            &self.apk,
+            os::is_root(),
+            "[sudo] password for %u to remove packages: ",
+        );
+        apk.arg("del");
+        apk.args(packages);
+        apk.run_inherited()?;
+        Ok(())
+    }
+
+    /// List all the packages which are installed.
+    pub fn list_installed(&self) -> Result<Vec<Package>, Error> {
+        let mut apk = self.apk.clone();
+        apk.arg("info");
+        Ok(parse_info(&apk.run_stdout()?))
+    }
+}
+
+/// Parse the output of `apk info`, which lists one installed package per line.
+fn parse_info(output: &str) -> Vec<Package> {
+    let mut out = Vec::new();
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        out.push(Package {
+            name: line.to_string(),
+            version: None,
+        });
+    }
+
+    out
+}
+
+/// Packages abstraction for Alpine.
+#[derive(Debug)]
+pub struct PackageManager {
+    apk: Apk,
+}
+
+impl PackageManager {
+    /// Construct a new alpine package manager.
+    pub fn new() -> Self {
+        PackageManager { apk: Apk::new() }
+    }
+}
+
+impl super::PackageManager for PackageManager {
+    fn primary(&self) -> bool {
+        true
+    }
+
+    fn needs_interaction(&self) -> bool {
+        // needs interaction if we have to use `sudo`.
+        !os::is_root()
+    }
+
+    fn name(&self) -> &str {
+        "alpine"
+    }
+
+    /// Test that we have everything we need.
+    fn test(&self) -> Result<bool, Error> {
+        self.apk.test()
+    }
+
+    fn list_packages(&self) -> Result<Vec<Package>, Error> {
+        self.apk.list_installed()
+    }
+
+    fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
+        self.apk.install_packages(packages)
+    }
+
+    fn remove_packages(&self, packages: &[String]) -> Result<(), Error> {
+        self.apk.remove_packages(packages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_info;
+
+    #[test]
+    fn test_parse_info() {
+        let output = "musl\nbusybox\n\nalpine-baselayout\n  ca-certificates-bundle \n";
+
+        let names = parse_info(output)
+            .into_iter()
+            .map(|p| p.name)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            vec![
+                "musl",
+                "busybox",
+                "alpine-baselayout",
+                "ca-certificates-bundle"
+            ]
+        );
+
+        assert!(parse_info("").is_empty());
+        assert!(parse_info("\n\n").is_empty());
+    }
+}
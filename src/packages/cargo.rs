@@ -1,7 +1,11 @@
 //! Packages abstraction for Cargo.
 
-use crate::{command, os, packages::Package};
+use crate::{
+    command, os,
+    packages::{Package, PackageSpec},
+};
 use anyhow::{anyhow, Error};
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::io;
 
@@ -33,7 +37,7 @@ impl Cargo {
         }
     }
 
-    /// List all the packages which are installed.
+    /// Install the given packages with a single invocation of `cargo install`.
     pub fn install_packages<I>(&self, packages: I) -> Result<(), Error>
     where
         I: IntoIterator,
@@ -42,8 +46,7 @@ impl Cargo {
         let mut cargo = self.cargo.clone();
         cargo.arg("install");
         cargo.args(packages);
-        cargo.run()?;
-        Ok(())
+        cargo.run_checked()
     }
 
     /// List all the packages which are installed.
@@ -68,8 +71,14 @@ impl Cargo {
 
             let name = it.next().ok_or_else(|| anyhow!("expected package name"))?;
 
+            // NB: lines look like `ripgrep v13.0.0:`.
+            let version = it
+                .next()
+                .map(|v| v.trim_end_matches(':').trim_start_matches('v'));
+
             out.push(Package {
                 name: name.to_string(),
+                version: version.map(String::from),
             });
         }
 
@@ -77,6 +86,51 @@ impl Cargo {
     }
 }
 
+/// Build the arguments to `cargo install` for the given package specifications.
+///
+/// Plain packages are installed with a single invocation, while packages with options each get an
+/// invocation of their own, since options like `--git` apply to every package being installed.
+fn install_args(specs: &[PackageSpec]) -> Vec<Vec<String>> {
+    let mut plain = Vec::new();
+    let mut out = Vec::new();
+
+    for spec in specs {
+        if !spec.has_options() {
+            plain.push(match spec.version.as_deref() {
+                Some(version) => format!("{}@{}", spec.name, version),
+                None => spec.name.clone(),
+            });
+
+            continue;
+        }
+
+        let mut args = Vec::new();
+
+        if spec.locked {
+            args.push(String::from("--locked"));
+        }
+
+        if let Some(version) = spec.version.as_deref() {
+            args.push(String::from("--version"));
+            args.push(version.to_string());
+        }
+
+        if let Some(git) = spec.git.as_deref() {
+            args.push(String::from("--git"));
+            args.push(git.to_string());
+        }
+
+        args.push(spec.name.clone());
+        out.push(args);
+    }
+
+    if !plain.is_empty() {
+        out.insert(0, plain);
+    }
+
+    out
+}
+
 /// Packages abstraction for Cargo.
 #[derive(Debug)]
 pub struct PackageManager {
@@ -93,6 +147,11 @@ impl PackageManager {
 }
 
 impl super::PackageManager for PackageManager {
+    fn exclusive(&self) -> bool {
+        // cargo locks its package cache, so concurrent installs conflict.
+        true
+    }
+
     fn primary(&self) -> bool {
         true
     }
@@ -110,7 +169,80 @@ impl super::PackageManager for PackageManager {
         self.cargo.list_installed()
     }
 
+    fn install_spec(&self, name: &str, version: &str) -> String {
+        format!("{}@{}", name, version)
+    }
+
     fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
         self.cargo.install_packages(packages)
     }
+
+    fn install_specs(
+        &self,
+        specs: &[PackageSpec],
+        _: &BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        for args in install_args(specs) {
+            self.cargo.install_packages(args)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::install_args;
+    use crate::packages::PackageSpec;
+
+    fn spec(spec: &str) -> PackageSpec {
+        serde_yaml::from_str(spec).unwrap()
+    }
+
+    #[test]
+    fn test_install_args() {
+        let specs = [
+            spec("ripgrep"),
+            spec("{name: tool, locked: true, version: '1.*', git: 'https://example.com/tool'}"),
+            spec("fd-find=8.*"),
+            spec("{name: cargo-edit, locked: true}"),
+        ];
+
+        assert_eq!(
+            install_args(&specs),
+            vec![
+                vec!["ripgrep", "fd-find@8.*"],
+                vec![
+                    "--locked",
+                    "--version",
+                    "1.*",
+                    "--git",
+                    "https://example.com/tool",
+                    "tool"
+                ],
+                vec!["--locked", "cargo-edit"],
+            ]
+        );
+
+        // NB: structured entries without options behave like plain ones.
+        let specs = [spec("{name: ripgrep, version: '13.*'}")];
+        assert_eq!(install_args(&specs), vec![vec!["ripgrep@13.*"]]);
+        assert!(install_args(&[]).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_install_failure() {
+        use super::Cargo;
+        use crate::command::{self, OutputError};
+
+        let cargo = Cargo {
+            cargo: command::failing("install failed"),
+        };
+
+        let e = cargo.install_packages(["ripgrep"]).unwrap_err();
+        let e = e.downcast::<OutputError>().expect("output error");
+        assert!(!e.status.success());
+        assert_eq!(e.stderr, "install failed\n");
+    }
 }
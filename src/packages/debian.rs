@@ -41,11 +41,33 @@ impl Apt {
         I: IntoIterator,
         I::Item: AsRef<OsStr>,
     {
-        let mut sudo = self.sudo.clone();
-        sudo.args(&["-p", "[sudo] password for %u to install packages: ", "--"]);
-        sudo.args(&["apt", "install", "-y"]);
-        sudo.args(packages);
-        sudo.run_inherited()?;
+        let mut apt = super::as_root(
+            &self.sudo,
+            &self.apt,
+            os::is_root(),
+            "[sudo] password for %u to install packages: ",
+        );
+        apt.args(&["install", "-y"]);
+        apt.args(packages);
+        apt.run_inherited()?;
+        Ok(())
+    }
+
+    /// Remove the given packages.
+    pub fn remove_packages<I>(&self, packages: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        let mut apt = super::as_root(
+            &self.sudo,
+            &self.apt,
+            os::is_root(),
+            "[sudo] password for %u to remove packages: ",
+        );
+        apt.args(&["remove", "-y"]);
+        apt.args(packages);
+        apt.run_inherited()?;
         Ok(())
     }
 }
@@ -68,7 +90,10 @@ impl DpkgQuery {
         let mut out = Vec::new();
 
         let mut dpkg_query = self.dpkg_query.clone();
-        dpkg_query.args(&["-W", "--showformat=${db:Status-Abbrev}${binary:Package}\\n"]);
+        dpkg_query.args(&[
+            "-W",
+            "--showformat=${db:Status-Abbrev}${binary:Package} ${Version}\\n",
+        ]);
 
         for line in dpkg_query.run_lines()? {
             let line = line.trim();
@@ -80,6 +105,7 @@ impl DpkgQuery {
             let mut it = line.split(' ');
             let status = it.next().ok_or_else(|| anyhow!("expected status"))?;
             let name = it.next().ok_or_else(|| anyhow!("expected package name"))?;
+            let version = it.next();
 
             if status != "ii" {
                 continue;
@@ -87,6 +113,7 @@ impl DpkgQuery {
 
             out.push(Package {
                 name: name.to_string(),
+                version: version.map(String::from),
             });
         }
 
@@ -117,8 +144,8 @@ impl super::PackageManager for PackageManager {
     }
 
     fn needs_interaction(&self) -> bool {
-        // needs interaction because we use `sudo`.
-        true
+        // needs interaction if we have to use `sudo`.
+        !os::is_root()
     }
 
     fn name(&self) -> &str {
@@ -137,4 +164,8 @@ impl super::PackageManager for PackageManager {
     fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
         self.apt.install_packages(packages)
     }
+
+    fn remove_packages(&self, packages: &[String]) -> Result<(), Error> {
+        self.apt.remove_packages(packages)
+    }
 }
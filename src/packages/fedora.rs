@@ -41,11 +41,33 @@ impl Dnf {
         I: IntoIterator,
         I::Item: AsRef<OsStr>,
     {
-        let mut sudo = self.sudo.clone();
-        sudo.args(&["-p", "[sudo] password for %u to install packages: ", "--"]);
-        sudo.args(&["dnf", "install", "-y"]);
-        sudo.args(packages);
-        sudo.run_inherited()?;
+        let mut dnf = super::as_root(
+            &self.sudo,
+            &self.dnf,
+            os::is_root(),
+            "[sudo] password for %u to install packages: ",
+        );
+        dnf.args(&["install", "-y"]);
+        dnf.args(packages);
+        dnf.run_inherited()?;
+        Ok(())
+    }
+
+    /// Remove the given packages.
+    pub fn remove_packages<I>(&self, packages: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        let mut dnf = super::as_root(
+            &self.sudo,
+            &self.dnf,
+            os::is_root(),
+            "[sudo] password for %u to remove packages: ",
+        );
+        dnf.args(&["remove", "-y"]);
+        dnf.args(packages);
+        dnf.run_inherited()?;
         Ok(())
     }
 
@@ -73,6 +95,7 @@ impl Dnf {
 
             out.push(Package {
                 name: name.to_string(),
+                version: None,
             });
         }
 
@@ -99,8 +122,8 @@ impl super::PackageManager for PackageManager {
     }
 
     fn needs_interaction(&self) -> bool {
-        // needs interaction because we use `sudo`.
-        true
+        // needs interaction if we have to use `sudo`.
+        !os::is_root()
     }
 
     fn name(&self) -> &str {
@@ -116,7 +139,15 @@ impl super::PackageManager for PackageManager {
         self.dnf.list_installed()
     }
 
+    fn install_spec(&self, name: &str, version: &str) -> String {
+        format!("{}-{}", name, version)
+    }
+
     fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
         self.dnf.install_packages(packages)
     }
+
+    fn remove_packages(&self, packages: &[String]) -> Result<(), Error> {
+        self.dnf.remove_packages(packages)
+    }
 }
@@ -0,0 +1,175 @@
+//! Packages abstraction for flatpak applications.
+
+use crate::{command, packages::Package};
+use anyhow::Error;
+use std::ffi::OsStr;
+use std::io;
+
+/// The remote that applications are installed from.
+const REMOTE: &str = "flathub";
+
+#[derive(Debug)]
+pub struct Flatpak {
+    flatpak: command::Command,
+}
+
+impl Flatpak {
+    /// Create a new flatpak command wrapper.
+    pub fn new() -> Self {
+        Flatpak {
+            flatpak: command::Command::new("flatpak"),
+        }
+    }
+
+    /// Test that the command is available.
+    pub fn test(&self) -> Result<bool, Error> {
+        let mut flatpak = self.flatpak.clone();
+        flatpak.arg("--version");
+
+        match flatpak.run() {
+            Ok(output) => Ok(output.status.success()),
+            Err(e) => match e.kind() {
+                // no such command.
+                io::ErrorKind::NotFound => Ok(false),
+                _ => Err(Error::from(e)),
+            },
+        }
+    }
+
+    /// Install the given applications.
+    pub fn install_packages<I>(&self, packages: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        let mut flatpak = self.flatpak.clone();
+        flatpak.args(&["install", "-y", REMOTE]);
+        flatpak.args(packages);
+        flatpak.run_checked()
+    }
+
+    /// Remove the given applications.
+    pub fn remove_packages<I>(&self, packages: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        let mut flatpak = self.flatpak.clone();
+        flatpak.args(&["uninstall", "-y"]);
+        flatpak.args(packages);
+        flatpak.run_checked()
+    }
+
+    /// List all the applications which are installed.
+    pub fn list_installed(&self) -> Result<Vec<Package>, Error> {
+        let mut flatpak = self.flatpak.clone();
+        flatpak.args(&["list", "--app", "--columns=application"]);
+        Ok(parse_list(&flatpak.run_stdout()?))
+    }
+}
+
+/// Parse the output of `flatpak list --columns=application`.
+///
+/// A header is only printed when flatpak is connected to a terminal, but it is skipped just in
+/// case.
+fn parse_list(output: &str) -> Vec<Package> {
+    let mut out = Vec::new();
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        // NB: application ids are reverse DNS names, which always contain dots.
+        if line.is_empty() || line == "Application ID" || !line.contains('.') {
+            continue;
+        }
+
+        out.push(Package {
+            name: line.to_string(),
+            version: None,
+        });
+    }
+
+    out
+}
+
+/// Packages abstraction for flatpak applications.
+#[derive(Debug)]
+pub struct PackageManager {
+    flatpak: Flatpak,
+}
+
+impl PackageManager {
+    /// Construct a new flatpak package manager.
+    pub fn new() -> Self {
+        PackageManager {
+            flatpak: Flatpak::new(),
+        }
+    }
+}
+
+impl super::PackageManager for PackageManager {
+    fn name(&self) -> &str {
+        "flatpak"
+    }
+
+    /// Test that we have everything we need.
+    fn test(&self) -> Result<bool, Error> {
+        self.flatpak.test()
+    }
+
+    fn list_packages(&self) -> Result<Vec<Package>, Error> {
+        self.flatpak.list_installed()
+    }
+
+    fn install_spec(&self, name: &str, version: &str) -> String {
+        log::warn!(
+            "flatpak doesn't support installing a specific version of `{}`, ignoring `{}`",
+            name,
+            version
+        );
+
+        name.to_string()
+    }
+
+    fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
+        self.flatpak.install_packages(packages)
+    }
+
+    fn remove_packages(&self, packages: &[String]) -> Result<(), Error> {
+        self.flatpak.remove_packages(packages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_list;
+
+    fn names(output: &str) -> Vec<String> {
+        parse_list(output).into_iter().map(|p| p.name).collect()
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let output = "org.mozilla.firefox\ncom.valvesoftware.Steam\n\norg.gnome.Platform.Locale\n";
+
+        assert_eq!(
+            names(output),
+            vec![
+                "org.mozilla.firefox",
+                "com.valvesoftware.Steam",
+                "org.gnome.Platform.Locale",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_list_header() {
+        let output = "Application ID\norg.mozilla.firefox\n  com.spotify.Client  \n";
+        assert_eq!(
+            names(output),
+            vec!["org.mozilla.firefox", "com.spotify.Client"]
+        );
+        assert!(names("Application ID\n").is_empty());
+        assert!(names("").is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+//! Packages abstraction for globally installed npm packages.
+
+use crate::{command, packages::Package};
+use anyhow::{anyhow, bail, Error};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io;
+
+/// The name of the npm command.
+///
+/// NB: on Windows, npm is installed as a batch script.
+#[cfg(windows)]
+const NPM: &str = "npm.cmd";
+#[cfg(not(windows))]
+const NPM: &str = "npm";
+
+#[derive(Debug)]
+pub struct Npm {
+    npm: command::Command,
+}
+
+impl Npm {
+    /// Create a new npm command wrapper.
+    pub fn new() -> Self {
+        Npm {
+            npm: command::Command::new(NPM),
+        }
+    }
+
+    /// Test that the command is available.
+    pub fn test(&self) -> Result<bool, Error> {
+        let mut npm = self.npm.clone();
+        npm.arg("--version");
+
+        match npm.run() {
+            Ok(output) => Ok(output.status.success()),
+            Err(e) => match e.kind() {
+                // no such command.
+                io::ErrorKind::NotFound => Ok(false),
+                _ => Err(Error::from(e)),
+            },
+        }
+    }
+
+    /// Install the given packages globally.
+    pub fn install_packages<I>(&self, packages: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        let mut npm = self.npm.clone();
+        npm.args(&["install", "-g"]);
+        npm.args(packages);
+        npm.run_checked()
+    }
+
+    /// Remove the given globally installed packages.
+    pub fn remove_packages<I>(&self, packages: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        let mut npm = self.npm.clone();
+        npm.args(&["uninstall", "-g"]);
+        npm.args(packages);
+        npm.run_checked()
+    }
+
+    /// List all the packages which are installed globally.
+    pub fn list_installed(&self) -> Result<Vec<Package>, Error> {
+        let mut npm = self.npm.clone();
+        npm.args(&["ls", "-g", "--depth=0", "--json"]);
+
+        let output = npm.run()?;
+
+        // NB: `npm ls` exits with an error if there are problems with the installed packages, like
+        // missing peer dependencies, but still lists them.
+        if !output.status.success() && output.stdout.trim().is_empty() {
+            return Err(Error::from(output.into_error()));
+        }
+
+        parse_list(&output.stdout)
+    }
+}
+
+/// Parse the output of `npm ls --json`.
+fn parse_list(output: &str) -> Result<Vec<Package>, Error> {
+    #[derive(Deserialize)]
+    struct List {
+        #[serde(default)]
+        dependencies: BTreeMap<String, Dependency>,
+    }
+
+    #[derive(Deserialize)]
+    struct Dependency {
+        #[serde(default)]
+        version: Option<String>,
+    }
+
+    if output.trim().is_empty() {
+        bail!("no output from `npm ls`");
+    }
+
+    let list: List =
+        serde_json::from_str(output).map_err(|e| anyhow!("bad output from `npm ls`: {}", e))?;
+
+    Ok(list
+        .dependencies
+        .into_iter()
+        .map(|(name, dependency)| Package {
+            name,
+            version: dependency.version,
+        })
+        .collect())
+}
+
+/// Packages abstraction for globally installed npm packages.
+#[derive(Debug)]
+pub struct PackageManager {
+    npm: Npm,
+}
+
+impl PackageManager {
+    /// Construct a new npm package manager.
+    pub fn new() -> Self {
+        PackageManager { npm: Npm::new() }
+    }
+}
+
+impl super::PackageManager for PackageManager {
+    fn exclusive(&self) -> bool {
+        // npm locks its global prefix, so concurrent installs conflict.
+        true
+    }
+
+    fn name(&self) -> &str {
+        "npm"
+    }
+
+    /// Test that we have everything we need.
+    fn test(&self) -> Result<bool, Error> {
+        self.npm.test()
+    }
+
+    fn list_packages(&self) -> Result<Vec<Package>, Error> {
+        self.npm.list_installed()
+    }
+
+    fn install_spec(&self, name: &str, version: &str) -> String {
+        format!("{}@{}", name, version)
+    }
+
+    fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
+        self.npm.install_packages(packages)
+    }
+
+    fn remove_packages(&self, packages: &[String]) -> Result<(), Error> {
+        self.npm.remove_packages(packages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_list;
+
+    #[test]
+    fn test_parse_list() {
+        let output = r#"{
+  "name": "lib",
+  "dependencies": {
+    "typescript": {
+      "version": "5.2.2",
+      "overridden": false
+    },
+    "@angular/cli": {
+      "version": "16.2.0",
+      "overridden": false
+    },
+    "broken": {}
+  }
+}"#;
+
+        let packages = parse_list(output).unwrap();
+        let packages = packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.as_deref()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            packages,
+            vec![
+                ("@angular/cli", Some("16.2.0")),
+                ("broken", None),
+                ("typescript", Some("5.2.2")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_empty_list() {
+        assert!(parse_list("{}").unwrap().is_empty());
+        assert!(parse_list("{\"name\": \"lib\"}").unwrap().is_empty());
+        assert!(parse_list("").is_err());
+        assert!(parse_list("not json").is_err());
+    }
+}
@@ -47,11 +47,13 @@ impl Pip {
                 continue;
             }
 
-            let mut it = line.split(' ');
+            let mut it = line.split_whitespace();
             let name = it.next().ok_or_else(|| anyhow!("expected package name"))?;
+            let version = it.next();
 
             out.push(Package {
                 name: name.to_string(),
+                version: version.map(String::from),
             });
         }
 
@@ -68,8 +70,7 @@ impl Pip {
         command.arg("install");
         command.arg("--user");
         command.args(packages);
-        command.run()?;
-        Ok(())
+        command.run_checked()
     }
 }
 
@@ -91,6 +92,11 @@ impl PackageManager {
 }
 
 impl super::PackageManager for PackageManager {
+    fn exclusive(&self) -> bool {
+        // installing packages concurrently can corrupt the environment.
+        true
+    }
+
     fn primary(&self) -> bool {
         true
     }
@@ -108,7 +114,29 @@ impl super::PackageManager for PackageManager {
         self.pip.list_installed()
     }
 
+    fn install_spec(&self, name: &str, version: &str) -> String {
+        format!("{}=={}", name, version)
+    }
+
     fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
         self.pip.install_packages(packages)
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::Pip;
+    use crate::command::{self, OutputError};
+
+    #[test]
+    fn test_install_failure() {
+        let pip = Pip {
+            command: command::failing("install failed"),
+        };
+
+        let e = pip.install_packages(["requests"]).unwrap_err();
+        let e = e.downcast::<OutputError>().expect("output error");
+        assert!(!e.status.success());
+        assert_eq!(e.stderr, "install failed\n");
+    }
+}
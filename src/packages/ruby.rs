@@ -33,7 +33,7 @@ impl Gem {
         }
     }
 
-    /// List all the packages which are installed.
+    /// Install the given packages with a single invocation of `gem install`.
     pub fn install_packages<I>(&self, packages: I) -> Result<(), Error>
     where
         I: IntoIterator,
@@ -43,8 +43,7 @@ impl Gem {
         gem.arg("install");
         gem.arg("--user-install");
         gem.args(packages);
-        gem.run()?;
-        Ok(())
+        gem.run_checked()
     }
 
     /// List all the packages which are installed.
@@ -66,6 +65,7 @@ impl Gem {
 
             out.push(Package {
                 name: name.to_string(),
+                version: None,
             });
         }
 
@@ -87,6 +87,11 @@ impl PackageManager {
 }
 
 impl super::PackageManager for PackageManager {
+    fn exclusive(&self) -> bool {
+        // installing gems concurrently can corrupt the gem directory.
+        true
+    }
+
     fn primary(&self) -> bool {
         true
     }
@@ -104,7 +109,29 @@ impl super::PackageManager for PackageManager {
         self.gem.list_installed()
     }
 
+    fn install_spec(&self, name: &str, version: &str) -> String {
+        format!("{}:{}", name, version)
+    }
+
     fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
         self.gem.install_packages(packages)
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::Gem;
+    use crate::command::{self, OutputError};
+
+    #[test]
+    fn test_install_failure() {
+        let gem = Gem {
+            gem: command::failing("install failed"),
+        };
+
+        let e = gem.install_packages(["rake"]).unwrap_err();
+        let e = e.downcast::<OutputError>().expect("output error");
+        assert!(!e.status.success());
+        assert_eq!(e.stderr, "install failed\n");
+    }
+}
@@ -1,7 +1,7 @@
 //! Packages abstraction for rustup components.
 
 use crate::{packages::Package, rustup};
-use anyhow::Error;
+use anyhow::{bail, Error};
 
 /// Packages abstraction for rustup components.
 #[derive(Debug)]
@@ -19,6 +19,11 @@ impl PackageManager {
 }
 
 impl super::PackageManager for PackageManager {
+    fn exclusive(&self) -> bool {
+        // rustup locks its installation, so concurrent installs conflict.
+        true
+    }
+
     fn primary(&self) -> bool {
         true
     }
@@ -40,6 +45,14 @@ impl super::PackageManager for PackageManager {
         self.rustup.list_installed()
     }
 
+    fn check_version(&self, name: &str, _: &str) -> Result<(), Error> {
+        bail!(
+            "package manager `{}` does not support installing a specific version of `{}`",
+            self.name(),
+            name
+        );
+    }
+
     fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
         self.rustup.install_packages(packages)
     }
@@ -1,7 +1,12 @@
 //! Packages abstraction for WinGet.
 
-use crate::{command, os, packages::Package};
-use anyhow::Error;
+use crate::{
+    command, os,
+    packages::{Package, PackageSpec},
+};
+use anyhow::{bail, Error};
+use std::collections::BTreeMap;
+
 #[derive(Debug)]
 pub struct WinGet {
     winget: command::Command,
@@ -39,26 +44,60 @@ impl WinGet {
         Ok(false)
     }
 
-    /// List all the packages which are installed.
-    pub fn install_packages<I>(&self, packages: I) -> Result<(), Error>
+    /// Install the given packages, each optionally pinned to an exact version.
+    ///
+    /// NB: winget only installs one package per invocation, so each package is installed
+    /// separately. A package which fails to install doesn't prevent the remaining ones from being
+    /// installed, instead all failures are reported once every package has been attempted.
+    pub fn install_packages<'a, I>(&self, packages: I, options: &Options) -> Result<(), Error>
     where
-        I: IntoIterator,
-        I::Item: AsRef<str>,
+        I: IntoIterator<Item = (&'a str, Option<&'a str>)>,
     {
-        for package in packages {
+        let mut failed = Vec::new();
+
+        for (package, version) in packages {
             let mut winget = self.winget.clone();
-            winget.arg("install");
-            winget.arg("-e");
-            winget.arg(package.as_ref());
-            winget.run()?;
+            winget.args(install_args(package, version, options));
+
+            if let Err(e) = winget.run_checked() {
+                log::error!("Failed to install `{}`: {}", package, e);
+                failed.push(package.to_string());
+            }
+        }
+
+        if !failed.is_empty() {
+            bail!("Failed to install package(s): {}", failed.join(", "));
         }
 
         Ok(())
     }
 
     /// List all the packages which are installed.
+    ///
+    /// This uses `winget list`, falling back to enumerating MSI products if that fails.
     #[cfg(windows)]
     pub fn list_installed(&self) -> Result<Vec<Package>, Error> {
+        let mut winget = self.winget.clone();
+        winget.args(&["list", "--source", "winget", "--accept-source-agreements"]);
+
+        let e = match winget.run_stdout() {
+            Ok(output) => match parse_list(&output) {
+                Ok(packages) => return Ok(packages),
+                Err(e) => e,
+            },
+            Err(e) => e,
+        };
+
+        log::warn!(
+            "Failed to list packages using `winget list`, using MSI products: {}",
+            e
+        );
+        self.list_msi_products()
+    }
+
+    /// List all the packages which are installed as MSI products.
+    #[cfg(windows)]
+    fn list_msi_products(&self) -> Result<Vec<Package>, Error> {
         let mut out = Vec::new();
 
         for p in crate::ffi::win::msi::msi_enum_products()? {
@@ -85,7 +124,10 @@ impl WinGet {
                 continue;
             }
 
-            out.push(Package { name })
+            out.push(Package {
+                name,
+                version: None,
+            })
         }
 
         return Ok(out);
@@ -114,6 +156,149 @@ impl WinGet {
     }
 }
 
+/// Parse the output of `winget list`.
+///
+/// The output is a table, where the columns are aligned to the header and separated from the rows
+/// by a line of dashes. Columns are positioned by their display width, since names might contain
+/// wide characters.
+#[cfg(any(windows, test))]
+fn parse_list(output: &str) -> Result<Vec<Package>, Error> {
+    use anyhow::anyhow;
+
+    // NB: progress indicators are drawn before the table by rewinding the line with `\r`.
+    let mut lines = output
+        .lines()
+        .map(|line| line.rsplit('\r').next().unwrap_or(line));
+
+    let mut header = None;
+    let mut last = None;
+
+    for line in &mut lines {
+        let trimmed = line.trim();
+
+        if !trimmed.is_empty() && trimmed.chars().all(|c| c == '-') {
+            header = last;
+            break;
+        }
+
+        last = Some(line);
+    }
+
+    let header = header.ok_or_else(|| anyhow!("missing table header in `winget list` output"))?;
+    let columns = columns(header);
+
+    if columns.len() < 3 {
+        bail!(
+            "unexpected table header in `winget list` output: {}",
+            header.trim()
+        );
+    }
+
+    let mut out = Vec::new();
+
+    for line in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = fields(line, &columns);
+        let id = fields[1].trim();
+        let version = fields[2].trim();
+
+        // NB: truncated ids can't be matched.
+        if id.is_empty() || id.ends_with('…') {
+            continue;
+        }
+
+        out.push(Package {
+            name: id.to_string(),
+            version: match version {
+                "" | "Unknown" => None,
+                version => Some(version.to_string()),
+            },
+        });
+    }
+
+    return Ok(out);
+
+    /// The display offset of every column in the header.
+    fn columns(header: &str) -> Vec<usize> {
+        use unicode_width::UnicodeWidthChar as _;
+
+        let mut columns = Vec::new();
+        let mut offset = 0;
+        let mut space = true;
+
+        for c in header.chars() {
+            if c.is_whitespace() {
+                space = true;
+            } else if space {
+                columns.push(offset);
+                space = false;
+            }
+
+            offset += c.width().unwrap_or(0);
+        }
+
+        columns
+    }
+
+    /// Split a row into one field for each column.
+    fn fields(line: &str, columns: &[usize]) -> Vec<String> {
+        use unicode_width::UnicodeWidthChar as _;
+
+        let mut fields = vec![String::new(); columns.len()];
+        let mut offset = 0;
+
+        for c in line.chars() {
+            let index = columns
+                .iter()
+                .rposition(|&column| column <= offset)
+                .unwrap_or(0);
+
+            fields[index].push(c);
+            offset += c.width().unwrap_or(0);
+        }
+
+        fields
+    }
+}
+
+/// Options used when installing packages.
+#[derive(Debug, Default)]
+pub struct Options<'a> {
+    /// The scope to install packages in, like `user` or `machine`.
+    pub scope: Option<&'a str>,
+    /// The source to install packages from, like `winget` or `msstore`.
+    pub source: Option<&'a str>,
+}
+
+/// Build the arguments used to install a single package.
+fn install_args(package: &str, version: Option<&str>, options: &Options) -> Vec<String> {
+    let mut args = vec![
+        String::from("install"),
+        String::from("-e"),
+        package.to_string(),
+    ];
+
+    if let Some(version) = version {
+        args.push(String::from("--version"));
+        args.push(version.to_string());
+    }
+
+    if let Some(scope) = options.scope {
+        args.push(String::from("--scope"));
+        args.push(scope.to_string());
+    }
+
+    if let Some(source) = options.source {
+        args.push(String::from("--source"));
+        args.push(source.to_string());
+    }
+
+    args
+}
+
 /// Packages abstraction for WinGet.
 #[derive(Debug)]
 pub struct PackageManager {
@@ -147,7 +332,182 @@ impl super::PackageManager for PackageManager {
         self.winget.list_installed()
     }
 
+    fn options(&self) -> &[&str] {
+        &["scope", "source"]
+    }
+
+    fn check_version(&self, name: &str, version: &str) -> Result<(), Error> {
+        if version.contains('*') {
+            bail!(
+                "package manager `winget` can only install an exact version of `{}`, not `{}`",
+                name,
+                version
+            );
+        }
+
+        Ok(())
+    }
+
     fn install_packages(&self, packages: &[String]) -> Result<(), Error> {
-        self.winget.install_packages(packages)
+        let packages = packages.iter().map(|p| (p.as_str(), None));
+        self.winget.install_packages(packages, &Options::default())
+    }
+
+    fn install_specs(
+        &self,
+        specs: &[PackageSpec],
+        options: &BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        let options = Options {
+            scope: options.get("scope").map(String::as_str),
+            source: options.get("source").map(String::as_str),
+        };
+
+        for spec in specs {
+            if spec.has_options() {
+                log::warn!(
+                    "package manager `winget` ignores the options of package `{}`",
+                    spec
+                );
+            }
+        }
+
+        let packages = specs
+            .iter()
+            .map(|spec| (spec.name.as_str(), spec.version.as_deref()));
+
+        self.winget.install_packages(packages, &options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{install_args, parse_list, Options, PackageManager};
+    use crate::packages::PackageManager as _;
+
+    #[test]
+    fn test_parse_list() {
+        let output = "\r   - \r   \\ \rName                           Id                          Version   Available Source
+----------------------------------------------------------------------------------------------
+Git                            Git.Git                     2.42.0.2            winget
+Microsoft Visual Studio Code   Microsoft.VisualStudioCode  1.83.1    1.84.0    winget
+Ünïcödé Tool                   Some.Tool                   1.0                 winget
+日本語のアプリ                 Japanese.App                3.1                 winget
+Truncated Package Name         Truncated.Package.Ide…      1.0                 winget
+Mystery\u{a0}App                    Mystery.App                 Unknown             winget
+";
+
+        let packages = parse_list(output).unwrap();
+        let packages = packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.as_deref()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            packages,
+            vec![
+                ("Git.Git", Some("2.42.0.2")),
+                ("Microsoft.VisualStudioCode", Some("1.83.1")),
+                ("Some.Tool", Some("1.0")),
+                ("Japanese.App", Some("3.1")),
+                ("Mystery.App", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_list_bad_output() {
+        assert!(parse_list("").is_err());
+        assert!(parse_list("No installed package found matching input criteria.").is_err());
+        assert!(parse_list("Name Id\n-------\n").is_err());
+
+        let output = "Name  Id      Version Source\n----------------------------\n";
+        assert!(parse_list(output).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_install_args() {
+        assert_eq!(
+            install_args("Git.Git", None, &Options::default()),
+            vec!["install", "-e", "Git.Git"]
+        );
+
+        assert_eq!(
+            install_args("Git.Git", Some("2.42.0.2"), &Options::default()),
+            vec!["install", "-e", "Git.Git", "--version", "2.42.0.2"]
+        );
+
+        let options = Options {
+            scope: Some("user"),
+            source: Some("winget"),
+        };
+
+        assert_eq!(
+            install_args("Git.Git", None, &options),
+            vec!["install", "-e", "Git.Git", "--scope", "user", "--source", "winget"]
+        );
+    }
+
+    #[test]
+    fn test_check_version() {
+        let manager = PackageManager::new();
+        assert!(manager.check_version("Git.Git", "2.42.0.2").is_ok());
+
+        let e = manager
+            .check_version("Git.Git", "2.*")
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("exact version"), "{}", e);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_install_continues_after_failure() {
+        use super::WinGet;
+        use crate::command::Command;
+        use std::fs;
+
+        let root = tempfile::tempdir().unwrap();
+        let log = root.path().join("log");
+        let script = root.path().join("winget");
+
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho \"$3\" >> {}\n[ \"$3\" != \"Bad.Package\" ]\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+
+        // NB: run through `sh` to avoid executing a file which was just written to.
+        let mut command = Command::new("sh");
+        command.arg(&script);
+        let winget = WinGet { winget: command };
+
+        let e = winget
+            .install_packages(
+                vec![
+                    ("First.Package", None),
+                    ("Bad.Package", None),
+                    ("Last.Package", None),
+                ],
+                &Options::default(),
+            )
+            .unwrap_err()
+            .to_string();
+
+        assert!(e.contains("Bad.Package"), "{}", e);
+        assert!(!e.contains("First.Package"), "{}", e);
+        assert!(!e.contains("Last.Package"), "{}", e);
+
+        assert_eq!(
+            fs::read_to_string(&log).unwrap(),
+            "First.Package\nBad.Package\nLast.Package\n"
+        );
+
+        assert!(winget
+            .install_packages(vec![("First.Package", None)], &Options::default())
+            .is_ok());
     }
 }
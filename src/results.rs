@@ -0,0 +1,137 @@
+//! Machine-readable results of a run, which are written with `--report <path>`.
+
+use anyhow::{anyhow, Context as _, Error};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+
+/// The outcome of a system or a unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    /// Changes were applied.
+    Applied,
+    /// Nothing had to be done, since everything was up-to-date.
+    Skipped,
+    /// Applying failed.
+    Failed,
+    /// Never run, since something it depends on failed.
+    Unscheduled,
+}
+
+/// The results of a run.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Results {
+    /// If the configuration was updated from git during the run.
+    pub updated: bool,
+    /// How long the run took, in seconds.
+    pub duration: f64,
+    /// The error which caused the run to fail, if it failed.
+    pub error: Option<String>,
+    /// The results of every system.
+    pub systems: Vec<SystemResult>,
+}
+
+impl Results {
+    /// Write the results as JSON to the given path.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let f = File::create(path)
+            .with_context(|| anyhow!("Failed to create report: {}", path.display()))?;
+        serde_json::to_writer_pretty(f, self)
+            .with_context(|| anyhow!("Failed to write report: {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// The results of a single system.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SystemResult {
+    /// Description of the system.
+    pub system: String,
+    /// The id of the system, if it has one.
+    pub id: Option<String>,
+    /// The outcome of the system, based on the outcome of its units.
+    pub status: Status,
+    /// The error raised when the system was applied, if any.
+    pub error: Option<String>,
+    /// The results of the units the system created.
+    pub units: Vec<UnitResult>,
+}
+
+impl SystemResult {
+    /// Construct a new system result, without any units.
+    pub fn new(system: String, id: Option<String>, error: Option<String>) -> Self {
+        SystemResult {
+            system,
+            id,
+            status: if error.is_some() {
+                Status::Failed
+            } else {
+                Status::Skipped
+            },
+            error,
+            units: Vec::new(),
+        }
+    }
+
+    /// Add the result of a unit, updating the status of the system.
+    ///
+    /// A failed unit takes precedence over an unscheduled one, which takes precedence over one
+    /// that was applied.
+    pub fn push(&mut self, unit: UnitResult) {
+        self.status = match (self.status, unit.status) {
+            (Status::Failed, _) | (_, Status::Failed) => Status::Failed,
+            (Status::Unscheduled, _) | (_, Status::Unscheduled) => Status::Unscheduled,
+            (Status::Applied, _) | (_, Status::Applied) => Status::Applied,
+            _ => Status::Skipped,
+        };
+
+        self.units.push(unit);
+    }
+}
+
+/// The result of a single unit.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnitResult {
+    /// Description of the unit.
+    pub unit: String,
+    /// The outcome of the unit.
+    pub status: Status,
+    /// How long the unit took to apply, in seconds.
+    pub duration: f64,
+    /// The error raised when the unit was applied, if any.
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Status, SystemResult, UnitResult};
+
+    fn unit(status: Status) -> UnitResult {
+        UnitResult {
+            unit: String::from("unit"),
+            status,
+            duration: 0.0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_system_status() {
+        let mut system = SystemResult::new(String::from("system"), None, None);
+        assert_eq!(system.status, Status::Skipped);
+
+        system.push(unit(Status::Skipped));
+        assert_eq!(system.status, Status::Skipped);
+
+        system.push(unit(Status::Applied));
+        assert_eq!(system.status, Status::Applied);
+
+        system.push(unit(Status::Unscheduled));
+        assert_eq!(system.status, Status::Unscheduled);
+
+        system.push(unit(Status::Failed));
+        system.push(unit(Status::Applied));
+        assert_eq!(system.status, Status::Failed);
+    }
+}
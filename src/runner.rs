@@ -0,0 +1,734 @@
+//! Run quickcfg programmatically.
+//!
+//! This is what the `qc` binary uses to load a configuration, apply all of its systems and run the
+//! resulting units.
+//!
+//! ```no_run
+//! use clap::Parser as _;
+//! use quickcfg::opts::Opts;
+//! use std::path::Path;
+//!
+//! let opts = Opts::parse_from(["qc", "--non-interactive"]);
+//! let report = quickcfg::run(&opts, Path::new("dotfiles"))?;
+//!
+//! if let Some(summary) = report.summary {
+//!     println!("Summary: {}", summary);
+//! }
+//! # Ok::<_, anyhow::Error>(())
+//! ```
+
+use crate::{
+    command, cycles, environment as e,
+    facts::Facts,
+    git, hierarchy,
+    opts::Opts,
+    packages,
+    results::{Results, Status, SystemResult, UnitResult},
+    scheduler::Scheduler,
+    system::{self, SystemInput},
+    unit::{self, Unit, UnitAllocator, UnitInput},
+    Config, DiskState, FileSystem, Load as _, Save as _, State, Summary, Timestamp,
+};
+use anyhow::{anyhow, bail, Context as _, Error};
+use directories::BaseDirs;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// The outcome of a successful run.
+#[derive(Default)]
+pub struct RunReport {
+    /// If the configuration was updated from git during the run.
+    pub updated: bool,
+    /// The number of units which were run.
+    pub units: usize,
+    /// Summary of the actions taken, or `None` if no units were run. Like when only listing
+    /// systems.
+    pub summary: Option<Summary>,
+}
+
+/// Report the given error, and everything which caused it, to stderr.
+pub fn report_error(e: Error) {
+    let mut it = e.chain();
+
+    if let Some(e) = it.next() {
+        eprintln!("Error: {}", e);
+
+        #[cfg(feature = "nightly")]
+        {
+            if let Some(bt) = e.backtrace() {
+                eprintln!("{}", bt);
+            }
+        }
+    }
+
+    for e in it {
+        eprintln!("Caused by: {}", e);
+
+        #[cfg(feature = "nightly")]
+        {
+            if let Some(bt) = e.backtrace() {
+                eprintln!("{}", bt);
+            }
+        }
+    }
+}
+
+/// Load all facts, including the ones in the given facts file and the ones specified in `opts`.
+pub fn load_facts(opts: &Opts, facts_path: &Path) -> Result<Facts, Error> {
+    // NB: precedence is detected facts, then facts from file, then facts from the command line.
+    let mut facts = Facts::load().with_context(|| "Failed to load facts")?;
+    facts.extend(Facts::load_from(facts_path)?);
+    facts.extend(opts.facts.iter().cloned());
+    Ok(facts)
+}
+
+/// Apply the configuration in the given `root` directory.
+///
+/// This loads the configuration and state, updates the configuration from git when needed,
+/// applies all systems and runs the resulting units. The state is saved once done, even if the
+/// run failed.
+pub fn run(opts: &Opts, root: &Path) -> Result<RunReport, Error> {
+    let base_dirs = BaseDirs::new();
+    let git_system = git::setup().with_context(|| "failed to set up git system")?;
+
+    let config_path = opts.config_path(root)?;
+    let facts_path = root.join("facts.yml");
+    let state_path = opts.state_path(root);
+    let state_dir = opts.state_dir(root);
+
+    if !root.is_dir() {
+        bail!("Missing configuration directory: {}", root.display());
+    }
+
+    if !state_dir.is_dir() && !opts.inspect_only() && !opts.dry_run {
+        fs::create_dir_all(&state_dir).with_context(|| {
+            anyhow!("Failed to create state directory: {}", state_dir.display())
+        })?;
+    }
+
+    let config = Config::load_with_includes(&config_path, root, base_dirs.as_ref(), e::Real)
+        .with_context(|| anyhow!("Failed to load configuration: {}", config_path.display()))?
+        .unwrap_or_default();
+
+    for warning in config.warnings() {
+        log::warn!("{}: {}", config_path.display(), warning);
+    }
+
+    let now = Timestamp::now();
+
+    let state = match DiskState::load(&state_path) {
+        Ok(state) => state.unwrap_or_default(),
+        Err(err) => {
+            log::error!("Invalid disk state `{}`: {}", state_path.display(), err);
+
+            if !opts.prompt("Remove it?", true)? {
+                return Ok(RunReport::default());
+            }
+
+            DiskState::default()
+        }
+    };
+
+    let mut state = state.into_state(&config, now);
+    let mut results = Results::default();
+    let start = Instant::now();
+
+    let result = try_apply_config(
+        &*git_system,
+        opts,
+        &config,
+        now,
+        base_dirs.as_ref(),
+        root,
+        &facts_path,
+        &state_dir,
+        &mut state,
+        &mut results,
+    );
+
+    // NB: the state is saved before the report is written, so that a report which fails to be
+    // written doesn't lose it.
+    if let Some(serialized) = state.serialize() {
+        log::trace!("Writing state: {}", state_path.display());
+
+        if let Some(parent) = state_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).with_context(|| {
+                anyhow!(
+                    "Failed to create state file directory: {}",
+                    parent.display()
+                )
+            })?;
+        }
+
+        serialized.save(&state_path)?;
+    }
+
+    // NB: the report is written even if the run failed, since it describes what failed.
+    if let Some(path) = opts.report.as_deref() {
+        results.duration = start.elapsed().as_secs_f64();
+
+        if let Err(e) = &result {
+            results.error = Some(format!("{:#}", e));
+        }
+
+        results.write(path)?;
+    }
+
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+/// Internal method to try to apply the given configuration.
+fn try_apply_config<'c>(
+    git_system: &dyn git::GitSystem,
+    opts: &Opts,
+    config: &'c Config,
+    now: Timestamp,
+    base_dirs: Option<&BaseDirs>,
+    root: &Path,
+    facts_path: &Path,
+    state_dir: &Path,
+    state: &mut State<'c>,
+    results: &mut Results,
+) -> Result<RunReport, Error> {
+    use rayon::prelude::*;
+
+    let pool = opts.thread_pool()?;
+
+    // NB: listing or explaining systems should not modify anything, so don't update the
+    // configuration.
+    let updated =
+        !opts.inspect_only() && try_update_config(git_system, opts, config, now, root, state)?;
+    results.updated = updated;
+
+    let mut report = RunReport {
+        updated,
+        ..RunReport::default()
+    };
+
+    // if we only want to run on updates, exit now.
+    if !updated && opts.updates_only {
+        return Ok(report);
+    }
+
+    if opts.updates_only {
+        log::info!("Updated found, running...");
+    }
+
+    let facts = load_facts(opts, facts_path)?;
+    let environment = e::Real;
+    let data = hierarchy::load(&config.hierarchy, root, &facts, environment)
+        .with_context(|| "Failed to load hierarchy")?;
+
+    // translate systems that needs translation.
+    let systems = system::expand(&config.systems, &facts);
+
+    let systems = if opts.tags.is_empty() {
+        systems
+    } else {
+        system::select(systems, &opts.tags)
+    };
+
+    if opts.list_systems {
+        let stdout = std::io::stdout();
+        system::list(&mut stdout.lock(), &systems, &data, &facts, environment)?;
+        return Ok(report);
+    }
+
+    let packages = packages::detect(&facts)?;
+
+    let allocator = UnitAllocator::default();
+
+    let file_system = FileSystem::new(opts, state_dir, &allocator, &data);
+
+    // post-hook for all systems, mapped by id.
+    let mut post_systems = HashMap::new();
+    let mut all_units = Vec::new();
+    let mut pre_systems = Vec::new();
+    let mut errors = Vec::new();
+    // The index of the system in the results that each unit belongs to.
+    let mut unit_systems = HashMap::new();
+    // The index of the system in the results that each unit wrapping a system belongs to. These
+    // units don't have results of their own.
+    let mut system_units = HashMap::new();
+
+    let input = SystemInput {
+        root,
+        base_dirs,
+        facts: &facts,
+        data: &data,
+        packages: &packages,
+        environment,
+        allocator: &allocator,
+        file_system: &file_system,
+        state,
+        now,
+        opts,
+        git_system,
+        system: "",
+        force: false,
+    };
+
+    if let Some(id) = opts.explain.as_deref() {
+        let system = systems
+            .iter()
+            .find(|system| system.id() == Some(id))
+            .ok_or_else(|| anyhow!("No system with id `{}`", id))?;
+
+        let units = system.apply(input)?;
+        let stdout = std::io::stdout();
+        system::explain(&mut stdout.lock(), &units)?;
+        return Ok(report);
+    }
+
+    pool.install(|| {
+        let res = systems.par_iter().map(|system| {
+            let res = system.apply(input);
+
+            match res {
+                Ok(units) => Ok((system, units)),
+                Err(e) => Err((system, e)),
+            }
+        });
+
+        // Collect all units and map out a unit id to each system that can be used as a dependency.
+        for res in res.collect::<Vec<_>>() {
+            let (system, mut units) = match res {
+                Ok(result) => result,
+                Err((system, e)) => {
+                    results.systems.push(SystemResult::new(
+                        system.to_string(),
+                        system.id().map(String::from),
+                        Some(format!("{:#}", e)),
+                    ));
+                    errors.push((system, e));
+                    continue;
+                }
+            };
+
+            let index = results.systems.len();
+            results.systems.push(SystemResult::new(
+                system.to_string(),
+                system.id().map(String::from),
+                None,
+            ));
+            unit_systems.extend(units.iter().map(|unit| (unit.id, index)));
+
+            if !system.requires().is_empty() {
+                // Unit that all contained units depend on.
+                // This unit finishes _before_ any unit in the system.
+                let pre = allocator.unit(Unit::System);
+                system_units.insert(pre.id, index);
+
+                for unit in &mut units {
+                    unit.dependencies.push(unit::Dependency::Unit(pre.id));
+                }
+
+                pre_systems.push((
+                    pre,
+                    system,
+                    system::Dependency::Transitive(system.requires()),
+                ));
+            }
+
+            if let Some(system_id) = system.id() {
+                if units.is_empty() {
+                    // If system is empty, there is nothing to depend on.
+                    post_systems
+                        .insert(system_id, system::Dependency::Transitive(system.requires()));
+                    continue;
+                }
+
+                // Unit that other systems depend on.
+                // This unit finishes _after_ all units in the system have finished.
+                // System units depend on all units it contains.
+                let mut post = allocator.unit(Unit::System);
+                system_units.insert(post.id, index);
+                post.dependencies
+                    .extend(units.iter().map(|u| unit::Dependency::Unit(u.id)));
+                post_systems.insert(system_id, system::Dependency::Direct(post.id));
+                all_units.push(post);
+            }
+
+            all_units.extend(units);
+        }
+    });
+
+    file_system.validate()?;
+
+    if !errors.is_empty() {
+        for (system, e) in errors.into_iter() {
+            log::error!("System failed: {}", system);
+            report_error(e);
+        }
+
+        bail!("Failed to run all systems");
+    }
+
+    let declared = system::declared_ids(&config.systems);
+
+    // Wire up systems that have requires.
+    for (mut pre, system, depend) in pre_systems {
+        let name = system
+            .id()
+            .map(String::from)
+            .unwrap_or_else(|| system.to_string());
+        pre.dependencies
+            .extend(depend.resolve(&name, &post_systems, &declared)?);
+        all_units.push(pre);
+    }
+
+    cycles::check_cycles(&all_units, |id| {
+        let index = unit_systems.get(&id).or_else(|| system_units.get(&id))?;
+        let system = &results.systems[*index];
+        Some(system.id.as_deref().unwrap_or(&system.system))
+    })?;
+
+    // NB: a single client is shared by all downloads, so that connections can be reused.
+    let http = opts.http_client()?;
+
+    // Schedule all units to run as soon as their dependencies have been provided.
+    report.units = all_units.len();
+    let progress = opts.progress_bar(all_units.len() as u64);
+    let mut scheduler = Scheduler::new(all_units);
+
+    let summary = Summary::default();
+    let mut errors = Vec::new();
+    // NB: the state of each unit is merged as soon as it completes, so that units which run after
+    // it can see it.
+    let shared = RwLock::new(std::mem::replace(state, State::new(config, now)));
+    let read_state = &shared;
+
+    scheduler.run(
+        &pool,
+        |unit| {
+            let mut s = State::new(config, now);
+            let unit_summary = Summary::default();
+            let start = Instant::now();
+
+            let mut apply = || {
+                unit.apply(UnitInput {
+                    data: &data,
+                    packages: &packages,
+                    read_state,
+                    state: &mut s,
+                    now,
+                    git_system,
+                    http: &http,
+                    summary: &unit_summary,
+                    dry_run: opts.dry_run,
+                    diff: opts.diff,
+                })
+            };
+
+            // NB: thread-local units might interact with the terminal, so hide the progress bar
+            // while they run.
+            let res = if unit.thread_local {
+                progress.suspend(apply)
+            } else {
+                apply()
+            };
+
+            let elapsed = start.elapsed();
+            progress.inc(1);
+            log_timing(unit, elapsed, opts.slow_threshold);
+            (res, s, unit_summary, elapsed)
+        },
+        |unit, (res, s, unit_summary, elapsed)| {
+            summary.extend(&unit_summary);
+            record(results, &unit_systems, unit, &res, &unit_summary, elapsed);
+            read_state
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(s);
+
+            match res {
+                Ok(()) => true,
+                Err(e) => {
+                    errors.push((unit.to_string(), e));
+                    false
+                }
+            }
+        },
+    );
+
+    *state = shared.into_inner().unwrap_or_else(|e| e.into_inner());
+
+    progress.finish_and_clear();
+
+    let unscheduled = scheduler.into_unscheduled();
+
+    for unit in &unscheduled {
+        if let Some(&index) = unit_systems.get(&unit.id) {
+            results.systems[index].push(UnitResult {
+                unit: unit.unit().to_string(),
+                status: Status::Unscheduled,
+                duration: 0.0,
+                error: None,
+            });
+        }
+    }
+
+    if !errors.is_empty() {
+        log::warn!("Summary: {}", summary);
+
+        for (i, (unit, e)) in errors.into_iter().enumerate() {
+            log::error!("{:2}: {}", i, unit);
+            report_error(e);
+        }
+
+        bail!("Failed to run all units");
+    }
+
+    if !unscheduled.is_empty() {
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("Unable to schedule the following units:");
+
+            for (i, unit) in unscheduled.into_iter().enumerate() {
+                log::trace!("{:2}: {}", i, unit);
+            }
+        }
+
+        bail!("Could not schedule all units");
+    }
+
+    run_hooks(opts, root, &config.post_hooks)?;
+
+    if summary.changed() {
+        run_hooks(opts, root, &config.on_change)?;
+    }
+
+    // NB: only prune after a full run, since everything which wasn't observed is removed. Systems
+    // filtered out by tags are never observed.
+    if opts.prune_state && !opts.dry_run && opts.tags.is_empty() {
+        state.prune();
+    }
+
+    report.summary = Some(summary);
+    Ok(report)
+}
+
+/// Log how long the given unit took to apply, warning if it took longer than `threshold`.
+///
+/// Returns `true` if the unit was slow.
+fn log_timing(unit: &unit::SystemUnit, elapsed: Duration, threshold: Option<Duration>) -> bool {
+    log::trace!("{} took {:?}", unit, elapsed);
+
+    match threshold {
+        Some(threshold) if elapsed > threshold => {
+            log::warn!(
+                "slow unit, took {} (threshold: {}): {}",
+                humantime::format_duration(round(elapsed)),
+                humantime::format_duration(threshold),
+                unit.unit()
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Round the given duration to milliseconds, for display.
+fn round(duration: Duration) -> Duration {
+    Duration::from_millis(duration.as_millis() as u64)
+}
+
+/// Record the outcome of applying a unit in the results of the system it belongs to.
+fn record(
+    results: &mut Results,
+    unit_systems: &HashMap<unit::UnitId, usize>,
+    unit: &unit::SystemUnit,
+    res: &Result<(), Error>,
+    summary: &Summary,
+    elapsed: Duration,
+) {
+    let index = match unit_systems.get(&unit.id) {
+        Some(index) => *index,
+        None => return,
+    };
+
+    let (status, error) = match res {
+        Ok(()) if summary.is_up_to_date() => (Status::Skipped, None),
+        Ok(()) => (Status::Applied, None),
+        Err(e) => (Status::Failed, Some(format!("{:#}", e))),
+    };
+
+    results.systems[index].push(UnitResult {
+        unit: unit.unit().to_string(),
+        status,
+        duration: elapsed.as_secs_f64(),
+        error,
+    });
+}
+
+/// Run the given hooks through the shell, in the configuration root.
+///
+/// Hooks are only run after all units have been applied successfully.
+fn run_hooks(opts: &Opts, root: &Path, hooks: &[String]) -> Result<(), Error> {
+    for hook in hooks {
+        if opts.dry_run {
+            log::info!("would run hook: {}", hook);
+            continue;
+        }
+
+        log::info!("running hook: {}", hook);
+
+        let mut command = command::Command::shell(hook);
+        command.working_directory(root);
+        command
+            .run_inherited()
+            .with_context(|| anyhow!("Failed to run hook: {}", hook))?;
+    }
+
+    Ok(())
+}
+
+/// Try to update config from git.
+///
+/// Returns `true` if we have successfully downloaded a new update. `false` otherwise.
+fn try_update_config(
+    git_system: &dyn git::GitSystem,
+    opts: &Opts,
+    config: &Config,
+    now: Timestamp,
+    root: &Path,
+    state: &mut State,
+) -> Result<bool, Error> {
+    if opts.no_git {
+        log::trace!("Not checking `{}` for updates", root.display());
+        return Ok(false);
+    }
+
+    if let Some(last_update) = state.last_update("git") {
+        let duration = now.duration_since(*last_update)?;
+
+        if duration < opts.refresh.unwrap_or(config.git_refresh) {
+            return Ok(false);
+        }
+
+        log::info!("{}s since last git update...", duration.as_secs());
+    };
+
+    if !opts.prompt("Do you want to check for updates?", true)? {
+        return Ok(false);
+    }
+
+    if !git_system.test()? {
+        log::warn!("no working git command found");
+        state.touch("git");
+        return Ok(false);
+    }
+
+    let git = git_system.open(root, &git::Options::default())?;
+
+    if opts.dry_run {
+        if git.check_update_available()? {
+            log::info!("would update configuration in `{}`", root.display());
+        }
+
+        return Ok(false);
+    }
+
+    if !git.needs_update()? {
+        state.touch("git");
+        return Ok(false);
+    }
+
+    // NB: don't mark as updated, so that the warning is repeated on the next run.
+    if !git::update(&*git, opts.force || opts.force_dirty, opts.force_dirty)? {
+        return Ok(false);
+    }
+
+    state.touch("git");
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{log_timing, try_update_config};
+    use crate::{git, opts::Opts, Config, State, Timestamp};
+    use anyhow::{bail, Error};
+    use clap::Parser as _;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    /// A git system which isn't usable, but records that it was checked.
+    #[derive(Default)]
+    struct Unusable {
+        tested: AtomicBool,
+    }
+
+    impl git::GitSystem for Unusable {
+        fn test(&self) -> Result<bool, Error> {
+            self.tested.store(true, Ordering::SeqCst);
+            Ok(false)
+        }
+
+        fn clone(&self, _: &str, _: &Path, _: &git::Options) -> Result<Box<dyn git::Git>, Error> {
+            bail!("unusable")
+        }
+
+        fn open(&self, _: &Path, _: &git::Options) -> Result<Box<dyn git::Git>, Error> {
+            bail!("unusable")
+        }
+    }
+
+    /// Test if an update check is performed with the given arguments right after the last one.
+    fn checks_for_update(args: &[&str]) -> bool {
+        let root = tempfile::tempdir().unwrap();
+        let opts = Opts::parse_from(["qc", "--non-interactive"].iter().chain(args));
+        let config = Config {
+            git_refresh: Duration::from_secs(24 * 3600),
+            ..Config::default()
+        };
+        let mut state = State::new(&config, Timestamp::now());
+        state.touch("git");
+
+        let git_system = Unusable::default();
+        let updated = try_update_config(
+            &git_system,
+            &opts,
+            &config,
+            Timestamp::now(),
+            root.path(),
+            &mut state,
+        )
+        .unwrap();
+
+        assert!(!updated);
+        git_system.tested.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_refresh() {
+        assert!(!checks_for_update(&[]));
+        assert!(!checks_for_update(&["--refresh", "1h"]));
+        assert!(checks_for_update(&["--refresh", "0s"]));
+    }
+
+    #[test]
+    fn test_no_git() {
+        assert!(!checks_for_update(&["--no-git", "--refresh", "0s"]));
+    }
+
+    #[test]
+    fn test_slow_unit() {
+        use crate::unit::{SystemUnit, Unit};
+        use std::time::Instant;
+
+        let unit = SystemUnit::new(0, Unit::System);
+
+        // A deliberately slow unit.
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(50));
+        let elapsed = start.elapsed();
+
+        assert!(log_timing(&unit, elapsed, Some(Duration::from_millis(10))));
+        assert!(!log_timing(&unit, elapsed, Some(Duration::from_secs(10))));
+        assert!(!log_timing(&unit, elapsed, None));
+    }
+}
@@ -49,51 +49,150 @@ impl Rustup {
         rustup.arg(self.sub_command);
         rustup.arg(self.install);
         rustup.args(packages);
-        rustup.run()?;
-        Ok(())
+        rustup.run_checked()
     }
 
     /// List all the packages which are installed.
     pub fn list_installed(&self) -> Result<Vec<Package>, Error> {
         use std::env::consts;
 
-        let mut out = Vec::new();
-
         let mut rustup = self.rustup.clone();
         rustup.arg(self.sub_command);
         rustup.arg("list");
 
-        for line in rustup.run_lines()? {
-            if line.starts_with(char::is_whitespace) {
-                continue;
-            }
-
-            let line = line.trim();
+        // NB: `rustup component list` lists every available component and marks the installed
+        // ones, while `rustup toolchain list` only lists installed toolchains.
+        let marked = self.sub_command == "component";
+        parse_list(&rustup.run_lines()?, consts::ARCH, marked)
+    }
+}
 
-            if line.is_empty() {
-                continue;
-            }
+/// Parse the output of `rustup <sub-command> list`.
+///
+/// Lines look like `rust-std-x86_64-unknown-linux-gnu (installed)`, where the target suffix is
+/// stripped from the name if it matches `arch`. Components which are not specific to a target,
+/// like `rust-src`, are kept as they are.
+///
+/// If `marked` is set, only lines marked as `installed` or `default` are considered installed.
+fn parse_list(lines: &[String], arch: &str, marked: bool) -> Result<Vec<Package>, Error> {
+    let mut out = Vec::new();
+
+    for line in lines {
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
 
-            let mut it = line.split(' ');
+        let line = line.trim();
 
-            let name = it.next().ok_or_else(|| anyhow!("expected package name"))?;
+        if line.is_empty() || line == "no installed toolchains" {
+            continue;
+        }
 
-            match it.next() {
-                Some("(default)") => {}
-                Some("(installed)") => {}
-                _ => continue,
-            }
+        let (name, status) = match line.split_once(' ') {
+            Some((name, status)) => (name, Some(status.trim())),
+            None => (line, None),
+        };
 
-            let name = match name.find(consts::ARCH) {
-                Some(index) => name[..index].trim_matches('-'),
-                None => continue,
-            };
+        if name.is_empty() {
+            return Err(anyhow!("expected package name"));
+        }
 
-            out.push(Package {
-                name: name.to_string(),
-            });
+        // NB: markers look like `(installed)`, `(default)`, or `(active, default)`.
+        let installed = match status {
+            Some(status) => status
+                .trim_start_matches('(')
+                .trim_end_matches(')')
+                .split(',')
+                .any(|s| matches!(s.trim(), "installed" | "default" | "active")),
+            None => !marked,
+        };
+
+        if !installed {
+            continue;
         }
 
-        Ok(out)
+        let name = match name.find(&format!("-{}", arch)) {
+            Some(index) => &name[..index],
+            None => name,
+        };
+
+        out.push(Package {
+            name: name.to_string(),
+            version: None,
+        });
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_list;
+
+    fn lines(output: &str) -> Vec<String> {
+        output.split('\n').map(String::from).collect()
+    }
+
+    fn names(packages: Vec<crate::packages::Package>) -> Vec<String> {
+        packages.into_iter().map(|p| p.name).collect()
+    }
+
+    #[test]
+    fn test_parse_components() {
+        let output = lines(
+            "cargo-x86_64-unknown-linux-gnu (installed)
+clippy-x86_64-unknown-linux-gnu
+rust-src (installed)
+rust-std-aarch64-unknown-linux-gnu
+rust-std-x86_64-unknown-linux-gnu (installed)
+rustfmt (installed)
+rustfmt-x86_64-unknown-linux-gnu (default)
+",
+        );
+
+        assert_eq!(
+            names(parse_list(&output, "x86_64", true).unwrap()),
+            vec!["cargo", "rust-src", "rust-std", "rustfmt", "rustfmt"]
+        );
+    }
+
+    #[test]
+    fn test_parse_toolchains() {
+        let output = lines(
+            "stable-x86_64-unknown-linux-gnu (active, default)
+nightly-2023-01-01-x86_64-unknown-linux-gnu
+1.70.0-aarch64-unknown-linux-gnu
+",
+        );
+
+        assert_eq!(
+            names(parse_list(&output, "x86_64", false).unwrap()),
+            vec![
+                "stable",
+                "nightly-2023-01-01",
+                "1.70.0-aarch64-unknown-linux-gnu"
+            ]
+        );
+
+        let output = lines("no installed toolchains\n");
+        assert!(parse_list(&output, "x86_64", false).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_install_failure() {
+        use super::Rustup;
+        use crate::command::{self, OutputError};
+
+        let rustup = Rustup {
+            rustup: command::failing("install failed"),
+            sub_command: "toolchain",
+            install: "install",
+        };
+
+        let e = rustup.install_packages(["nightly"]).unwrap_err();
+        let e = e.downcast::<OutputError>().expect("output error");
+        assert!(!e.status.success());
+        assert_eq!(e.stderr, "install failed\n");
     }
 }
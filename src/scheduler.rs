@@ -0,0 +1,361 @@
+//! Scheduler which runs units as soon as all of their dependencies have been provided.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+
+use crate::unit::{Dependency, SystemUnit};
+
+/// Scheduler that feeds units to a thread pool as their dependencies complete.
+pub struct Scheduler {
+    /// Units which are waiting for dependencies, by index.
+    waiting: Vec<Option<SystemUnit>>,
+    /// The number of dependencies that each waiting unit is still waiting for.
+    remaining: Vec<usize>,
+    /// The indexes of waiting units, by the dependency that they are waiting for.
+    dependents: HashMap<Dependency, Vec<usize>>,
+    /// Dependencies which have been provided.
+    provided: HashSet<Dependency>,
+    /// Units which can be run in parallel.
+    ready: VecDeque<SystemUnit>,
+    /// Units which are ready, but need to run on the thread running the scheduler.
+    thread_locals: VecDeque<SystemUnit>,
+}
+
+impl Scheduler {
+    /// Construct a new scheduler out of an iterator of units.
+    pub fn new(units: impl IntoIterator<Item = SystemUnit>) -> Self {
+        let mut scheduler = Scheduler {
+            waiting: Vec::new(),
+            remaining: Vec::new(),
+            dependents: HashMap::new(),
+            provided: HashSet::new(),
+            ready: VecDeque::new(),
+            thread_locals: VecDeque::new(),
+        };
+
+        for unit in units {
+            let dependencies = unit.dependencies.iter().copied().collect::<HashSet<_>>();
+
+            if dependencies.is_empty() {
+                scheduler.push_ready(unit);
+                continue;
+            }
+
+            let index = scheduler.waiting.len();
+
+            for dependency in &dependencies {
+                scheduler
+                    .dependents
+                    .entry(*dependency)
+                    .or_default()
+                    .push(index);
+            }
+
+            scheduler.waiting.push(Some(unit));
+            scheduler.remaining.push(dependencies.len());
+        }
+
+        scheduler
+    }
+
+    /// Run all units that can be scheduled in the given pool.
+    ///
+    /// Units are spawned in the pool the moment all of their dependencies have been provided.
+    /// Thread-local units are run one at a time on the calling thread. Once one is ready, no more
+    /// units are spawned in the pool until the running ones have completed and all ready
+    /// thread-local units have been run.
+    ///
+    /// `complete` is called on the calling thread with the outcome of every unit as it
+    /// completes, and returns `true` if the unit succeeded. Only units which succeeded provide
+    /// their dependencies.
+    pub fn run<O, A, C>(&mut self, pool: &rayon::ThreadPool, apply: A, mut complete: C)
+    where
+        O: Send,
+        A: Fn(&SystemUnit) -> O + Sync,
+        C: FnMut(&SystemUnit, O) -> bool,
+    {
+        let (tx, rx) = mpsc::channel();
+        let apply = &apply;
+
+        pool.in_place_scope(|scope| {
+            // The number of units which are running in the pool.
+            let mut running = 0;
+
+            loop {
+                // NB: units are only spawned in the pool while no thread-local units are waiting,
+                // so that thread-local units never run while a unit in the pool is running.
+                if self.thread_locals.is_empty() {
+                    while let Some(unit) = self.ready.pop_front() {
+                        log::trace!("Spawn: {}", unit);
+                        let tx = tx.clone();
+                        running += 1;
+
+                        scope.spawn(move |_| {
+                            // NB: catch panics so that the scheduler isn't left waiting forever.
+                            let outcome = panic::catch_unwind(AssertUnwindSafe(|| apply(&unit)));
+                            let _ = tx.send((unit, outcome));
+                        });
+                    }
+                }
+
+                if running > 0 {
+                    let (unit, outcome) = rx.recv().expect("scheduler channel closed");
+                    running -= 1;
+
+                    let outcome = match outcome {
+                        Ok(outcome) => outcome,
+                        Err(payload) => panic::resume_unwind(payload),
+                    };
+
+                    if complete(&unit, outcome) {
+                        self.mark(unit);
+                    }
+
+                    continue;
+                }
+
+                let unit = match self.thread_locals.pop_front() {
+                    Some(unit) => unit,
+                    None => break,
+                };
+
+                log::trace!("Run thread-local: {}", unit);
+                let outcome = apply(&unit);
+
+                if complete(&unit, outcome) {
+                    self.mark(unit);
+                }
+            }
+        });
+    }
+
+    /// Mark the specified unit as successfully processed, making any units that were waiting for
+    /// it ready to run.
+    pub fn mark(&mut self, unit: SystemUnit) {
+        log::trace!("Mark: {}", unit);
+
+        let provides = unit
+            .provides
+            .into_iter()
+            .chain(std::iter::once(Dependency::Unit(unit.id)));
+
+        for dependency in provides {
+            if !self.provided.insert(dependency) {
+                continue;
+            }
+
+            for index in self.dependents.remove(&dependency).unwrap_or_default() {
+                self.remaining[index] -= 1;
+
+                if self.remaining[index] > 0 {
+                    continue;
+                }
+
+                if let Some(unit) = self.waiting[index].take() {
+                    self.push_ready(unit);
+                }
+            }
+        }
+    }
+
+    /// Convert into units which could not be scheduled.
+    pub fn into_unscheduled(self) -> Vec<SystemUnit> {
+        self.waiting.into_iter().flatten().collect()
+    }
+
+    /// Push a unit which is ready to run.
+    fn push_ready(&mut self, unit: SystemUnit) {
+        if unit.thread_local {
+            self.thread_locals.push_back(unit);
+        } else {
+            self.ready.push_back(unit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scheduler;
+    use crate::unit::{test_unit as unit, Dependency, SystemUnit, UnitId};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn pool(threads: usize) -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+    }
+
+    /// Run the given units to completion, failing the units in `failed`.
+    ///
+    /// Returns the ids of the units in the order they completed, and the ids of the units which
+    /// could not be scheduled.
+    fn run(units: Vec<SystemUnit>, failed: &[UnitId]) -> (Vec<UnitId>, Vec<UnitId>) {
+        let mut scheduler = Scheduler::new(units);
+        let thread = std::thread::current().id();
+        let mut completed = Vec::new();
+
+        scheduler.run(
+            &pool(2),
+            |unit| {
+                if unit.thread_local {
+                    assert_eq!(std::thread::current().id(), thread);
+                }
+
+                !failed.contains(&unit.id)
+            },
+            |unit, ok| {
+                completed.push(unit.id);
+                ok
+            },
+        );
+
+        let unscheduled = scheduler
+            .into_unscheduled()
+            .into_iter()
+            .map(|u| u.id)
+            .collect();
+
+        (completed, unscheduled)
+    }
+
+    #[test]
+    fn test_diamond() {
+        let (completed, unscheduled) = run(
+            vec![
+                unit(3, false, vec![Dependency::Unit(1), Dependency::Unit(2)]),
+                unit(1, false, vec![Dependency::Unit(0)]),
+                unit(2, false, vec![Dependency::Unit(0)]),
+                unit(0, false, vec![]),
+            ],
+            &[],
+        );
+
+        assert_eq!(completed.len(), 4);
+        assert_eq!(completed[0], 0);
+        assert_eq!(completed[3], 3);
+        assert!(unscheduled.is_empty());
+    }
+
+    #[test]
+    fn test_diamond_failed() {
+        let (completed, unscheduled) = run(
+            vec![
+                unit(0, false, vec![]),
+                unit(1, false, vec![Dependency::Unit(0)]),
+                unit(2, false, vec![Dependency::Unit(0)]),
+                unit(3, false, vec![Dependency::Unit(1), Dependency::Unit(2)]),
+            ],
+            &[1],
+        );
+
+        assert_eq!(completed.len(), 3);
+        assert!(!completed.contains(&3));
+        assert_eq!(unscheduled, vec![3]);
+    }
+
+    #[test]
+    fn test_provides() {
+        let mut provider = unit(1, false, vec![]);
+        provider.provides.push(Dependency::File(1));
+
+        let (completed, unscheduled) = run(
+            vec![
+                unit(0, false, vec![Dependency::File(1), Dependency::File(1)]),
+                provider,
+                unit(2, false, vec![Dependency::File(2)]),
+            ],
+            &[],
+        );
+
+        assert_eq!(completed, vec![1, 0]);
+        assert_eq!(unscheduled, vec![2]);
+    }
+
+    #[test]
+    fn test_thread_local_dependencies() {
+        let (completed, unscheduled) = run(
+            vec![
+                unit(0, true, vec![Dependency::Unit(1)]),
+                unit(1, true, vec![]),
+                unit(2, false, vec![]),
+            ],
+            &[],
+        );
+
+        let position = |id| completed.iter().position(|c| *c == id).unwrap();
+        assert_eq!(completed.len(), 3);
+        assert!(position(1) < position(0));
+        assert!(unscheduled.is_empty());
+    }
+
+    #[test]
+    fn test_thread_local_after_parallel() {
+        // The thread-local unit 1 is ready before the parallel unit 2 has run, while unit 3
+        // depends on both of them.
+        let (completed, unscheduled) = run(
+            vec![
+                unit(0, false, vec![]),
+                unit(1, true, vec![]),
+                unit(2, false, vec![Dependency::Unit(0)]),
+                unit(3, true, vec![Dependency::Unit(1), Dependency::Unit(2)]),
+            ],
+            &[],
+        );
+
+        let position = |id| completed.iter().position(|c| *c == id).unwrap();
+        assert_eq!(completed.len(), 4);
+        assert!(position(0) < position(2));
+        assert_eq!(completed[3], 3);
+        assert!(unscheduled.is_empty());
+    }
+
+    #[test]
+    fn test_thread_local_exclusive() {
+        // The thread-local unit 2 becomes ready while the slow unit 0 is still running in the
+        // pool, and unit 3 would be spawned in the pool as soon as unit 1 has completed.
+        let units = vec![
+            unit(0, false, vec![]),
+            unit(1, false, vec![]),
+            unit(2, true, vec![Dependency::Unit(1)]),
+            unit(3, false, vec![Dependency::Unit(1)]),
+            unit(4, true, vec![Dependency::Unit(3)]),
+        ];
+
+        let mut scheduler = Scheduler::new(units);
+        let running = AtomicUsize::new(0);
+        let overlapped = AtomicBool::new(false);
+        let mut completed = Vec::new();
+
+        scheduler.run(
+            &pool(2),
+            |unit| {
+                if unit.thread_local {
+                    if running.load(Ordering::SeqCst) > 0 {
+                        overlapped.store(true, Ordering::SeqCst);
+                    }
+
+                    return;
+                }
+
+                running.fetch_add(1, Ordering::SeqCst);
+
+                if unit.id == 0 {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+
+                running.fetch_sub(1, Ordering::SeqCst);
+            },
+            |unit, ()| {
+                completed.push(unit.id);
+                true
+            },
+        );
+
+        assert!(!overlapped.load(Ordering::SeqCst));
+        assert_eq!(completed.len(), 5);
+        assert!(scheduler.into_unscheduled().is_empty());
+    }
+}
@@ -2,11 +2,22 @@
 
 use crate::config::Config;
 use crate::Timestamp;
-use anyhow::Error;
+use anyhow::{anyhow, bail, Error};
 use fxhash::FxHasher64;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// The `last_update` key used for the configuration repository, which is never pruned.
+const GIT: &str = "git";
+
+/// The current version of the state file.
+///
+/// State files without a version are treated as version 1.
+pub const VERSION: u32 = 1;
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
@@ -18,17 +29,125 @@ pub struct Hashed {
 }
 
 /// The way the state is serialized.
-#[derive(Deserialize, Serialize, Default, Debug, PartialEq, Eq)]
-#[serde(deny_unknown_fields)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(try_from = "serde_yaml::Value")]
 pub struct DiskState {
+    /// The version of the state file.
+    pub version: u32,
     /// Last time git was updated.
-    #[serde(default)]
     pub last_update: BTreeMap<String, Timestamp>,
     /// Things that should only happen once.
-    #[serde(default)]
     pub once: BTreeMap<String, Timestamp>,
-    #[serde(default)]
     pub hashes: BTreeMap<String, Hashed>,
+    /// Files created by systems which delete orphans.
+    pub manifests: BTreeMap<String, BTreeSet<PathBuf>>,
+}
+
+impl Default for DiskState {
+    fn default() -> Self {
+        DiskState {
+            version: VERSION,
+            last_update: BTreeMap::new(),
+            once: BTreeMap::new(),
+            hashes: BTreeMap::new(),
+            manifests: BTreeMap::new(),
+        }
+    }
+}
+
+impl TryFrom<serde_yaml::Value> for DiskState {
+    type Error = Error;
+
+    fn try_from(value: serde_yaml::Value) -> Result<Self, Self::Error> {
+        let version = match value.get("version") {
+            Some(version) => version
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| anyhow!("Bad state file version: {:?}", version))?,
+            None => 1,
+        };
+
+        if version > VERSION {
+            bail!(
+                "State file has version {}, but only versions up to {} are supported",
+                version,
+                VERSION
+            );
+        }
+
+        let mut map = match migrate(value, version)? {
+            serde_yaml::Value::Mapping(map) => map,
+            serde_yaml::Value::Null => serde_yaml::Mapping::new(),
+            _ => bail!("State file is not a mapping"),
+        };
+
+        map.remove("version");
+
+        // NB: every entry is loaded separately, so that a corrupt entry doesn't discard the rest
+        // of the state.
+        let state = DiskState {
+            version: VERSION,
+            last_update: section(&mut map, "last_update"),
+            once: section(&mut map, "once"),
+            hashes: section(&mut map, "hashes"),
+            manifests: section(&mut map, "manifests"),
+        };
+
+        for (key, _) in map {
+            log::warn!("Ignoring unknown section in state file: {:?}", key);
+        }
+
+        Ok(state)
+    }
+}
+
+/// Load a single section of the state file, discarding any entries in it which are corrupt.
+fn section<T>(map: &mut serde_yaml::Mapping, key: &str) -> BTreeMap<String, T>
+where
+    T: DeserializeOwned,
+{
+    let mut out = BTreeMap::new();
+
+    let entries = match map.remove(key) {
+        Some(serde_yaml::Value::Mapping(entries)) => entries,
+        None | Some(serde_yaml::Value::Null) => return out,
+        Some(_) => {
+            log::warn!("Discarding corrupt `{}` section in state file", key);
+            return out;
+        }
+    };
+
+    for (id, value) in entries {
+        let entry = serde_yaml::from_value::<String>(id.clone())
+            .and_then(|id| Ok((id, serde_yaml::from_value::<T>(value)?)));
+
+        match entry {
+            Ok((id, value)) => {
+                out.insert(id, value);
+            }
+            Err(e) => {
+                log::warn!(
+                    "Discarding corrupt entry {:?} in `{}` section of state file: {}",
+                    id,
+                    key,
+                    e
+                );
+            }
+        }
+    }
+
+    out
+}
+
+/// Migrate the state file from the given version to the layout of the current version.
+///
+/// Every incompatible change to the layout of the state file bumps [VERSION], and adds an arm here
+/// which upgrades the previous version.
+fn migrate(value: serde_yaml::Value, version: u32) -> Result<serde_yaml::Value, Error> {
+    match version {
+        VERSION => Ok(value),
+        version => bail!("Cannot migrate state file from version {}", version),
+    }
 }
 
 impl DiskState {
@@ -39,6 +158,8 @@ impl DiskState {
             last_update: self.last_update,
             once: self.once,
             hashes: self.hashes,
+            manifests: self.manifests,
+            observed: Default::default(),
             config,
             now,
         }
@@ -48,7 +169,7 @@ impl DiskState {
 /// State model.
 /// This keeps track of any changes with the dirty flag, which is an indication whether it should
 /// be serialized or not.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug)]
 pub struct State<'a> {
     pub dirty: bool,
     /// Last time git was updated.
@@ -57,6 +178,10 @@ pub struct State<'a> {
     pub once: BTreeMap<String, Timestamp>,
     /// Things that have been tested against a hash.
     pub hashes: BTreeMap<String, Hashed>,
+    /// Files created by systems which delete orphans.
+    pub manifests: BTreeMap<String, BTreeSet<PathBuf>>,
+    /// Ids which have been observed during this run, used when pruning.
+    observed: Mutex<BTreeSet<String>>,
     /// The current configuration.
     pub config: &'a Config,
     /// Current timestamp.
@@ -70,6 +195,8 @@ impl<'a> State<'a> {
             last_update: Default::default(),
             once: Default::default(),
             hashes: Default::default(),
+            manifests: Default::default(),
+            observed: Default::default(),
             config,
             now,
         }
@@ -77,39 +204,51 @@ impl<'a> State<'a> {
 
     /// Get the last update timestamp for the given thing named `name`.
     pub fn last_update<'time>(&'time self, name: &str) -> Option<&'time Timestamp> {
+        self.observe(name);
         self.last_update.get(name)
     }
 
     /// Touch the thing with the given name.
     pub fn touch(&mut self, name: &str) {
+        self.observe(name);
         self.dirty = true;
         self.last_update.insert(name.to_string(), Timestamp::now());
     }
 
     /// Check if the given ID has run once.
     pub fn has_run_once(&self, id: &str) -> bool {
+        self.observe(id);
         self.once.contains_key(id)
     }
 
     /// Mark that something has happened once.
     pub fn touch_once(&mut self, id: &str) {
+        self.observe(id);
         self.dirty = true;
         self.once.insert(id.to_string(), Timestamp::now());
     }
 
-    /// Touch the hashed item.
-    pub fn is_hash_fresh<H: Hash>(&self, id: &str, hash: H) -> Result<bool, Error> {
+    /// Test if the hashed item is the same as the one last touched, regardless of when it was
+    /// touched.
+    pub fn is_hash_current<H: Hash>(&self, id: &str, hash: H) -> bool {
+        self.observe(id);
+
         let hashed = match self.hashes.get(id) {
             Some(hashed) => hashed,
-            None => return Ok(false),
+            None => return false,
         };
 
         let mut state = FxHasher64::default();
         hash.hash(&mut state);
+        hashed.hash == state.finish()
+    }
 
-        if hashed.hash != state.finish() {
-            return Ok(false);
-        }
+    /// Touch the hashed item.
+    pub fn is_hash_fresh<H: Hash>(&self, id: &str, hash: H) -> Result<bool, Error> {
+        let hashed = match self.hashes.get(id) {
+            Some(hashed) if self.is_hash_current(id, hash) => hashed,
+            _ => return Ok(false),
+        };
 
         let age = self.now.duration_since(hashed.updated)?;
         Ok(age < self.config.package_refresh)
@@ -120,6 +259,7 @@ impl<'a> State<'a> {
         let mut state = FxHasher64::default();
         hash.hash(&mut state);
 
+        self.observe(id);
         self.dirty = true;
 
         self.hashes.insert(
@@ -133,8 +273,30 @@ impl<'a> State<'a> {
         Ok(())
     }
 
+    /// Get the files previously created by the system with the given id.
+    pub fn manifest(&self, id: &str) -> Option<&BTreeSet<PathBuf>> {
+        self.observe(id);
+        self.manifests.get(id)
+    }
+
+    /// Store the files created by the system with the given id.
+    pub fn set_manifest(&mut self, id: &str, manifest: BTreeSet<PathBuf>) {
+        self.observe(id);
+        self.dirty = true;
+        self.manifests.insert(id.to_string(), manifest);
+    }
+
     /// Extend this state with another.
     pub fn extend(&mut self, other: State) {
+        let observed = other
+            .observed
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+        self.observed
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(observed);
+
         // nothing to extend.
         if !other.dirty {
             return;
@@ -144,6 +306,43 @@ impl<'a> State<'a> {
         self.last_update.extend(other.last_update);
         self.once.extend(other.once);
         self.hashes.extend(other.hashes);
+        self.manifests.extend(other.manifests);
+    }
+
+    /// Record that the given id has been observed during this run.
+    fn observe(&self, id: &str) {
+        let mut observed = self.observed.lock().unwrap_or_else(|e| e.into_inner());
+
+        if !observed.contains(id) {
+            observed.insert(id.to_string());
+        }
+    }
+
+    /// Remove all entries which haven't been observed during this run.
+    ///
+    /// The last update of the configuration repository is always kept.
+    pub fn prune(&mut self) {
+        let before = self.len();
+
+        let observed = self.observed.get_mut().unwrap_or_else(|e| e.into_inner());
+
+        self.last_update
+            .retain(|id, _| id == GIT || observed.contains(id));
+        self.once.retain(|id, _| observed.contains(id));
+        self.hashes.retain(|id, _| observed.contains(id));
+        self.manifests.retain(|id, _| observed.contains(id));
+
+        let pruned = before - self.len();
+
+        if pruned > 0 {
+            log::info!("Pruned {} stale state entries", pruned);
+            self.dirty = true;
+        }
+    }
+
+    /// The total number of entries in the state.
+    fn len(&self) -> usize {
+        self.last_update.len() + self.once.len() + self.hashes.len() + self.manifests.len()
     }
 
     /// Serialize the state, returning `None` unless it is dirty.
@@ -153,9 +352,113 @@ impl<'a> State<'a> {
         }
 
         Some(DiskState {
+            version: VERSION,
             last_update: self.last_update,
             once: self.once,
             hashes: self.hashes,
+            manifests: self.manifests,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{DiskState, State};
+    use crate::{Config, Timestamp};
+
+    #[test]
+    fn test_prune() {
+        let config = Config::default();
+        let now = Timestamp::now();
+
+        // A first run with two systems.
+        let mut state = State::new(&config, now);
+        state.touch("git");
+        state.touch_once("kept");
+        state.touch_once("removed");
+        state.touch_hash("removed-hash", "packages").unwrap();
+        let disk = state.serialize().expect("dirty state");
+
+        // A second run, where the system `removed` no longer exists.
+        let mut state = disk.into_state(&config, now);
+        assert!(state.has_run_once("kept"));
+        state.prune();
+
+        let disk = state.serialize().expect("dirty state");
+        assert!(disk.last_update.contains_key("git"));
+        assert!(disk.once.contains_key("kept"));
+        assert!(!disk.once.contains_key("removed"));
+        assert!(disk.hashes.is_empty());
+
+        // Nothing to prune, so nothing needs to be written.
+        let mut state = disk.into_state(&config, now);
+        assert!(state.has_run_once("kept"));
+        state.prune();
+        assert!(state.serialize().is_none());
+    }
+
+    #[test]
+    fn test_prune_observed_by_units() {
+        let config = Config::default();
+        let now = Timestamp::now();
+
+        let mut state = DiskState::default().into_state(&config, now);
+        state.touch_once("script");
+        let disk = state.serialize().expect("dirty state");
+
+        let mut state = disk.into_state(&config, now);
+
+        // Units observe the read state, and record their changes in a separate state.
+        let mut unit_state = State::new(&config, now);
+        assert!(!state.is_hash_current("template", "content"));
+        unit_state.touch_hash("other", "content").unwrap();
+        state.extend(unit_state);
+        state.prune();
+
+        let disk = state.serialize().expect("dirty state");
+        assert!(disk.once.is_empty());
+        assert!(disk.hashes.contains_key("other"));
+    }
+
+    #[test]
+    fn test_version() {
+        let config = Config::default();
+        let now = Timestamp::now();
+
+        let state = "once:\n  script: 1000\n";
+        let disk = serde_yaml::from_str::<DiskState>(state).unwrap();
+        assert_eq!(disk.version, 1);
+        assert!(disk.into_state(&config, now).has_run_once("script"));
+
+        let state = "version: 1\nonce: {}\n";
+        let disk = serde_yaml::from_str::<DiskState>(state).unwrap();
+        assert_eq!(disk.version, 1);
+
+        let state = "version: 1000\nonce: {}\n";
+        let e = serde_yaml::from_str::<DiskState>(state).unwrap_err();
+        assert!(e.to_string().contains("only versions up to"), "{}", e);
+
+        // NB: the version is always written.
+        let mut state = DiskState::default().into_state(&config, now);
+        state.touch_once("script");
+        let disk = state.serialize().expect("dirty state");
+        let serialized = serde_yaml::to_string(&disk).unwrap();
+        assert!(serialized.starts_with("version: 1\n"), "{}", serialized);
+    }
+
+    #[test]
+    fn test_corrupt_section() {
+        let state = "once:\n  script: 1000\nhashes:\n  packages: not a hash\n  template:\n    hash: 1\n    updated: 1000\nunknown: 42\n";
+        let disk = serde_yaml::from_str::<DiskState>(state).unwrap();
+        assert!(disk.once.contains_key("script"));
+        assert!(!disk.hashes.contains_key("packages"));
+        assert!(disk.hashes.contains_key("template"));
+
+        let state = "once:\n  script: 1000\nlast_update: [1, 2]\n";
+        let disk = serde_yaml::from_str::<DiskState>(state).unwrap();
+        assert!(disk.once.contains_key("script"));
+        assert!(disk.last_update.is_empty());
+
+        assert!(serde_yaml::from_str::<DiskState>("[1, 2]").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Summary of the actions taken during a run.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counters for the actions taken by units.
+///
+/// Shared between all units, which might be running in parallel.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Files copied.
+    files_copied: AtomicUsize,
+    /// Templates rendered.
+    templates_rendered: AtomicUsize,
+    /// Symlinks created.
+    symlinks_created: AtomicUsize,
+    /// Hardlinks created.
+    hardlinks_created: AtomicUsize,
+    /// Archives extracted.
+    archives_extracted: AtomicUsize,
+    /// Orphaned files removed.
+    files_removed: AtomicUsize,
+    /// Packages installed.
+    packages_installed: AtomicUsize,
+    /// Packages removed.
+    packages_removed: AtomicUsize,
+    /// Commands run.
+    commands_run: AtomicUsize,
+    /// Files downloaded.
+    files_downloaded: AtomicUsize,
+    /// Directories created.
+    directories_created: AtomicUsize,
+    /// File modes changed.
+    modes_changed: AtomicUsize,
+    /// Git repositories cloned.
+    repositories_cloned: AtomicUsize,
+    /// Git repositories updated.
+    repositories_updated: AtomicUsize,
+    /// Units which had nothing to do, since they were already up-to-date.
+    skipped: AtomicUsize,
+}
+
+impl Summary {
+    /// Record that a file was copied.
+    pub fn file_copied(&self) {
+        self.files_copied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a template was rendered.
+    pub fn template_rendered(&self) {
+        self.templates_rendered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a symlink was created.
+    pub fn symlink_created(&self) {
+        self.symlinks_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a hardlink was created.
+    pub fn hardlink_created(&self) {
+        self.hardlinks_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that an archive was extracted.
+    pub fn archive_extracted(&self) {
+        self.archives_extracted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that an orphaned file was removed.
+    pub fn file_removed(&self) {
+        self.files_removed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that the given number of packages were installed.
+    pub fn packages_installed(&self, count: usize) {
+        self.packages_installed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record that the given number of packages were removed.
+    pub fn packages_removed(&self, count: usize) {
+        self.packages_removed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record that a command was run.
+    pub fn command_run(&self) {
+        self.commands_run.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a file was downloaded.
+    pub fn file_downloaded(&self) {
+        self.files_downloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a directory was created.
+    pub fn directory_created(&self) {
+        self.directories_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that the mode of a file was changed.
+    pub fn mode_changed(&self) {
+        self.modes_changed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a git repository was cloned.
+    pub fn repository_cloned(&self) {
+        self.repositories_cloned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a git repository was updated.
+    pub fn repository_updated(&self) {
+        self.repositories_updated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a unit was skipped since it was up-to-date.
+    pub fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add all counters recorded in `other` to this summary.
+    pub fn extend(&self, other: &Summary) {
+        for (to, from) in self.counters().iter().zip(other.counters()) {
+            to.fetch_add(from.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+
+        self.skipped
+            .fetch_add(other.skipped.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// Test if nothing was changed, but something was recorded as up-to-date.
+    pub fn is_up_to_date(&self) -> bool {
+        !self.changed() && self.skipped.load(Ordering::Relaxed) > 0
+    }
+
+    /// Test if any action which changed something was recorded.
+    pub fn changed(&self) -> bool {
+        self.counters()
+            .iter()
+            .any(|counter| counter.load(Ordering::Relaxed) > 0)
+    }
+
+    /// All counters for actions which change something.
+    fn counters(&self) -> [&AtomicUsize; 14] {
+        [
+            &self.files_copied,
+            &self.templates_rendered,
+            &self.symlinks_created,
+            &self.hardlinks_created,
+            &self.archives_extracted,
+            &self.files_removed,
+            &self.packages_installed,
+            &self.packages_removed,
+            &self.commands_run,
+            &self.files_downloaded,
+            &self.directories_created,
+            &self.modes_changed,
+            &self.repositories_cloned,
+            &self.repositories_updated,
+        ]
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} file(s) copied, {} template(s) rendered, {} symlink(s) created, \
+             {} hardlink(s) created, {} archive(s) extracted, {} file(s) removed, \
+             {} package(s) installed, {} package(s) removed, {} command(s) run, \
+             {} file(s) downloaded, {} directory(s) created, {} mode(s) changed, \
+             {} repository(s) cloned, {} repository(s) updated, {} unit(s) up-to-date",
+            self.files_copied.load(Ordering::Relaxed),
+            self.templates_rendered.load(Ordering::Relaxed),
+            self.symlinks_created.load(Ordering::Relaxed),
+            self.hardlinks_created.load(Ordering::Relaxed),
+            self.archives_extracted.load(Ordering::Relaxed),
+            self.files_removed.load(Ordering::Relaxed),
+            self.packages_installed.load(Ordering::Relaxed),
+            self.packages_removed.load(Ordering::Relaxed),
+            self.commands_run.load(Ordering::Relaxed),
+            self.files_downloaded.load(Ordering::Relaxed),
+            self.directories_created.load(Ordering::Relaxed),
+            self.modes_changed.load(Ordering::Relaxed),
+            self.repositories_cloned.load(Ordering::Relaxed),
+            self.repositories_updated.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+
+    #[test]
+    fn test_display() {
+        let summary = Summary::default();
+        summary.file_copied();
+        summary.file_copied();
+        summary.template_rendered();
+        summary.packages_installed(3);
+        summary.command_run();
+        summary.repository_updated();
+        summary.skipped();
+
+        assert_eq!(
+            summary.to_string(),
+            "2 file(s) copied, 1 template(s) rendered, 0 symlink(s) created, \
+             0 hardlink(s) created, 0 archive(s) extracted, 0 file(s) removed, \
+             3 package(s) installed, 0 package(s) removed, 1 command(s) run, \
+             0 file(s) downloaded, 0 directory(s) created, 0 mode(s) changed, \
+             0 repository(s) cloned, 1 repository(s) updated, 1 unit(s) up-to-date"
+        );
+    }
+
+    #[test]
+    fn test_changed() {
+        let summary = Summary::default();
+        summary.skipped();
+        assert!(!summary.changed());
+
+        assert!(summary.is_up_to_date());
+
+        summary.symlink_created();
+        assert!(summary.changed());
+        assert!(!summary.is_up_to_date());
+
+        for record in [
+            Summary::file_downloaded,
+            Summary::directory_created,
+            Summary::mode_changed,
+            Summary::repository_cloned,
+            Summary::repository_updated,
+        ] {
+            let summary = Summary::default();
+            record(&summary);
+            assert!(summary.changed());
+        }
+
+        let total = Summary::default();
+        total.extend(&summary);
+        total.extend(&summary);
+        assert!(total
+            .to_string()
+            .starts_with("0 file(s) copied, 0 template(s) rendered, 2 symlink(s) created"));
+        assert!(total.to_string().ends_with("2 unit(s) up-to-date"));
+    }
+}
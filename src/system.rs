@@ -4,34 +4,46 @@ use crate::{
     environment as e, git, packages, state::State, Data, Facts, FileSystem, Opts, SystemUnit,
     Timestamp, UnitAllocator, UnitId,
 };
-use anyhow::Error;
+use anyhow::{bail, Error};
 use directories::BaseDirs;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 
 #[macro_use]
 mod macros;
+mod archive;
+mod condition;
 mod copy_dir;
 mod download;
 mod download_and_run;
 mod from_db;
 mod git_sync;
+mod hardlink;
 mod install;
 mod link;
 mod link_dir;
+mod mkdir;
+mod mode;
 mod only_for;
+mod template_string;
 
+use self::archive::Archive;
 use self::copy_dir::CopyDir;
 use self::download::Download;
 use self::download_and_run::DownloadAndRun;
 use self::from_db::FromDb;
 use self::git_sync::GitSync;
+use self::hardlink::Hardlink;
 use self::install::Install;
 use self::link::Link;
 use self::link_dir::LinkDir;
+use self::mkdir::Mkdir;
+use self::mode::Mode;
 use self::only_for::OnlyFor;
+use self::template_string::TemplateString;
 
 /// What should happen after a system has been translated.
 pub enum Translation<'a> {
@@ -46,11 +58,33 @@ pub enum Translation<'a> {
 macro_rules! system_impl {
     ($($name:ident,)*) => {
         impl System {
-            pub fn translate(&self) -> Translation<'_> {
+            pub fn translate(&self, facts: &Facts) -> Translation<'_> {
+                use self::System::*;
+
+                if !self.enabled() {
+                    return Translation::Discard;
+                }
+
+                match self {
+                    $($name(system) => system.translate(facts),)*
+                }
+            }
+
+            /// Get the tags of this system.
+            pub fn tags(&self) -> &[String] {
+                use self::System::*;
+
+                match self {
+                    $($name(system) => system.tags(),)*
+                }
+            }
+
+            /// Test if this system is enabled.
+            pub fn enabled(&self) -> bool {
                 use self::System::*;
 
                 match self {
-                    $($name(system) => system.translate(),)*
+                    $($name(system) => system.enabled(),)*
                 }
             }
 
@@ -72,6 +106,24 @@ macro_rules! system_impl {
                 }
             }
 
+            /// Get the condition that must hold for this system to apply.
+            pub fn when(&self) -> Option<&self::condition::Condition> {
+                use self::System::*;
+
+                match self {
+                    $($name(system) => system.when(),)*
+                }
+            }
+
+            /// Get the external paths which must exist before this system is applied.
+            pub fn wait_for(&self) -> &[crate::template::Template] {
+                use self::System::*;
+
+                match self {
+                    $($name(system) => system.wait_for(),)*
+                }
+            }
+
             /// Apply changes for this system.
             #[allow(unused)]
             pub fn apply<E>(&self, input: $crate::system::SystemInput<E>)
@@ -82,9 +134,27 @@ macro_rules! system_impl {
                 use anyhow::{Context as _, anyhow};
                 use self::System::*;
 
+                let label = self.to_string();
+                let force = self.id().map_or(false, |id| input.opts.is_forced(id));
+                let input = $crate::system::SystemInput { system: &label, force, ..input };
+
+                // NB: systems loaded from the database aren't translated, so they have to be
+                // checked here as well.
+                if !self.enabled() {
+                    return Ok(vec![]);
+                }
+
+                if let Some(when) = self.when() {
+                    // condition does not hold, nothing to apply.
+                    if !when.matches(input.facts) {
+                        return Ok(vec![]);
+                    }
+                }
+
                 let res = match self {
                     $($name(system) => system.apply(input),)*
-                };
+                }
+                .and_then(|units| $crate::system::wait_for(self.wait_for(), units, input));
 
                 res.with_context(|| anyhow!("Failed to run system: {:?}", self))
             }
@@ -123,12 +193,22 @@ pub enum System {
     Download(Download),
     #[serde(rename = "link")]
     Link(Link),
+    #[serde(rename = "hardlink")]
+    Hardlink(Hardlink),
     #[serde(rename = "git-sync")]
     GitSync(GitSync),
     #[serde(rename = "only-for")]
     OnlyFor(OnlyFor),
     #[serde(rename = "from-db")]
     FromDb(FromDb),
+    #[serde(rename = "template-string")]
+    TemplateString(TemplateString),
+    #[serde(rename = "archive")]
+    Archive(Archive),
+    #[serde(rename = "mode")]
+    Mode(Mode),
+    #[serde(rename = "mkdir")]
+    Mkdir(Mkdir),
 }
 
 system_impl![
@@ -138,11 +218,160 @@ system_impl![
     DownloadAndRun,
     Download,
     Link,
+    Hardlink,
     GitSync,
     OnlyFor,
     FromDb,
+    TemplateString,
+    Archive,
+    Mode,
+    Mkdir,
 ];
 
+/// Expand all systems that need translation, like `only-for`, and drop the ones that are
+/// discarded.
+pub fn expand<'a>(systems: &'a [System], facts: &Facts) -> Vec<&'a System> {
+    use std::collections::VecDeque;
+
+    let mut out = Vec::with_capacity(systems.len());
+    let mut queue = VecDeque::new();
+    queue.extend(systems);
+
+    while let Some(system) = queue.pop_back() {
+        match system.translate(facts) {
+            Translation::Discard => {}
+            Translation::Keep => out.push(system),
+            Translation::Expand(systems) => queue.extend(systems),
+        }
+    }
+
+    out
+}
+
+/// Collect the ids of every declared system, including systems which are disabled or belong to
+/// only-for systems which don't apply.
+pub fn declared_ids(systems: &[System]) -> HashSet<&str> {
+    let mut ids = HashSet::new();
+    let mut queue = systems.iter().collect::<Vec<_>>();
+
+    while let Some(system) = queue.pop() {
+        ids.extend(system.id());
+
+        if let System::OnlyFor(only_for) = system {
+            queue.extend(&only_for.systems);
+        }
+    }
+
+    ids
+}
+
+/// Select the expanded systems which have any of the given tags, together with the systems they
+/// transitively require.
+pub fn select<'a>(systems: Vec<&'a System>, tags: &[String]) -> Vec<&'a System> {
+    let mut by_id = HashMap::new();
+
+    for (index, system) in systems.iter().enumerate() {
+        if let Some(id) = system.id() {
+            by_id.insert(id, index);
+        }
+    }
+
+    let mut selected = vec![false; systems.len()];
+    let mut queue = Vec::new();
+
+    for (index, system) in systems.iter().enumerate() {
+        if system.tags().iter().any(|tag| tags.contains(tag)) {
+            selected[index] = true;
+            queue.push(index);
+        }
+    }
+
+    while let Some(index) = queue.pop() {
+        for id in systems[index].requires() {
+            if let Some(&required) = by_id.get(id.as_str()) {
+                if !selected[required] {
+                    selected[required] = true;
+                    queue.push(required);
+                }
+            }
+        }
+    }
+
+    systems
+        .into_iter()
+        .zip(selected)
+        .filter(|(_, selected)| *selected)
+        .map(|(system, _)| system)
+        .collect()
+}
+
+/// List the given expanded systems, including the systems loaded by `from-db` from the
+/// hierarchy.
+pub fn list<W, E>(
+    out: &mut W,
+    systems: &[&System],
+    data: &Data,
+    facts: &Facts,
+    environment: E,
+) -> Result<(), Error>
+where
+    W: io::Write,
+    E: Copy + e::Environment,
+{
+    return list(out, systems, data, facts, environment, 0);
+
+    fn list<W, E>(
+        out: &mut W,
+        systems: &[&System],
+        data: &Data,
+        facts: &Facts,
+        environment: E,
+        depth: usize,
+    ) -> Result<(), Error>
+    where
+        W: io::Write,
+        E: Copy + e::Environment,
+    {
+        for system in systems {
+            write!(out, "{:indent$}- {}", "", system, indent = depth * 2)?;
+
+            if !system.requires().is_empty() {
+                write!(out, " (requires: {})", system.requires().join(", "))?;
+            }
+
+            writeln!(out)?;
+
+            if let System::FromDb(from_db) = system {
+                let systems = from_db.systems(data, facts, environment)?;
+                list(
+                    out,
+                    &expand(&systems, facts),
+                    data,
+                    facts,
+                    environment,
+                    depth + 1,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Explain the units that a system would create, including what they depend on and provide.
+pub fn explain<W>(out: &mut W, units: &[SystemUnit]) -> Result<(), Error>
+where
+    W: io::Write,
+{
+    for unit in units {
+        writeln!(out, "unit({:03}): {}", unit.id, unit.unit())?;
+        writeln!(out, "  depends: {:?}", unit.dependencies)?;
+        writeln!(out, "  provides: {:?}", unit.provides)?;
+    }
+
+    Ok(())
+}
+
 /// All inputs for a system.
 #[derive(Clone, Copy)]
 pub struct SystemInput<'a, 'f, E>
@@ -173,6 +402,65 @@ where
     pub opts: &'a Opts,
     /// The current git system.
     pub git_system: &'a dyn git::GitSystem,
+    /// Label of the system being applied, used for diagnostics.
+    ///
+    /// This is set when the system is applied.
+    pub system: &'a str,
+    /// If the system being applied should ignore its state and run again.
+    ///
+    /// This is set when the system is applied, based on `--force-system`.
+    pub force: bool,
+}
+
+/// Make all the given units depend on units which wait for each path in `wait_for` to exist.
+pub fn wait_for<E>(
+    wait_for: &[crate::template::Template],
+    mut units: Vec<SystemUnit>,
+    input: SystemInput<E>,
+) -> Result<Vec<SystemUnit>, Error>
+where
+    E: Copy + e::Environment,
+{
+    if units.is_empty() {
+        return Ok(units);
+    }
+
+    let SystemInput {
+        root,
+        base_dirs,
+        facts,
+        environment,
+        allocator,
+        opts,
+        ..
+    } = input;
+
+    let mut waits = Vec::new();
+
+    for template in wait_for {
+        // NB: external paths are commonly absolute, which are used as-is.
+        let path = match template.as_string(facts, environment)? {
+            Some(path) if Path::new(&path).is_absolute() => PathBuf::from(path),
+            Some(_) => match template.as_path(root, base_dirs, facts, environment)? {
+                Some(path) => path,
+                None => continue,
+            },
+            None => continue,
+        };
+
+        waits.push(allocator.unit(crate::unit::WaitFor {
+            path,
+            timeout: opts.wait,
+        }));
+    }
+
+    for unit in &mut units {
+        unit.dependencies
+            .extend(waits.iter().map(|w| crate::unit::Dependency::Unit(w.id)));
+    }
+
+    units.extend(waits);
+    Ok(units)
 }
 
 /// Helper structure used to resolve dependencies.
@@ -188,23 +476,39 @@ pub enum Dependency<'a> {
 }
 
 impl<'a> Dependency<'a> {
-    /// Resolve all unit dependencies for the current dependency.
+    /// Resolve all unit dependencies for the current dependency, which belongs to the system
+    /// identified by `name`.
+    ///
+    /// Systems which are in `declared` but not in `systems`, like disabled systems, don't add any
+    /// dependencies. Errors if any system requires an id which isn't declared.
     pub fn resolve(
         &self,
+        name: &str,
         systems: &HashMap<&'a str, Dependency<'a>>,
-    ) -> impl IntoIterator<Item = crate::unit::Dependency> {
+        declared: &HashSet<&str>,
+    ) -> Result<Vec<crate::unit::Dependency>, Error> {
         use std::collections::VecDeque;
 
         let mut ids = Vec::new();
+        // NB: systems without units might require each other.
+        let mut visited = HashSet::new();
 
         let mut queue = VecDeque::new();
-        queue.push_back(self);
+        queue.push_back((name, self));
 
-        while let Some(dependency) = queue.pop_front() {
+        while let Some((name, dependency)) = queue.pop_front() {
             match *dependency {
                 Dependency::Transitive(requires) => {
                     for id in requires {
-                        queue.extend(systems.get(id.as_str()));
+                        let (id, dependency) = match systems.get_key_value(id.as_str()) {
+                            Some(entry) => entry,
+                            None if declared.contains(id.as_str()) => continue,
+                            None => bail!("system `{}` requires unknown system `{}`", name, id),
+                        };
+
+                        if visited.insert(*id) {
+                            queue.push_back((*id, dependency));
+                        }
                     }
                 }
                 Dependency::Direct(id) => ids.push(crate::unit::Dependency::Unit(id)),
@@ -212,6 +516,547 @@ impl<'a> Dependency<'a> {
             }
         }
 
-        ids
+        Ok(ids)
+    }
+}
+
+/// Apply the given system with a default input, using `root` as the project root.
+#[cfg(test)]
+pub(crate) fn test_apply(root: &Path, system: &System) -> Result<Vec<SystemUnit>, Error> {
+    let config = crate::Config::default();
+    let state = State::new(&config, Timestamp::now());
+    test_apply_with_state(root, system, &state)
+}
+
+/// Apply the given system with a default input and the given state.
+#[cfg(test)]
+pub(crate) fn test_apply_with_state(
+    root: &Path,
+    system: &System,
+    state: &State,
+) -> Result<Vec<SystemUnit>, Error> {
+    test_apply_with_facts(root, system, state, &Facts::new(Vec::new()))
+}
+
+/// Apply the given system with a default input and the given state and facts.
+#[cfg(test)]
+pub(crate) fn test_apply_with_facts(
+    root: &Path,
+    system: &System,
+    state: &State,
+    facts: &Facts,
+) -> Result<Vec<SystemUnit>, Error> {
+    use clap::Parser as _;
+
+    let opts = Opts::parse_from(["qc"]);
+    let mut units = test_apply_all(root, std::slice::from_ref(system), &opts, state, facts)?;
+    Ok(units.remove(0))
+}
+
+/// Apply all the given systems with the given options, state and facts, returning the units of
+/// each system.
+///
+/// The systems share the same file system, which is validated once all of them have been applied.
+#[cfg(test)]
+pub(crate) fn test_apply_all(
+    root: &Path,
+    systems: &[System],
+    opts: &Opts,
+    state: &State,
+    facts: &Facts,
+) -> Result<Vec<Vec<SystemUnit>>, Error> {
+    let now = Timestamp::now();
+    let data = Data::new(None, Vec::new());
+    let packages = packages::detect(facts)?;
+    let allocator = UnitAllocator::default();
+    let file_system = FileSystem::new(opts, root, &allocator, &data);
+    let git_system = git::setup()?;
+
+    let units = systems
+        .iter()
+        .map(|system| {
+            system.apply(SystemInput {
+                root,
+                base_dirs: None,
+                facts,
+                data: &data,
+                environment: e::Real,
+                packages: &packages,
+                allocator: &allocator,
+                file_system: &file_system,
+                state,
+                now,
+                opts,
+                git_system: &*git_system,
+                system: "",
+                force: false,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    file_system.validate()?;
+    Ok(units)
+}
+
+/// Apply the given system and run all of its units in order, storing changes into `state`.
+#[cfg(test)]
+pub(crate) fn test_run(root: &Path, system: &System, state: &mut State) -> Result<(), Error> {
+    let units = test_apply_with_state(root, system, state)?;
+
+    let now = Timestamp::now();
+    let config = state.config;
+    let data = Data::new(None, Vec::new());
+    let packages = packages::detect(&Facts::new(Vec::new()))?;
+    let git_system = git::setup()?;
+    let http = reqwest::blocking::Client::new();
+    let summary = crate::Summary::default();
+    let shared = std::sync::RwLock::new(std::mem::replace(state, State::new(config, now)));
+
+    let result = units.into_iter().try_for_each(|unit| {
+        let mut unit_state = State::new(config, now);
+
+        unit.apply(crate::unit::UnitInput {
+            packages: &packages,
+            data: &data,
+            read_state: &shared,
+            state: &mut unit_state,
+            now,
+            git_system: &*git_system,
+            http: &http,
+            summary: &summary,
+            dry_run: false,
+            diff: false,
+        })?;
+
+        shared.write().unwrap().extend(unit_state);
+        Ok(())
+    });
+
+    *state = shared.into_inner().unwrap();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand, explain, list, test_apply_all, test_apply_with_facts, Dependency, System};
+    use crate::{
+        environment as e, facts::Facts, hierarchy::Data, opts::Opts, Config, State, Timestamp,
+    };
+    use clap::Parser as _;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Systems {
+        systems: Vec<System>,
+    }
+
+    #[test]
+    fn test_list() {
+        let root = tempfile::tempdir().unwrap();
+        let to = root.path().join("to");
+
+        let config = format!(
+            r#"
+systems:
+  - type: copy-dir
+    id: copy
+    from: from
+    to: {to}
+  - type: only-for
+    os: {os}
+    systems:
+      - type: install
+        requires: [copy]
+  - type: only-for
+    os: not-an-os
+    systems:
+      - type: install
+        id: discarded
+  - type: from-db
+    system: link
+    key: link
+"#,
+            to = to.display(),
+            os = std::env::consts::OS,
+        );
+
+        let systems = serde_yaml::from_str::<Systems>(&config).unwrap().systems;
+
+        let db = serde_yaml::from_str(
+            r#"
+link:
+  - path: home://.vimrc
+    link: vimrc
+"#,
+        )
+        .unwrap();
+
+        let data = Data::new(None, vec![db]);
+
+        let mut out = Vec::new();
+        let facts = Facts::new(Vec::new());
+        list(&mut out, &expand(&systems, &facts), &data, &facts, e::Real).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("- copy: copy "), "{}", out);
+        assert!(out.contains(" (requires: copy)\n"), "{}", out);
+        assert!(!out.contains("discarded"), "{}", out);
+        assert!(
+            out.contains("- system `link` from database key `link`\n  - link `home://.vimrc`"),
+            "{}",
+            out
+        );
+        assert!(!to.exists());
+    }
+
+    #[test]
+    fn test_explain() {
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("from");
+        let to = root.path().join("to");
+        std::fs::create_dir_all(from.join("sub")).unwrap();
+        std::fs::write(from.join("a.txt"), "a").unwrap();
+        std::fs::write(from.join("sub").join("b.txt"), "b").unwrap();
+
+        let config = "systems:\n  - type: copy-dir\n    id: copy\n    from: from\n    to: to\n";
+        let systems = serde_yaml::from_str::<Systems>(config).unwrap().systems;
+
+        let units = super::test_apply(root.path(), &systems[0]).unwrap();
+
+        let mut out = Vec::new();
+        explain(&mut out, &units).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(out.matches("create directory").count(), 2, "{}", out);
+        assert_eq!(out.matches("copy file").count(), 2, "{}", out);
+        assert_eq!(out.matches("  depends: ").count(), units.len(), "{}", out);
+        assert_eq!(out.matches("  provides: [").count(), units.len(), "{}", out);
+        assert!(!to.exists());
+    }
+
+    #[test]
+    fn test_conflicting_paths() {
+        let root = tempfile::tempdir().unwrap();
+
+        let config = r#"
+systems:
+  - type: template-string
+    id: first
+    content: "a"
+    to: out.txt
+  - type: template-string
+    id: second
+    content: "b"
+    to: out.txt
+"#;
+        let systems = serde_yaml::from_str::<Systems>(config).unwrap().systems;
+
+        let opts = Opts::parse_from(["qc"]);
+        let config = Config::default();
+        let state = State::new(&config, Timestamp::now());
+
+        let e = test_apply_all(
+            root.path(),
+            &systems,
+            &opts,
+            &state,
+            &Facts::new(Vec::new()),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(e.contains("out.txt"), "{}", e);
+        assert!(e.contains("first: "), "{}", e);
+        assert!(e.contains("second: "), "{}", e);
+    }
+
+    #[test]
+    fn test_resolve_requires() {
+        let requires = ["empty".to_string()];
+        let nested = ["copy".to_string()];
+
+        let mut systems = std::collections::HashMap::new();
+        systems.insert("copy", Dependency::Direct(1));
+        systems.insert("empty", Dependency::Transitive(&nested));
+        let declared = ["copy", "empty", "install"].iter().copied().collect();
+
+        let ids = Dependency::Transitive(&requires)
+            .resolve("install", &systems, &declared)
+            .unwrap();
+        assert_eq!(ids, vec![crate::unit::Dependency::Unit(1)]);
+
+        let missing = ["copy".to_string(), "typo".to_string()];
+        let e = Dependency::Transitive(&missing)
+            .resolve("install", &systems, &declared)
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "system `install` requires unknown system `typo`"
+        );
+
+        // Unknown ids required by other systems name the requiring system.
+        let broken = ["typo".to_string()];
+        systems.insert("empty", Dependency::Transitive(&broken));
+        let e = Dependency::Transitive(&requires)
+            .resolve("install", &systems, &declared)
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "system `empty` requires unknown system `typo`"
+        );
+    }
+
+    #[test]
+    fn test_when() {
+        let root = tempfile::tempdir().unwrap();
+        let facts = Facts::new(vec![("distro".to_string(), "debian".to_string())]);
+        let config = Config::default();
+        let state = State::new(&config, Timestamp::now());
+
+        let system = |when: &str| {
+            let config = format!("type: mkdir\npath: dir\nwhen: \"{}\"\n", when);
+            serde_yaml::from_str::<System>(&config)
+        };
+
+        let units = test_apply_with_facts(
+            root.path(),
+            &system("distro == debian").unwrap(),
+            &state,
+            &facts,
+        );
+        assert_eq!(units.unwrap().len(), 1);
+
+        let units = test_apply_with_facts(
+            root.path(),
+            &system("distro != debian").unwrap(),
+            &state,
+            &facts,
+        );
+        assert!(units.unwrap().is_empty());
+
+        // NB: malformed conditions are reported when the configuration is loaded.
+        let e = system("distro ~ debian").unwrap_err().to_string();
+        assert!(e.contains("bad condition"), "{}", e);
+    }
+
+    #[test]
+    fn test_resolve_requires_filtered() {
+        let config = r#"
+systems:
+  - type: mkdir
+    id: base
+    path: base
+    enabled: false
+  - type: only-for
+    os: not-an-os
+    systems:
+      - type: mkdir
+        id: other
+        path: other
+  - type: mkdir
+    id: child
+    path: child
+    requires: [base, other]
+"#;
+        let systems = serde_yaml::from_str::<Systems>(config).unwrap().systems;
+        let declared = super::declared_ids(&systems);
+
+        let expanded = expand(&systems, &Facts::new(Vec::new()));
+        assert_eq!(expanded.len(), 1);
+
+        // NB: neither `base` nor `other` are applied, so there is nothing to depend on.
+        let ids = Dependency::Transitive(expanded[0].requires())
+            .resolve("child", &Default::default(), &declared)
+            .unwrap();
+        assert!(ids.is_empty());
+
+        let unknown = ["nope".to_string()];
+        let e = Dependency::Transitive(&unknown)
+            .resolve("child", &Default::default(), &declared)
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "system `child` requires unknown system `nope`"
+        );
+    }
+
+    #[test]
+    fn test_resolve_requires_cycle() {
+        let a = ["b".to_string()];
+        let b = ["a".to_string()];
+
+        let mut systems = std::collections::HashMap::new();
+        systems.insert("a", Dependency::Transitive(&a));
+        systems.insert("b", Dependency::Transitive(&b));
+        let declared = ["a", "b"].iter().copied().collect();
+
+        // NB: systems without units which require each other resolve to nothing.
+        let ids = Dependency::Transitive(&a)
+            .resolve("c", &systems, &declared)
+            .unwrap();
+        assert!(ids.is_empty());
+    }
+
+    #[test]
+    fn test_mode_on_created_path() {
+        let root = tempfile::tempdir().unwrap();
+
+        let mkdir = "  - type: mkdir\n    path: dir\n";
+        let mode = "  - type: mode\n    path: dir\n    mode: \"0700\"\n";
+
+        let opts = Opts::parse_from(["qc"]);
+        let config = Config::default();
+        let state = State::new(&config, Timestamp::now());
+        let facts = Facts::new(Vec::new());
+
+        for config in &[
+            format!("systems:\n{}{}", mkdir, mode),
+            format!("systems:\n{}{}", mode, mkdir),
+        ] {
+            let systems = serde_yaml::from_str::<Systems>(config).unwrap().systems;
+            let units = test_apply_all(root.path(), &systems, &opts, &state, &facts).unwrap();
+
+            let provided = units
+                .iter()
+                .flatten()
+                .flat_map(|u| u.provides.iter().copied())
+                .collect::<Vec<_>>();
+
+            // NB: the mode can only be set once the directory has been created.
+            for unit in units.iter().flatten() {
+                for dependency in &unit.dependencies {
+                    assert!(provided.contains(dependency), "{:?}", units);
+                }
+            }
+        }
+
+        let config = format!("systems:\n{}", mode);
+        let systems = serde_yaml::from_str::<Systems>(&config).unwrap().systems;
+        let e = test_apply_all(root.path(), &systems, &opts, &state, &facts)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("path `"), "{}", e);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_for() {
+        let root = tempfile::tempdir().unwrap();
+
+        let config = "systems:\n  - type: template-string\n    content: hello\n    to: out.txt\n    wait_for: [/opt/foo]\n";
+        let systems = serde_yaml::from_str::<Systems>(config).unwrap().systems;
+
+        let units = super::test_apply(root.path(), &systems[0]).unwrap();
+        assert_eq!(units.len(), 2, "{:?}", units);
+
+        let wait = units
+            .iter()
+            .find(|u| u.unit().to_string() == "wait for /opt/foo")
+            .expect("wait for unit");
+        let render = units.iter().find(|u| u.id != wait.id).unwrap();
+        assert!(render
+            .dependencies
+            .contains(&crate::unit::Dependency::Unit(wait.id)));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let root = tempfile::tempdir().unwrap();
+
+        let config = "systems:\n  - type: mkdir\n    path: a\n    enabled: false\n  - type: only-for\n    os: {os}\n    enabled: false\n    systems:\n      - type: mkdir\n        path: b\n  - type: mkdir\n    path: c\n    enabled: true\n";
+        let config = config.replace("{os}", std::env::consts::OS);
+        let systems = serde_yaml::from_str::<Systems>(&config).unwrap().systems;
+
+        let facts = Facts::new(Vec::new());
+        let expanded = expand(&systems, &facts);
+        assert_eq!(expanded.len(), 1);
+        assert_eq!(expanded[0].to_string(), "create directory `c`");
+
+        assert!(super::test_apply(root.path(), &systems[0])
+            .unwrap()
+            .is_empty());
+        assert!(!super::test_apply(root.path(), &systems[2])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_select() {
+        use super::select;
+
+        let config = r#"
+systems:
+  - type: mkdir
+    id: base
+    path: base
+  - type: mkdir
+    id: editor
+    path: editor
+    requires: [base]
+  - type: mkdir
+    path: vim
+    tags: [vim]
+    requires: [editor]
+  - type: mkdir
+    path: shell
+    tags: [shell]
+  - type: mkdir
+    path: other
+"#;
+
+        let systems = serde_yaml::from_str::<Systems>(config).unwrap().systems;
+        let facts = Facts::new(Vec::new());
+
+        let selected = |tags: &[&str]| {
+            let tags = tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+            let mut selected = select(expand(&systems, &facts), &tags)
+                .into_iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>();
+            selected.sort();
+            selected
+        };
+
+        assert_eq!(
+            selected(&["vim"]),
+            vec![
+                "base: create directory `base`",
+                "create directory `vim`",
+                "editor: create directory `editor`",
+            ]
+        );
+        assert_eq!(selected(&["shell"]), vec!["create directory `shell`"]);
+        assert_eq!(selected(&["vim", "shell"]).len(), 4);
+        assert!(selected(&["missing"]).is_empty());
+    }
+
+    #[test]
+    fn test_force_system() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("a"), "").unwrap();
+        std::fs::write(root.path().join("b"), "").unwrap();
+
+        let config = "systems:\n  - type: download-and-run\n    id: a\n    url: https://example.com/a\n  - type: download-and-run\n    id: b\n    url: https://example.com/b\n";
+        let systems = serde_yaml::from_str::<Systems>(config).unwrap().systems;
+
+        let opts = Opts::parse_from(["qc", "--force-system", "a"]);
+        let config = crate::Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        state.touch_once("a");
+        state.touch_once("b");
+
+        let units = test_apply_all(
+            root.path(),
+            &systems,
+            &opts,
+            &state,
+            &Facts::new(Vec::new()),
+        )
+        .unwrap();
+
+        // NB: the forced system runs again, even though it has already run once.
+        assert!(units[0]
+            .iter()
+            .any(|u| u.unit().to_string().starts_with("run ")));
+        assert!(units[1].is_empty());
     }
 }
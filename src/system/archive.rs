@@ -0,0 +1,153 @@
+use crate::{
+    environment as e,
+    system::SystemInput,
+    template::Template,
+    unit::{self, ArchiveFormat, Dependency, SystemUnit},
+};
+use anyhow::{anyhow, bail, Context as _, Error};
+use std::fmt;
+use std::fs;
+
+system_struct! {
+    #[doc = "Builds units to download and extract an archive."]
+    Archive {
+        #[doc="URL to download the archive from."]
+        #[serde(default)]
+        pub url: Option<String>,
+        #[doc="Local archive to extract."]
+        #[serde(default)]
+        pub from: Option<Template>,
+        #[doc="Directory to extract the archive into."]
+        pub to: Template,
+        #[doc="Number of leading path components to strip from every entry in the archive."]
+        #[serde(default)]
+        pub strip_components: usize,
+    }
+}
+
+impl Archive {
+    system_defaults!(translate);
+
+    /// Extract an archive.
+    pub fn apply<E>(&self, input: SystemInput<E>) -> Result<Vec<SystemUnit>, Error>
+    where
+        E: Copy + e::Environment,
+    {
+        let SystemInput {
+            root,
+            base_dirs,
+            facts,
+            environment,
+            allocator,
+            file_system,
+            state,
+            system,
+            force,
+            ..
+        } = input;
+
+        let mut units = Vec::new();
+
+        let to = match self.to.as_path(root, base_dirs, facts, environment)? {
+            Some(to) => to,
+            None => return Ok(units),
+        };
+
+        if to.exists() && !to.is_dir() {
+            bail!("Exists but is not a directory: {}", to.display());
+        }
+
+        let mut download = None;
+
+        let (id, format, archive) = match (self.url.as_deref(), self.from.as_ref()) {
+            (Some(url), None) => {
+                let url = reqwest::Url::parse(url).with_context(|| anyhow!("illegal `url`"))?;
+                let format = ArchiveFormat::from_name(url.path())?;
+                let id = id_from(&(url.as_str(), &to));
+
+                if state.has_run_once(&id) && !force {
+                    return Ok(units);
+                }
+
+                let archive = file_system.state_path(&format!("{}.{}", id, format.extension()));
+
+                if !archive.is_file() {
+                    download = Some(allocator.unit(unit::Download {
+                        url,
+                        path: archive.clone(),
+                        id: None,
+                        headers: Vec::new(),
+                        auth: None,
+                        force: false,
+                    }));
+                }
+
+                (id, format, archive)
+            }
+            (None, Some(from)) => {
+                let from = match from.as_path(root, base_dirs, facts, environment)? {
+                    Some(from) => from,
+                    None => return Ok(units),
+                };
+
+                let format = ArchiveFormat::from_name(&from.to_string_lossy())?;
+
+                // NB: local archives are keyed by their contents, so that changing the archive
+                // extracts it again.
+                let content = fs::read(&from)
+                    .with_context(|| anyhow!("failed to read archive: {}", from.display()))?;
+                let id = id_from(&(&content, &to));
+
+                if state.has_run_once(&id) && !force {
+                    return Ok(units);
+                }
+
+                (id, format, from)
+            }
+            _ => bail!("exactly one of `url` or `from` must be specified"),
+        };
+
+        let mut extract = allocator.unit(unit::Extract {
+            id,
+            format,
+            archive,
+            to: to.clone(),
+            strip_components: self.strip_components,
+        });
+
+        if !to.is_dir() {
+            units.extend(file_system.create_dir_all(system, &to)?);
+            extract
+                .dependencies
+                .push(file_system.dir_dependency(system, &to)?);
+        }
+
+        if let Some(download) = download {
+            extract.dependencies.push(Dependency::Unit(download.id));
+            units.push(download);
+        }
+
+        units.push(extract);
+        Ok(units)
+    }
+}
+
+impl fmt::Display for Archive {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match (self.url.as_deref(), self.from.as_ref()) {
+            (Some(url), _) => write!(fmt, "extract `{}` to `{}`", url, self.to),
+            (None, Some(from)) => write!(fmt, "extract `{}` to `{}`", from, self.to),
+            (None, None) => write!(fmt, "extract archive to `{}`", self.to),
+        }
+    }
+}
+
+/// Generate a unique ID for the archive being extracted.
+fn id_from(value: &(impl std::hash::Hash + ?Sized)) -> String {
+    use std::hash::Hasher;
+
+    let mut state = fxhash::FxHasher64::default();
+    value.hash(&mut state);
+
+    format!("archive-{:x}", state.finish())
+}
@@ -0,0 +1,157 @@
+//! Simple fact expressions used to conditionally apply systems.
+
+use crate::facts::Facts;
+use anyhow::{bail, Error};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A parsed condition, like `distro == debian`.
+///
+/// Conditions are parsed when deserialized, so that malformed conditions are reported when the
+/// configuration is loaded.
+#[derive(Deserialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Condition {
+    /// The expression the condition was parsed from.
+    expr: String,
+    /// The fact to look up.
+    key: String,
+    /// The value to compare against.
+    value: String,
+    /// If the comparison is negated (`!=`).
+    negated: bool,
+}
+
+impl Condition {
+    /// Parse a condition from the given expression.
+    pub fn parse(expr: &str) -> Result<Condition, Error> {
+        let (key, value, negated) = if let Some((key, value)) = expr.split_once("!=") {
+            (key, value, true)
+        } else if let Some((key, value)) = expr.split_once("==") {
+            (key, value, false)
+        } else {
+            bail!(
+                "bad condition `{}`: expected `<key> == <value>` or `<key> != <value>`",
+                expr
+            );
+        };
+
+        let key = key.trim();
+        let value = value.trim();
+
+        // NB: `!=` is split on first, so a chained comparison might be on either side.
+        if [key, value]
+            .iter()
+            .any(|part| part.contains("==") || part.contains("!="))
+        {
+            bail!("bad condition `{}`: multiple comparisons", expr);
+        }
+
+        if key.is_empty() {
+            bail!("bad condition `{}`: missing key", expr);
+        }
+
+        if value.is_empty() {
+            bail!("bad condition `{}`: missing value", expr);
+        }
+
+        Ok(Condition {
+            expr: expr.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            negated,
+        })
+    }
+
+    /// Test the condition against the given set of facts.
+    ///
+    /// A missing fact never compares equal to anything.
+    pub fn matches(&self, facts: &Facts) -> bool {
+        let equal = facts.get(self.key.as_str()) == Some(self.value.as_str());
+        equal != self.negated
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = Error;
+
+    fn try_from(expr: String) -> Result<Self, Self::Error> {
+        Condition::parse(&expr)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.expr.fmt(fmt)
+    }
+}
+
+impl fmt::Debug for Condition {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.expr.fmt(fmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condition;
+    use crate::facts::Facts;
+    use anyhow::Error;
+
+    fn evaluate(expr: &str, facts: &Facts) -> Result<bool, Error> {
+        Ok(Condition::parse(expr)?.matches(facts))
+    }
+
+    fn facts() -> Facts {
+        Facts::new(vec![
+            ("os".to_string(), "linux".to_string()),
+            ("distro".to_string(), "debian".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_matching() {
+        let facts = facts();
+        assert!(evaluate("os == linux", &facts).unwrap());
+        assert!(evaluate("distro==debian", &facts).unwrap());
+        assert!(evaluate("os != windows", &facts).unwrap());
+        assert!(evaluate("missing != value", &facts).unwrap());
+    }
+
+    #[test]
+    fn test_non_matching() {
+        let facts = facts();
+        assert!(!evaluate("os == windows", &facts).unwrap());
+        assert!(!evaluate("distro != debian", &facts).unwrap());
+        assert!(!evaluate("missing == value", &facts).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let condition: Condition = serde_yaml::from_str("os == linux").unwrap();
+        assert!(condition.matches(&facts()));
+        assert!(serde_yaml::from_str::<Condition>("os == linux != x").is_err());
+    }
+
+    #[test]
+    fn test_malformed() {
+        for expr in &[
+            "os",
+            "os = linux",
+            "== linux",
+            "os ==",
+            "os == linux == x",
+            "os == linux != x",
+            "os != linux == x",
+        ] {
+            let e = Condition::parse(expr).unwrap_err();
+            assert!(
+                e.to_string()
+                    .starts_with(&format!("bad condition `{}`", expr)),
+                "unexpected error: {}",
+                e
+            );
+        }
+    }
+}
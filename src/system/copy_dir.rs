@@ -1,9 +1,13 @@
 use crate::{
-    environment as e, system::SystemInput, template::Template, unit::SystemUnit, FileSystem,
+    environment as e, file_system::TemplateOptions, system::SystemInput, template::Template,
+    unit::SystemUnit, FileSystem,
 };
-use anyhow::{bail, Error};
+use anyhow::{anyhow, bail, Context as _, Error};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::BTreeSet;
 use std::fmt;
 use std::fs;
+use std::path::Path;
 
 system_struct! {
     #[doc = "Builds one unit for every directory and file that needs to be copied."]
@@ -15,6 +19,24 @@ system_struct! {
         #[serde(default)]
         #[doc="If we should treat files as templates."]
         pub templates: bool,
+        #[serde(default)]
+        #[doc="Fail to render templates which refer to variables that are not defined, instead of rendering them as empty."]
+        pub strict: bool,
+        #[serde(default)]
+        #[doc="Only copy files matching any of the given globs. If empty, copies all files."]
+        pub include: Vec<String>,
+        #[serde(default)]
+        #[doc="Don't copy files or directories matching any of the given globs."]
+        pub exclude: Vec<String>,
+        #[serde(default)]
+        #[doc="Back up existing files to `<name>.bak` before overwriting them with different content."]
+        pub backup: bool,
+        #[serde(default)]
+        #[doc="Follow symlinks in the source, copying what they point to instead of recreating the links."]
+        pub follow_symlinks: bool,
+        #[serde(default)]
+        #[doc="Delete files previously copied by this system which no longer exist in the source."]
+        pub delete_orphans: bool,
     }
 }
 
@@ -32,6 +54,8 @@ impl CopyDir {
             facts,
             environment,
             file_system,
+            state,
+            system,
             ..
         } = input;
 
@@ -48,25 +72,71 @@ impl CopyDir {
             None => return Ok(units),
         };
 
-        for e in ignore::WalkBuilder::new(&from).hidden(false).build() {
+        let filter = Filter::new(&self.include, &self.exclude)?;
+        let mut manifest = BTreeSet::new();
+
+        let template = if self.templates {
+            Some(TemplateOptions {
+                strict: self.strict,
+            })
+        } else {
+            None
+        };
+
+        let walker = {
+            let from = from.clone();
+            let exclude = filter.exclude.clone();
+
+            ignore::WalkBuilder::new(&from)
+                .hidden(false)
+                .follow_links(self.follow_symlinks)
+                .filter_entry(move |e| match e.path().strip_prefix(&from) {
+                    Ok(path) => !exclude.is_match(path),
+                    Err(_) => true,
+                })
+                .build()
+        };
+
+        for e in walker {
             let e = e?;
             let from_path = e.path();
-            let to_path = to.join(from_path.strip_prefix(&from)?);
+            let relative = from_path.strip_prefix(&from)?;
+            let to_path = to.join(relative);
 
-            let from = from_path.symlink_metadata()?;
+            let from = if self.follow_symlinks {
+                from_path.metadata()?
+            } else {
+                from_path.symlink_metadata()?
+            };
             let to = FileSystem::try_open_meta(&to_path)?;
 
             let source_type = from.file_type();
 
+            if !source_type.is_dir() && filter.include.is_some() {
+                if !filter.is_included(relative) {
+                    continue;
+                }
+
+                // NB: directories are only created as needed when we are filtering.
+                if let Some(parent) = to_path.parent() {
+                    units.extend(file_system.create_dir_all(system, parent)?);
+                }
+            }
+
+            if !source_type.is_dir() {
+                manifest.insert(to_path.clone());
+            }
+
             if source_type.is_symlink() {
                 let link = fs::read_link(from_path)?;
-                units.extend(file_system.symlink(&to_path, link, to.as_ref())?);
+                units.extend(file_system.symlink(system, &to_path, link, to.as_ref(), false)?);
                 continue;
             }
 
             if source_type.is_dir() {
-                if FileSystem::should_create_dir(&to_path, to.as_ref())? {
-                    units.extend(file_system.create_dir_all(&to_path)?);
+                if filter.include.is_none() && FileSystem::should_create_dir(&to_path, to.as_ref())?
+                {
+                    units.extend(file_system.create_dir_all(system, &to_path)?);
                 }
 
                 continue;
@@ -74,11 +144,13 @@ impl CopyDir {
 
             if source_type.is_file() {
                 units.extend(file_system.copy_file(
+                    system,
                     from_path,
                     from,
                     &to_path,
                     to.as_ref(),
-                    self.templates,
+                    template,
+                    self.backup,
                 )?);
                 continue;
             }
@@ -90,12 +162,234 @@ impl CopyDir {
             );
         }
 
+        if self.delete_orphans {
+            let id = match self.id.as_deref() {
+                Some(id) => id.to_string(),
+                None => id_from(&(&from, &to)),
+            };
+
+            units.extend(file_system.delete_orphans(system, state, &id, manifest)?);
+        }
+
         Ok(units)
     }
 }
 
+/// Generate a unique ID for the directory being copied.
+fn id_from(value: &(impl std::hash::Hash + ?Sized)) -> String {
+    use std::hash::Hasher;
+
+    let mut state = fxhash::FxHasher64::default();
+    value.hash(&mut state);
+
+    format!("copy-dir-{:x}", state.finish())
+}
+
+/// Include and exclude filters for files being copied.
+///
+/// Globs are matched against the path relative to the directory being copied from. Excludes take
+/// precedence over includes, and an excluded directory excludes everything inside of it.
+struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    /// Construct a new filter out of the given include and exclude globs.
+    fn new(include: &[String], exclude: &[String]) -> Result<Self, Error> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build(include).with_context(|| anyhow!("bad `include` glob"))?)
+        };
+
+        let exclude = build(exclude).with_context(|| anyhow!("bad `exclude` glob"))?;
+        return Ok(Filter { include, exclude });
+
+        fn build(globs: &[String]) -> Result<GlobSet, Error> {
+            let mut builder = GlobSetBuilder::new();
+
+            for glob in globs {
+                builder.add(Glob::new(glob)?);
+            }
+
+            Ok(builder.build()?)
+        }
+    }
+
+    /// Test if the given relative file path is included by the filter.
+    fn is_included(&self, path: &Path) -> bool {
+        if self.exclude.is_match(path) {
+            return false;
+        }
+
+        match self.include.as_ref() {
+            Some(include) => include.is_match(path),
+            None => true,
+        }
+    }
+}
+
 impl fmt::Display for CopyDir {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(fmt, "copy directory from `{}` to `{}`", self.from, self.to)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+    use std::path::Path;
+
+    #[cfg(unix)]
+    fn symlinked_tree(follow_symlinks: bool) -> Vec<String> {
+        use crate::system::{test_apply, System};
+
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("from");
+        std::fs::create_dir_all(from.join("real")).unwrap();
+        std::fs::write(from.join("real").join("a.txt"), "a").unwrap();
+        std::os::unix::fs::symlink("real", from.join("linked")).unwrap();
+
+        let config = format!(
+            "type: copy-dir\nfrom: from\nto: to\nfollow_symlinks: {}\n",
+            follow_symlinks
+        );
+        let system = serde_yaml::from_str::<System>(&config).unwrap();
+
+        test_apply(root.path(), &system)
+            .unwrap()
+            .into_iter()
+            .map(|unit| unit.to_string())
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_dir() {
+        let units = symlinked_tree(false);
+        let linked = Path::new("to").join("linked");
+        let linked = linked.display().to_string();
+
+        assert!(
+            units
+                .iter()
+                .any(|u| u.contains("link file") && u.contains(&linked)),
+            "{:?}",
+            units
+        );
+        assert_eq!(
+            units.iter().filter(|u| u.contains("copy file")).count(),
+            1,
+            "{:?}",
+            units
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_dir_followed() {
+        let units = symlinked_tree(true);
+        let copied = Path::new("linked").join("a.txt");
+        let copied = copied.display().to_string();
+
+        assert!(
+            !units.iter().any(|u| u.contains("link file")),
+            "{:?}",
+            units
+        );
+        assert!(
+            units
+                .iter()
+                .any(|u| u.contains("copy file") && u.contains(&copied)),
+            "{:?}",
+            units
+        );
+        assert_eq!(
+            units.iter().filter(|u| u.contains("copy file")).count(),
+            2,
+            "{:?}",
+            units
+        );
+    }
+
+    fn filter(include: &[&str], exclude: &[&str]) -> Filter {
+        let include = include.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let exclude = exclude.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        Filter::new(&include, &exclude).unwrap()
+    }
+
+    /// Copy a directory, remove a file from the source and copy it again.
+    ///
+    /// Returns `true` if the removed file is still present in the destination.
+    fn removed_file_is_kept(delete_orphans: bool) -> bool {
+        use crate::system::{test_run, System};
+        use crate::{Config, State, Timestamp};
+
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("from");
+        let to = root.path().join("to");
+        std::fs::create_dir_all(&from).unwrap();
+        std::fs::write(from.join("a.txt"), "a").unwrap();
+        std::fs::write(from.join("b.txt"), "b").unwrap();
+
+        let config = format!(
+            "type: copy-dir\nfrom: from\nto: to\ndelete_orphans: {}\n",
+            delete_orphans
+        );
+        let system = serde_yaml::from_str::<System>(&config).unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+
+        test_run(root.path(), &system, &mut state).unwrap();
+        assert!(to.join("b.txt").is_file());
+
+        // NB: files not created by the system are left alone.
+        std::fs::write(to.join("user.txt"), "user").unwrap();
+        std::fs::remove_file(from.join("b.txt")).unwrap();
+        test_run(root.path(), &system, &mut state).unwrap();
+
+        assert!(to.join("a.txt").is_file());
+        assert!(to.join("user.txt").is_file());
+        to.join("b.txt").exists()
+    }
+
+    #[test]
+    fn test_delete_orphans() {
+        assert!(!removed_file_is_kept(true));
+        assert!(removed_file_is_kept(false));
+    }
+
+    #[test]
+    fn test_include() {
+        let filter = filter(&["*.conf"], &[]);
+        assert!(filter.is_included(Path::new("a.conf")));
+        assert!(filter.is_included(Path::new("dir/b.conf")));
+        assert!(!filter.is_included(Path::new("a.txt")));
+    }
+
+    #[test]
+    fn test_exclude() {
+        let filter = filter(&[], &["*.bak"]);
+        assert!(filter.include.is_none());
+        assert!(filter.is_included(Path::new("a.conf")));
+        assert!(!filter.is_included(Path::new("a.conf.bak")));
+        assert!(!filter.is_included(Path::new("dir/a.bak")));
+    }
+
+    #[test]
+    fn test_exclude_takes_precedence() {
+        let filter = filter(&["*.conf"], &["secret.conf", "private/**"]);
+        assert!(filter.is_included(Path::new("a.conf")));
+        assert!(!filter.is_included(Path::new("secret.conf")));
+        assert!(!filter.is_included(Path::new("private/a.conf")));
+        assert!(!filter.is_included(Path::new("a.txt")));
+    }
+
+    #[test]
+    fn test_bad_glob() {
+        let include = vec![String::from("a[")];
+        assert!(Filter::new(&include, &[]).is_err());
+    }
+}
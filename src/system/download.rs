@@ -1,19 +1,63 @@
 use crate::{
     environment as e,
+    facts::Facts,
     system::SystemInput,
     template::Template,
     unit::{self, Dependency},
 };
 use anyhow::{anyhow, bail, Context as _, Error};
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::fmt;
 
 system_struct! {
     #[doc = "Builds one unit for every directory and file that needs to be copied."]
     Download {
-        #[doc="URL to download."]
-        pub url: String,
-        #[doc="Where to download the file to."]
+        #[doc="URL to download, which is a template."]
+        pub url: Template,
+        #[doc="Where to download the file to. If this is a directory, the name of the file is taken from the URL."]
         pub path: Template,
+        #[doc="Headers to add to the request, which are templates."]
+        #[serde(default)]
+        pub headers: HashMap<String, Template>,
+        #[doc="Basic authentication to use for the request."]
+        #[serde(default)]
+        pub auth: Option<Auth>,
+    }
+}
+
+/// Basic authentication for a download.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Auth {
+    /// The user to authenticate as.
+    pub user: Template,
+    /// The password of the user.
+    #[serde(default)]
+    pub password: Option<Template>,
+}
+
+impl Auth {
+    /// Render the credentials to use.
+    pub(crate) fn render<E>(&self, facts: &Facts, environment: E) -> Result<unit::BasicAuth, Error>
+    where
+        E: Copy + e::Environment,
+    {
+        let user = self
+            .user
+            .as_string(facts, environment)?
+            .ok_or_else(|| anyhow!("Cannot render `auth.user`"))?;
+
+        let password = match self.password.as_ref() {
+            Some(password) => Some(
+                password
+                    .as_string(facts, environment)?
+                    .ok_or_else(|| anyhow!("Cannot render `auth.password`"))?,
+            ),
+            None => None,
+        };
+
+        Ok(unit::BasicAuth { user, password })
     }
 }
 
@@ -33,44 +77,77 @@ impl Download {
             facts,
             environment,
             file_system,
+            system,
+            force,
             ..
         } = input;
 
-        let url = reqwest::Url::parse(&self.url).with_context(|| anyhow!("illegal `url`"))?;
+        let rendered = self
+            .url
+            .as_url(facts, environment)?
+            .ok_or_else(|| anyhow!("Cannot render `url`"))?;
+
+        let url = reqwest::Url::parse(&rendered).with_context(|| anyhow!("illegal `url`"))?;
         let base = url_base_name(&url);
 
         let generated_id;
 
         let id = {
             if let Some(base) = base {
-                generated_id = format!("{id}-{base}", id = id_from_url(&self.url), base = base);
+                generated_id = format!("{id}-{base}", id = id_from_url(&rendered), base = base);
             } else {
-                generated_id = id_from_url(&self.url);
+                generated_id = id_from_url(&rendered);
             }
 
             generated_id.as_str()
         };
 
-        if state.has_run_once(id) {
-            return Ok(vec![]);
-        }
-
         let path = match self.path.as_path(root, base_dirs, facts, environment)? {
             Some(path) => path,
             None => bail!("target path is not supported"),
         };
 
+        // NB: the trailing separator is lost when the template is converted into a path.
+        let is_dir = self.path.to_string().ends_with(['/', '\\']) || path.is_dir();
+
+        let path = if is_dir {
+            match base {
+                Some(base) => path.join(base),
+                None => bail!(
+                    "cannot download to directory `{}`, since the url has no file name",
+                    path.display()
+                ),
+            }
+        } else {
+            path
+        };
+
+        // NB: the file is downloaded again if it has been removed.
+        if state.has_run_once(id) && path.is_file() && !force {
+            return Ok(vec![]);
+        }
+
         let mut units = Vec::new();
         let mut create_dirs = Vec::new();
 
         if let Some(parent) = path.parent() {
-            create_dirs.extend(file_system.create_dir_all(parent)?);
+            create_dirs.extend(file_system.create_dir_all(system, parent)?);
         }
 
+        let headers = render_headers(&self.headers, facts, environment)?;
+
+        let auth = match self.auth.as_ref() {
+            Some(auth) => Some(auth.render(facts, environment)?),
+            None => None,
+        };
+
         let mut download = allocator.unit(unit::Download {
             url,
             path,
-            id: None,
+            id: Some(id.into()),
+            headers,
+            auth,
+            force,
         });
 
         download
@@ -90,6 +167,31 @@ impl fmt::Display for Download {
     }
 }
 
+/// Render the headers to use for a download.
+pub(crate) fn render_headers<E>(
+    headers: &HashMap<String, Template>,
+    facts: &Facts,
+    environment: E,
+) -> Result<Vec<(String, String)>, Error>
+where
+    E: Copy + e::Environment,
+{
+    let mut headers = headers.iter().collect::<Vec<_>>();
+    headers.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut rendered = Vec::new();
+
+    for (key, value) in headers {
+        let value = value
+            .as_string(facts, environment)?
+            .ok_or_else(|| anyhow!("Cannot render header `{}`", key))?;
+
+        rendered.push((key.to_string(), value));
+    }
+
+    Ok(rendered)
+}
+
 /// Generate a unique ID from the URL provided.
 fn id_from_url(url: &str) -> String {
     use std::hash::{Hash, Hasher};
@@ -110,3 +212,133 @@ fn url_base_name(url: &reqwest::Url) -> Option<&str> {
 
     Some(base)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::system::{test_apply, test_apply_with_facts, test_apply_with_state, System};
+    use crate::{config::Config, facts::Facts, state::State, Timestamp};
+    use std::path::Path;
+
+    /// Apply a download to `path`, returning the path that is downloaded to.
+    fn download(root: &Path, path: &str) -> String {
+        let config = format!(
+            "type: download\nurl: https://example.com/files/plug.vim\npath: \"{}\"\n",
+            path
+        );
+        let system = serde_yaml::from_str::<System>(&config).unwrap();
+        let units = test_apply(root, &system).unwrap();
+        let prefix = "download https://example.com/files/plug.vim to ";
+
+        let unit = units
+            .iter()
+            .map(|unit| unit.to_string())
+            .find(|unit| unit.contains(prefix))
+            .expect("download unit");
+
+        let path = &unit[unit.find(prefix).unwrap() + prefix.len()..];
+        path[..path.rfind(" (depends").unwrap()].to_string()
+    }
+
+    fn display(path: impl AsRef<Path>) -> String {
+        path.as_ref().display().to_string()
+    }
+
+    #[test]
+    fn test_download_to_file() {
+        let root = tempfile::tempdir().unwrap();
+
+        assert_eq!(
+            download(root.path(), "autoload/other.vim"),
+            display(root.path().join("autoload").join("other.vim"))
+        );
+    }
+
+    #[test]
+    fn test_download_to_dir() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("existing")).unwrap();
+
+        assert_eq!(
+            download(root.path(), "existing"),
+            display(root.path().join("existing").join("plug.vim"))
+        );
+
+        assert_eq!(
+            download(root.path(), "autoload/"),
+            display(root.path().join("autoload").join("plug.vim"))
+        );
+    }
+
+    #[test]
+    fn test_download_once() {
+        let root = tempfile::tempdir().unwrap();
+        let config = "type: download\nurl: https://example.com/files/plug.vim\npath: plug.vim\n";
+        let system = serde_yaml::from_str::<System>(config).unwrap();
+
+        let units = test_apply(root.path(), &system).unwrap();
+        let id = units
+            .iter()
+            .find_map(|unit| match unit.unit() {
+                crate::unit::Unit::Download(download) => download.id.clone(),
+                _ => None,
+            })
+            .expect("download id");
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        state.touch_once(&id);
+
+        // NB: the file is missing, so it's downloaded again.
+        let units = test_apply_with_state(root.path(), &system, &state).unwrap();
+        assert!(!units.is_empty());
+
+        std::fs::write(root.path().join("plug.vim"), "").unwrap();
+        let units = test_apply_with_state(root.path(), &system, &state).unwrap();
+        assert!(units.is_empty());
+    }
+
+    #[test]
+    fn test_download_to_dir_without_name() {
+        let root = tempfile::tempdir().unwrap();
+        let config = "type: download\nurl: https://example.com/\npath: autoload/\n";
+        let system = serde_yaml::from_str::<System>(config).unwrap();
+        assert!(test_apply(root.path(), &system).is_err());
+    }
+
+    #[test]
+    fn test_download_url_template() {
+        let root = tempfile::tempdir().unwrap();
+        let config = "type: download\nurl: https://example.com/tool-{arch}\npath: bin/\n";
+        let system = serde_yaml::from_str::<System>(config).unwrap();
+
+        let config = Config::default();
+        let state = State::new(&config, Timestamp::now());
+
+        let download = |arch: &str| {
+            let facts = Facts::new(vec![(String::from("arch"), String::from(arch))]);
+            let units = test_apply_with_facts(root.path(), &system, &state, &facts).unwrap();
+
+            units
+                .iter()
+                .find_map(|unit| match unit.unit() {
+                    crate::unit::Unit::Download(download) => {
+                        Some((download.url.to_string(), download.id.clone().unwrap()))
+                    }
+                    _ => None,
+                })
+                .expect("download unit")
+        };
+
+        let (url, x86_64) = download("x86_64");
+        assert_eq!(url, "https://example.com/tool-x86_64");
+        assert!(x86_64.ends_with("-tool-x86_64"));
+
+        // NB: the id is generated from the rendered url.
+        let (url, aarch64) = download("aarch64");
+        assert_eq!(url, "https://example.com/tool-aarch64");
+        assert_ne!(x86_64, aarch64);
+
+        // NB: urls which can't be rendered are an error.
+        assert!(test_apply(root.path(), &system).is_err());
+    }
+}
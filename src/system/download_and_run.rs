@@ -1,17 +1,22 @@
 use crate::{
-    environment as e, os,
-    system::SystemInput,
+    config, environment as e, os,
+    system::{
+        download::{render_headers, Auth},
+        SystemInput,
+    },
     template::Template,
     unit::{AddMode, Dependency, Download, Mode, RunOnce, SystemUnit},
 };
-use anyhow::{anyhow, Context as _, Error};
+use anyhow::{anyhow, bail, Context as _, Error};
+use std::collections::HashMap;
 use std::fmt;
+use std::time::Duration;
 
 system_struct! {
     #[doc = "Builds one unit for every directory and file that needs to be copied."]
     DownloadAndRun {
-        #[doc="URL to download."]
-        pub url: String,
+        #[doc="URL to download, which is a template."]
+        pub url: Template,
         #[doc="Run the command through `/bin/sh`."]
         #[serde(default)]
         pub shell: bool,
@@ -27,6 +32,30 @@ system_struct! {
         /// Run the downloaded command as root.
         #[serde(default)]
         pub root: bool,
+        #[doc="Kill the command if it runs for longer than this."]
+        #[serde(default, deserialize_with = "config::human_duration_option")]
+        pub timeout: Option<Duration>,
+        #[doc="Environment variables to set when running the command."]
+        #[serde(default)]
+        pub env: HashMap<String, Template>,
+        #[doc="Run the command again if the downloaded file has changed since it last ran."]
+        #[serde(default)]
+        pub rerun_on_change: bool,
+        #[doc="The working directory to run the command in."]
+        #[serde(default)]
+        pub cwd: Option<Template>,
+        #[doc="Only run the command if this shell command succeeds."]
+        #[serde(default)]
+        pub only_if: Option<Template>,
+        #[doc="Only run the command if this shell command fails."]
+        #[serde(default)]
+        pub unless: Option<Template>,
+        #[doc="Headers to add to the download request, which are templates."]
+        #[serde(default)]
+        pub headers: HashMap<String, Template>,
+        #[doc="Basic authentication to use for the download request."]
+        #[serde(default)]
+        pub auth: Option<Auth>,
     }
 }
 
@@ -39,15 +68,29 @@ impl DownloadAndRun {
         E: Copy + e::Environment,
     {
         let SystemInput {
+            root,
+            base_dirs,
             allocator,
             file_system,
             state,
             facts,
             environment,
+            force,
             ..
         } = input;
 
-        let url = reqwest::Url::parse(&self.url).with_context(|| anyhow!("illegal `url`"))?;
+        // NB: commands with a timeout run in their own process group, which prevents them from
+        // reading from the terminal.
+        if self.timeout.is_some() && (self.interactive || self.root) {
+            bail!("`timeout` cannot be used together with `interactive` or `root`");
+        }
+
+        let rendered = self
+            .url
+            .as_url(facts, environment)?
+            .ok_or_else(|| anyhow!("Cannot render `url`"))?;
+
+        let url = reqwest::Url::parse(&rendered).with_context(|| anyhow!("illegal `url`"))?;
         let base = url_base_name(&url);
 
         let generated_id;
@@ -56,16 +99,17 @@ impl DownloadAndRun {
             Some(id) => id,
             None => {
                 if let Some(base) = base {
-                    generated_id = format!("{id}-{base}", id = id_from_url(&self.url), base = base);
+                    generated_id = format!("{id}-{base}", id = id_from_url(&rendered), base = base);
                 } else {
-                    generated_id = id_from_url(&self.url);
+                    generated_id = id_from_url(&rendered);
                 }
 
                 generated_id.as_str()
             }
         };
 
-        if state.has_run_once(id) {
+        // NB: with `rerun_on_change` the run unit decides based on the contents of the file.
+        if state.has_run_once(id) && !self.rerun_on_change && !force {
             return Ok(vec![]);
         }
 
@@ -80,12 +124,24 @@ impl DownloadAndRun {
 
         let mut units = Vec::new();
 
-        let download = if !path.is_file() {
+        // NB: with `rerun_on_change` the file is downloaded again, so that changes upstream are
+        // picked up by the run unit.
+        let download = if !path.is_file() || self.rerun_on_change {
             // Download the file.
+            let headers = render_headers(&self.headers, facts, environment)?;
+
+            let auth = match self.auth.as_ref() {
+                Some(auth) => Some(auth.render(facts, environment)?),
+                None => None,
+            };
+
             Some(allocator.unit(Download {
                 url,
                 path: path.clone().into_owned(),
                 id: None,
+                headers,
+                auth,
+                force: self.rerun_on_change,
             }))
         } else {
             None
@@ -102,6 +158,34 @@ impl DownloadAndRun {
         let mut run_once = RunOnce::new(id.to_string(), path.into_owned());
         run_once.shell = self.shell;
         run_once.root = self.root;
+        run_once.timeout = self.timeout;
+        run_once.rerun_on_change = self.rerun_on_change;
+        run_once.force = force;
+        run_once.interactive = self.interactive;
+
+        if let Some(cwd) = self.cwd.as_ref() {
+            let cwd = cwd
+                .as_path(root, base_dirs, facts, environment)?
+                .ok_or_else(|| anyhow!("Cannot render `cwd`"))?;
+
+            run_once.cwd = Some(cwd);
+        }
+
+        if let Some(only_if) = self.only_if.as_ref() {
+            let only_if = only_if
+                .as_string(facts, environment)?
+                .ok_or_else(|| anyhow!("Cannot render `only_if`"))?;
+
+            run_once.only_if = Some(only_if);
+        }
+
+        if let Some(unless) = self.unless.as_ref() {
+            let unless = unless
+                .as_string(facts, environment)?
+                .ok_or_else(|| anyhow!("Cannot render `unless`"))?;
+
+            run_once.unless = Some(unless);
+        }
 
         for (i, arg) in self.args.iter().enumerate() {
             let arg = arg
@@ -111,6 +195,20 @@ impl DownloadAndRun {
             run_once.args.push(arg);
         }
 
+        // NB: facts are available as `QUICKCFG_<FACT>`, but explicit variables take precedence.
+        run_once.env.extend(facts.env_vars());
+
+        let mut env = self.env.iter().collect::<Vec<_>>();
+        env.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (key, value) in env {
+            let value = value
+                .as_string(facts, environment)?
+                .ok_or_else(|| anyhow!("Cannot render environment variable `{}`", key))?;
+
+            run_once.env.push((key.to_string(), value));
+        }
+
         let mut run = allocator.unit(run_once);
         run.dependencies.push(Dependency::Unit(add_mode.id));
         run.thread_local = self.interactive || self.root;
@@ -149,3 +247,76 @@ fn url_base_name(url: &reqwest::Url) -> Option<&str> {
 
     Some(base)
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::system::{test_run, System};
+    use crate::{Config, State, Timestamp};
+
+    /// Serve one request for every body on a local port, responding with the bodies in order.
+    fn serve(bodies: Vec<String>) -> (String, std::thread::JoinHandle<()>) {
+        use std::io::{BufRead as _, BufReader, Write as _};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            for body in bodies {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                }
+
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (format!("http://{}/script.sh", addr), handle)
+    }
+
+    #[test]
+    fn test_rerun_on_change_download() {
+        let root = tempfile::tempdir().unwrap();
+        let out = root.path().join("out");
+        let script = format!("echo run >> {}\n", out.display());
+        let changed = format!("# changed\n{}", script);
+
+        let (url, handle) = serve(vec![script.clone(), script, changed]);
+
+        let config = format!(
+            "type: download-and-run\nid: script\nurl: {}\nshell: true\nrerun_on_change: true\n",
+            url
+        );
+        let system = serde_yaml::from_str::<System>(&config).unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        let runs = || std::fs::read_to_string(&out).unwrap().lines().count();
+
+        test_run(root.path(), &system, &mut state).unwrap();
+        assert_eq!(runs(), 1);
+
+        // NB: the file is downloaded again, but hasn't changed.
+        test_run(root.path(), &system, &mut state).unwrap();
+        assert_eq!(runs(), 1);
+
+        test_run(root.path(), &system, &mut state).unwrap();
+        assert_eq!(runs(), 2);
+
+        handle.join().unwrap();
+    }
+}
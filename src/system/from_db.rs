@@ -1,9 +1,13 @@
 use crate::{
     environment as e,
-    system::{System, SystemInput, SystemUnit, Translation},
-    unit,
+    facts::Facts,
+    system::{self, System, SystemInput, SystemUnit},
+    template::Template,
+    unit, Data,
 };
 use anyhow::Result;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
 use std::fmt;
 
 system_struct! {
@@ -13,6 +17,9 @@ system_struct! {
         pub system: String,
         #[doc="The key to use when instantiating from the database."]
         pub key: String,
+        #[doc="Only instantiate entries with fields matching all of the given values."]
+        #[serde(default)]
+        pub filter: Option<HashMap<String, Template>>,
     }
 }
 
@@ -24,10 +31,12 @@ impl FromDb {
     where
         E: Copy + e::Environment,
     {
-        use serde_yaml::Value;
-
         let SystemInput {
-            allocator, data, ..
+            allocator,
+            data,
+            facts,
+            environment,
+            ..
         } = input;
 
         let mut unit = allocator.unit(unit::FromDb {
@@ -35,37 +44,65 @@ impl FromDb {
             key: self.key.clone(),
         });
 
-        let systems = data.load_array::<serde_yaml::Mapping>(&self.system)?;
+        let systems = self.systems(data, facts, environment)?;
         let mut out = Vec::new();
 
-        for mut system in systems {
-            system.insert("type".into(), self.system.clone().into());
-            let system = serde_yaml::from_value::<System>(Value::Mapping(system))?;
-
-            match system.translate() {
-                Translation::Discard => continue,
-                Translation::Keep => {
-                    for s in system.apply(input)? {
-                        unit.dependencies.push(unit::Dependency::Unit(s.id));
-                        out.push(s);
-                    }
-                }
-                Translation::Expand(systems) => {
-                    for system in systems {
-                        for s in system.apply(input)? {
-                            unit.dependencies.push(unit::Dependency::Unit(s.id));
-                            out.push(s);
-                        }
-                    }
-
-                    continue;
-                }
+        for system in system::expand(&systems, facts) {
+            for s in system.apply(input)? {
+                unit.dependencies.push(unit::Dependency::Unit(s.id));
+                out.push(s);
             }
         }
 
         out.push(unit);
         Ok(out)
     }
+
+    /// Load the systems to instantiate from the database.
+    pub fn systems<E>(&self, data: &Data, facts: &Facts, environment: E) -> Result<Vec<System>>
+    where
+        E: Copy + e::Environment,
+    {
+        let mut filter = Vec::new();
+
+        for (key, value) in self.filter.iter().flatten() {
+            filter.push((key.as_str(), value.as_string(facts, environment)?));
+        }
+
+        let mut out = Vec::new();
+
+        for mut system in data.load_array::<Mapping>(&self.system)? {
+            if !filter_matches(&mut system, &filter) {
+                continue;
+            }
+
+            system.insert("type".into(), self.system.clone().into());
+            out.push(serde_yaml::from_value::<System>(Value::Mapping(system))?);
+        }
+
+        Ok(out)
+    }
+}
+
+/// Test if the given entry matches the filter.
+///
+/// The fields being filtered on are removed, since they are not part of the system.
+fn filter_matches(entry: &mut Mapping, filter: &[(&str, Option<String>)]) -> bool {
+    let mut matches = true;
+
+    for (key, expected) in filter {
+        let value = match entry.remove(*key) {
+            Some(Value::String(s)) => Some(s),
+            Some(Value::Number(n)) => Some(n.to_string()),
+            Some(Value::Bool(b)) => Some(b.to_string()),
+            _ => None,
+        };
+
+        // NB: a value which can't be rendered never matches.
+        matches &= expected.is_some() && value == *expected;
+    }
+
+    matches
 }
 
 impl fmt::Display for FromDb {
@@ -77,3 +114,61 @@ impl fmt::Display for FromDb {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::FromDb;
+    use crate::{environment as e, facts::Facts, system::System, Data};
+
+    #[test]
+    fn test_filter() {
+        let from_db = serde_yaml::from_str::<FromDb>(
+            r#"
+system: link
+key: links
+filter:
+  host: "{hostname}"
+"#,
+        )
+        .unwrap();
+
+        let db = serde_yaml::from_str(
+            r#"
+link:
+  - host: laptop
+    path: home://a
+    link: a
+  - host: desktop
+    path: home://b
+    link: b
+  - path: home://c
+    link: c
+  - host: laptop
+    path: home://d
+    link: d
+"#,
+        )
+        .unwrap();
+
+        let data = Data::new(None, vec![db]);
+        let facts = Facts::new(vec![(String::from("hostname"), String::from("laptop"))]);
+
+        let systems = from_db.systems(&data, &facts, e::Real).unwrap();
+
+        let paths = systems
+            .iter()
+            .map(|system| match system {
+                System::Link(link) => link.path.to_string(),
+                other => panic!("unexpected system: {:?}", other),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(paths, vec!["home://a", "home://d"]);
+
+        // A filter on a missing fact matches nothing.
+        let systems = from_db
+            .systems(&data, &Facts::new(Vec::new()), e::Real)
+            .unwrap();
+        assert!(systems.is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    config, environment as e,
+    config, environment as e, git,
     system::SystemInput,
     template::Template,
     unit::{GitClone, GitUpdate, SystemUnit},
@@ -15,8 +15,21 @@ system_struct! {
     GitSync {
         #[doc="Path to check out the repo."]
         pub path: Template,
-        #[doc="Remote to keep in sync with."]
-        pub remote: String,
+        #[doc="Remote to keep in sync with. Can be a list of remotes, which are tried in order."]
+        #[serde(deserialize_with = "remotes")]
+        pub remote: Vec<String>,
+        #[doc="Branch to track, instead of the default branch of the remote."]
+        #[serde(default)]
+        pub branch: Option<String>,
+        #[doc="Create a shallow clone truncated to the given number of commits."]
+        #[serde(default)]
+        pub depth: Option<u32>,
+        #[doc="SSH private key to authenticate against the remote with."]
+        #[serde(default)]
+        pub identity: Option<Template>,
+        #[doc="Initialize and update submodules after cloning or updating."]
+        #[serde(default)]
+        pub submodules: bool,
         #[serde(
             default = "default_refresh",
             deserialize_with = "config::human_duration"
@@ -25,6 +38,32 @@ system_struct! {
     }
 }
 
+/// Deserialize either a single remote or a list of remotes.
+fn remotes<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::{de::Error as _, Deserialize};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Remotes {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let remotes = match Remotes::deserialize(deserializer)? {
+        Remotes::One(remote) => vec![remote],
+        Remotes::Many(remotes) => remotes,
+    };
+
+    if remotes.is_empty() {
+        return Err(D::Error::custom("expected at least one remote"));
+    }
+
+    Ok(remotes)
+}
+
 /// Get default refresh.
 fn default_refresh() -> Duration {
     Duration::from_secs(DEFAULT_REFRESH)
@@ -49,6 +88,8 @@ impl GitSync {
             now,
             opts,
             git_system,
+            system,
+            force,
             ..
         } = input;
 
@@ -63,7 +104,7 @@ impl GitSync {
             None => return Ok(units),
         };
 
-        if let Some(last_update) = state.last_update(&id) {
+        if let Some(last_update) = state.last_update(&id).filter(|_| !force) {
             let duration = now.duration_since(*last_update)?;
 
             if duration < self.refresh {
@@ -76,11 +117,26 @@ impl GitSync {
             return Ok(units);
         }
 
+        let identity = match self.identity.as_ref() {
+            Some(identity) => identity.as_path(root, base_dirs, facts, environment)?,
+            None => None,
+        };
+
+        let options = git::Options {
+            branch: self.branch.clone(),
+            depth: self.depth,
+            identity,
+            submodules: self.submodules,
+            remotes: self.remote.clone(),
+        };
+
         if path.is_dir() {
             let git_update = allocator.unit(GitUpdate {
                 id,
                 path,
-                force: opts.force,
+                force: opts.force || opts.force_dirty,
+                force_dirty: opts.force_dirty,
+                options,
             });
 
             units.push(git_update);
@@ -90,22 +146,18 @@ impl GitSync {
         // Initial clone.
         let parent_dir = match path.parent() {
             Some(parent) if !parent.is_dir() => {
-                units.extend(file_system.create_dir_all(parent)?);
-                Some(file_system.dir_dependency(parent)?)
+                units.extend(file_system.create_dir_all(system, parent)?);
+                Some(file_system.dir_dependency(system, parent)?)
             }
             _ => None,
         };
 
-        let dir_dependencies = file_system.dir_dependency(&path)?;
+        let dir_dependencies = file_system.provide_dir(system, &path)?;
 
-        let mut git_clone = allocator.unit(GitClone {
-            id,
-            path,
-            remote: self.remote.to_string(),
-        });
+        let mut git_clone = allocator.unit(GitClone { id, path, options });
 
         git_clone.dependencies.extend(parent_dir);
-        git_clone.provides.push(dir_dependencies);
+        git_clone.provides.extend(dir_dependencies);
 
         units.push(git_clone);
         Ok(units)
@@ -114,6 +166,40 @@ impl GitSync {
 
 impl fmt::Display for GitSync {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "syncing remote `{}` to `{}`", self.remote, self.path)
+        write!(
+            fmt,
+            "syncing remote `{}` to `{}`",
+            self.remote.join("`, `"),
+            self.path
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GitSync;
+
+    #[test]
+    fn test_remotes() {
+        let system: GitSync =
+            serde_yaml::from_str("path: home://repo\nremote: https://example.com/a.git\n").unwrap();
+        assert_eq!(
+            system.remote,
+            vec![String::from("https://example.com/a.git")]
+        );
+
+        let system: GitSync = serde_yaml::from_str(
+            "path: home://repo\nremote:\n  - https://example.com/a.git\n  - https://example.com/b.git\n",
+        )
+        .unwrap();
+        assert_eq!(
+            system.remote,
+            vec![
+                String::from("https://example.com/a.git"),
+                String::from("https://example.com/b.git"),
+            ]
+        );
+
+        assert!(serde_yaml::from_str::<GitSync>("path: home://repo\nremote: []\n").is_err());
     }
 }
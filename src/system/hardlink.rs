@@ -0,0 +1,54 @@
+use crate::{environment as e, system::SystemInput, template::Template, unit::SystemUnit};
+use anyhow::Error;
+use std::fmt;
+
+system_struct! {
+    #[doc = "Builds a unit to create a hardlink."]
+    Hardlink {
+        #[doc="Where to create the hardlink."]
+        pub path: Template,
+        #[doc="The existing file the hardlink should refer to."]
+        pub link: Template,
+    }
+}
+
+impl Hardlink {
+    system_defaults!(translate);
+
+    /// Create a hardlink.
+    pub fn apply<E>(&self, input: SystemInput<E>) -> Result<Vec<SystemUnit>, Error>
+    where
+        E: Copy + e::Environment,
+    {
+        let SystemInput {
+            root,
+            base_dirs,
+            facts,
+            environment,
+            file_system,
+            system,
+            ..
+        } = input;
+
+        let mut units = Vec::new();
+
+        let path = match self.path.as_path(root, base_dirs, facts, environment)? {
+            Some(path) => path,
+            None => return Ok(units),
+        };
+
+        let link = match self.link.as_path(root, base_dirs, facts, environment)? {
+            Some(link) => link,
+            None => return Ok(units),
+        };
+
+        units.extend(file_system.hardlink(system, &path, link)?);
+        Ok(units)
+    }
+}
+
+impl fmt::Display for Hardlink {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "hardlink `{}` to `{}`", self.path, self.link)
+    }
+}
@@ -1,11 +1,13 @@
 use crate::{
     environment as e,
+    packages::{Package, PackageSpec},
     system::SystemInput,
     unit::{self, SystemUnit},
 };
-use anyhow::{anyhow, Error};
-use std::collections::{BTreeSet, HashSet};
+use anyhow::{anyhow, bail, Error};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
+use std::sync::Arc;
 
 system_struct! {
     #[doc = "Builds one unit for every batch of packages to install."]
@@ -13,8 +15,14 @@ system_struct! {
         #[doc="Hierarchy key to lookup for packages to install."]
         #[serde(default = "default_key")]
         pub key: String,
+        #[doc="Hierarchy key to lookup for packages to remove."]
+        #[serde(default = "default_remove_key")]
+        pub remove_key: String,
         #[doc="Package provider to use."]
         pub provider: Option<String>,
+        #[doc="Options passed to the package provider, like `scope` for `winget`."]
+        #[serde(default)]
+        pub provider_options: BTreeMap<String, String>,
     }
 }
 
@@ -23,6 +31,11 @@ fn default_key() -> String {
     String::from("packages")
 }
 
+/// Default key to look up for removing packages.
+fn default_remove_key() -> String {
+    String::from("packages_remove")
+}
+
 impl Install {
     system_defaults!(translate);
 
@@ -36,6 +49,7 @@ impl Install {
             data,
             allocator,
             state,
+            force,
             ..
         } = input;
 
@@ -66,10 +80,56 @@ impl Install {
             },
         };
 
-        all_packages.extend(data.load_or_default::<Vec<String>>(&key)?);
+        all_packages.extend(data.load_or_default::<Vec<PackageSpec>>(&key)?);
+
+        let remove_key = match provider {
+            Some(provider) => format!("{}::{}", provider, self.remove_key),
+            None => self.remove_key.to_string(),
+        };
+
+        let mut all_removed = BTreeSet::new();
+        all_removed.extend(data.load_or_default::<Vec<String>>(&remove_key)?);
+
+        if let Some(package) = all_packages
+            .iter()
+            .find(|package| all_removed.contains(&package.name))
+        {
+            bail!(
+                "package `{}` is listed both in `{}` and `{}`",
+                package.name,
+                key,
+                remove_key
+            );
+        }
+
+        // NB: validated before testing if the hashes are fresh, so that errors are always reported.
+        if let Some(package_manager) = package_manager.as_deref() {
+            for option in self.provider_options.keys() {
+                if !package_manager.options().contains(&option.as_str()) {
+                    bail!(
+                        "package manager `{}` does not support option `{}`",
+                        package_manager.name(),
+                        option
+                    );
+                }
+            }
 
-        // test if stored hash is stale.
-        if state.is_hash_fresh(&id, &all_packages)? {
+            for spec in &all_packages {
+                if let Some(version) = spec.version.as_deref() {
+                    package_manager.check_version(&spec.name, version)?;
+                }
+            }
+        }
+
+        let remove_id = format!("{}/remove", id);
+
+        // test if stored hashes are stale.
+        let install_fresh =
+            !force && state.is_hash_fresh(&id, (&all_packages, &self.provider_options))?;
+        let remove_fresh =
+            all_removed.is_empty() || (!force && state.is_hash_fresh(&remove_id, &all_removed)?);
+
+        if install_fresh && remove_fresh {
             log::trace!("Skipping `{}` since hash is fresh", id);
             return Ok(units);
         }
@@ -94,31 +154,66 @@ impl Install {
             }
         };
 
-        let mut to_install = all_packages.iter().cloned().collect::<HashSet<_>>();
-
-        for package in package_manager.list_packages()? {
-            to_install.remove(&package.name);
-        }
-
-        let to_install = to_install.into_iter().collect();
+        let installed = packages.list_packages(&*package_manager)?;
+        let to_install = to_install(&all_packages, &installed)?
+            .into_iter()
+            .cloned()
+            .collect();
+        let to_remove = to_remove(&all_removed, &installed);
 
         // thread-local if package manager requires user interaction.
         let thread_local = package_manager.needs_interaction();
 
         let mut unit = allocator.unit(unit::Install {
-            package_manager,
+            package_manager: Arc::clone(&package_manager),
             all_packages,
             to_install,
+            options: self.provider_options.clone(),
             id,
+            all_removed,
+            to_remove,
+            remove_id,
         });
 
         // NB: sometimes requires user input.
         unit.thread_local = thread_local;
+        packages.serialize(&*package_manager, &mut unit)?;
         units.push(unit);
         Ok(units)
     }
 }
 
+/// Packages which are not installed, and need to be.
+///
+/// A package which is pinned to a version is also installed if the installed version differs.
+fn to_install<'a>(
+    all_packages: &'a BTreeSet<PackageSpec>,
+    installed: &[Package],
+) -> Result<Vec<&'a PackageSpec>, Error> {
+    let mut to_install = Vec::new();
+
+    'outer: for spec in all_packages {
+        for package in installed {
+            if spec.is_satisfied_by(package)? {
+                continue 'outer;
+            }
+        }
+
+        to_install.push(spec);
+    }
+
+    Ok(to_install)
+}
+
+/// Packages which are installed, and need to be removed.
+fn to_remove(all_removed: &BTreeSet<String>, installed: &[Package]) -> Vec<String> {
+    installed
+        .iter()
+        .filter(|package| all_removed.contains(&package.name))
+        .map(|package| package.name.clone())
+        .collect()
+}
+
 impl fmt::Display for Install {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self.provider.as_ref() {
@@ -127,3 +222,65 @@ impl fmt::Display for Install {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{to_install, to_remove};
+    use crate::packages::{Package, PackageSpec};
+    use std::collections::BTreeSet;
+
+    fn installed() -> Vec<Package> {
+        [
+            ("git", None),
+            ("vim", None),
+            ("nano", None),
+            ("nodejs", Some("16.1.0")),
+        ]
+        .iter()
+        .map(|(name, version)| Package {
+            name: name.to_string(),
+            version: version.map(String::from),
+        })
+        .collect()
+    }
+
+    fn set(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn specs(specs: &[&str]) -> BTreeSet<PackageSpec> {
+        specs
+            .iter()
+            .map(|spec| PackageSpec::parse(spec).unwrap())
+            .collect()
+    }
+
+    fn names(specs: Vec<&PackageSpec>) -> Vec<String> {
+        specs.into_iter().map(|spec| spec.to_string()).collect()
+    }
+
+    #[test]
+    fn test_to_install() {
+        let all = specs(&["git", "zsh", "curl"]);
+        let pending = to_install(&all, &installed()).unwrap();
+        assert_eq!(names(pending), vec!["curl", "zsh"]);
+    }
+
+    #[test]
+    fn test_to_install_pinned() {
+        let all = specs(&["nodejs=18.*", "git=2.*"]);
+        let pending = to_install(&all, &installed()).unwrap();
+        // NB: versions are not reported for `git`, so it's considered installed.
+        assert_eq!(names(pending), vec!["nodejs=18.*"]);
+
+        let all = specs(&["nodejs=16.*"]);
+        assert!(to_install(&all, &installed()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_to_remove() {
+        let removed = to_remove(&set(&["nano", "emacs"]), &installed());
+        assert_eq!(removed, vec!["nano"]);
+        assert!(to_remove(&BTreeSet::new(), &installed()).is_empty());
+    }
+}
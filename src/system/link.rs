@@ -11,6 +11,9 @@ system_struct! {
         pub path: Template,
         #[doc="Where to point the created symlink."]
         pub link: Template,
+        #[serde(default)]
+        #[doc="Copy the file instead if we are not privileged to create symlinks on Windows."]
+        pub symlink_fallback_copy: bool,
     }
 }
 
@@ -28,6 +31,7 @@ impl Link {
             facts,
             environment,
             file_system,
+            system,
             ..
         } = input;
 
@@ -54,7 +58,13 @@ impl Link {
             link
         };
 
-        units.extend(file_system.symlink(&path, link, m.as_ref())?);
+        units.extend(file_system.symlink(
+            system,
+            &path,
+            link,
+            m.as_ref(),
+            self.symlink_fallback_copy,
+        )?);
         Ok(units)
     }
 }
@@ -2,6 +2,7 @@ use crate::{
     environment as e, system::SystemInput, template::Template, unit::SystemUnit, FileSystem,
 };
 use anyhow::Error;
+use std::collections::BTreeSet;
 use std::fmt;
 
 system_struct! {
@@ -11,6 +12,12 @@ system_struct! {
         pub from: Template,
         #[doc="Where to link files to."]
         pub to: Template,
+        #[serde(default)]
+        #[doc="Delete links previously created by this system which no longer exist in the source."]
+        pub delete_orphans: bool,
+        #[serde(default)]
+        #[doc="Copy files instead if we are not privileged to create symlinks on Windows."]
+        pub symlink_fallback_copy: bool,
     }
 }
 
@@ -27,6 +34,8 @@ impl LinkDir {
             facts,
             environment,
             file_system,
+            state,
+            system,
             ..
         } = input;
 
@@ -43,6 +52,8 @@ impl LinkDir {
             None => return Ok(units),
         };
 
+        let mut manifest = BTreeSet::new();
+
         for e in ignore::WalkBuilder::new(&from).hidden(false).build() {
             let e = e?;
             let from_path = e.path();
@@ -55,7 +66,7 @@ impl LinkDir {
 
             if source_type.is_dir() {
                 if FileSystem::should_create_dir(&to_path, to.as_ref())? {
-                    units.extend(file_system.create_dir_all(&to_path)?);
+                    units.extend(file_system.create_dir_all(system, &to_path)?);
                 }
 
                 continue;
@@ -66,16 +77,85 @@ impl LinkDir {
                 .and_then(|p| FileSystem::path_relative_from(from_path, p))
                 .unwrap_or_else(|| from_path.to_owned());
 
+            manifest.insert(to_path.clone());
+
             // Maybe create a symlink!
-            units.extend(file_system.symlink(&to_path, link, to.as_ref())?);
+            units.extend(file_system.symlink(
+                system,
+                &to_path,
+                link,
+                to.as_ref(),
+                self.symlink_fallback_copy,
+            )?);
+        }
+
+        if self.delete_orphans {
+            let id = match self.id.as_deref() {
+                Some(id) => id.to_string(),
+                None => id_from(&(&from, &to)),
+            };
+
+            units.extend(file_system.delete_orphans(system, state, &id, manifest)?);
         }
 
         Ok(units)
     }
 }
 
+/// Generate a unique ID for the directory being linked.
+fn id_from(value: &(impl std::hash::Hash + ?Sized)) -> String {
+    use std::hash::Hasher;
+
+    let mut state = fxhash::FxHasher64::default();
+    value.hash(&mut state);
+
+    format!("link-dir-{:x}", state.finish())
+}
+
 impl fmt::Display for LinkDir {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(fmt, "link directory `{}` to `{}`", self.from, self.to)
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::system::{test_run, System};
+    use crate::{Config, State, Timestamp};
+
+    /// Link a directory, remove a file from the source and link it again.
+    ///
+    /// Returns `true` if the link to the removed file is still present in the destination.
+    fn removed_link_is_kept(delete_orphans: bool) -> bool {
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("from");
+        let to = root.path().join("to");
+        std::fs::create_dir_all(&from).unwrap();
+        std::fs::write(from.join("a.txt"), "a").unwrap();
+        std::fs::write(from.join("b.txt"), "b").unwrap();
+
+        let config = format!(
+            "type: link-dir\nfrom: from\nto: to\ndelete_orphans: {}\n",
+            delete_orphans
+        );
+        let system = serde_yaml::from_str::<System>(&config).unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+
+        test_run(root.path(), &system, &mut state).unwrap();
+        assert!(to.join("b.txt").symlink_metadata().is_ok());
+
+        std::fs::remove_file(from.join("b.txt")).unwrap();
+        test_run(root.path(), &system, &mut state).unwrap();
+
+        assert!(to.join("a.txt").is_file());
+        to.join("b.txt").symlink_metadata().is_ok()
+    }
+
+    #[test]
+    fn test_delete_orphans() {
+        assert!(!removed_link_is_kept(true));
+        assert!(removed_link_is_kept(false));
+    }
+}
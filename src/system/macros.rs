@@ -19,6 +19,22 @@ macro_rules! system_struct {
             /// Things that this system requires.
             pub requires: Vec<String>,
 
+            #[serde(default)]
+            /// Condition on facts that must hold for this system to apply.
+            pub when: Option<$crate::system::condition::Condition>,
+
+            #[serde(default)]
+            /// Paths provided outside of quickcfg which must exist before this system is applied.
+            pub wait_for: Vec<$crate::template::Template>,
+
+            #[serde(default)]
+            /// Set to `false` to skip this system entirely.
+            pub enabled: Option<bool>,
+
+            #[serde(default)]
+            /// Tags used to select a subset of systems to run.
+            pub tags: Vec<String>,
+
             $($(#[$attr])* pub $field: $field_ty,)*
         }
 
@@ -30,6 +46,22 @@ macro_rules! system_struct {
             pub fn requires(&self) -> &[String] {
                 &self.requires
             }
+
+            pub fn when(&self) -> Option<&$crate::system::condition::Condition> {
+                self.when.as_ref()
+            }
+
+            pub fn wait_for(&self) -> &[$crate::template::Template] {
+                &self.wait_for
+            }
+
+            pub fn enabled(&self) -> bool {
+                self.enabled.unwrap_or(true)
+            }
+
+            pub fn tags(&self) -> &[String] {
+                &self.tags
+            }
         }
     }
 }
@@ -37,7 +69,7 @@ macro_rules! system_struct {
 macro_rules! system_defaults {
     (@method translate) => {
         /// Default translation implementation for the given system.
-        pub fn translate(&self) -> crate::system::Translation<'_> {
+        pub fn translate(&self, _: &crate::facts::Facts) -> crate::system::Translation<'_> {
             crate::system::Translation::Keep
         }
     };
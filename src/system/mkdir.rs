@@ -0,0 +1,159 @@
+use crate::{
+    environment as e, os,
+    system::{mode::parse_mode, SystemInput},
+    template::Template,
+    unit::{AddMode, SystemUnit},
+    FileSystem,
+};
+use anyhow::Error;
+use std::fmt;
+
+system_struct! {
+    #[doc = "Builds units to create a directory, and all of its parents."]
+    Mkdir {
+        #[doc="The directory to create."]
+        pub path: Template,
+        #[doc="The mode to set on the directory, in octal. Like `0700`."]
+        #[serde(default)]
+        pub mode: Option<String>,
+    }
+}
+
+impl Mkdir {
+    system_defaults!(translate);
+
+    /// Create a directory.
+    pub fn apply<E>(&self, input: SystemInput<E>) -> Result<Vec<SystemUnit>, Error>
+    where
+        E: Copy + e::Environment,
+    {
+        let SystemInput {
+            root,
+            base_dirs,
+            facts,
+            environment,
+            allocator,
+            file_system,
+            system,
+            ..
+        } = input;
+
+        let mut units = Vec::new();
+
+        let mode = self.mode.as_deref().map(parse_mode).transpose()?;
+
+        let path = match self.path.as_path(root, base_dirs, facts, environment)? {
+            Some(path) => path,
+            None => return Ok(units),
+        };
+
+        let meta = FileSystem::try_open_meta(&path)?;
+        let create = FileSystem::should_create_dir(&path, meta.as_ref())?;
+
+        if create {
+            units.extend(file_system.create_dir_all(system, &path)?);
+        }
+
+        let mode = match mode {
+            Some(mode) => AddMode::from_unix_mode(&path, mode).replace(),
+            None => return Ok(units),
+        };
+
+        if !create && os::has_mode(&mode)? {
+            return Ok(units);
+        }
+
+        let mut unit = allocator.unit(mode);
+
+        if create {
+            unit.dependencies
+                .push(file_system.dir_dependency(system, &path)?);
+        }
+
+        units.push(unit);
+        Ok(units)
+    }
+}
+
+impl fmt::Display for Mkdir {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "create directory `{}`", self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::system::{test_run, System};
+    use crate::{Config, State, Timestamp};
+
+    #[test]
+    fn test_mkdir() {
+        let root = tempfile::tempdir().unwrap();
+        let config = "type: mkdir\npath: a/b/c\n";
+        let system = serde_yaml::from_str::<System>(config).unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+
+        test_run(root.path(), &system, &mut state).unwrap();
+        assert!(root.path().join("a").join("b").join("c").is_dir());
+
+        // NB: existing directories are left alone.
+        test_run(root.path(), &system, &mut state).unwrap();
+        assert!(root.path().join("a").join("b").join("c").is_dir());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mkdir_mode() {
+        use crate::system::test_apply;
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let config = "type: mkdir\npath: a/b\nmode: \"0700\"\n";
+        let system = serde_yaml::from_str::<System>(config).unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+
+        test_run(root.path(), &system, &mut state).unwrap();
+
+        let mode = |path: &str| {
+            root.path()
+                .join(path)
+                .metadata()
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777
+        };
+
+        assert_eq!(mode("a/b"), 0o700);
+        assert_ne!(mode("a"), 0o700);
+
+        // NB: the directory already has the right mode.
+        assert!(test_apply(root.path(), &system).unwrap().is_empty());
+
+        std::fs::set_permissions(
+            root.path().join("a").join("b"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        test_run(root.path(), &system, &mut state).unwrap();
+        assert_eq!(mode("a/b"), 0o700);
+    }
+
+    #[test]
+    fn test_mkdir_not_a_dir() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("file"), "").unwrap();
+
+        let config = "type: mkdir\npath: file\n";
+        let system = serde_yaml::from_str::<System>(config).unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        assert!(test_run(root.path(), &system, &mut state).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{
+    environment as e, os,
+    system::SystemInput,
+    template::Template,
+    unit::{AddMode, SystemUnit},
+};
+use anyhow::{anyhow, bail, Context as _, Error};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+system_struct! {
+    #[doc = "Builds units to set the permissions of a path."]
+    Mode {
+        #[doc="The path to set permissions for."]
+        pub path: Template,
+        #[doc="The mode to set, in octal. Like `0755`."]
+        pub mode: String,
+        #[doc="Also set the mode of everything inside of the path, if it is a directory."]
+        #[serde(default)]
+        pub recursive: bool,
+    }
+}
+
+impl Mode {
+    system_defaults!(translate);
+
+    /// Set the mode of a path.
+    pub fn apply<E>(&self, input: SystemInput<E>) -> Result<Vec<SystemUnit>, Error>
+    where
+        E: Copy + e::Environment,
+    {
+        let SystemInput {
+            root,
+            base_dirs,
+            facts,
+            environment,
+            allocator,
+            file_system,
+            system,
+            ..
+        } = input;
+
+        let mut units = Vec::new();
+
+        let mode = parse_mode(&self.mode)?;
+
+        let path = match self.path.as_path(root, base_dirs, facts, environment)? {
+            Some(path) => path,
+            None => return Ok(units),
+        };
+
+        if !path.exists() {
+            if self.recursive {
+                bail!("Path does not exist: {}", path.display());
+            }
+
+            // NB: the path might be provided by another system.
+            let mut unit = allocator.unit(AddMode::from_unix_mode(&path, mode).replace());
+            unit.dependencies
+                .push(file_system.require(system, &path, "path")?);
+            units.push(unit);
+            return Ok(units);
+        }
+
+        let mut paths = vec![path.clone()];
+
+        if self.recursive {
+            walk(&path, &mut paths)?;
+        }
+
+        for path in paths {
+            let add_mode = AddMode::from_unix_mode(&path, mode).replace();
+
+            if os::has_mode(&add_mode)? {
+                continue;
+            }
+
+            units.push(allocator.unit(add_mode));
+        }
+
+        Ok(units)
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "set mode `{}` on `{}`", self.mode, self.path)
+    }
+}
+
+/// Parse an octal mode, like `0755`.
+pub(crate) fn parse_mode(mode: &str) -> Result<u32, Error> {
+    let digits = mode.trim();
+    let digits = digits.strip_prefix("0o").unwrap_or(digits);
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(8)) {
+        bail!("bad mode `{}`: expected an octal number like `0755`", mode);
+    }
+
+    let value = u32::from_str_radix(digits, 8).with_context(|| anyhow!("bad mode `{}`", mode))?;
+
+    if value > 0o777 {
+        bail!(
+            "bad mode `{}`: only permission bits (up to `0777`) are supported",
+            mode
+        );
+    }
+
+    Ok(value)
+}
+
+/// Recursively collect everything inside of the given directory, without following symlinks.
+fn walk(dir: &Path, out: &mut Vec<std::path::PathBuf>) -> Result<(), Error> {
+    if !dir.symlink_metadata()?.is_dir() {
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| anyhow!("failed to read directory: {}", dir.display()))?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    entries.sort();
+
+    for path in entries {
+        if path.symlink_metadata()?.file_type().is_symlink() {
+            continue;
+        }
+
+        out.push(path.clone());
+        walk(&path, out)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_mode;
+    use crate::unit::AddMode;
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("0755").unwrap(), 0o755);
+        assert_eq!(parse_mode("644").unwrap(), 0o644);
+        assert_eq!(parse_mode("0o600").unwrap(), 0o600);
+        assert_eq!(parse_mode("0").unwrap(), 0);
+
+        for mode in &["", "0o", "0855", "rwx", "-755", "01755", "7777"] {
+            assert!(parse_mode(mode).is_err(), "expected error: {:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_unix_mode() {
+        for mode in &["0755", "0644", "0600", "0750", "0421"] {
+            let mode = parse_mode(mode).unwrap();
+            let add_mode = AddMode::from_unix_mode("file", mode);
+            assert_eq!(add_mode.unix_mode(), mode);
+        }
+
+        assert!(AddMode::from_unix_mode("file", 0o755).is_executable());
+        assert!(AddMode::from_unix_mode("file", 0o001).is_executable());
+        assert!(!AddMode::from_unix_mode("file", 0o644).is_executable());
+    }
+}
@@ -1,33 +1,93 @@
 use crate::{
     environment as e,
-    system::{System, SystemInput, SystemUnit, Translation},
+    facts::{self, Facts},
+    system::{condition::Condition, System, SystemInput, SystemUnit, Translation},
 };
 use anyhow::{bail, Error};
+use serde::Deserialize;
 use std::fmt;
 
+/// How multiple conditions are combined.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Match {
+    /// All conditions must hold.
+    #[default]
+    All,
+    /// Any condition must hold.
+    Any,
+}
+
 system_struct! {
     #[doc = "Conditionally run only for the given operating system."]
     OnlyFor {
         #[doc="Which OS to run the given systems for."]
         pub os: Option<String>,
+        #[doc="Which OS to not run the given systems for."]
+        pub not_os: Option<String>,
+        #[doc="Which distro to run the given systems for."]
+        pub distro: Option<String>,
+        #[doc="Which distro to not run the given systems for."]
+        pub not_distro: Option<String>,
+        #[doc="How to combine the conditions."]
+        #[serde(default, rename = "match")]
+        pub mode: Match,
+        #[doc="Conditions on facts, like `distro == debian`."]
+        #[serde(default)]
+        pub conditions: Vec<Condition>,
         pub systems: Vec<System>,
     }
 }
 
 impl OnlyFor {
-    pub fn translate(&self) -> Translation<'_> {
-        if let Some(os) = self.os.as_ref() {
-            match (os.as_str(), std::env::consts::OS) {
-                (current, actual) if current == actual => (),
-                ("unix", "linux") => (),
-                ("unix", "macos") => (),
-                _ => return Translation::Discard,
+    pub fn translate(&self, facts: &Facts) -> Translation<'_> {
+        if let Some(os) = self.os.as_deref() {
+            if !is_os(facts, os) {
+                return Translation::Discard;
+            }
+        }
+
+        if let Some(os) = self.not_os.as_deref() {
+            if is_os(facts, os) {
+                return Translation::Discard;
             }
         }
 
+        let distro = facts.get(facts::DISTRO);
+
+        if let Some(expected) = self.distro.as_deref() {
+            if distro != Some(expected) {
+                return Translation::Discard;
+            }
+        }
+
+        if let Some(expected) = self.not_distro.as_deref() {
+            if distro == Some(expected) {
+                return Translation::Discard;
+            }
+        }
+
+        if !self.matches(facts) {
+            return Translation::Discard;
+        }
+
         Translation::Expand(&self.systems)
     }
 
+    /// Test if the conditions hold for the given set of facts.
+    fn matches(&self, facts: &Facts) -> bool {
+        if self.conditions.is_empty() {
+            return true;
+        }
+
+        let mut it = self.conditions.iter().map(|c| c.matches(facts));
+
+        match self.mode {
+            Match::All => it.all(|m| m),
+            Match::Any => it.any(|m| m),
+        }
+    }
+
     /// Copy one directory to another.
     pub fn apply<E>(&self, _: SystemInput<E>) -> Result<Vec<SystemUnit>, Error>
     where
@@ -37,8 +97,138 @@ impl OnlyFor {
     }
 }
 
+/// Test if we are running on the given OS, where `unix` matches any unix-like OS.
+///
+/// The OS is taken from the `os` fact, falling back to the OS we were built for.
+fn is_os(facts: &Facts, os: &str) -> bool {
+    let current = facts.get(facts::OS).unwrap_or(std::env::consts::OS);
+    matches!((os, current), ("unix", "linux") | ("unix", "macos")) || os == current
+}
+
 impl fmt::Display for OnlyFor {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "conditionally run for (os: {:?})", self.os)
+        write!(
+            fmt,
+            "conditionally run for (os: {:?}, not_os: {:?}, distro: {:?}, not_distro: {:?}, {:?}: {:?})",
+            self.os, self.not_os, self.distro, self.not_distro, self.mode, self.conditions
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OnlyFor;
+    use crate::{facts::Facts, system::Translation};
+
+    fn facts() -> Facts {
+        Facts::new(vec![
+            ("os".to_string(), "linux".to_string()),
+            ("distro".to_string(), "debian".to_string()),
+        ])
+    }
+
+    fn only_for(config: &str) -> OnlyFor {
+        serde_yaml::from_str(config).unwrap()
+    }
+
+    fn expands(only_for: &OnlyFor) -> bool {
+        matches!(only_for.translate(&facts()), Translation::Expand(_))
+    }
+
+    #[test]
+    fn test_all() {
+        let matching = only_for("conditions: [os == linux, distro == debian]\nsystems: []");
+        assert!(expands(&matching));
+
+        let partial = only_for("conditions: [os == linux, distro == fedora]\nsystems: []");
+        assert!(!expands(&partial));
+    }
+
+    #[test]
+    fn test_any() {
+        let partial =
+            only_for("match: any\nconditions: [os == linux, distro == fedora]\nsystems: []");
+        assert!(expands(&partial));
+
+        let none =
+            only_for("match: any\nconditions: [os == windows, distro == fedora]\nsystems: []");
+        assert!(!expands(&none));
+    }
+
+    #[test]
+    fn test_backwards_compatible() {
+        assert!(expands(&only_for("systems: []")));
+
+        assert!(expands(&only_for("os: linux\nsystems: []")));
+        assert!(!expands(&only_for("os: not-an-os\nsystems: []")));
+
+        // The os is required in addition to the conditions.
+        let config = "os: not-an-os\nmatch: any\nconditions: [os == linux]\nsystems: []";
+        assert!(!expands(&only_for(config)));
+    }
+
+    #[test]
+    fn test_os() {
+        assert!(expands(&only_for("os: linux\nsystems: []")));
+        assert!(!expands(&only_for("os: not-an-os\nsystems: []")));
+
+        assert!(!expands(&only_for("not_os: linux\nsystems: []")));
+        assert!(expands(&only_for("not_os: not-an-os\nsystems: []")));
+
+        assert!(expands(&only_for("os: unix\nsystems: []")));
+        assert!(!expands(&only_for("not_os: unix\nsystems: []")));
+
+        let windows = Facts::new(vec![("os".to_string(), "windows".to_string())]);
+        let unix = only_for("os: unix\nsystems: []");
+        assert!(matches!(unix.translate(&windows), Translation::Discard));
+        let linux = only_for("os: linux\nsystems: []");
+        assert!(matches!(linux.translate(&windows), Translation::Discard));
+    }
+
+    #[test]
+    fn test_os_fallback() {
+        // Without an `os` fact, the OS we were built for is used.
+        let facts = Facts::new(Vec::new());
+        let os = only_for(&format!("os: {}\nsystems: []", std::env::consts::OS));
+        assert!(matches!(os.translate(&facts), Translation::Expand(_)));
+        let not_os = only_for(&format!("not_os: {}\nsystems: []", std::env::consts::OS));
+        assert!(matches!(not_os.translate(&facts), Translation::Discard));
+    }
+
+    #[test]
+    fn test_distro() {
+        assert!(expands(&only_for("distro: debian\nsystems: []")));
+        assert!(!expands(&only_for("distro: fedora\nsystems: []")));
+
+        assert!(!expands(&only_for("not_distro: debian\nsystems: []")));
+        assert!(expands(&only_for("not_distro: fedora\nsystems: []")));
+
+        // A missing distro fact never matches.
+        let facts = Facts::new(Vec::new());
+        let distro = only_for("distro: debian\nsystems: []");
+        assert!(matches!(distro.translate(&facts), Translation::Discard));
+        let not_distro = only_for("not_distro: debian\nsystems: []");
+        assert!(matches!(
+            not_distro.translate(&facts),
+            Translation::Expand(_)
+        ));
+    }
+
+    #[test]
+    fn test_shorthands_and_conditions() {
+        let config = "distro: debian\nconditions: [os == linux]\nsystems: []";
+        assert!(expands(&only_for(config)));
+
+        let config = "distro: debian\nconditions: [os == windows]\nsystems: []";
+        assert!(!expands(&only_for(config)));
+
+        let config = "not_distro: debian\nmatch: any\nconditions: [os == linux]\nsystems: []";
+        assert!(!expands(&only_for(config)));
+    }
+
+    #[test]
+    fn test_bad_condition() {
+        let e = serde_yaml::from_str::<OnlyFor>("conditions: [os]\nsystems: []").unwrap_err();
+        assert!(e.to_string().contains("bad condition `os`"), "{}", e);
     }
 }
@@ -0,0 +1,76 @@
+use crate::{
+    environment as e,
+    system::SystemInput,
+    template::Template,
+    unit::{self, SystemUnit},
+    FileSystem,
+};
+use anyhow::{bail, Error};
+use std::fmt;
+
+system_struct! {
+    #[doc = "Builds a unit to render a template string into a file."]
+    TemplateString {
+        #[doc="The content of the template to render."]
+        pub content: String,
+        #[doc="Where to write the rendered template."]
+        pub to: Template,
+    }
+}
+
+impl TemplateString {
+    system_defaults!(translate);
+
+    /// Render a template string.
+    pub fn apply<E>(&self, input: SystemInput<E>) -> Result<Vec<SystemUnit>, Error>
+    where
+        E: Copy + e::Environment,
+    {
+        let SystemInput {
+            root,
+            base_dirs,
+            facts,
+            environment,
+            allocator,
+            file_system,
+            system,
+            ..
+        } = input;
+
+        let mut units = Vec::new();
+
+        let to = match self.to.as_path(root, base_dirs, facts, environment)? {
+            Some(to) => to,
+            None => return Ok(units),
+        };
+
+        if let Some(meta) = FileSystem::try_open_meta(&to)? {
+            if !meta.is_file() {
+                bail!("File exists but is not a regular file: {}", to.display());
+            }
+        }
+
+        let mut unit = allocator.unit(unit::TemplateString {
+            content: self.content.clone(),
+            to: to.clone(),
+        });
+
+        if let Some(parent) = to.parent() {
+            if !parent.is_dir() {
+                units.extend(file_system.create_dir_all(system, parent)?);
+                unit.dependencies
+                    .push(file_system.dir_dependency(system, parent)?);
+            }
+        }
+
+        unit.provides.push(file_system.provide_file(system, &to)?);
+        units.push(unit);
+        Ok(units)
+    }
+}
+
+impl fmt::Display for TemplateString {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "template string to `{}`", self.to)
+    }
+}
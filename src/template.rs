@@ -99,10 +99,9 @@ impl Template {
         ) -> Result<(usize, &str), Error> {
             let (start, _) = it.next().ok_or_else(|| anyhow!("missing char"))?;
 
-            while let Some((index, c)) = it.next() {
+            for (index, c) in it {
                 if c == '}' {
-                    let (end, _) = it.next().ok_or_else(|| anyhow!("missing char"))?;
-                    return Ok((end, &input[start..index]));
+                    return Ok((index + 1, &input[start..index]));
                 }
             }
 
@@ -197,6 +196,30 @@ impl Template {
         self.render(vars, environment, |_| Ok(()))
     }
 
+    /// Render as a URL, keeping the protocol.
+    pub fn as_url(
+        &self,
+        vars: impl Vars,
+        environment: impl Environment,
+    ) -> Result<Option<String>, Error> {
+        let mut scheme = None;
+
+        let protocol = |proto: &str| {
+            scheme = Some(proto.to_string());
+            Ok(())
+        };
+
+        let value = match self.render(vars, environment, protocol)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        match scheme {
+            Some(scheme) => Ok(Some(format!("{}://{}", scheme, value))),
+            None => Ok(Some(value)),
+        }
+    }
+
     /// Render the template variable.
     fn render(
         &self,
@@ -271,4 +294,39 @@ mod tests {
             Some("root/baz/home/bar.yaml".to_string())
         );
     }
+
+    #[test]
+    fn test_parse_adjacent_variables() {
+        let t = Template::parse("{hostname}").unwrap();
+        assert_eq!(t.parts, vec![Variable("hostname".to_string())]);
+
+        let t = Template::parse("{a}{b}$HOME").unwrap();
+
+        assert_eq!(
+            t.parts,
+            vec![
+                Variable("a".to_string()),
+                Variable("b".to_string()),
+                Environ("HOME".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_as_url() {
+        let t = Template::parse("https://example.com/tool-{arch}-{os}").unwrap();
+        let facts = Facts::new(vec![
+            ("arch".to_string(), "x86_64".to_string()),
+            ("os".to_string(), "linux".to_string()),
+        ]);
+        let environment = HashMap::<String, String>::new();
+
+        assert_eq!(
+            t.as_url(&facts, &environment).unwrap(),
+            Some("https://example.com/tool-x86_64-linux".to_string())
+        );
+
+        let facts = Facts::new(Vec::new());
+        assert_eq!(t.as_url(&facts, &environment).unwrap(), None);
+    }
 }
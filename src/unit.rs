@@ -1,16 +1,22 @@
 //! A unit of work. Does a single thing and DOES IT WELL.
 
 use crate::{
-    git::GitSystem, hierarchy::Data, os, packages, packages::PackageManager, state::State,
-    FileSystem, Timestamp,
+    git::{self, GitSystem},
+    hierarchy::Data,
+    os, packages,
+    packages::{PackageManager, PackageSpec},
+    state::State,
+    FileSystem, Summary, Timestamp,
 };
-use anyhow::{anyhow, Context as _, Error};
-use std::collections::BTreeSet;
+use anyhow::{anyhow, bail, Context as _, Error};
+use serde_yaml::Mapping;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -60,15 +66,22 @@ pub struct UnitInput<'a, 's> {
     pub packages: &'a packages::Provider,
     /// Data loaded from the hierarchy.
     pub data: &'a Data,
-    /// Read-only state.
-    /// If none, the read state is the mutated state.
-    pub read_state: &'a State<'s>,
+    /// State shared between all units, which includes the state of units that have completed.
+    pub read_state: &'a RwLock<State<'s>>,
     /// Unit-local state that can be mutated.
     pub state: &'a mut State<'s>,
     /// Current timestamp.
     pub now: Timestamp,
     /// Current git system.
     pub git_system: &'a dyn GitSystem,
+    /// HTTP client shared by all downloads.
+    pub http: &'a reqwest::blocking::Client,
+    /// Summary of the actions taken.
+    pub summary: &'a Summary,
+    /// Only report what the unit would do, without doing it.
+    pub dry_run: bool,
+    /// Show a diff of the content of files before they are changed.
+    pub diff: bool,
 }
 
 /// Declare unit enum.
@@ -85,6 +98,10 @@ macro_rules! unit {
             pub fn apply(&self, input: UnitInput) -> Result<(), Error> {
                 use self::Unit::*;
 
+                if input.dry_run {
+                    return self.dry_run(input);
+                }
+
                 let res = match *self {
                     // do nothing.
                     System => Ok(()),
@@ -109,18 +126,52 @@ macro_rules! unit {
     }
 }
 
+impl Unit {
+    /// Report what applying the unit would do, without doing it.
+    fn dry_run(&self, input: UnitInput) -> Result<(), Error> {
+        match self {
+            Unit::System => Ok(()),
+            Unit::GitUpdate(unit) => unit
+                .dry_run(input)
+                .with_context(|| anyhow!("Failed to check unit: {:?}", self)),
+            Unit::CopyFile(unit) if input.diff => {
+                log::info!("would run: {}", unit);
+                log_diff(&unit.to, &fs::read(&unit.from)?)
+            }
+            Unit::CopyTemplate(unit) if input.diff => {
+                log::info!("would run: {}", unit);
+
+                if let Some(diff) = unit.diff(input.data)? {
+                    log::info!("{}", diff);
+                }
+
+                Ok(())
+            }
+            unit => {
+                log::info!("would run: {}", unit);
+                Ok(())
+            }
+        }
+    }
+}
+
 unit![
     FromDb,
     CopyFile,
     CopyTemplate,
+    TemplateString,
     Symlink,
+    Hardlink,
+    DeleteOrphans,
     CreateDir,
     Install,
     Download,
+    Extract,
     AddMode,
     RunOnce,
     GitClone,
     GitUpdate,
+    WaitFor,
 ];
 
 /// A system unit, which is a unit coupled with a set of dependencies.
@@ -161,12 +212,30 @@ impl SystemUnit {
         }
     }
 
+    /// Access the unit of work.
+    pub fn unit(&self) -> &Unit {
+        &self.unit
+    }
+
     /// Apply the unit of work.
     pub fn apply(&self, input: UnitInput) -> Result<(), Error> {
         self.unit.apply(input)
     }
 }
 
+/// Construct a unit with the given dependencies, used to test how units are scheduled.
+#[cfg(test)]
+pub(crate) fn test_unit(
+    id: UnitId,
+    thread_local: bool,
+    dependencies: Vec<Dependency>,
+) -> SystemUnit {
+    let mut unit = SystemUnit::new(id, CreateDir(format!("dir{}", id).into()));
+    unit.thread_local = thread_local;
+    unit.dependencies = dependencies;
+    unit
+}
+
 /// The configuration for a unit to copy a single file.
 #[derive(Debug, Hash)]
 pub struct FromDb {
@@ -207,11 +276,12 @@ impl fmt::Display for CreateDir {
 }
 
 impl CreateDir {
-    fn apply(&self, _: UnitInput) -> Result<(), Error> {
+    fn apply(&self, input: UnitInput) -> Result<(), Error> {
         use std::fs;
         let CreateDir(ref dir) = self;
         log::info!("creating dir: {}", dir.display());
         fs::create_dir(dir)?;
+        input.summary.directory_created();
         Ok(())
     }
 }
@@ -223,14 +293,18 @@ impl From<CreateDir> for Unit {
 }
 
 /// The configuration for a unit to copy a single file.
-#[derive(Debug, Hash)]
+#[derive(Debug)]
 pub struct CopyFile {
     /// The source file.
     pub from: PathBuf,
     /// Source file modification time.
     pub from_modified: SystemTime,
+    /// Source file permissions.
+    pub permissions: fs::Permissions,
     /// The destination file.
     pub to: PathBuf,
+    /// Back up the destination file if it exists with different content.
+    pub backup: bool,
 }
 
 impl fmt::Display for CopyFile {
@@ -245,18 +319,48 @@ impl fmt::Display for CopyFile {
 }
 
 impl CopyFile {
-    fn apply(&self, _: UnitInput) -> Result<(), Error> {
+    fn apply(&self, input: UnitInput) -> Result<(), Error> {
         use std::fs::File;
         use std::io;
 
         let CopyFile {
             ref from,
             ref from_modified,
+            ref permissions,
             ref to,
+            backup,
         } = *self;
 
+        if backup && to.is_file() && fs::read(from)? != fs::read(to)? {
+            backup_file(to)?;
+        }
+
+        if input.diff {
+            log_diff(to, &fs::read(from)?)?;
+        }
+
         log::info!("{} -> {}", from.display(), to.display());
-        io::copy(&mut File::open(from)?, &mut File::create(to)?)?;
+
+        // NB: the destination has the permissions of the source, which might be read-only. So the
+        // copy is written next to it and renamed over it, instead of writing to it directly.
+        let temporary = suffixed_path(to, ".tmp")?;
+
+        if temporary.symlink_metadata().is_ok() {
+            fs::remove_file(&temporary)?;
+        }
+
+        io::copy(&mut File::open(from)?, &mut File::create(&temporary)?)?;
+        os::copy_permissions(&temporary, permissions)?;
+
+        fs::rename(&temporary, to).with_context(|| {
+            anyhow!(
+                "failed to rename {} to {}",
+                temporary.display(),
+                to.display()
+            )
+        })?;
+
+        input.summary.file_copied();
         // make sure timestamp is in sync.
         FileSystem::touch(to, from_modified)
     }
@@ -279,6 +383,10 @@ pub struct CopyTemplate {
     pub to: PathBuf,
     /// If the destination file exists, we assume that its content is the same.
     pub to_exists: bool,
+    /// Back up the destination file if it exists with different content.
+    pub backup: bool,
+    /// Fail on references to variables which are not defined.
+    pub strict: bool,
 }
 
 impl fmt::Display for CopyTemplate {
@@ -303,22 +411,59 @@ impl CopyTemplate {
         format!("copy-template/{:x}", state.finish())
     }
 
+    /// Read the template and load the hierarchy variables it uses.
+    fn load(&self, data: &Data) -> Result<(String, Mapping), Error> {
+        let from = &self.from;
+
+        let content = fs::read_to_string(from)
+            .map_err(|e| anyhow!("failed to read path: {}: {}", from.display(), e))?;
+
+        let data = data.load_from_spec(&content).map_err(|e| {
+            anyhow!(
+                "failed to load hierarchy for path: {}: {}",
+                from.display(),
+                e
+            )
+        })?;
+
+        Ok((content, data))
+    }
+
+    /// Render the template with the given hierarchy variables.
+    fn render(&self, content: &str, data: &Mapping) -> Result<Vec<u8>, Error> {
+        let from = &self.from;
+        render_template(&from.display().to_string(), content, data, self.strict)
+            .with_context(|| anyhow!("failed to render template: {}", from.display()))
+    }
+
+    /// Render the template and diff it against the current destination.
+    ///
+    /// Returns `None` if the content would not change.
+    fn diff(&self, data: &Data) -> Result<Option<String>, Error> {
+        let (content, data) = self.load(data)?;
+        let out = self.render(&content, &data)?;
+        content_diff(&self.to, &out)
+    }
+
     fn apply(&self, input: UnitInput) -> Result<(), Error> {
-        use handlebars::{Context, Handlebars, Output, RenderContext, Renderable, Template};
-        use std::fs::{self, File};
-        use std::io::{self, Cursor, Write};
+        use std::fs::File;
+        use std::io::Write;
 
         let CopyTemplate {
             ref from,
             ref from_modified,
             ref to,
             to_exists,
+            backup,
+            ..
         } = *self;
 
         let UnitInput {
             data,
             read_state,
             state,
+            summary,
+            diff,
             ..
         } = input;
 
@@ -329,67 +474,262 @@ impl CopyTemplate {
         // This includes:
         // * Reading the template file to determine which database variables to use.
 
-        let content = fs::read_to_string(from)
-            .map_err(|e| anyhow!("failed to read path: {}: {}", from.display(), e))?;
-
-        let data = data.load_from_spec(&content).map_err(|e| {
-            anyhow!(
-                "failed to load hierarchy for path: {}: {}",
-                from.display(),
-                e
-            )
-        })?;
+        let (content, data) = self.load(data)?;
 
         let id = self.id();
         let hash = (&data, &content);
 
-        if to_exists && read_state.is_hash_fresh(&id, hash)? {
+        let fresh = read_state
+            .read()
+            .map_err(|_| anyhow!("Lock poisoned"))?
+            .is_hash_fresh(&id, hash)?;
+
+        if to_exists && fresh {
             // Nothing about the template would change, only update the modified time of the file.
             log::info!("touching {}", to.display());
+            summary.skipped();
             // only need to update timestamp.
             return FileSystem::touch(to, from_modified);
         }
 
-        let reg = Handlebars::new();
+        let out = self.render(&content, &data)?;
 
-        let mut out = Vec::<u8>::new();
-
-        let mut tpl = Template::compile(&content)?;
-        tpl.name = Some(from.display().to_string());
+        if backup && to.is_file() && fs::read(to)? != out {
+            backup_file(to)?;
+        }
 
-        tpl.render(
-            &reg,
-            &Context::wraps(&data)?,
-            &mut RenderContext::new(None),
-            &mut WriteOutput::new(Cursor::new(&mut out)),
-        )?;
+        if diff {
+            log_diff(to, &out)?;
+        }
 
         log::info!("{} -> {} (template)", from.display(), to.display());
         File::create(to)?.write_all(&out)?;
         state.touch_hash(&id, hash)?;
-        return FileSystem::touch(to, from_modified);
+        summary.template_rendered();
+        FileSystem::touch(to, from_modified)
+    }
+}
 
-        pub struct WriteOutput<W: Write> {
-            write: W,
-        }
+impl From<CopyTemplate> for Unit {
+    fn from(value: CopyTemplate) -> Unit {
+        Unit::CopyTemplate(value)
+    }
+}
 
-        impl<W: Write> Output for WriteOutput<W> {
-            fn write(&mut self, seg: &str) -> Result<(), io::Error> {
-                self.write.write_all(seg.as_bytes())
-            }
-        }
+/// The configuration for a unit to render a template string into a file.
+#[derive(Debug)]
+pub struct TemplateString {
+    /// The content of the template.
+    pub content: String,
+    /// The destination file.
+    pub to: PathBuf,
+}
 
-        impl<W: Write> WriteOutput<W> {
-            pub fn new(write: W) -> WriteOutput<W> {
-                WriteOutput { write }
-            }
+impl fmt::Display for TemplateString {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "template string -> {}", self.to.display())
+    }
+}
+
+impl TemplateString {
+    /// Construct the ID for this unit.
+    fn id(&self) -> String {
+        use std::hash::{Hash, Hasher};
+
+        let mut state = fxhash::FxHasher64::default();
+        self.to.hash(&mut state);
+
+        format!("template-string/{:x}", state.finish())
+    }
+
+    fn apply(&self, input: UnitInput) -> Result<(), Error> {
+        use std::fs::File;
+        use std::io::Write;
+
+        let TemplateString {
+            ref content,
+            ref to,
+        } = *self;
+
+        let UnitInput {
+            data,
+            read_state,
+            state,
+            summary,
+            ..
+        } = input;
+
+        let data = data.load_from_spec(content).map_err(|e| {
+            anyhow!(
+                "failed to load hierarchy for template string: {}: {}",
+                to.display(),
+                e
+            )
+        })?;
+
+        let out = render_template(&to.display().to_string(), content, &data, false)?;
+        let id = self.id();
+
+        let fresh = read_state
+            .read()
+            .map_err(|_| anyhow!("Lock poisoned"))?
+            .is_hash_fresh(&id, &out)?;
+
+        if to.is_file() && fresh {
+            log::trace!("template string is fresh: {}", to.display());
+            summary.skipped();
+            return Ok(());
         }
+
+        log::info!("{} (template string)", to.display());
+        File::create(to)?.write_all(&out)?;
+        state.touch_hash(&id, &out)?;
+        summary.template_rendered();
+        Ok(())
     }
 }
 
-impl From<CopyTemplate> for Unit {
-    fn from(value: CopyTemplate) -> Unit {
-        Unit::CopyTemplate(value)
+impl From<TemplateString> for Unit {
+    fn from(value: TemplateString) -> Unit {
+        Unit::TemplateString(value)
+    }
+}
+
+/// Move the given file out of the way to `<name>.bak`, or `<name>.bak.<n>` if that already exists.
+fn backup_file(path: &Path) -> Result<PathBuf, Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("no file name: {}", path.display()))?;
+
+    let mut backup = name.to_owned();
+    backup.push(".bak");
+    let mut backup_path = path.with_file_name(&backup);
+    let mut n = 1;
+
+    while FileSystem::try_open_meta(&backup_path)?.is_some() {
+        let mut backup = backup.clone();
+        backup.push(format!(".{}", n));
+        backup_path = path.with_file_name(backup);
+        n += 1;
+    }
+
+    log::info!("backing up {} to {}", path.display(), backup_path.display());
+
+    fs::rename(path, &backup_path).with_context(|| {
+        anyhow!(
+            "failed to back up {} to {}",
+            path.display(),
+            backup_path.display()
+        )
+    })?;
+
+    Ok(backup_path)
+}
+
+/// Construct the registry used to render templates, with our helpers registered.
+///
+/// * `upper` and `lower` change the case of a string.
+/// * `default` uses its second argument if the first is missing, null, or an empty string.
+/// * `env` reads an environment variable, which is empty if it's not set.
+fn template_registry() -> handlebars::Handlebars<'static> {
+    use handlebars::{handlebars_helper, Handlebars, JsonValue};
+
+    handlebars_helper!(upper: |s: str| s.to_uppercase());
+    handlebars_helper!(lower: |s: str| s.to_lowercase());
+    handlebars_helper!(default: |value: Json, fallback: Json| match value {
+        JsonValue::Null => fallback.clone(),
+        JsonValue::String(s) if s.is_empty() => fallback.clone(),
+        value => value.clone(),
+    });
+    handlebars_helper!(env: |name: str| std::env::var(name).unwrap_or_default());
+
+    let mut reg = Handlebars::new();
+    reg.register_helper("upper", Box::new(upper));
+    reg.register_helper("lower", Box::new(lower));
+    reg.register_helper("default", Box::new(default));
+    reg.register_helper("env", Box::new(env));
+    reg
+}
+
+/// Build a unified diff between the current content of `to` and `new`.
+///
+/// A destination which doesn't exist is treated as empty. Returns `None` if the content is the
+/// same.
+fn content_diff(to: &Path, new: &[u8]) -> Result<Option<String>, Error> {
+    let old = if to.is_file() {
+        fs::read(to)?
+    } else {
+        Vec::new()
+    };
+
+    if old == new {
+        return Ok(None);
+    }
+
+    let old = String::from_utf8_lossy(&old);
+    let new = String::from_utf8_lossy(new);
+    let name = to.display().to_string();
+
+    let diff = similar::TextDiff::from_lines(&*old, &*new)
+        .unified_diff()
+        .header(&name, &name)
+        .to_string();
+
+    Ok(Some(diff))
+}
+
+/// Log a diff between the current content of `to` and `new`, if there is one.
+fn log_diff(to: &Path, new: &[u8]) -> Result<(), Error> {
+    if let Some(diff) = content_diff(to, new)? {
+        log::info!("{}", diff);
+    }
+
+    Ok(())
+}
+
+/// Render the given template content with the data provided.
+///
+/// If `strict` is set, referring to a variable which is not defined is an error.
+fn render_template(
+    name: &str,
+    content: &str,
+    data: &Mapping,
+    strict: bool,
+) -> Result<Vec<u8>, Error> {
+    use handlebars::{Context, Output, RenderContext, Renderable, Template};
+    use std::io::{self, Cursor, Write};
+
+    let mut reg = template_registry();
+    reg.set_strict_mode(strict);
+
+    let mut out = Vec::<u8>::new();
+
+    let mut tpl = Template::compile(content)?;
+    tpl.name = Some(name.to_string());
+
+    tpl.render(
+        &reg,
+        &Context::wraps(data)?,
+        &mut RenderContext::new(None),
+        &mut WriteOutput::new(Cursor::new(&mut out)),
+    )?;
+
+    return Ok(out);
+
+    pub struct WriteOutput<W: Write> {
+        write: W,
+    }
+
+    impl<W: Write> Output for WriteOutput<W> {
+        fn write(&mut self, seg: &str) -> Result<(), io::Error> {
+            self.write.write_all(seg.as_bytes())
+        }
+    }
+
+    impl<W: Write> WriteOutput<W> {
+        pub fn new(write: W) -> WriteOutput<W> {
+            WriteOutput { write }
+        }
     }
 }
 
@@ -402,6 +742,10 @@ pub struct Symlink {
     pub path: PathBuf,
     /// link to set up.
     pub link: PathBuf,
+    /// Copy the file instead if we are not privileged to create symlinks.
+    ///
+    /// NB: only used on Windows.
+    pub fallback_copy: bool,
 }
 
 impl fmt::Display for Symlink {
@@ -416,8 +760,10 @@ impl fmt::Display for Symlink {
 }
 
 impl Symlink {
-    fn apply(&self, _: UnitInput) -> Result<(), Error> {
-        os::create_symlink(self)
+    fn apply(&self, input: UnitInput) -> Result<(), Error> {
+        os::create_symlink(self)?;
+        input.summary.symlink_created();
+        Ok(())
     }
 }
 
@@ -427,44 +773,205 @@ impl From<Symlink> for Unit {
     }
 }
 
-/// Install a number of packages.
+/// The configuration for a unit to create a hardlink.
+#[derive(Debug)]
+pub struct Hardlink {
+    /// `true` if the destination file needs to be removed.
+    pub remove: bool,
+    /// destination file to create.
+    pub path: PathBuf,
+    /// existing file to link to.
+    pub link: PathBuf,
+}
+
+impl fmt::Display for Hardlink {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "hardlink file {} to {}",
+            self.path.display(),
+            self.link.display()
+        )
+    }
+}
+
+impl Hardlink {
+    fn apply(&self, input: UnitInput) -> Result<(), Error> {
+        let Hardlink {
+            remove,
+            ref path,
+            ref link,
+        } = *self;
+
+        log::info!("hardlinking {} to {}", path.display(), link.display());
+
+        if remove {
+            fs::remove_file(path)
+                .with_context(|| anyhow!("failed to remove file: {}", path.display()))?;
+        }
+
+        fs::hard_link(link, path).with_context(|| {
+            anyhow!(
+                "failed to create hardlink {} to {}",
+                path.display(),
+                link.display()
+            )
+        })?;
+
+        input.summary.hardlink_created();
+        Ok(())
+    }
+}
+
+impl From<Hardlink> for Unit {
+    fn from(value: Hardlink) -> Unit {
+        Unit::Hardlink(value)
+    }
+}
+
+/// Delete files which were previously created by a system, but which no longer exist in its
+/// source.
+#[derive(Debug)]
+pub struct DeleteOrphans {
+    /// The id used to store the manifest.
+    pub id: String,
+    /// All files which are currently created by the system.
+    pub manifest: BTreeSet<PathBuf>,
+    /// Files to delete.
+    pub orphans: Vec<PathBuf>,
+}
+
+impl fmt::Display for DeleteOrphans {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "delete {} orphaned file(s) for `{}`",
+            self.orphans.len(),
+            self.id
+        )
+    }
+}
+
+impl DeleteOrphans {
+    fn apply(&self, input: UnitInput) -> Result<(), Error> {
+        let UnitInput { state, summary, .. } = input;
+
+        for path in &self.orphans {
+            let meta = match path.symlink_metadata() {
+                Ok(meta) => meta,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if meta.is_dir() {
+                log::warn!("Not deleting orphaned directory: {}", path.display());
+                continue;
+            }
+
+            log::info!("deleting orphan {}", path.display());
+
+            fs::remove_file(path)
+                .with_context(|| anyhow!("failed to remove file: {}", path.display()))?;
+
+            summary.file_removed();
+        }
+
+        state.set_manifest(&self.id, self.manifest.clone());
+        Ok(())
+    }
+}
+
+impl From<DeleteOrphans> for Unit {
+    fn from(value: DeleteOrphans) -> Unit {
+        Unit::DeleteOrphans(value)
+    }
+}
+
+/// Install and remove a number of packages.
 #[derive(Debug)]
 pub struct Install {
     pub package_manager: Arc<dyn PackageManager>,
-    pub all_packages: BTreeSet<String>,
-    pub to_install: Vec<String>,
+    pub all_packages: BTreeSet<PackageSpec>,
+    pub to_install: Vec<PackageSpec>,
+    /// Options passed to the package manager when installing.
+    pub options: BTreeMap<String, String>,
     pub id: String,
+    /// All packages which should be absent.
+    pub all_removed: BTreeSet<String>,
+    /// Installed packages which should be removed.
+    pub to_remove: Vec<String>,
+    /// The id used to hash the set of removed packages.
+    pub remove_id: String,
 }
 
 impl fmt::Display for Install {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        if self.to_install.is_empty() {
+        if self.to_install.is_empty() && self.to_remove.is_empty() {
             return write!(fmt, "install packages");
         }
 
-        let names = self.to_install.join(", ");
-        write!(fmt, "{}: install packages: {}", self.id, names)
+        write!(fmt, "{}:", self.id)?;
+
+        if !self.to_install.is_empty() {
+            let names = self
+                .to_install
+                .iter()
+                .map(|spec| spec.to_string())
+                .collect::<Vec<_>>();
+            write!(fmt, " install packages: {}", names.join(", "))?;
+        }
+
+        if !self.to_remove.is_empty() {
+            write!(fmt, " remove packages: {}", self.to_remove.join(", "))?;
+        }
+
+        Ok(())
     }
 }
 
 impl Install {
     fn apply(&self, input: UnitInput) -> Result<(), Error> {
-        let UnitInput { state, .. } = input;
+        let UnitInput { state, summary, .. } = input;
 
         let Install {
             ref package_manager,
             ref all_packages,
             ref to_install,
+            ref options,
             ref id,
+            ref all_removed,
+            ref to_remove,
+            ref remove_id,
         } = *self;
 
         if !to_install.is_empty() {
-            let names = to_install.join(", ");
-            log::info!("Installing packages for `{}`: {}", id, names);
-            package_manager.install_packages(to_install)?;
+            let names = to_install
+                .iter()
+                .map(|spec| spec.to_string())
+                .collect::<Vec<_>>();
+            log::info!("Installing packages for `{}`: {}", id, names.join(", "));
+            package_manager.install_specs(to_install, options)?;
+            summary.packages_installed(to_install.len());
+        }
+
+        if !to_remove.is_empty() {
+            let names = to_remove.join(", ");
+            log::info!("Removing packages for `{}`: {}", id, names);
+            package_manager.remove_packages(to_remove)?;
+            summary.packages_removed(to_remove.len());
+        }
+
+        if to_install.is_empty() && to_remove.is_empty() {
+            summary.skipped();
+        }
+
+        // NB: options are part of the hash, so that changing them installs packages again.
+        state.touch_hash(id, (all_packages, options))?;
+
+        if !all_removed.is_empty() {
+            state.touch_hash(remove_id, all_removed)?;
         }
 
-        state.touch_hash(id, all_packages)?;
         Ok(())
     }
 }
@@ -475,12 +982,25 @@ impl From<Install> for Unit {
     }
 }
 
+/// Credentials used for HTTP basic authentication.
+#[derive(Debug)]
+pub struct BasicAuth {
+    pub user: String,
+    pub password: Option<String>,
+}
+
 /// Download the given URL as an executable and write to the given path.
 #[derive(Debug)]
 pub struct Download {
     pub url: reqwest::Url,
     pub path: PathBuf,
     pub id: Option<Box<str>>,
+    /// Headers to add to the request.
+    pub headers: Vec<(String, String)>,
+    /// Basic authentication to use for the request.
+    pub auth: Option<BasicAuth>,
+    /// Download the file again, even if it already exists.
+    pub force: bool,
 }
 
 impl fmt::Display for Download {
@@ -491,34 +1011,416 @@ impl fmt::Display for Download {
 
 impl Download {
     fn apply(&self, input: UnitInput) -> Result<(), Error> {
-        use std::fs::File;
-        let UnitInput { state, .. } = input;
-        let Download { url, path, id } = self;
+        use reqwest::StatusCode;
+        use std::fs::{File, OpenOptions};
 
-        if !path.is_file() {
-            let mut out =
-                File::create(path).with_context(|| anyhow!("open file: {}", path.display()))?;
+        let UnitInput {
+            state,
+            summary,
+            http,
+            ..
+        } = input;
 
-            let mut response = reqwest::blocking::get(url.clone())
-                .with_context(|| anyhow!("download url: {}", url))?;
+        let Download {
+            url,
+            path,
+            id,
+            force,
+            ..
+        } = self;
 
-            response.copy_to(&mut out)?;
-        }
+        if *force || !path.is_file() {
+            // NB: the file is downloaded to `<name>.part` first, so that a partial download is
+            // never mistaken for a complete one, and can be resumed if the download fails.
+            let partial = partial_path(path)?;
 
-        if let Some(id) = id {
-            state.touch_once(id);
-        }
+            let offset = match FileSystem::try_open_meta(&partial)? {
+                Some(m) if m.is_file() => m.len(),
+                _ => 0,
+            };
 
-        Ok(())
-    }
-}
+            let mut response = self.request(http, offset)?;
 
-impl From<Download> for Unit {
-    fn from(value: Download) -> Unit {
+            let resumed = offset > 0
+                && response.status() == StatusCode::PARTIAL_CONTENT
+                && range_start(&response) == Some(offset);
+
+            if offset > 0 && !resumed {
+                log::info!(
+                    "server does not support resuming, downloading again: {}",
+                    url
+                );
+
+                // NB: the server might not have ignored the range, so request the whole file.
+                if response.status() != StatusCode::OK {
+                    response = self.request(http, 0)?;
+                }
+            }
+
+            let mut response = response
+                .error_for_status()
+                .with_context(|| anyhow!("download url: {}", url))?;
+
+            let mut out = if resumed {
+                log::info!("resuming download at {} bytes: {}", offset, url);
+                OpenOptions::new().append(true).open(&partial)
+            } else {
+                File::create(&partial)
+            }
+            .with_context(|| anyhow!("open file: {}", partial.display()))?;
+
+            response
+                .copy_to(&mut out)
+                .with_context(|| anyhow!("download url: {}", url))?;
+
+            drop(out);
+
+            fs::rename(&partial, path).with_context(|| {
+                anyhow!(
+                    "failed to rename {} to {}",
+                    partial.display(),
+                    path.display()
+                )
+            })?;
+
+            summary.file_downloaded();
+        } else {
+            summary.skipped();
+        }
+
+        if let Some(id) = id {
+            state.touch_once(id);
+        }
+
+        Ok(())
+    }
+
+    /// Send the request for the download, starting at the given offset.
+    fn request(
+        &self,
+        http: &reqwest::blocking::Client,
+        offset: u64,
+    ) -> Result<reqwest::blocking::Response, Error> {
+        let mut request = http.get(self.url.clone());
+
+        for (key, value) in &self.headers {
+            request = request.header(key.as_str(), value.as_str());
+        }
+
+        if let Some(auth) = &self.auth {
+            request = request.basic_auth(&auth.user, auth.password.as_ref());
+        }
+
+        if offset > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+        }
+
+        request
+            .send()
+            .with_context(|| anyhow!("download url: {}", self.url))
+    }
+}
+
+/// The path that a file is downloaded to before it's complete, which is `<name>.part`.
+fn partial_path(path: &Path) -> Result<PathBuf, Error> {
+    suffixed_path(path, ".part")
+}
+
+/// The path of a file next to `path`, which has the given suffix added to its name.
+fn suffixed_path(path: &Path, suffix: &str) -> Result<PathBuf, Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("no file name: {}", path.display()))?;
+
+    let mut suffixed = name.to_owned();
+    suffixed.push(suffix);
+    Ok(path.with_file_name(suffixed))
+}
+
+/// Parse the start of the range in the `Content-Range` header of a response.
+fn range_start(response: &reqwest::blocking::Response) -> Option<u64> {
+    let range = response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+
+    let range = range.strip_prefix("bytes ")?;
+    let (start, _) = range.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+impl From<Download> for Unit {
+    fn from(value: Download) -> Unit {
         Unit::Download(value)
     }
 }
 
+/// The format of an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A gzipped tarball.
+    TarGz,
+    /// A zip archive.
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detect the format of an archive from its file name.
+    pub fn from_name(name: &str) -> Result<ArchiveFormat, Error> {
+        let lower = name.to_lowercase();
+
+        if lower.ends_with(".tar.gz") || lower.ends_with(".tgz") {
+            return Ok(ArchiveFormat::TarGz);
+        }
+
+        if lower.ends_with(".zip") {
+            return Ok(ArchiveFormat::Zip);
+        }
+
+        bail!(
+            "unsupported archive `{}`, expected `.tar.gz`, `.tgz`, or `.zip`",
+            name
+        );
+    }
+
+    /// The file extension used for archives of this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+}
+
+/// Extract an archive into a directory.
+#[derive(Debug)]
+pub struct Extract {
+    /// The id used to mark that the archive has been extracted.
+    pub id: String,
+    /// The format of the archive.
+    pub format: ArchiveFormat,
+    /// The archive to extract.
+    pub archive: PathBuf,
+    /// The directory to extract into.
+    pub to: PathBuf,
+    /// Number of leading path components to strip from each entry.
+    pub strip_components: usize,
+}
+
+impl fmt::Display for Extract {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "extract {} to {}",
+            self.archive.display(),
+            self.to.display()
+        )
+    }
+}
+
+impl Extract {
+    fn apply(&self, input: UnitInput) -> Result<(), Error> {
+        use std::fs::File;
+
+        let Extract {
+            ref id,
+            format,
+            ref archive,
+            ref to,
+            strip_components,
+        } = *self;
+
+        let UnitInput { state, summary, .. } = input;
+
+        log::info!("extracting {} to {}", archive.display(), to.display());
+
+        let file =
+            File::open(archive).with_context(|| anyhow!("open archive: {}", archive.display()))?;
+
+        match format {
+            ArchiveFormat::TarGz => {
+                let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+
+                for entry in archive.entries()? {
+                    let mut entry = entry?;
+
+                    let relative = match strip_path(&entry.path()?, strip_components)? {
+                        Some(relative) => relative,
+                        None => continue,
+                    };
+
+                    check_ancestors(to, &relative)?;
+                    let path = to.join(&relative);
+                    let entry_type = entry.header().entry_type();
+
+                    if entry_type.is_symlink() || entry_type.is_hard_link() {
+                        let link = entry.link_name()?.ok_or_else(|| {
+                            anyhow!("link without a target in archive: {}", relative.display())
+                        })?;
+
+                        // NB: hardlinks are relative to the root of the archive, so they have
+                        // their components stripped like any other path.
+                        if entry_type.is_hard_link() {
+                            let link = strip_path(&link, strip_components)?.ok_or_else(|| {
+                                anyhow!("unsafe hardlink in archive: {}", relative.display())
+                            })?;
+
+                            check_ancestors(to, &link)?;
+
+                            if let Some(parent) = path.parent() {
+                                fs::create_dir_all(parent)?;
+                            }
+
+                            fs::hard_link(to.join(&link), &path).with_context(|| {
+                                anyhow!("failed to extract: {}", path.display())
+                            })?;
+
+                            continue;
+                        }
+
+                        if escapes(relative.parent().unwrap_or(Path::new("")), &link) {
+                            bail!(
+                                "symlink in archive points outside of the destination: {} -> {}",
+                                relative.display(),
+                                link.display()
+                            );
+                        }
+                    }
+
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+
+                    entry
+                        .unpack(&path)
+                        .with_context(|| anyhow!("failed to extract: {}", path.display()))?;
+                }
+            }
+            ArchiveFormat::Zip => {
+                use std::io;
+
+                let mut archive = zip::ZipArchive::new(file)?;
+
+                for index in 0..archive.len() {
+                    let mut entry = archive.by_index(index)?;
+
+                    let path = match strip_path(Path::new(entry.name()), strip_components)? {
+                        Some(relative) => {
+                            check_ancestors(to, &relative)?;
+                            to.join(relative)
+                        }
+                        None => continue,
+                    };
+
+                    if entry.is_dir() {
+                        fs::create_dir_all(&path)?;
+                        continue;
+                    }
+
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+
+                    io::copy(&mut entry, &mut File::create(&path)?)
+                        .with_context(|| anyhow!("failed to extract: {}", path.display()))?;
+
+                    #[cfg(unix)]
+                    if let Some(mode) = entry.unix_mode() {
+                        use std::os::unix::fs::PermissionsExt as _;
+                        fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+                    }
+                }
+            }
+        }
+
+        state.touch_once(id);
+        summary.archive_extracted();
+        Ok(())
+    }
+}
+
+/// Strip the given number of leading components from a path in an archive.
+///
+/// Returns `None` if nothing remains of the path. Paths which could escape the directory being
+/// extracted to are rejected.
+fn strip_path(path: &Path, strip_components: usize) -> Result<Option<PathBuf>, Error> {
+    use std::path::Component;
+
+    let mut out = PathBuf::new();
+    let mut skip = strip_components;
+
+    for c in path.components() {
+        match c {
+            Component::CurDir => continue,
+            Component::Normal(c) => {
+                if skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+
+                out.push(c);
+            }
+            _ => bail!("unsafe path in archive: {}", path.display()),
+        }
+    }
+
+    if out.as_os_str().is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(out))
+}
+
+/// Check that none of the parents of a path being extracted is a symlink, since anything written
+/// through it could end up outside of the directory being extracted to.
+fn check_ancestors(to: &Path, relative: &Path) -> Result<(), Error> {
+    let mut current = to.to_owned();
+
+    for c in relative.parent().into_iter().flat_map(Path::components) {
+        current.push(c);
+
+        match current.symlink_metadata() {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                bail!(
+                    "refusing to extract through symlink in archive: {}",
+                    current.display()
+                );
+            }
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Test if a symlink target, relative to the directory `base` in the archive, points outside of
+/// the directory being extracted to.
+fn escapes(base: &Path, target: &Path) -> bool {
+    use std::path::Component;
+
+    let mut depth = base.components().count();
+
+    for c in target.components() {
+        match c {
+            Component::CurDir => continue,
+            Component::Normal(..) => depth += 1,
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return true,
+        }
+    }
+
+    false
+}
+
+impl From<Extract> for Unit {
+    fn from(value: Extract) -> Unit {
+        Unit::Extract(value)
+    }
+}
+
 /// Mode modifications to apply.
 #[repr(u32)]
 pub enum Mode {
@@ -527,6 +1429,64 @@ pub enum Mode {
     Write = 4,
 }
 
+/// Wait for a path which is provided outside of quickcfg to exist.
+#[derive(Debug)]
+pub struct WaitFor {
+    /// The path to wait for.
+    pub path: PathBuf,
+    /// How long to wait for the path to exist. If `None`, fail right away if it doesn't.
+    pub timeout: Option<Duration>,
+}
+
+impl fmt::Display for WaitFor {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "wait for {}", self.path.display())
+    }
+}
+
+impl WaitFor {
+    /// How frequently to check if the path exists.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    fn apply(&self, _: UnitInput) -> Result<(), Error> {
+        use std::time::Instant;
+
+        let WaitFor { ref path, timeout } = *self;
+
+        if path.exists() {
+            return Ok(());
+        }
+
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => bail!("path does not exist: {}", path.display()),
+        };
+
+        log::info!("waiting up to {:?} for {}", timeout, path.display());
+        let deadline = Instant::now() + timeout;
+
+        while Instant::now() < deadline {
+            std::thread::sleep(Self::POLL_INTERVAL);
+
+            if path.exists() {
+                return Ok(());
+            }
+        }
+
+        bail!(
+            "path does not exist after waiting {:?}: {}",
+            timeout,
+            path.display()
+        );
+    }
+}
+
+impl From<WaitFor> for Unit {
+    fn from(value: WaitFor) -> Unit {
+        Unit::WaitFor(value)
+    }
+}
+
 /// Change the permissions of the given file.
 #[derive(Debug)]
 pub struct AddMode {
@@ -534,6 +1494,8 @@ pub struct AddMode {
     user: u32,
     group: u32,
     other: u32,
+    /// Replace the existing permissions, instead of adding to them.
+    replace: bool,
 }
 
 impl AddMode {
@@ -547,9 +1509,35 @@ impl AddMode {
             user: 0,
             group: 0,
             other: 0,
+            replace: false,
         }
     }
 
+    /// Create a new unit out of a unix mode, like `0o755`.
+    pub fn from_unix_mode<P>(path: &P, mode: u32) -> Self
+    where
+        P: ?Sized + AsRef<Path>,
+    {
+        Self {
+            path: path.as_ref().to_owned(),
+            user: (mode >> (3 * 2)) & 0o7,
+            group: (mode >> 3) & 0o7,
+            other: mode & 0o7,
+            replace: false,
+        }
+    }
+
+    /// Replace the existing permissions with exactly this mode, instead of adding to them.
+    pub fn replace(mut self) -> Self {
+        self.replace = true;
+        self
+    }
+
+    /// Test if the existing permissions should be replaced.
+    pub fn is_replace(&self) -> bool {
+        self.replace
+    }
+
     /// If the added mode is executable.
     pub fn is_executable(&self) -> bool {
         if self.user & (Mode::Execute as u32) != 0 {
@@ -599,18 +1587,29 @@ impl AddMode {
 
 impl fmt::Display for AddMode {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            fmt,
-            "add mode {:o} to {}",
-            self.unix_mode(),
-            self.path.display()
-        )
+        if self.replace {
+            write!(
+                fmt,
+                "set mode {:o} on {}",
+                self.unix_mode(),
+                self.path.display()
+            )
+        } else {
+            write!(
+                fmt,
+                "add mode {:o} to {}",
+                self.unix_mode(),
+                self.path.display()
+            )
+        }
     }
 }
 
 impl AddMode {
-    fn apply(&self, _: UnitInput) -> Result<(), Error> {
-        os::add_mode(self)
+    fn apply(&self, input: UnitInput) -> Result<(), Error> {
+        os::add_mode(self)?;
+        input.summary.mode_changed();
+        Ok(())
     }
 }
 
@@ -633,6 +1632,24 @@ pub struct RunOnce {
     pub root: bool,
     /// Arguments to add when running the command.
     pub args: Vec<String>,
+    /// Environment variables to set when running the command.
+    pub env: Vec<(String, String)>,
+    /// Kill the command if it runs for longer than this.
+    pub timeout: Option<Duration>,
+    /// Run the command again if the contents of `path` have changed since it last ran.
+    pub rerun_on_change: bool,
+    /// Run the command even if the contents of `path` haven't changed since it last ran.
+    pub force: bool,
+    /// The working directory to run the command in.
+    pub cwd: Option<PathBuf>,
+    /// The command requires interaction, so it inherits stdin from quickcfg.
+    ///
+    /// Output is always streamed as the command runs.
+    pub interactive: bool,
+    /// Only run the command if this shell command succeeds.
+    pub only_if: Option<String>,
+    /// Only run the command if this shell command fails.
+    pub unless: Option<String>,
 }
 
 impl fmt::Display for RunOnce {
@@ -650,7 +1667,67 @@ impl RunOnce {
             shell: false,
             root: false,
             args: Vec::new(),
+            env: Vec::new(),
+            timeout: None,
+            rerun_on_change: false,
+            force: false,
+            cwd: None,
+            interactive: false,
+            only_if: None,
+            unless: None,
+        }
+    }
+
+    /// Test if the guards of this command allow it to run.
+    fn guards_pass(&self) -> Result<bool, Error> {
+        if let Some(only_if) = self.only_if.as_deref() {
+            if !self.guard(only_if)? {
+                log::trace!("skipping `{}`, since `only_if` failed", self.path.display());
+                return Ok(false);
+            }
+        }
+
+        if let Some(unless) = self.unless.as_deref() {
+            if self.guard(unless)? {
+                log::trace!(
+                    "skipping `{}`, since `unless` succeeded",
+                    self.path.display()
+                );
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Run a guard through the shell, and test if it succeeded.
+    fn guard(&self, guard: &str) -> Result<bool, Error> {
+        use crate::command::Command;
+
+        let mut cmd = Command::shell(guard);
+
+        if let Some(cwd) = self.cwd.as_ref() {
+            cmd.working_directory(cwd);
+        }
+
+        for (key, value) in &self.env {
+            cmd.env(key, value);
         }
+
+        cmd.null_stdin();
+
+        let output = cmd
+            .run()
+            .with_context(|| anyhow!("failed to run guard: {}", guard))?;
+
+        Ok(output.status.success())
+    }
+
+    /// Test if the command inherits stdin.
+    ///
+    /// NB: commands run as root might need to ask for a password.
+    fn inherits_stdin(&self) -> bool {
+        self.interactive || self.root
     }
 
     /// Apply the unit.
@@ -658,7 +1735,12 @@ impl RunOnce {
         use crate::command::Command;
         use std::io;
 
-        let UnitInput { state, .. } = input;
+        let UnitInput {
+            read_state,
+            state,
+            summary,
+            ..
+        } = input;
 
         let RunOnce {
             ref id,
@@ -666,16 +1748,69 @@ impl RunOnce {
             shell,
             root,
             ref args,
+            ref env,
+            timeout,
+            rerun_on_change,
+            ref cwd,
+            ..
         } = *self;
 
+        let content = if rerun_on_change {
+            let content =
+                fs::read(path).with_context(|| anyhow!("failed to read: {}", path.display()))?;
+
+            // NB: unlike other hashes, this one doesn't expire.
+            let current = read_state
+                .read()
+                .map_err(|_| anyhow!("Lock poisoned"))?
+                .is_hash_current(id, &content);
+
+            if current && !self.force {
+                log::trace!("unchanged since last run: {}", path.display());
+                summary.skipped();
+                return Ok(());
+            }
+
+            Some(content)
+        } else {
+            None
+        };
+
+        if let Some(cwd) = cwd.as_deref() {
+            if !cwd.is_dir() {
+                bail!("working directory does not exist: {}", cwd.display());
+            }
+        }
+
+        // NB: a command skipped by its guards isn't marked as run, so that the guards are
+        // checked again next time.
+        if !self.guards_pass()? {
+            summary.skipped();
+            return Ok(());
+        }
+
+        // NB: scripts run through the shell don't have to be executable.
+        if !shell && !os::is_executable(path)? {
+            bail!("downloaded file is not executable: {}", path.display());
+        }
+
         if self.args.is_empty() {
             log::info!("running: {}", path.display());
         } else {
             log::info!("running: {} {}", path.display(), self.args.join(" "));
         }
 
-        let status = run_command(path, root, shell, args)
-            .with_context(|| anyhow!("failed to run: {}", path.display()))?;
+        let status = run_command(
+            path,
+            root,
+            shell,
+            args,
+            env,
+            timeout,
+            cwd.as_deref(),
+            self.inherits_stdin(),
+        )
+        .with_context(|| anyhow!("failed to run: {}", path.display()))?;
 
         if status != 0 {
             return Err(anyhow!(
@@ -685,40 +1820,82 @@ impl RunOnce {
             ));
         }
 
+        summary.command_run();
         state.touch_once(id);
+
+        if let Some(content) = content {
+            state.touch_hash(id, &content)?;
+        }
+
         return Ok(());
 
         #[cfg(windows)]
+        #[allow(clippy::too_many_arguments)]
         fn run_command(
             path: &Path,
             root: bool,
             _shell: bool,
             args: &Vec<String>,
+            env: &[(String, String)],
+            timeout: Option<Duration>,
+            cwd: Option<&Path>,
+            inherit_stdin: bool,
         ) -> io::Result<i32> {
             let mut cmd = Command::new(path);
             cmd.args(args);
 
+            if !inherit_stdin {
+                cmd.null_stdin();
+            }
+
+            if let Some(cwd) = cwd {
+                cmd.working_directory(cwd);
+            }
+
+            if root && !env.is_empty() {
+                log::warn!("environment variables are not passed to commands run as administrator");
+            }
+
+            for (key, value) in env {
+                cmd.env(key, value);
+            }
+
+            if let Some(timeout) = timeout {
+                cmd.timeout(timeout);
+            }
+
             Ok(if root {
                 cmd.runas()?
             } else {
                 let status = cmd.status()?;
                 status
                     .code()
-                    .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no status code"))?
+                    .ok_or_else(|| io::Error::other("no status code"))?
             })
         }
 
         #[cfg(not(windows))]
+        #[allow(clippy::too_many_arguments)]
         fn run_command(
             path: &Path,
             root: bool,
             shell: bool,
             args: &Vec<String>,
+            env: &[(String, String)],
+            timeout: Option<Duration>,
+            cwd: Option<&Path>,
+            inherit_stdin: bool,
         ) -> io::Result<i32> {
             let mut cmd = if root {
                 let mut cmd = Command::new("sudo");
                 cmd.args(&["-p", "[sudo] password for %u to run downloaded exe: ", "--"]);
 
+                // NB: sudo resets the environment, so pass variables through `env` instead.
+                if !env.is_empty() {
+                    cmd.arg("env");
+                    cmd.args(env.iter().map(|(key, value)| format!("{}={}", key, value)));
+                }
+
                 if shell {
                     cmd.arg("/bin/sh");
                     cmd.arg("--");
@@ -737,10 +1914,30 @@ impl RunOnce {
             };
 
             cmd.args(args);
+
+            // NB: sudo runs the command in the directory it was started in.
+            if let Some(cwd) = cwd {
+                cmd.working_directory(cwd);
+            }
+
+            if !inherit_stdin {
+                cmd.null_stdin();
+            }
+
+            if !root {
+                for (key, value) in env {
+                    cmd.env(key, value);
+                }
+            }
+
+            if let Some(timeout) = timeout {
+                cmd.timeout(timeout);
+            }
+
             let status = cmd.status()?;
             let code = status
                 .code()
-                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no status code"))?;
+                .ok_or_else(|| io::Error::other("no status code"))?;
             Ok(code)
         }
     }
@@ -757,10 +1954,10 @@ impl From<RunOnce> for Unit {
 pub struct GitClone {
     /// The ID of the thing being cloned.
     pub id: String,
-    /// Remote to clone.
-    pub remote: String,
     /// Git repository.
     pub path: PathBuf,
+    /// Options to use when cloning, the remotes are tried in order.
+    pub options: git::Options,
 }
 
 impl fmt::Display for GitClone {
@@ -768,7 +1965,7 @@ impl fmt::Display for GitClone {
         write!(
             fmt,
             "git clone `{}` to `{}`",
-            self.remote,
+            self.options.remotes.join("`, `"),
             self.path.display()
         )
     }
@@ -778,18 +1975,47 @@ impl GitClone {
     /// Apply the unit.
     fn apply(&self, input: UnitInput) -> Result<(), Error> {
         let UnitInput {
-            state, git_system, ..
+            state,
+            git_system,
+            summary,
+            ..
         } = input;
 
         let GitClone {
             ref id,
-            ref remote,
             ref path,
+            ref options,
         } = *self;
 
-        log::info!("Cloning `{}` into `{}`", remote, path.display());
-        GitSystem::clone(git_system, remote, path)?;
+        if options.remotes.is_empty() {
+            bail!("no remotes to clone `{}` from", path.display());
+        }
+
+        let git = git::with_remotes(&options.remotes, |remote| {
+            log::info!("Cloning `{}` into `{}`", remote, path.display());
+
+            match GitSystem::clone(git_system, remote, path, options) {
+                Ok(git) => Ok(git),
+                Err(e) => {
+                    // NB: the path didn't exist when the clone was scheduled, so remove anything
+                    // left behind by the failed clone before trying the next remote.
+                    if path.exists() {
+                        fs::remove_dir_all(path).with_context(|| {
+                            anyhow!("failed to clean up failed clone: {}", path.display())
+                        })?;
+                    }
+
+                    Err(e)
+                }
+            }
+        })?;
+
+        if options.submodules {
+            git.update_submodules()?;
+        }
+
         state.touch(id);
+        summary.repository_cloned();
         Ok(())
     }
 }
@@ -809,6 +2035,10 @@ pub struct GitUpdate {
     pub path: PathBuf,
     /// If the update should be forced.
     pub force: bool,
+    /// If a forced update should discard local modifications.
+    pub force_dirty: bool,
+    /// Options to use when updating.
+    pub options: git::Options,
 }
 
 impl fmt::Display for GitUpdate {
@@ -821,30 +2051,55 @@ impl GitUpdate {
     /// Apply the unit.
     fn apply(&self, input: UnitInput) -> Result<(), Error> {
         let UnitInput {
-            state, git_system, ..
+            state,
+            git_system,
+            summary,
+            ..
         } = input;
 
         let GitUpdate {
             ref id,
             ref path,
             force,
+            force_dirty,
+            ref options,
         } = *self;
 
-        let git = git_system.open(path)?;
+        let git = git_system.open(path, options)?;
 
         if git.needs_update()? {
-            if force {
-                log::info!("Force updating `{}`", git.path().display());
-                git.force_update()?;
-            } else {
-                log::info!("Updating `{}`", git.path().display());
-                git.update()?;
+            // NB: don't mark as updated, so that the warning is repeated on the next run.
+            if !git::update(&*git, force, force_dirty)? {
+                return Ok(());
             }
+
+            if options.submodules {
+                git.update_submodules()?;
+            }
+
+            summary.repository_updated();
+        } else {
+            summary.skipped();
         }
 
         state.touch(id);
         Ok(())
     }
+
+    /// Check if an update is available, without updating or fetching anything.
+    fn dry_run(&self, input: UnitInput) -> Result<(), Error> {
+        let UnitInput { git_system, .. } = input;
+
+        let git = git_system.open(&self.path, &self.options)?;
+
+        if git.check_update_available()? {
+            log::info!("would update `{}`", self.path.display());
+        } else {
+            log::info!("`{}` is up-to-date", self.path.display());
+        }
+
+        Ok(())
+    }
 }
 
 impl From<GitUpdate> for Unit {
@@ -852,3 +2107,1107 @@ impl From<GitUpdate> for Unit {
         Unit::GitUpdate(value)
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{Unit, UnitInput};
+    use crate::{facts::Facts, git, hierarchy::Data, packages, Config, State, Summary, Timestamp};
+    use anyhow::Error;
+
+    /// Apply the given unit with an empty configuration, returning the summary of what it did.
+    fn apply(unit: impl Into<Unit>) -> Result<Summary, Error> {
+        apply_with_data(unit, &Data::new(None, Vec::new()))
+    }
+
+    /// Apply the given unit with an empty configuration and the given hierarchy.
+    fn apply_with_data(unit: impl Into<Unit>, data: &Data) -> Result<Summary, Error> {
+        let config = Config::default();
+        apply_with_state(unit, data, &mut State::new(&config, Timestamp::now()))
+    }
+
+    /// Apply the given unit, reading from and storing changes into the given state.
+    fn apply_with_state(
+        unit: impl Into<Unit>,
+        data: &Data,
+        read_state: &mut State,
+    ) -> Result<Summary, Error> {
+        let http = reqwest::blocking::Client::new();
+        apply_with_http(unit, data, read_state, &http)
+    }
+
+    /// Apply the given unit using the given HTTP client.
+    fn apply_with_http(
+        unit: impl Into<Unit>,
+        data: &Data,
+        read_state: &mut State,
+        http: &reqwest::blocking::Client,
+    ) -> Result<Summary, Error> {
+        let now = Timestamp::now();
+        let config = read_state.config;
+        read_state.now = now;
+        let mut state = State::new(config, now);
+        let packages = packages::detect(&Facts::new(Vec::new()))?;
+        let git_system = git::setup()?;
+        let summary = Summary::default();
+        let shared = std::sync::RwLock::new(std::mem::replace(read_state, State::new(config, now)));
+
+        let result = unit.into().apply(UnitInput {
+            packages: &packages,
+            data,
+            read_state: &shared,
+            state: &mut state,
+            now,
+            git_system: &*git_system,
+            http,
+            summary: &summary,
+            dry_run: false,
+            diff: false,
+        });
+
+        *read_state = shared.into_inner().unwrap();
+        result?;
+        read_state.extend(state);
+        Ok(summary)
+    }
+
+    #[test]
+    fn test_hardlink() {
+        use super::Hardlink;
+        use std::os::unix::fs::MetadataExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let link = root.path().join("source");
+        let path = root.path().join("hardlink");
+        std::fs::write(&link, "content").unwrap();
+
+        apply(Hardlink {
+            remove: false,
+            path: path.clone(),
+            link: link.clone(),
+        })
+        .unwrap();
+
+        let expected = link.metadata().unwrap();
+        let actual = path.metadata().unwrap();
+        assert_eq!(actual.dev(), expected.dev());
+        assert_eq!(actual.ino(), expected.ino());
+    }
+
+    #[test]
+    fn test_copy_file_backup() {
+        use super::CopyFile;
+
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("from");
+        let to = root.path().join("to");
+        let bak = |name: &str| root.path().join(name);
+
+        let copy = || CopyFile {
+            from: from.clone(),
+            from_modified: from.metadata().unwrap().modified().unwrap(),
+            permissions: from.metadata().unwrap().permissions(),
+            to: to.clone(),
+            backup: true,
+        };
+
+        std::fs::write(&from, "new").unwrap();
+        std::fs::write(&to, "old").unwrap();
+
+        apply(copy()).unwrap();
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "new");
+        assert_eq!(std::fs::read_to_string(bak("to.bak")).unwrap(), "old");
+
+        // Unchanged content doesn't need a backup.
+        apply(copy()).unwrap();
+        assert!(!bak("to.bak.1").exists());
+
+        std::fs::write(&from, "newer").unwrap();
+        apply(copy()).unwrap();
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "newer");
+        assert_eq!(std::fs::read_to_string(bak("to.bak")).unwrap(), "old");
+        assert_eq!(std::fs::read_to_string(bak("to.bak.1")).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_copy_file_read_only() {
+        use super::CopyFile;
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("from");
+        let to = root.path().join("to");
+
+        let copy = || CopyFile {
+            from: from.clone(),
+            from_modified: from.metadata().unwrap().modified().unwrap(),
+            permissions: from.metadata().unwrap().permissions(),
+            to: to.clone(),
+            backup: false,
+        };
+
+        let write = |content: &str| {
+            if from.exists() {
+                fs::set_permissions(&from, fs::Permissions::from_mode(0o644)).unwrap();
+            }
+
+            fs::write(&from, content).unwrap();
+            fs::set_permissions(&from, fs::Permissions::from_mode(0o444)).unwrap();
+        };
+
+        write("first");
+        apply(copy()).unwrap();
+
+        // NB: the destination is read-only after the first copy.
+        write("second");
+        apply(copy()).unwrap();
+
+        assert_eq!(fs::read_to_string(&to).unwrap(), "second");
+        assert_eq!(to.metadata().unwrap().permissions().mode() & 0o777, 0o444);
+        assert!(!root.path().join("to.tmp").exists());
+    }
+
+    #[test]
+    fn test_copy_template_backup() {
+        use super::CopyTemplate;
+
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("from");
+        let to = root.path().join("to");
+        std::fs::write(&from, "# quickcfg: foo\nfoo is {{foo}}\n").unwrap();
+        std::fs::write(&to, "local changes\n").unwrap();
+
+        let mut mapping = serde_yaml::Mapping::new();
+        mapping.insert("foo".into(), "bar".into());
+        let data = Data::new(None, vec![mapping]);
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+
+        let copy = || CopyTemplate {
+            from: from.clone(),
+            from_modified: from.metadata().unwrap().modified().unwrap(),
+            to: to.clone(),
+            to_exists: true,
+            backup: true,
+            strict: false,
+        };
+
+        apply_with_state(copy(), &data, &mut state).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&to).unwrap(),
+            "# quickcfg: foo\nfoo is bar\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("to.bak")).unwrap(),
+            "local changes\n"
+        );
+
+        // Rendering the same content again doesn't need a backup, even if the state is lost.
+        apply_with_data(copy(), &data).unwrap();
+        assert!(!root.path().join("to.bak.1").exists());
+    }
+
+    #[test]
+    fn test_copy_template_strict() {
+        use super::CopyTemplate;
+
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("from");
+        let to = root.path().join("to");
+        std::fs::write(&from, "# quickcfg: foo\nfoo is {{foo}}, bar is {{bar}}\n").unwrap();
+
+        let mut mapping = serde_yaml::Mapping::new();
+        mapping.insert("foo".into(), "set".into());
+        let data = Data::new(None, vec![mapping]);
+
+        let copy = |strict| CopyTemplate {
+            from: from.clone(),
+            from_modified: from.metadata().unwrap().modified().unwrap(),
+            to: to.clone(),
+            to_exists: false,
+            backup: false,
+            strict,
+        };
+
+        let e = apply_with_data(copy(true), &data).unwrap_err();
+        let e = format!("{:#}", e);
+        assert!(e.contains(&from.display().to_string()), "{}", e);
+        assert!(e.contains("bar"), "{}", e);
+        assert!(!to.exists());
+
+        apply_with_data(copy(false), &data).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&to).unwrap(),
+            "# quickcfg: foo\nfoo is set, bar is \n"
+        );
+    }
+
+    #[test]
+    fn test_copy_template_diff() {
+        use super::CopyTemplate;
+
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("from");
+        let to = root.path().join("to");
+        std::fs::write(&from, "# quickcfg: foo\nfoo is {{foo}}\nsame\n").unwrap();
+        std::fs::write(&to, "# quickcfg: foo\nfoo is old\nsame\n").unwrap();
+
+        let mut mapping = serde_yaml::Mapping::new();
+        mapping.insert("foo".into(), "new".into());
+        let data = Data::new(None, vec![mapping]);
+
+        let copy = CopyTemplate {
+            from: from.clone(),
+            from_modified: from.metadata().unwrap().modified().unwrap(),
+            to: to.clone(),
+            to_exists: true,
+            backup: false,
+            strict: false,
+        };
+
+        let diff = copy.diff(&data).unwrap().expect("content should differ");
+        assert!(diff.contains("-foo is old\n"), "{}", diff);
+        assert!(diff.contains("+foo is new\n"), "{}", diff);
+        assert!(diff.contains(" same\n"), "{}", diff);
+
+        apply_with_data(copy, &data).unwrap();
+
+        let copy = CopyTemplate {
+            from: from.clone(),
+            from_modified: from.metadata().unwrap().modified().unwrap(),
+            to: to.clone(),
+            to_exists: true,
+            backup: false,
+            strict: false,
+        };
+
+        assert!(copy.diff(&data).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_wait_for() {
+        use super::WaitFor;
+        use std::time::{Duration, Instant};
+
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("external");
+
+        let e = apply(WaitFor {
+            path: path.clone(),
+            timeout: None,
+        })
+        .unwrap_err();
+        assert!(
+            format!("{:#}", e).contains("path does not exist"),
+            "{:#}",
+            e
+        );
+
+        let start = Instant::now();
+        assert!(apply(WaitFor {
+            path: path.clone(),
+            timeout: Some(Duration::from_millis(200)),
+        })
+        .is_err());
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        std::fs::write(&path, "").unwrap();
+
+        apply(WaitFor {
+            path: path.clone(),
+            timeout: None,
+        })
+        .unwrap();
+    }
+
+    /// Extract the archive fixture with the given number of stripped components.
+    fn extract_fixture(strip_components: usize) -> tempfile::TempDir {
+        use super::{ArchiveFormat, Extract};
+
+        let to = tempfile::tempdir().unwrap();
+        let archive =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/archive.tar.gz");
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+
+        apply_with_state(
+            Extract {
+                id: String::from("archive"),
+                format: ArchiveFormat::from_name(&archive.to_string_lossy()).unwrap(),
+                archive,
+                to: to.path().to_owned(),
+                strip_components,
+            },
+            &Data::new(None, Vec::new()),
+            &mut state,
+        )
+        .unwrap();
+
+        assert!(state.has_run_once("archive"));
+        to
+    }
+
+    #[test]
+    fn test_extract_tar_gz() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let to = extract_fixture(0);
+        let root = to.path().join("tool-1.0");
+        assert_eq!(
+            std::fs::read_to_string(root.join("README")).unwrap(),
+            "hello\n"
+        );
+
+        let mode = root
+            .join("bin/tool")
+            .metadata()
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o111, 0o111);
+    }
+
+    #[test]
+    fn test_extract_strip_components() {
+        let to = extract_fixture(1);
+        assert!(!to.path().join("tool-1.0").exists());
+        assert_eq!(
+            std::fs::read_to_string(to.path().join("README")).unwrap(),
+            "hello\n"
+        );
+        assert!(to.path().join("bin/tool").is_file());
+
+        let to = extract_fixture(2);
+        assert!(!to.path().join("README").exists());
+        assert!(to.path().join("tool").is_file());
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        use super::{ArchiveFormat, Extract};
+        use std::io::Write as _;
+
+        let root = tempfile::tempdir().unwrap();
+        let archive = root.path().join("archive.zip");
+        let to = root.path().join("out");
+        std::fs::create_dir(&to).unwrap();
+
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+        let options = zip::write::FileOptions::default();
+        zip.add_directory("tool-1.0/", options).unwrap();
+        zip.start_file("tool-1.0/README", options).unwrap();
+        zip.write_all(b"hello\n").unwrap();
+        zip.finish().unwrap();
+
+        apply(Extract {
+            id: String::from("archive"),
+            format: ArchiveFormat::Zip,
+            archive,
+            to: to.clone(),
+            strip_components: 1,
+        })
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(to.join("README")).unwrap(),
+            "hello\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_path() {
+        use super::strip_path;
+        use std::path::{Path, PathBuf};
+
+        assert_eq!(
+            strip_path(Path::new("./a/b/c"), 1).unwrap(),
+            Some(PathBuf::from("b/c"))
+        );
+        assert_eq!(strip_path(Path::new("a/"), 1).unwrap(), None);
+        assert!(strip_path(Path::new("a/../../b"), 0).is_err());
+        assert!(strip_path(Path::new("/etc/passwd"), 0).is_err());
+    }
+
+    /// Extract a tarball with the given entries, where symlink and hardlink entries have a target.
+    fn extract_tar(
+        entries: &[(&str, tar::EntryType, &str)],
+    ) -> (tempfile::TempDir, Result<Summary, Error>) {
+        use super::{ArchiveFormat, Extract};
+
+        let root = tempfile::tempdir().unwrap();
+        let archive = root.path().join("archive.tar.gz");
+        std::fs::create_dir(root.path().join("out")).unwrap();
+
+        let encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&archive).unwrap(),
+            flate2::Compression::default(),
+        );
+
+        let mut builder = tar::Builder::new(encoder);
+
+        for (path, entry_type, target) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_entry_type(*entry_type);
+
+            if entry_type.is_file() {
+                header.set_size(5);
+                builder
+                    .append_data(&mut header, path, &b"hello"[..])
+                    .unwrap();
+            } else {
+                header.set_size(0);
+                builder.append_link(&mut header, path, target).unwrap();
+            }
+        }
+
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let result = apply(Extract {
+            id: String::from("archive"),
+            format: ArchiveFormat::TarGz,
+            archive,
+            to: root.path().join("out"),
+            strip_components: 0,
+        });
+
+        (root, result)
+    }
+
+    #[test]
+    fn test_extract_malicious_tar() {
+        use tar::EntryType::{Link, Regular, Symlink};
+
+        let outside = tempfile::tempdir().unwrap();
+        let target = outside.path().to_string_lossy().into_owned();
+
+        let (_, result) = extract_tar(&[("evil", Symlink, &target), ("evil/.bashrc", Regular, "")]);
+        assert!(result.is_err());
+        assert!(!outside.path().join(".bashrc").exists());
+
+        let (root, result) = extract_tar(&[("evil", Symlink, ".."), ("evil/.bashrc", Regular, "")]);
+        assert!(result.is_err());
+        assert!(!root.path().join(".bashrc").exists());
+
+        let (_, result) = extract_tar(&[("evil", Link, "../secret")]);
+        assert!(result.is_err());
+
+        // NB: links inside of the destination are fine, but nothing is written through them.
+        let (root, result) = extract_tar(&[
+            ("dir/a.txt", Regular, ""),
+            ("link", Symlink, "dir"),
+            ("dir/b", Symlink, "../dir/a.txt"),
+            ("c.txt", Link, "dir/a.txt"),
+        ]);
+        result.unwrap();
+        let out = root.path().join("out");
+        assert_eq!(std::fs::read_to_string(out.join("dir/b")).unwrap(), "hello");
+        assert_eq!(std::fs::read_to_string(out.join("c.txt")).unwrap(), "hello");
+
+        let (_, result) = extract_tar(&[
+            ("dir/a.txt", Regular, ""),
+            ("link", Symlink, "dir"),
+            ("link/b.txt", Regular, ""),
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_create_dir() {
+        use super::CreateDir;
+
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("dir");
+
+        let summary = apply(CreateDir(dir.clone())).unwrap();
+        assert!(dir.is_dir());
+        assert!(summary.changed());
+    }
+
+    #[test]
+    fn test_git_remote_fallback() {
+        use super::{GitClone, GitUpdate};
+        use crate::command::Command;
+
+        let git = |dir: &std::path::Path, args: &[&str]| {
+            let mut command = Command::new("git");
+            command.working_directory(dir);
+            command.args(&["-c", "user.name=test", "-c", "user.email=test@example.com"]);
+            command.args(args);
+            command.run_checked().unwrap();
+        };
+
+        let root = tempfile::tempdir().unwrap();
+        let remote = root.path().join("remote");
+        let checkout = root.path().join("checkout");
+        let missing = root.path().join("missing");
+
+        git(root.path(), &["init", "-b", "main", "remote"]);
+        std::fs::write(remote.join("first.txt"), "first").unwrap();
+        git(&remote, &["add", "first.txt"]);
+        git(&remote, &["commit", "-m", "first"]);
+
+        let options = git::Options {
+            remotes: vec![missing.display().to_string(), remote.display().to_string()],
+            ..git::Options::default()
+        };
+
+        let summary = apply(GitClone {
+            id: String::from("git-sync/test"),
+            path: checkout.clone(),
+            options: options.clone(),
+        })
+        .unwrap();
+
+        assert!(checkout.join("first.txt").is_file());
+        assert!(summary.changed());
+
+        let update = || GitUpdate {
+            id: String::from("git-sync/test"),
+            path: checkout.clone(),
+            force: false,
+            force_dirty: false,
+            options: options.clone(),
+        };
+
+        assert!(!apply(update()).unwrap().changed());
+
+        std::fs::write(remote.join("second.txt"), "second").unwrap();
+        git(&remote, &["add", "second.txt"]);
+        git(&remote, &["commit", "-m", "second"]);
+
+        assert!(apply(update()).unwrap().changed());
+        assert!(checkout.join("second.txt").is_file());
+    }
+
+    #[test]
+    fn test_template_string() {
+        use super::TemplateString;
+
+        let root = tempfile::tempdir().unwrap();
+        let to = root.path().join("generated");
+
+        let mut mapping = serde_yaml::Mapping::new();
+        mapping.insert("foo".into(), "bar".into());
+        let data = Data::new(None, vec![mapping]);
+
+        apply_with_data(
+            TemplateString {
+                content: String::from("# quickcfg: foo\nfoo is {{foo}}\n"),
+                to: to.clone(),
+            },
+            &data,
+        )
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&to).unwrap(),
+            "# quickcfg: foo\nfoo is bar\n"
+        );
+    }
+
+    #[test]
+    fn test_rerun_on_change() {
+        use super::RunOnce;
+
+        let root = tempfile::tempdir().unwrap();
+        let script = root.path().join("script.sh");
+        let out = root.path().join("out");
+        let write = format!("echo run >> {}\n", out.display());
+        std::fs::write(&script, &write).unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        let data = Data::new(None, Vec::new());
+
+        let run_once = || {
+            let mut run_once = RunOnce::new(String::from("script"), script.clone());
+            run_once.shell = true;
+            run_once.rerun_on_change = true;
+            run_once
+        };
+
+        let runs = || std::fs::read_to_string(&out).unwrap().lines().count();
+
+        apply_with_state(run_once(), &data, &mut state).unwrap();
+        assert_eq!(runs(), 1);
+        assert!(state.has_run_once("script"));
+
+        apply_with_state(run_once(), &data, &mut state).unwrap();
+        assert_eq!(runs(), 1);
+
+        std::fs::write(&script, format!("# changed\n{}", write)).unwrap();
+        apply_with_state(run_once(), &data, &mut state).unwrap();
+        assert_eq!(runs(), 2);
+    }
+
+    #[test]
+    fn test_download_existing() {
+        use super::Download;
+
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("plug.vim");
+        std::fs::write(&path, "existing").unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        let data = Data::new(None, Vec::new());
+
+        let download = Download {
+            url: reqwest::Url::parse("https://example.com/plug.vim").unwrap(),
+            path: path.clone(),
+            id: Some("plug".into()),
+            headers: Vec::new(),
+            auth: None,
+            force: false,
+        };
+
+        // NB: the file exists, so nothing is downloaded but the id is still marked.
+        apply_with_state(download, &data, &mut state).unwrap();
+        assert!(state.has_run_once("plug"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "existing");
+    }
+
+    /// Serve a single request on a local port, responding with `200 OK` if the request contains
+    /// the given header line and `401 Unauthorized` otherwise.
+    fn serve_once(header: &'static str) -> (reqwest::Url, std::thread::JoinHandle<bool>) {
+        use std::io::{BufRead as _, BufReader, Write as _};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut found = false;
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+
+                if line.is_empty() {
+                    break;
+                }
+
+                found |= line.eq_ignore_ascii_case(header);
+            }
+
+            let response = if found {
+                "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+            } else {
+                "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            };
+
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            found
+        });
+
+        let url = reqwest::Url::parse(&format!("http://{}/file", addr)).unwrap();
+        (url, handle)
+    }
+
+    #[test]
+    fn test_download_headers() {
+        use super::{BasicAuth, Download};
+
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("file");
+
+        let (url, handle) = serve_once("x-token: secret");
+
+        let download = Download {
+            url,
+            path: path.clone(),
+            id: None,
+            headers: vec![(String::from("X-Token"), String::from("secret"))],
+            auth: None,
+            force: false,
+        };
+
+        apply(download).unwrap();
+        assert!(handle.join().unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello");
+
+        // NB: "user:pass" in base64.
+        let (url, handle) = serve_once("authorization: Basic dXNlcjpwYXNz");
+        let path = root.path().join("auth");
+
+        let download = Download {
+            url,
+            path: path.clone(),
+            id: None,
+            headers: Vec::new(),
+            auth: Some(BasicAuth {
+                user: String::from("user"),
+                password: Some(String::from("pass")),
+            }),
+            force: false,
+        };
+
+        apply(download).unwrap();
+        assert!(handle.join().unwrap());
+        assert!(path.is_file());
+    }
+
+    #[test]
+    fn test_download_shared_client() {
+        use super::Download;
+
+        let root = tempfile::tempdir().unwrap();
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        let data = Data::new(None, Vec::new());
+        let http = reqwest::blocking::Client::new();
+
+        for name in ["a", "b"] {
+            let (url, handle) = serve_once("x-token: secret");
+            let path = root.path().join(name);
+
+            let download = Download {
+                url,
+                path: path.clone(),
+                id: Some(name.into()),
+                headers: vec![(String::from("X-Token"), String::from("secret"))],
+                auth: None,
+                force: false,
+            };
+
+            apply_with_http(download, &data, &mut state, &http).unwrap();
+            assert!(handle.join().unwrap());
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello");
+            assert!(state.has_run_once(name));
+        }
+    }
+
+    /// Serve a single request for `content` on a local port, returning the `Range` header of the
+    /// request. Ranges are only respected if `ranges` is set.
+    fn serve_ranged(
+        content: &'static str,
+        ranges: bool,
+    ) -> (reqwest::Url, std::thread::JoinHandle<Option<String>>) {
+        use std::io::{BufRead as _, BufReader, Write as _};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut range = None;
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+
+                if line.is_empty() {
+                    break;
+                }
+
+                if let Some((key, value)) = line.split_once(':') {
+                    if key.eq_ignore_ascii_case("range") {
+                        range = Some(value.trim().to_string());
+                    }
+                }
+            }
+
+            let start = range
+                .as_deref()
+                .filter(|_| ranges)
+                .and_then(|r| r.strip_prefix("bytes="))
+                .and_then(|r| r.strip_suffix('-'))
+                .map(|r| r.parse::<usize>().unwrap());
+
+            let response = match start {
+                Some(start) => format!(
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    start,
+                    content.len() - 1,
+                    content.len(),
+                    content.len() - start,
+                    &content[start..]
+                ),
+                None => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    content.len(),
+                    content
+                ),
+            };
+
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            range
+        });
+
+        let url = reqwest::Url::parse(&format!("http://{}/file", addr)).unwrap();
+        (url, handle)
+    }
+
+    #[test]
+    fn test_download_resume() {
+        use super::Download;
+
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("file");
+        let partial = root.path().join("file.part");
+        std::fs::write(&partial, "hello").unwrap();
+
+        let (url, handle) = serve_ranged("hello world", true);
+
+        let download = Download {
+            url,
+            path: path.clone(),
+            id: None,
+            headers: Vec::new(),
+            auth: None,
+            force: false,
+        };
+
+        let summary = apply(download).unwrap();
+        assert!(summary.changed());
+        assert_eq!(handle.join().unwrap().as_deref(), Some("bytes=5-"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+        assert!(!partial.exists());
+    }
+
+    #[test]
+    fn test_download_resume_unsupported() {
+        use super::Download;
+
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("file");
+        let partial = root.path().join("file.part");
+        std::fs::write(&partial, "junk").unwrap();
+
+        let (url, handle) = serve_ranged("hello world", false);
+
+        let download = Download {
+            url,
+            path: path.clone(),
+            id: None,
+            headers: Vec::new(),
+            auth: None,
+            force: false,
+        };
+
+        // NB: the server responds with the whole file, which replaces the partial download.
+        apply(download).unwrap();
+        assert_eq!(handle.join().unwrap().as_deref(), Some("bytes=4-"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+        assert!(!partial.exists());
+    }
+
+    #[test]
+    fn test_download_missing_header() {
+        use super::Download;
+
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("file");
+
+        let (url, handle) = serve_once("x-token: secret");
+
+        let download = Download {
+            url,
+            path: path.clone(),
+            id: None,
+            headers: Vec::new(),
+            auth: None,
+            force: false,
+        };
+
+        // NB: failed requests don't leave an empty file behind.
+        assert!(apply(download).is_err());
+        assert!(!handle.join().unwrap());
+        assert!(!path.exists());
+        assert!(!root.path().join("file.part").exists());
+    }
+
+    #[test]
+    fn test_run_once_cwd() {
+        use super::RunOnce;
+
+        let root = tempfile::tempdir().unwrap();
+        let script = root.path().join("script.sh");
+        let work = root.path().join("work");
+        std::fs::write(&script, "echo hello > out.txt\n").unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        let data = Data::new(None, Vec::new());
+
+        let mut run_once = RunOnce::new(String::from("script"), script.clone());
+        run_once.shell = true;
+        run_once.cwd = Some(work.clone());
+
+        let e = apply_with_state(run_once, &data, &mut state).unwrap_err();
+        assert!(format!("{:#}", e).contains("working directory does not exist"));
+
+        std::fs::create_dir(&work).unwrap();
+
+        let mut run_once = RunOnce::new(String::from("script"), script.clone());
+        run_once.shell = true;
+        run_once.cwd = Some(work.clone());
+
+        apply_with_state(run_once, &data, &mut state).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(work.join("out.txt")).unwrap(),
+            "hello\n"
+        );
+        assert!(!root.path().join("out.txt").exists());
+    }
+
+    #[test]
+    fn test_run_once_guards() {
+        use super::RunOnce;
+
+        let root = tempfile::tempdir().unwrap();
+        let script = root.path().join("script.sh");
+        let out = root.path().join("out");
+        std::fs::write(&script, format!("echo run >> {}\n", out.display())).unwrap();
+
+        let config = Config::default();
+        let data = Data::new(None, Vec::new());
+
+        let run = |only_if: Option<&str>, unless: Option<&str>| {
+            let _ = std::fs::remove_file(&out);
+            let mut state = State::new(&config, Timestamp::now());
+
+            let mut run_once = RunOnce::new(String::from("script"), script.clone());
+            run_once.shell = true;
+            run_once.only_if = only_if.map(String::from);
+            run_once.unless = unless.map(String::from);
+
+            apply_with_state(run_once, &data, &mut state).unwrap();
+            assert_eq!(state.has_run_once("script"), out.is_file());
+            out.is_file()
+        };
+
+        assert!(run(Some("true"), None));
+        assert!(!run(Some("false"), None));
+        assert!(run(None, Some("false")));
+        assert!(!run(None, Some("test -d /")));
+        assert!(!run(Some("true"), Some("true")));
+    }
+
+    #[test]
+    fn test_run_once_not_executable() {
+        use super::RunOnce;
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let script = root.path().join("script.sh");
+        let out = root.path().join("out");
+        std::fs::write(
+            &script,
+            format!("#!/bin/sh\necho run > {}\n", out.display()),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        let data = Data::new(None, Vec::new());
+
+        let run_once = RunOnce::new(String::from("script"), script.clone());
+        let e = apply_with_state(run_once, &data, &mut state).unwrap_err();
+        assert!(format!("{:#}", e).contains("downloaded file is not executable"));
+        assert!(!state.has_run_once("script"));
+        assert!(!out.exists());
+
+        // NB: guards are checked before the file has to be executable.
+        let mut run_once = RunOnce::new(String::from("script"), script.clone());
+        run_once.only_if = Some(String::from("false"));
+        apply_with_state(run_once, &data, &mut state).unwrap();
+        assert!(!state.has_run_once("script"));
+
+        // NB: running through the shell doesn't require the file to be executable.
+        let mut run_once = RunOnce::new(String::from("script"), script);
+        run_once.shell = true;
+        apply_with_state(run_once, &data, &mut state).unwrap();
+        assert!(state.has_run_once("script"));
+        assert!(out.is_file());
+    }
+
+    #[test]
+    fn test_run_once_stdin() {
+        use super::RunOnce;
+
+        let root = tempfile::tempdir().unwrap();
+        let script = root.path().join("script.sh");
+        let out = root.path().join("out");
+
+        std::fs::write(
+            &script,
+            format!(
+                "if read line; then echo read; else echo eof; fi > {}\n",
+                out.display()
+            ),
+        )
+        .unwrap();
+
+        let config = Config::default();
+        let mut state = State::new(&config, Timestamp::now());
+        let data = Data::new(None, Vec::new());
+
+        let mut run_once = RunOnce::new(String::from("script"), script.clone());
+        run_once.shell = true;
+        assert!(!run_once.inherits_stdin());
+
+        // NB: non-interactive commands can't wait for input.
+        apply_with_state(run_once, &data, &mut state).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "eof\n");
+
+        let mut run_once = RunOnce::new(String::from("script"), script.clone());
+        run_once.interactive = true;
+        assert!(run_once.inherits_stdin());
+
+        let mut run_once = RunOnce::new(String::from("script"), script);
+        run_once.root = true;
+        assert!(run_once.inherits_stdin());
+    }
+
+    fn render(content: &str, data: &str) -> String {
+        let data = serde_yaml::from_str(data).unwrap();
+        String::from_utf8(super::render_template("test", content, &data, false).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(
+            render("{{upper name}}, {{lower name}}", "name: Hello"),
+            "HELLO, hello"
+        );
+        assert_eq!(
+            render(
+                "{{default name \"fallback\"}} {{default missing \"fallback\"}}",
+                "name: set"
+            ),
+            "set fallback"
+        );
+        assert_eq!(
+            render("{{default empty \"fallback\"}}", "empty: ''"),
+            "fallback"
+        );
+        assert_eq!(render("{{upper (default missing \"x\")}}", "{}"), "X");
+        assert_eq!(
+            render("{{#if (eq os \"linux\")}}yes{{else}}no{{/if}}", "os: linux"),
+            "yes"
+        );
+
+        std::env::set_var("QUICKCFG_TEMPLATE_TEST", "from env");
+        assert_eq!(
+            render("{{env \"QUICKCFG_TEMPLATE_TEST\"}}", "{}"),
+            "from env"
+        );
+        assert_eq!(
+            render(
+                "{{default (env \"QUICKCFG_TEMPLATE_MISSING\") \"none\"}}",
+                "{}"
+            ),
+            "none"
+        );
+    }
+}
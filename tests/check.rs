@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Run `qc --check` non-interactively against the given root.
+fn check(root: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_qc"))
+        .args(["--non-interactive", "--check", "--root"])
+        .arg(root)
+        .output()
+        .unwrap()
+}
+
+/// Check a root with the given configuration, and assert that it fails with the given error.
+fn assert_invalid(config: &str, error: &str) {
+    let root = tempfile::tempdir().unwrap();
+    fs::write(root.path().join("quickcfg.yml"), config).unwrap();
+
+    let output = check(root.path());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{}", stderr);
+    assert!(stderr.contains(error), "{}", stderr);
+}
+
+#[test]
+fn test_check_valid() {
+    let root = tempfile::tempdir().unwrap();
+    fs::write(
+        root.path().join("quickcfg.yml"),
+        "hierarchy: []\nsystems:\n  - type: link-dir\n    id: a\n    from: a\n    to: home://a\n    when: os == linux\n  - type: link-dir\n    from: b\n    to: home://b\n    requires: [a]\n",
+    )
+    .unwrap();
+
+    let output = check(root.path());
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_check_unbalanced_template() {
+    assert_invalid(
+        "hierarchy: []\nsystems:\n  - type: copy-dir\n    from: a\n    to: home://{foo\n",
+        "missing closing",
+    );
+}
+
+#[test]
+fn test_check_bad_condition() {
+    assert_invalid(
+        "hierarchy: []\nsystems:\n  - type: link-dir\n    from: a\n    to: home://a\n    when: os linux\n",
+        "bad condition `os linux`",
+    );
+}
+
+#[test]
+fn test_check_unknown_requires() {
+    assert_invalid(
+        "hierarchy: []\nsystems:\n  - type: link-dir\n    from: a\n    to: home://a\n    requires: [nope]\n",
+        "unknown system `nope`",
+    );
+}
+
+#[test]
+fn test_check_missing_root() {
+    let root = tempfile::tempdir().unwrap();
+    let output = check(&root.path().join("missing"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains("Missing configuration directory"),
+        "{}",
+        stderr
+    );
+}
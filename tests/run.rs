@@ -0,0 +1,209 @@
+use clap::Parser as _;
+use quickcfg::{
+    opts::Opts,
+    results::{Results, Status},
+    DiskState, Load as _,
+};
+use std::fs;
+use std::path::Path;
+
+/// Parse options for a non-interactive run with the given extra arguments.
+///
+/// NB: fixtures aren't git repositories, so they are never checked for updates.
+fn opts(args: &[&str]) -> Opts {
+    let mut all = vec!["qc", "--non-interactive", "--no-git"];
+    all.extend(args);
+    Opts::parse_from(all)
+}
+
+/// Recursively copy the fixture directory `from` into `to`.
+fn copy_fixture(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+
+    for e in fs::read_dir(from).unwrap() {
+        let e = e.unwrap();
+        let to = to.join(e.file_name());
+
+        if e.file_type().unwrap().is_dir() {
+            copy_fixture(&e.path(), &to);
+        } else {
+            fs::copy(e.path(), &to).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_run() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    copy_fixture(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/run"),
+        root,
+    );
+
+    let opts = opts(&[]);
+
+    let report = quickcfg::run(&opts, root).unwrap();
+    assert!(!report.updated);
+    assert!(report.units > 0);
+
+    let summary = report.summary.expect("units to have run").to_string();
+    assert!(summary.contains("2 file(s) copied"), "{}", summary);
+    assert!(summary.contains("1 template(s) rendered"), "{}", summary);
+
+    assert_eq!(fs::read_to_string(root.join("out/a.txt")).unwrap(), "a\n");
+    assert_eq!(
+        fs::read_to_string(root.join("out/sub/b.txt")).unwrap(),
+        "b\n"
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("out/hello.txt")).unwrap(),
+        "hello from quickcfg"
+    );
+
+    // Everything is up-to-date on a second run.
+    let report = quickcfg::run(&opts, root).unwrap();
+    let summary = report.summary.expect("units to have run").to_string();
+    assert!(summary.contains("0 file(s) copied"), "{}", summary);
+    assert!(summary.contains("0 template(s) rendered"), "{}", summary);
+}
+
+#[test]
+fn test_run_missing_root() {
+    let root = tempfile::tempdir().unwrap();
+    let opts = opts(&[]);
+
+    let e = quickcfg::run(&opts, &root.path().join("missing")).err();
+    let e = e.expect("missing root to fail").to_string();
+    assert!(e.contains("Missing configuration directory"), "{}", e);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_run_hooks() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    fs::create_dir_all(root.join("files")).unwrap();
+    fs::write(root.join("files/a.txt"), "{{missing}}").unwrap();
+
+    let config = |strict: bool| {
+        format!(
+            "hierarchy: []\n\
+             systems:\n  \
+               - type: copy-dir\n    \
+                 from: files\n    \
+                 to: out\n    \
+                 templates: true\n    \
+                 strict: {strict}\n\
+             post_hooks: [\"echo post >> hooks.txt\"]\n\
+             on_change: [\"echo change >> hooks.txt\"]\n",
+            strict = strict
+        )
+    };
+
+    let opts = opts(&[]);
+
+    // A unit fails to render, so no hooks are run.
+    fs::write(root.join("quickcfg.yml"), config(true)).unwrap();
+    assert!(quickcfg::run(&opts, root).is_err());
+    assert!(!root.join("hooks.txt").exists());
+
+    fs::write(root.join("quickcfg.yml"), config(false)).unwrap();
+    quickcfg::run(&opts, root).unwrap();
+    assert_eq!(
+        fs::read_to_string(root.join("hooks.txt")).unwrap(),
+        "post\nchange\n"
+    );
+
+    // Nothing changes on the second run, so only the post hooks run.
+    quickcfg::run(&opts, root).unwrap();
+    assert_eq!(
+        fs::read_to_string(root.join("hooks.txt")).unwrap(),
+        "post\nchange\npost\n"
+    );
+}
+
+#[test]
+fn test_run_report() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    copy_fixture(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/run"),
+        root,
+    );
+
+    let report = root.join("report.json");
+    let opts = opts(&["--report", report.to_str().unwrap()]);
+
+    let load = || serde_json::from_slice::<Results>(&fs::read(&report).unwrap()).unwrap();
+
+    quickcfg::run(&opts, root).unwrap();
+    let results = load();
+    assert!(!results.updated);
+    assert!(results.error.is_none());
+    assert_eq!(results.systems.len(), 2);
+
+    let copy = results
+        .systems
+        .iter()
+        .find(|s| s.system.contains("copy directory"))
+        .expect("copy-dir system");
+    assert_eq!(copy.status, Status::Applied);
+    assert!(copy.units.iter().any(|u| u.unit.contains("copy file")));
+
+    // Everything is up-to-date on a second run.
+    quickcfg::run(&opts, root).unwrap();
+    let results = load();
+    assert!(results.systems.iter().all(|s| s.status == Status::Skipped));
+
+    // The report is written even if the run fails.
+    fs::write(root.join("files/c.txt"), "{{missing}}").unwrap();
+    fs::write(
+        root.join("quickcfg.yml"),
+        "hierarchy: []\n\
+         systems:\n  \
+           - type: copy-dir\n    \
+             from: files\n    \
+             to: out\n    \
+             templates: true\n    \
+             strict: true\n",
+    )
+    .unwrap();
+
+    assert!(quickcfg::run(&opts, root).is_err());
+    let results = load();
+    assert!(results.error.is_some());
+    assert_eq!(results.systems.len(), 1);
+    assert_eq!(results.systems[0].status, Status::Failed);
+
+    let failed = results.systems[0]
+        .units
+        .iter()
+        .find(|u| u.status == Status::Failed)
+        .expect("failed unit");
+    assert!(failed.unit.contains("c.txt"), "{}", failed.unit);
+    assert!(failed.error.as_deref().unwrap().contains("missing"));
+}
+
+#[test]
+fn test_run_report_unwritable() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    copy_fixture(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/run"),
+        root,
+    );
+
+    // NB: a directory can't be written to as a report.
+    let report = root.join("report");
+    fs::create_dir(&report).unwrap();
+    let opts = opts(&["--report", report.to_str().unwrap()]);
+
+    let e = quickcfg::run(&opts, root).err();
+    let e = e.expect("report to fail").to_string();
+    assert!(e.contains("Failed to create report"), "{}", e);
+
+    let state = DiskState::load(&root.join(".state.yml")).unwrap();
+    let state = state.expect("state to be saved");
+    assert!(!state.hashes.is_empty());
+}